@@ -0,0 +1,138 @@
+/* The C API of the Second Best! solver. Generated by cbindgen, do not edit. */
+
+#ifndef SECOND_BEST_H
+#define SECOND_BEST_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A position, see [`Position`].
+typedef struct SbPosition SbPosition;
+
+// The solver, see [`Solver`]. The transposition table is kept between searches.
+typedef struct SbSolver SbSolver;
+
+// The result of a completed iteration of a search.
+// The strings are only valid during the callback.
+typedef struct SbSearchInfo {
+  size_t depth;
+  // The largest number of plies searched from the position.
+  size_t seldepth;
+  // The score from the perspective of the player to move.
+  ptrdiff_t score;
+  size_t nodes;
+  uint64_t time_ms;
+  // How full the transposition table is, in per mille.
+  size_t ttfull;
+  // The principal variation, with the moves separated by spaces.
+  const char *pv;
+} SbSearchInfo;
+
+// Called with the result of every completed iteration of a search.
+typedef void (*SbInfoCallback)(const struct SbSearchInfo *info, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Free a string returned by one of the functions of this API.
+//
+// # Safety
+//
+// `s` has to be returned by this API, and not be freed before, or be null.
+void sb_string_free(char *s);
+
+// Create the starting position.
+struct SbPosition *sb_position_new(void);
+
+// Create a position from the compact notation, see [`Position::from_fen`].
+// Returns null if the notation is invalid.
+//
+// # Safety
+//
+// `fen` has to be a valid nul terminated string.
+struct SbPosition *sb_position_from_fen(const char *fen);
+
+// # Safety
+//
+// `pos` has to be created by this API, and not be freed before, or be null.
+void sb_position_free(struct SbPosition *pos);
+
+// Play the given moves, e.g. "0 1 2" or "0-1!". Returns false if one of
+// the moves is invalid or illegal, in which case none of them are played.
+//
+// # Safety
+//
+// `pos` has to be a valid position, and `moves` a valid nul terminated string.
+bool sb_position_play(struct SbPosition *pos, const char *moves);
+
+// Undo the last move. Returns false if there was nothing to undo.
+//
+// # Safety
+//
+// `pos` has to be a valid position.
+bool sb_position_undo(struct SbPosition *pos);
+
+// The position in the compact notation.
+//
+// # Safety
+//
+// `pos` has to be a valid position.
+char *sb_position_fen(const struct SbPosition *pos);
+
+// The legal moves, separated by spaces.
+//
+// # Safety
+//
+// `pos` has to be a valid position.
+char *sb_position_legal_moves(const struct SbPosition *pos);
+
+// # Safety
+//
+// `pos` has to be a valid position.
+bool sb_position_game_over(const struct SbPosition *pos);
+
+struct SbSolver *sb_solver_new(void);
+
+// # Safety
+//
+// `solver` has to be created by this API, and not be freed before, or be null.
+void sb_solver_free(struct SbSolver *solver);
+
+// Set a function which gets called with the result of every completed
+// iteration of a search, together with `user_data`.
+//
+// # Safety
+//
+// `solver` has to be a valid solver, and `user_data` has to stay valid
+// and usable from the thread doing the searches, until the callback is
+// replaced or the solver is freed.
+void sb_solver_set_info_callback(struct SbSolver *solver, SbInfoCallback callback, void *user_data);
+
+// Search the position until it is solved, or one of the limits is reached.
+// A limit of 0 means there is no limit. Returns the score of the position
+// from the perspective of the player to move.
+//
+// # Safety
+//
+// `solver` has to be a valid solver, and `pos` a valid position.
+ptrdiff_t sb_solver_search(struct SbSolver *solver,
+                           const struct SbPosition *pos,
+                           size_t max_depth,
+                           uint64_t max_time_ms);
+
+// The principal variation of the last search, with the moves separated by spaces.
+//
+// # Safety
+//
+// `solver` has to be a valid solver.
+char *sb_solver_pv(struct SbSolver *solver);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SECOND_BEST_H */
@@ -0,0 +1,839 @@
+use crate::error::{Error, Result};
+use crate::eval::{self, ExplainableEval};
+use crate::position::{PlayerMove, Position, Rules, Variant};
+use crate::progress::{ProgressHandler, ProgressReporter};
+use crate::solver::{SearchLimits, Solver};
+
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::Path;
+use web_time::Instant;
+
+/// The result of analyzing a single position of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Analysis {
+    /// The line of the file the position is on, starting from 1.
+    pub line: usize,
+    /// The position as it was written in the file.
+    pub position: String,
+    /// The score of the position, from the perspective of the player to move.
+    pub score: isize,
+    /// The score decoded into a win, loss or undetermined result.
+    pub result: ExplainableEval,
+    /// The best move found, or `None` if the game is over.
+    pub best_move: Option<PlayerMove>,
+    pub nodes: usize,
+    pub time_ms: u128,
+}
+
+/// Parse a line of a file to analyze. The line is either a position
+/// in compact notation, or a list of moves from the start of the game,
+/// on a board of the given size.
+pub(crate) fn parse_line(line: &str, variant: Variant) -> Option<Position> {
+    if line.contains('/') {
+        return Position::from_fen_with_variant(line, variant).ok();
+    }
+    let mut pos = Position::with_variant(variant);
+    let moves = line.split_whitespace().map(|s| s.to_string()).collect();
+    pos.parse_and_play_moves(moves).ok()?;
+    Some(pos)
+}
+
+/// Solve every position in the file at `path` up to the given depth.
+/// The file has one position per line: either a position in compact
+/// notation, or a list of moves from the start of the game.
+/// Empty lines and lines starting with `#` are skipped.
+///
+/// The searches are done by `solver`, so its options, rules and board size
+/// are used. Stops early if the solver is aborted.
+/// The number of positions done is reported to `progress`.
+pub fn analyze_file(
+    solver: &mut Solver,
+    path: &Path,
+    depth: usize,
+    progress: Option<ProgressHandler>,
+) -> Result<Vec<Analysis>> {
+    let file = std::fs::read_to_string(path)?;
+    let (rules, variant) = (solver.position.rules(), solver.position.variant());
+    let mut positions = vec![];
+    for (line_i, line) in file.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut pos = parse_line(line, variant).ok_or(Error::InvalidPositionLine(line_i + 1))?;
+        pos.set_rules(rules);
+        positions.push((line_i + 1, line, pos));
+    }
+
+    let mut progress =
+        progress.map(|handler| ProgressReporter::new("analysis", Some(positions.len()), handler));
+    let mut total_nodes = 0;
+    let mut analyses = vec![];
+    for (line, text, pos) in positions {
+        if let Some(progress) = &mut progress {
+            progress.update(analyses.len(), total_nodes);
+        }
+        solver.position = pos;
+        let now = Instant::now();
+        let score = solver.search(depth);
+        if solver.abort_search() {
+            break;
+        }
+        let time_ms = now.elapsed().as_millis();
+        total_nodes += solver.nodes();
+        analyses.push(Analysis {
+            line,
+            position: text.to_string(),
+            score,
+            result: eval::decode_eval(score, solver.position.ply() as isize),
+            best_move: solver.principal_variation().first().copied(),
+            nodes: solver.nodes(),
+            time_ms,
+        });
+    }
+    Ok(analyses)
+}
+
+/// The result as a word, and the number of moves to the end of the game if known.
+fn result_and_moves(analysis: &Analysis) -> (&'static str, Option<isize>) {
+    match analysis.result {
+        ExplainableEval::Win(moves) => ("win", Some(moves)),
+        ExplainableEval::Loss(moves) => ("loss", Some(moves)),
+        ExplainableEval::Undetermined(_) => ("undetermined", None),
+    }
+}
+
+/// Write the analyses to a report file. The report is a JSON array if
+/// the file name ends in `.json`, and CSV otherwise.
+pub fn write_report(analyses: &[Analysis], path: &Path) -> Result<()> {
+    let best_move = |analysis: &Analysis| analysis.best_move.map(|pmove| pmove.to_string());
+    let text = if path.extension().is_some_and(|ext| ext == "json") {
+        let report: Vec<_> = analyses
+            .iter()
+            .map(|analysis| {
+                let (result, moves) = result_and_moves(analysis);
+                json!({
+                    "line": analysis.line,
+                    "position": analysis.position,
+                    "score": analysis.score,
+                    "result": result,
+                    "moves": moves,
+                    "best_move": best_move(analysis),
+                    "nodes": analysis.nodes,
+                    "time_ms": analysis.time_ms,
+                })
+            })
+            .collect();
+        serde_json::to_string_pretty(&report).map_err(std::io::Error::from)?
+    } else {
+        let mut text = "line,position,score,result,moves,best_move,nodes,time_ms\n".to_string();
+        for analysis in analyses {
+            let (result, moves) = result_and_moves(analysis);
+            text.push_str(&format!(
+                "{},\"{}\",{},{result},{},{},{},{}\n",
+                analysis.line,
+                analysis.position,
+                analysis.score,
+                moves.map(|m| m.to_string()).unwrap_or_default(),
+                best_move(analysis).unwrap_or_default(),
+                analysis.nodes,
+                analysis.time_ms,
+            ));
+        }
+        text
+    };
+    std::fs::write(path, text)?;
+    Ok(())
+}
+
+/// The number of positions read by [`dedupe_positions`], and how many were kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deduplicated {
+    pub read: usize,
+    pub kept: usize,
+}
+
+/// Read a file of move sequences, one per line, and write the positions
+/// they lead to without duplicates to `output`. Positions are the same if
+/// they are rotations or mirror images of each other, and of every group
+/// of the same positions the shortest sequence is kept, in the order the
+/// positions are first found in. Empty lines and lines starting with `#`
+/// are skipped. The moves are played with the given rules, on a board of
+/// the given size.
+pub fn dedupe_positions(
+    input: &Path,
+    output: &Path,
+    rules: Rules,
+    variant: Variant,
+) -> Result<Deduplicated> {
+    let file = std::fs::read_to_string(input)?;
+    let mut read = 0;
+    let mut index_of_key: HashMap<_, usize> = HashMap::new();
+    let mut kept: Vec<(usize, &str)> = vec![];
+    for (line_i, line) in file.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut pos = Position::with_variant(variant);
+        pos.set_rules(rules);
+        let moves = line.split_whitespace().map(|s| s.to_string()).collect();
+        pos.parse_and_play_moves(moves)
+            .map_err(|_| Error::InvalidPositionLine(line_i + 1))?;
+        read += 1;
+        // The symmetric key doesn't tell if the last move was called
+        // second best, or who is to move.
+        let key = (pos.symmetric_key(), pos.banned_move().is_some(), pos.ply());
+        let num_moves = line.split_whitespace().count();
+        match index_of_key.get(&key) {
+            Some(&i) if kept[i].0 > num_moves => kept[i] = (num_moves, line),
+            Some(_) => {}
+            None => {
+                index_of_key.insert(key, kept.len());
+                kept.push((num_moves, line));
+            }
+        }
+    }
+    let text: String = kept.iter().map(|(_, line)| format!("{line}\n")).collect();
+    std::fs::write(output, text)?;
+    Ok(Deduplicated {
+        read,
+        kept: kept.len(),
+    })
+}
+
+/// The expected result of a position in a test suite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedResult {
+    /// The player to move wins, optionally in exactly this many moves.
+    Win(Option<isize>),
+    /// The player to move loses, optionally in exactly this many moves.
+    Loss(Option<isize>),
+}
+
+impl ExpectedResult {
+    /// Whether the solver found this result.
+    pub fn matches(self, found: ExplainableEval) -> bool {
+        match (self, found) {
+            (Self::Win(moves), ExplainableEval::Win(found_moves))
+            | (Self::Loss(moves), ExplainableEval::Loss(found_moves)) => {
+                moves.is_none_or(|moves| moves == found_moves)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for ExpectedResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (result, moves) = match self {
+            Self::Win(moves) => ("win", moves),
+            Self::Loss(moves) => ("loss", moves),
+        };
+        match moves {
+            Some(moves) => write!(f, "{result} in {moves}"),
+            None => write!(f, "{result}"),
+        }
+    }
+}
+
+/// A position of a test suite, with what the solver should find.
+#[derive(Clone)]
+pub struct TestCase {
+    /// The line of the file the position is on, starting from 1.
+    pub line: usize,
+    pub position: Position,
+    pub result: Option<ExpectedResult>,
+    /// The solver passes if it finds one of these moves. Empty if any move is fine.
+    pub best_moves: Vec<PlayerMove>,
+    /// The depth to search to, if different from the default depth of the suite.
+    pub depth: Option<usize>,
+}
+
+/// Parse a test suite, similar to EPD files in chess.
+/// Every line has a position, followed by operations separated by `;`:
+/// - `result win [moves]` or `result loss [moves]`: the expected result.
+/// - `bm <move> [<move> ...]`: the best moves, any of them is accepted.
+/// - `depth <depth>`: the depth to search this position to.
+///
+/// For example: `0 1 4 5 0 1; result loss 4; bm 5; depth 9`.
+/// Empty lines and lines starting with `#` are skipped.
+pub fn parse_test_suite(text: &str) -> Result<Vec<TestCase>> {
+    let mut cases = vec![];
+    for (line_i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || Error::InvalidPositionLine(line_i + 1);
+        let mut parts = line.split(';');
+        let position = parts
+            .next()
+            .and_then(|p| parse_line(p.trim(), Variant::STANDARD))
+            .ok_or_else(invalid)?;
+        let mut case = TestCase {
+            line: line_i + 1,
+            position,
+            result: None,
+            best_moves: vec![],
+            depth: None,
+        };
+        for operation in parts {
+            let mut words = operation.split_whitespace();
+            match words.next() {
+                Some("result") => {
+                    let kind = words.next();
+                    let moves = match words.next() {
+                        Some(moves) => Some(moves.parse().map_err(|_| invalid())?),
+                        None => None,
+                    };
+                    case.result = Some(match kind {
+                        Some("win") => ExpectedResult::Win(moves),
+                        Some("loss") => ExpectedResult::Loss(moves),
+                        _ => return Err(invalid()),
+                    });
+                }
+                Some("bm") => {
+                    let moves = PlayerMove::parse_moves(&words.collect::<Vec<_>>().join(" "));
+                    case.best_moves = moves.map_err(|_| invalid())?;
+                }
+                Some("depth") => {
+                    let depth = words.next().and_then(|d| d.parse().ok());
+                    case.depth = Some(depth.ok_or_else(invalid)?);
+                }
+                None => (),
+                Some(_) => return Err(invalid()),
+            }
+        }
+        cases.push(case);
+    }
+    Ok(cases)
+}
+
+/// Run the solver on a test case. Returns `None` if the solver found the
+/// expected result and best move, and otherwise what went wrong.
+pub fn run_test_case(solver: &mut Solver, case: &TestCase, depth: usize) -> Option<String> {
+    let rules = solver.position.rules();
+    solver.position = case.position.clone();
+    solver.position.set_rules(rules);
+    let score = solver.search(case.depth.unwrap_or(depth));
+    let found = eval::decode_eval(score, solver.position.ply() as isize);
+    if let Some(result) = case.result {
+        if !result.matches(found) {
+            return Some(format!("expected {result}, found {found}"));
+        }
+    }
+    let best_move = solver.principal_variation().first().copied();
+    match best_move {
+        _ if case.best_moves.is_empty() => None,
+        Some(pmove) if case.best_moves.contains(&pmove) => None,
+        Some(pmove) => Some(format!(
+            "expected one of {}, found {pmove}",
+            PlayerMove::to_long_form(&case.best_moves)
+        )),
+        None => Some("no best move found".to_string()),
+    }
+}
+
+/// The score as "win in 3", "loss in 2" or "score 4".
+pub fn explain_score(score: isize, ply: isize) -> String {
+    match eval::decode_eval(score, ply) {
+        ExplainableEval::Win(moves) => format!("win in {moves}"),
+        ExplainableEval::Loss(moves) => format!("loss in {moves}"),
+        ExplainableEval::Undetermined(score) => format!("score {score}"),
+    }
+}
+
+/// A line found by the solver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// The score, from the perspective of the player to move.
+    pub score: isize,
+    /// The principal variation, starting with the first move of the line.
+    pub pv: Vec<PlayerMove>,
+}
+
+/// Whether calling "Second Best!" on the opponent's last move is better
+/// than allowing it, see [`second_best_advice`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecondBestAdvice {
+    /// The move "Second Best!" can be called on.
+    pub last_move: PlayerMove,
+    /// The best line which allows the move, or `None` if the opponent
+    /// has an alignment, so that the call is forced.
+    pub allow: Option<Line>,
+    /// The best line starting with the call.
+    pub call: Line,
+    /// The ply of the position, to explain the scores.
+    ply: isize,
+}
+
+impl SecondBestAdvice {
+    /// How much better calling "Second Best!" is than allowing the move.
+    /// Negative if allowing the move is better.
+    pub fn difference(&self) -> Option<isize> {
+        self.allow
+            .as_ref()
+            .map(|allow| self.call.score - allow.score)
+    }
+
+    fn explain(&self, line: &Line) -> String {
+        explain_score(line.score, self.ply)
+    }
+}
+
+impl std::fmt::Display for SecondBestAdvice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pv = |line: &Line| PlayerMove::to_long_form(&line.pv);
+        let call = format!("{} (pv {})", self.explain(&self.call), pv(&self.call));
+        let Some(allow) = &self.allow else {
+            return write!(
+                f,
+                "Calling \"Second Best!\" on {} is forced: {call}",
+                self.last_move
+            );
+        };
+        writeln!(
+            f,
+            "Allowing {}: {} (pv {})",
+            self.last_move,
+            self.explain(allow),
+            pv(allow)
+        )?;
+        writeln!(f, "Calling \"Second Best!\": {call}")?;
+        match self.difference().unwrap() {
+            0 => write!(f, "Both are equally good"),
+            difference if difference > 0 => write!(f, "Calling is better by {difference}"),
+            difference => write!(f, "Allowing is better by {}", -difference),
+        }
+    }
+}
+
+/// Compare allowing the opponent's last move with calling "Second Best!" on
+/// it, by searching the current position once with only the call, and once
+/// with all other moves. Returns `None` if "Second Best!" can't be called.
+pub fn second_best_advice(solver: &mut Solver, limits: SearchLimits) -> Option<SecondBestAdvice> {
+    let pos = &solver.position;
+    // Only set if "Second Best!" can be called on it.
+    let last_move = pos.board_state().last_move?;
+    let ply = pos.ply() as isize;
+    let others: Vec<PlayerMove> = pos
+        .legal_moves()
+        .into_iter()
+        .filter(|&pmove| pmove != PlayerMove::SecondBest)
+        .collect();
+    let mut search = |moves: &[PlayerMove]| {
+        // The moves are legal, so the search can't fail.
+        let score = solver.search_moves_with_limits(moves, limits).unwrap();
+        Line {
+            score,
+            pv: solver.principal_variation(),
+        }
+    };
+    let call = search(&[PlayerMove::SecondBest]);
+    let allow = (!others.is_empty()).then(|| search(&others));
+    Some(SecondBestAdvice {
+        last_move,
+        allow,
+        call,
+        ply,
+    })
+}
+
+/// A move of the player to move, with whether the opponent should call
+/// "Second Best!" on it, see [`second_best_map`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecondBestMapEntry {
+    pub pmove: PlayerMove,
+    /// The advice for the opponent after the move, so the scores are
+    /// from the perspective of the opponent.
+    pub advice: SecondBestAdvice,
+}
+
+impl SecondBestMapEntry {
+    /// Whether the opponent should call "Second Best!" on the move: calling
+    /// is better than allowing it, or the call is forced.
+    pub fn refuted(&self) -> bool {
+        self.advice
+            .difference()
+            .is_none_or(|difference| difference > 0)
+    }
+}
+
+impl std::fmt::Display for SecondBestMapEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let call = self.advice.explain(&self.advice.call);
+        let Some(allow) = &self.advice.allow else {
+            return write!(f, "{}: the call is forced ({call})", self.pmove);
+        };
+        let allow = self.advice.explain(allow);
+        let verdict = match self.advice.difference().unwrap() {
+            0 => "calling is as good as allowing".to_string(),
+            difference if difference > 0 => format!("calling is better by {difference}"),
+            difference => format!("allowing is better by {}", -difference),
+        };
+        write!(
+            f,
+            "{}: {verdict} (call: {call}, allow: {allow})",
+            self.pmove
+        )
+    }
+}
+
+/// For every legal move in the current position, whether the opponent
+/// should call "Second Best!" on it, as with [`second_best_advice`] in the
+/// position after the move. Moves on which "Second Best!" can't be called
+/// are left out, so the map is empty if the player to move has just called it.
+///
+/// The searches are done by `solver`, whose position is put back at the end.
+/// Stops early if the solver is aborted.
+pub fn second_best_map(solver: &mut Solver, limits: SearchLimits) -> Vec<SecondBestMapEntry> {
+    let start = solver.position.clone();
+    let mut map = vec![];
+    for pmove in start.legal_moves() {
+        if pmove == PlayerMove::SecondBest {
+            continue;
+        }
+        solver.position = start.clone();
+        // The move is legal.
+        solver.position.try_make_move(pmove).unwrap();
+        let advice = second_best_advice(solver, limits);
+        if solver.abort_search() {
+            break;
+        }
+        if let Some(advice) = advice {
+            map.push(SecondBestMapEntry { pmove, advice });
+        }
+    }
+    solver.position = start;
+    map
+}
+
+/// What is special about a move of a game, see [`annotate_game`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Annotation {
+    /// The only move which wins: `!`.
+    OnlyWin,
+    /// A move which is worse than the best move by more than the threshold: `?`.
+    Mistake,
+    /// A move which misses a win, or loses a position which wasn't lost: `??`.
+    Blunder,
+}
+
+impl Annotation {
+    /// The symbol of the annotation, as used in chess.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::OnlyWin => "!",
+            Self::Mistake => "?",
+            Self::Blunder => "??",
+        }
+    }
+}
+
+/// A move of a game, with how it compares to the best move.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotatedMove {
+    /// The ply of the position the move was played in.
+    pub ply: usize,
+    pub pmove: PlayerMove,
+    /// The score of the move played, from the perspective of the player who played it.
+    pub score: isize,
+    /// The best move found, which is the move played if it was just as good.
+    pub best_move: PlayerMove,
+    pub best_score: isize,
+    pub annotation: Option<Annotation>,
+    /// Whether the position was won before the move, but not after it.
+    pub missed_win: bool,
+}
+
+impl std::fmt::Display for AnnotatedMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ply = self.ply as isize;
+        write!(f, "{}. {}", self.ply + 1, self.pmove)?;
+        match self.annotation {
+            None => Ok(()),
+            Some(Annotation::OnlyWin) => write!(
+                f,
+                " {} (the only winning move, {})",
+                Annotation::OnlyWin.symbol(),
+                explain_score(self.score, ply)
+            ),
+            Some(annotation) => {
+                write!(f, " {}", annotation.symbol())?;
+                if self.missed_win {
+                    write!(f, " missed a win")?;
+                }
+                write!(
+                    f,
+                    " ({}, best was {}: {})",
+                    explain_score(self.score, ply),
+                    self.best_move,
+                    explain_score(self.best_score, ply)
+                )
+            }
+        }
+    }
+}
+
+/// Evaluate every move of a game played from `start`, and annotate the moves
+/// which are a lot worse than the best move, miss a win, or are the only
+/// winning move. Every position is searched to the same depth, and moves
+/// are mistakes if the score drops by more than `threshold`.
+///
+/// The searches are done by `solver`, so its options and rules are used,
+/// and its position is changed. Stops early if the solver is aborted.
+/// The number of moves done is reported to `progress`.
+pub fn annotate_game(
+    solver: &mut Solver,
+    start: &Position,
+    moves: &[PlayerMove],
+    depth: usize,
+    threshold: isize,
+    progress: Option<ProgressHandler>,
+) -> Result<Vec<AnnotatedMove>> {
+    let mut pos = start.clone();
+    pos.set_rules(solver.position.rules());
+    // Check the whole game first, instead of failing halfway through.
+    let mut end = pos.clone();
+    for &pmove in moves {
+        end.try_make_move(pmove)?;
+    }
+
+    let mut progress =
+        progress.map(|handler| ProgressReporter::new("annotation", Some(moves.len()), handler));
+    let limits = SearchLimits::depth(depth);
+    let mut total_nodes = 0;
+    let mut annotated = vec![];
+    for &pmove in moves {
+        if let Some(progress) = &mut progress {
+            progress.update(annotated.len(), total_nodes);
+        }
+        let ply = pos.ply() as isize;
+        solver.position = pos.clone();
+        let Some(&(best_move, best_score)) = solver.top_moves(1, limits).first() else {
+            // Aborted before the first iteration was done.
+            break;
+        };
+        total_nodes += solver.nodes();
+        let mut search = |moves: &[PlayerMove]| {
+            // The moves are legal, so the search can't fail.
+            let score = solver.search_moves_with_limits(moves, limits).unwrap();
+            total_nodes += solver.nodes();
+            score
+        };
+        let score = match best_move == pmove {
+            true => best_score,
+            false => search(&[pmove]),
+        };
+        let is_win = |score| matches!(eval::decode_eval(score, ply), ExplainableEval::Win(_));
+        let is_loss = |score| matches!(eval::decode_eval(score, ply), ExplainableEval::Loss(_));
+        let mut only_win = || {
+            let others: Vec<PlayerMove> = pos
+                .legal_moves()
+                .into_iter()
+                .filter(|&other| other != pmove)
+                .collect();
+            !others.is_empty() && !is_win(search(&others))
+        };
+        let missed_win = is_win(best_score) && !is_win(score);
+        let annotation = if missed_win || (is_loss(score) && !is_loss(best_score)) {
+            Some(Annotation::Blunder)
+        } else if !is_win(score) && !is_loss(best_score) && best_score - score > threshold {
+            // Winning slower or losing faster is not a mistake.
+            Some(Annotation::Mistake)
+        } else if is_win(score) && best_move == pmove && only_win() {
+            Some(Annotation::OnlyWin)
+        } else {
+            None
+        };
+        if solver.abort_search() {
+            break;
+        }
+        annotated.push(AnnotatedMove {
+            ply: ply as usize,
+            pmove,
+            score,
+            best_move,
+            best_score,
+            annotation,
+            missed_win,
+        });
+        pos.try_make_move(pmove)?;
+    }
+    Ok(annotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn analyze_positions() {
+        let dir = std::env::temp_dir();
+        let path = dir.join("second_best_analysis_test.txt");
+        std::fs::write(
+            &path,
+            "# Lost for white\n0 1 0 2 0\n\nO/X/XX/O/XO/././O X 3 -\n",
+        )
+        .unwrap();
+        let mut solver = Solver::default();
+        let analyses = analyze_file(&mut solver, &path, 3, None).unwrap();
+        assert_eq!(analyses.len(), 2);
+        assert_eq!(analyses[0].line, 2);
+        assert_eq!(analyses[0].best_move, Some(PlayerMove::SecondBest));
+        assert_eq!(analyses[1].line, 4);
+
+        let report = dir.join("second_best_analysis_test.json");
+        write_report(&analyses, &report).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+        assert_eq!(json[0]["best_move"], "!");
+
+        std::fs::write(&path, "0 1\n0 0 0 0\n").unwrap();
+        assert!(matches!(
+            analyze_file(&mut solver, &path, 3, None),
+            Err(Error::InvalidPositionLine(2))
+        ));
+    }
+
+    #[test]
+    fn dedupe() {
+        let dir = std::env::temp_dir();
+        let input = dir.join("second_best_dedupe_test.txt");
+        let output = dir.join("second_best_dedupe_test.out.txt");
+        // A rotation, a mirror image, a transposition, and the same stones
+        // with a different last move.
+        std::fs::write(
+            &input,
+            "0 1 0 2 0\n# comment\n1 2 1 3 1\n7 6 7 5 7\n0 2 0 1 0\n0 1 0 2\n0 0 2 0 1\n",
+        )
+        .unwrap();
+        let result =
+            dedupe_positions(&input, &output, Rules::default(), Variant::STANDARD).unwrap();
+        assert_eq!(result, Deduplicated { read: 6, kept: 3 });
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "0 1 0 2 0\n0 1 0 2\n0 0 2 0 1\n"
+        );
+
+        std::fs::write(&input, "0 1\n0 0 0 0\n").unwrap();
+        assert!(matches!(
+            dedupe_positions(&input, &output, Rules::default(), Variant::STANDARD),
+            Err(Error::InvalidPositionLine(2))
+        ));
+    }
+
+    #[test]
+    fn test_suite() {
+        let suite = "# Test suite\n\
+            2 2 0 0 0 1 4 2 6 5 4 7 5 5 1 6 0-7 0-7 0-1 5-4 5-6; result win 3; bm 2-3\n\
+            O/X/XX/O/XO/././O X 3 -; result win; bm 1 4; depth 8\n\
+            0 1 0 2 0; result loss 2\n";
+        let cases = parse_test_suite(suite).unwrap();
+        assert_eq!(cases.len(), 3);
+        assert_eq!(cases[1].result, Some(ExpectedResult::Win(None)));
+        assert_eq!(cases[1].depth, Some(8));
+        let mut solver = Solver::default();
+        assert_eq!(run_test_case(&mut solver, &cases[0], 5), None);
+        assert_eq!(run_test_case(&mut solver, &cases[1], 5), None);
+        assert_eq!(
+            run_test_case(&mut solver, &cases[2], 3).unwrap(),
+            "expected loss in 2, found undetermined"
+        );
+
+        assert!(matches!(
+            parse_test_suite("0 1; result draw"),
+            Err(Error::InvalidPositionLine(1))
+        ));
+        assert!(matches!(
+            parse_test_suite("0\n0 1; bm 9-"),
+            Err(Error::InvalidPositionLine(2))
+        ));
+    }
+
+    #[test]
+    fn advice() {
+        let mut solver = Solver::default();
+        solver.position = Position::from_fen("O/X/XX/O/XO/././O X 3 -").unwrap();
+        let advice = second_best_advice(&mut solver, SearchLimits::depth(6)).unwrap();
+        assert_eq!(
+            advice.last_move,
+            PlayerMove::StoneMove { from: None, to: 3 }
+        );
+        assert_eq!(advice.call.pv[0], PlayerMove::SecondBest);
+        let allow = advice.allow.as_ref().unwrap();
+        assert_ne!(allow.pv[0], PlayerMove::SecondBest);
+        assert_eq!(advice.difference(), Some(advice.call.score - allow.score));
+        // Calling or allowing is one of the moves, so the best
+        // of them is the score of the position.
+        let best = advice.call.score.max(allow.score);
+        assert_eq!(solver.search(6), best);
+
+        solver
+            .position
+            .try_make_move(PlayerMove::SecondBest)
+            .unwrap();
+        assert_eq!(
+            second_best_advice(&mut solver, SearchLimits::depth(6)),
+            None
+        );
+    }
+
+    #[test]
+    fn map() {
+        let mut solver = Solver::default();
+        solver
+            .position
+            .parse_and_play_moves(["0", "1", "0", "2"].map(String::from).to_vec())
+            .unwrap();
+        let map = second_best_map(&mut solver, SearchLimits::depth(4));
+        assert_eq!(map.len(), Variant::STANDARD.stacks());
+        assert_eq!(solver.position.ply(), 4);
+        // Black threatens to complete an alignment on 0, which has to be
+        // banned by the call.
+        let zero = PlayerMove::StoneMove { from: None, to: 0 };
+        let entry = map.iter().find(|entry| entry.pmove == zero).unwrap();
+        assert!(entry.refuted());
+        assert_eq!(entry.advice.last_move, zero);
+
+        solver.position.try_make_move(zero).unwrap();
+        solver
+            .position
+            .try_make_move(PlayerMove::SecondBest)
+            .unwrap();
+        assert!(second_best_map(&mut solver, SearchLimits::depth(4)).is_empty());
+    }
+
+    #[test]
+    fn annotate() {
+        let mut solver = Solver::default();
+        let start = Position::default();
+        // A position from the benchmark which is won in 3 moves,
+        // followed by a move which doesn't win.
+        let game = PlayerMove::parse_moves("1 1 3 6 3 7 5 1 2 6 0").unwrap();
+        let annotated = annotate_game(&mut solver, &start, &game, 6, 100, None).unwrap();
+        assert_eq!(annotated.len(), game.len());
+        assert_eq!(annotated[10].ply, 10);
+        assert_eq!(annotated[10].pmove, game[10]);
+        // Playing 6 loses, and 0 misses the win.
+        assert_eq!(annotated[9].annotation, Some(Annotation::Blunder));
+        assert!(!annotated[9].missed_win);
+        assert_eq!(annotated[10].annotation, Some(Annotation::Blunder));
+        assert!(annotated[10].missed_win);
+        assert!(annotated[10].to_string().contains("??"));
+
+        // Calling "Second Best!" is the only way to win.
+        let game = PlayerMove::parse_moves("7 2 6 2 6 5 7 7 5 4 0 6 5 3 2!").unwrap();
+        let annotated = annotate_game(&mut solver, &start, &game, 6, 100, None).unwrap();
+        assert_eq!(annotated[15].annotation, Some(Annotation::OnlyWin));
+        assert_eq!(annotated[15].best_move, PlayerMove::SecondBest);
+
+        let illegal = PlayerMove::parse_moves("0 1 0 2 0 1").unwrap();
+        assert!(matches!(
+            annotate_game(&mut solver, &start, &illegal, 6, 100, None),
+            Err(Error::InvalidMove(_))
+        ));
+    }
+}
@@ -1,76 +1,130 @@
+use crate::analysis;
+use crate::error::{Error, Result};
 use crate::eval;
 use crate::eval::ExplainableEval;
+use crate::gamefile::today;
 use crate::movegen;
 use crate::position::BitboardMove;
 use crate::position::Position;
+use crate::progress::{MessageHandler, ProgressHandler, ProgressReporter};
+use crate::rng::Rng;
 use crate::solver;
 
+use serde::{Deserialize, Serialize};
 use std::io::{self, Write};
 use std::ops::Range;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::vec;
 
+/// The directory with the benchmark files, if no other one is configured.
 pub const BENCHMARKS_PATH: &str = "./benchmarks/";
+/// The subdirectory of the benchmarks directory where the
+/// results of the benchmark runs are saved.
+pub const RESULTS_DIR: &str = "results";
 
 /// Generate a benchmark file with the given specifications
 /// 1. `num_positions` is the number of positions in the benchmark
 /// 2. `moves` is the bounds on the number of moves that need to played
-///     for the position to be in the benchmark.
+///    for the position to be in the benchmark.
 /// 3. `depth` gives a lower and upper bound on the depth needed to solve
 ///    the position.
+/// 4. `seed` is the seed of the random moves. The same seed and
+///    parameters always give the same benchmark file.
 ///
 /// The benchmark consists of lines with moves to be played.
+/// It is saved in the directory `dir`. What is done is reported
+/// to `messages`.
+#[allow(clippy::too_many_arguments)]
 pub fn generate_benchmark_file(
     abort: Arc<AtomicBool>,
+    dir: &Path,
     num_positions: usize,
     moves_range: Range<usize>,
     depth_range: Range<usize>,
-) -> io::Result<()> {
-    let mut positions = vec::Vec::with_capacity(num_positions);
-    let mut counter = 0;
-    while positions.len() < num_positions {
-        counter += 1;
-        print!("\rGenerating position {}", positions.len() + 1);
-        io::stdout().flush().unwrap();
-        let mut solver = solver::Solver::new(abort.clone());
-        let moves = generate_random_position(&mut solver, &moves_range, &depth_range, counter);
-        if abort.load(std::sync::atomic::Ordering::Relaxed) {
-            println!("\nStopping benchmark generation.");
-            break;
-        }
-        let moves = moves.unwrap();
-        if !positions.contains(&moves) {
-            positions.push(moves);
-        }
+    seed: u64,
+    progress: Option<ProgressHandler>,
+    messages: MessageHandler,
+) -> Result<()> {
+    messages(format!("Generating benchmark with seed {seed}"));
+    let positions = generate_positions(
+        abort.clone(),
+        num_positions,
+        &moves_range,
+        &depth_range,
+        seed,
+        progress,
+    )?;
+    if abort.load(std::sync::atomic::Ordering::Relaxed) {
+        messages("Stopping benchmark generation.".to_string());
     }
-    println!();
     if positions.is_empty() {
         // Don't create the file if nothing was generated.
-        println!("No benchmarks generated.");
+        messages("No benchmarks generated.".to_string());
         return Ok(());
     }
     let file_name = format!(
         "bench_{}-{}_{}-{}",
         moves_range.start, moves_range.end, depth_range.start, depth_range.end
     );
-    let path = PathBuf::from(BENCHMARKS_PATH);
-    std::fs::create_dir_all(&path)?;
-    let path = path.join(file_name);
-    println!("Saved bench to {:?} ({} positions)", path, positions.len());
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(file_name);
+    messages(format!(
+        "Saved bench to {:?} ({} positions)",
+        path,
+        positions.len()
+    ));
     let mut file = std::fs::File::create(path)?;
     file.write_all(positions.join("\n").as_bytes())?;
     Ok(())
 }
 
+/// Generate distinct random positions satisfying the given parameters,
+/// until there are `num_positions` of them or the generation is aborted.
+fn generate_positions(
+    abort: Arc<AtomicBool>,
+    num_positions: usize,
+    moves_range: &Range<usize>,
+    depth_range: &Range<usize>,
+    seed: u64,
+    progress: Option<ProgressHandler>,
+) -> Result<vec::Vec<String>> {
+    let mut rng = Rng::new(seed);
+    let mut progress = progress
+        .map(|handler| ProgressReporter::new("generating positions", Some(num_positions), handler));
+    let mut nodes = 0;
+    let mut positions = vec::Vec::with_capacity(num_positions);
+    while positions.len() < num_positions {
+        // A new solver for every position, so that the searches don't
+        // depend on what is left in the transposition table.
+        let mut solver = solver::Solver::new(abort.clone());
+        let moves = generate_random_position(&mut solver, moves_range, depth_range, &mut rng);
+        if abort.load(std::sync::atomic::Ordering::Relaxed) {
+            break;
+        }
+        nodes += solver.nodes();
+        if let Some(progress) = &mut progress {
+            progress.update(positions.len(), nodes);
+        }
+        let Some(moves) = moves else {
+            // No position was found from the starting position, try other moves.
+            continue;
+        };
+        if !positions.contains(&moves) {
+            positions.push(moves);
+        }
+    }
+    Ok(positions)
+}
+
 /// Recursive utility function to generate a random position
 /// satisfying the given parameters.
 fn generate_random_position(
     solver: &mut solver::Solver,
     moves_range: &Range<usize>,
     depth_range: &Range<usize>,
-    mut seed: usize,
+    rng: &mut Rng,
 ) -> Option<String> {
     if solver.abort_search() {
         return None;
@@ -103,79 +157,396 @@ fn generate_random_position(
     }
     // Generate a new move 'randomly'.
     let mut moves = movegen::MoveGen::new(&solver.position, None).collect::<vec::Vec<_>>();
-    let mut move_i;
 
     loop {
         if moves.is_empty() {
             return None;
         }
-        (move_i, seed) = next_rand(seed);
-        let smove = moves[move_i % moves.len()];
+        let move_i = rng.below(moves.len());
+        let smove = moves[move_i];
         let smove = match smove {
             BitboardMove::SecondBest => {
-                moves.swap_remove(move_i % moves.len());
+                moves.swap_remove(move_i);
                 continue;
             }
             BitboardMove::StoneMove(smove) => smove,
         };
         solver.position.make_stone_move(smove);
-        if let Some(result) = generate_random_position(solver, moves_range, depth_range, seed) {
+        if let Some(result) = generate_random_position(solver, moves_range, depth_range, rng) {
             return Some(result);
         }
         // Didn't work, try another move.
         solver.position.unmake_stone_move();
-        moves.swap_remove(move_i % moves.len());
+        moves.swap_remove(move_i);
     }
 }
 
-/// Generate a pseudo-random number for the given seed.
-/// The generated number and a new seed are returned.
-fn next_rand(seed: usize) -> (usize, usize) {
-    let a = 1103515245;
-    let c = 12345;
-    let m = 1 << 31;
-    let seed = (a * seed + c) % m;
-    (seed >> 4, seed)
+/// How hard a position is to solve, by the depth at which its result is
+/// first known. Used to generate benchmarks of a similar difficulty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
 }
 
-/// Run all the benchmarks and print statistics.
+impl Difficulty {
+    /// The names of the difficulties, as parsed by [`Difficulty::from_str`](std::str::FromStr).
+    pub const NAMES: [&'static str; 3] = ["easy", "medium", "hard"];
+
+    /// The depths needed to solve the positions of this difficulty,
+    /// as used by [`generate_benchmark_file`].
+    pub fn depth_range(self) -> Range<usize> {
+        match self {
+            Self::Easy => 1..5,
+            Self::Medium => 5..9,
+            Self::Hard => 9..13,
+        }
+    }
+
+    /// The difficulty of a position whose result is first known at `depth`,
+    /// or `None` if it is harder than [`Difficulty::Hard`].
+    pub fn of_depth(depth: usize) -> Option<Self> {
+        [Self::Easy, Self::Medium, Self::Hard]
+            .into_iter()
+            .find(|difficulty| depth < difficulty.depth_range().end)
+    }
+}
+
+impl std::str::FromStr for Difficulty {
+    type Err = solver::InvalidOption;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Self::Easy),
+            "medium" => Ok(Self::Medium),
+            "hard" => Ok(Self::Hard),
+            _ => Err(solver::InvalidOption::InvalidValue),
+        }
+    }
+}
+
+impl std::fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Easy => "easy",
+            Self::Medium => "medium",
+            Self::Hard => "hard",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// How hard it was to solve a position of a file, see [`estimate_difficulty`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DifficultyEstimate {
+    /// The position as it was written in the file.
+    pub position: String,
+    /// The first depth at which the result of the position was known,
+    /// or `None` if it wasn't known at the maximal depth.
+    pub decided_depth: Option<usize>,
+    /// The number of nodes searched until the result was known,
+    /// or by the whole search if it wasn't.
+    pub nodes: usize,
+}
+
+impl DifficultyEstimate {
+    pub fn difficulty(&self) -> Option<Difficulty> {
+        self.decided_depth.and_then(Difficulty::of_depth)
+    }
+}
+
+/// Search every position in the file at `path` up to `max_depth`, and
+/// record the depth at which its result was first known, and the number
+/// of nodes needed for it. The file has one position per line, as with
+/// [`analysis::analyze_file`]. Empty lines and lines starting with `#`
+/// are skipped.
+///
+/// Every search starts from an empty table, so that the number of nodes
+/// doesn't depend on the positions before it. The searches are done by
+/// `solver`, so its options and rules are used. Stops early if the solver
+/// is aborted. The number of positions done is reported to `progress`.
+pub fn estimate_difficulty(
+    solver: &mut solver::Solver,
+    path: &Path,
+    max_depth: usize,
+    progress: Option<ProgressHandler>,
+) -> Result<vec::Vec<DifficultyEstimate>> {
+    let file = std::fs::read_to_string(path)?;
+    let (rules, variant) = (solver.position.rules(), solver.position.variant());
+    let mut positions = vec![];
+    for (line_i, line) in file.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut pos =
+            analysis::parse_line(line, variant).ok_or(Error::InvalidPositionLine(line_i + 1))?;
+        pos.set_rules(rules);
+        positions.push((line, pos));
+    }
+
+    let mut progress =
+        progress.map(|handler| ProgressReporter::new("difficulty", Some(positions.len()), handler));
+    // The ply of the searched position, and the depth and nodes of the
+    // first iteration which knew the result.
+    let decided = Arc::new(Mutex::new((0, None)));
+    let handler_decided = decided.clone();
+    solver.set_info_handler(move |info| {
+        let (ply, decided) = &mut *handler_decided.lock().unwrap();
+        let known = !matches!(
+            eval::decode_eval(info.score, *ply as isize),
+            ExplainableEval::Undetermined(_)
+        );
+        if decided.is_none() && known {
+            *decided = Some((info.depth, info.nodes));
+        }
+    });
+    let mut total_nodes = 0;
+    let mut estimates = vec![];
+    for (text, pos) in positions {
+        if let Some(progress) = &mut progress {
+            progress.update(estimates.len(), total_nodes);
+        }
+        *decided.lock().unwrap() = (pos.ply(), None);
+        solver.position = pos;
+        solver.clear_tt();
+        solver.search(max_depth);
+        if solver.abort_search() {
+            break;
+        }
+        total_nodes += solver.nodes();
+        let decided = decided.lock().unwrap().1;
+        estimates.push(DifficultyEstimate {
+            position: text.to_string(),
+            decided_depth: decided.map(|(depth, _)| depth),
+            nodes: decided.map_or(solver.nodes(), |(_, nodes)| nodes),
+        });
+    }
+    solver.clear_info_handler();
+    Ok(estimates)
+}
+
+/// Write the estimates to a file, one position per line with its labels,
+/// e.g. `0 1 0 2 0; depth 3; nodes 120; difficulty easy`. Positions which
+/// weren't solved get `difficulty unsolved`, and positions which are
+/// harder than [`Difficulty::Hard`] get `difficulty harder`.
+pub fn write_difficulty_labels(estimates: &[DifficultyEstimate], path: &Path) -> Result<()> {
+    let mut text = String::new();
+    for estimate in estimates {
+        let difficulty = match (estimate.decided_depth, estimate.difficulty()) {
+            (_, Some(difficulty)) => difficulty.to_string(),
+            (Some(_), None) => "harder".to_string(),
+            (None, None) => "unsolved".to_string(),
+        };
+        text.push_str(&estimate.position);
+        if let Some(depth) = estimate.decided_depth {
+            text.push_str(&format!("; depth {depth}"));
+        }
+        text.push_str(&format!(
+            "; nodes {}; difficulty {difficulty}\n",
+            estimate.nodes
+        ));
+    }
+    std::fs::write(path, text)?;
+    Ok(())
+}
+
+/// The results of a single position in a benchmark.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PositionResult {
+    pub position: String,
+    pub nodes: usize,
+    /// Time taken in microseconds.
+    pub time: u128,
+}
+
+/// The results of running one benchmark file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuiteResult {
+    /// The name of the benchmark file.
+    pub name: String,
+    pub positions: vec::Vec<PositionResult>,
+}
+
+impl SuiteResult {
+    fn average_nodes(&self) -> f64 {
+        let total: usize = self.positions.iter().map(|p| p.nodes).sum();
+        total as f64 / self.positions.len() as f64
+    }
+
+    /// Average time in seconds.
+    fn average_time(&self) -> f64 {
+        let total: u128 = self.positions.iter().map(|p| p.time).sum();
+        total as f64 / 1_000_000.0 / self.positions.len() as f64
+    }
+}
+
+/// The results of a full benchmark run, which are saved in [`RESULTS_DIR`]
+/// so that later runs can be compared to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchResults {
+    /// The git commit of the engine, or "unknown".
+    pub git_hash: String,
+    /// The date of the run, as "YYYY.MM.DD".
+    pub date: String,
+    pub suites: vec::Vec<SuiteResult>,
+}
+
+impl BenchResults {
+    /// Save the results to a new file in [`RESULTS_DIR`] in the benchmarks
+    /// directory `dir`. Returns the path of the file.
+    fn save(&self, dir: &Path) -> Result<PathBuf> {
+        let path = dir.join(RESULTS_DIR);
+        std::fs::create_dir_all(&path)?;
+        let seconds = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let path = path.join(format!("{seconds}_{}.json", self.git_hash));
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::from)?;
+        std::fs::write(&path, json)?;
+        Ok(path)
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        serde_json::from_str(&json).map_err(|_| Error::InvalidBenchmark(path.display().to_string()))
+    }
+
+    /// Report the change in speed and nodes searched of every suite
+    /// compared to the `baseline` to `messages`.
+    fn report_comparison(&self, baseline: &BenchResults, messages: &MessageHandler) {
+        messages(format!(
+            "Comparing to {} ({}):",
+            baseline.git_hash, baseline.date
+        ));
+        for suite in &self.suites {
+            let Some(old) = baseline.suites.iter().find(|old| old.name == suite.name) else {
+                messages(format!("{}: not in the baseline", suite.name));
+                continue;
+            };
+            let same_positions = old.positions.iter().map(|p| &p.position);
+            if !same_positions.eq(suite.positions.iter().map(|p| &p.position)) {
+                messages(format!(
+                    "{}: the positions are different from the baseline",
+                    suite.name
+                ));
+                continue;
+            }
+            let speedup = old.average_time() / suite.average_time();
+            let (nodes, old_nodes) = (suite.average_nodes(), old.average_nodes());
+            messages(format!(
+                "{}: {:.4}s -> {:.4}s ({}), nodes {old_nodes:.2} -> {nodes:.2} ({:+.2})",
+                suite.name,
+                old.average_time(),
+                suite.average_time(),
+                match speedup >= 1.0 {
+                    true => format!("{:.1}% faster", (speedup - 1.0) * 100.0),
+                    false => format!("{:.1}% slower", (1.0 / speedup - 1.0) * 100.0),
+                },
+                nodes - old_nodes,
+            ));
+        }
+    }
+}
+
+/// The short hash of the current git commit, if it can be found.
+fn git_hash() -> String {
+    std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map_or("unknown".to_string(), |hash| hash.trim().to_string())
+}
+
+/// Run all the benchmarks in the directory `dir` and report statistics
+/// to `messages`.
 /// To make the benchmark run faster, the work can be spread
 /// over multiple threads. Each position is still assigned to
 /// a unique thread.
-pub fn run_benchmarks(abort: Arc<AtomicBool>, num_threads: usize) -> io::Result<()> {
-    let files = std::fs::read_dir(BENCHMARKS_PATH)?;
+/// The solvers are configured with the given `options` and `eval_params`,
+/// so that the effect of the options can be compared.
+///
+/// The results are saved in [`RESULTS_DIR`]. If a `baseline` results
+/// file is given, the results are compared to it.
+/// The progress of the first thread is reported to `progress`.
+#[allow(clippy::too_many_arguments)]
+pub fn run_benchmarks(
+    abort: Arc<AtomicBool>,
+    dir: &Path,
+    num_threads: usize,
+    options: solver::SolverOptions,
+    eval_params: eval::EvalParams,
+    baseline: Option<&Path>,
+    progress: Option<ProgressHandler>,
+    messages: MessageHandler,
+) -> Result<()> {
+    // Load the baseline first, so that a wrong path is noticed right away.
+    let baseline = baseline.map(BenchResults::load).transpose()?;
+    let mut results = BenchResults {
+        git_hash: git_hash(),
+        date: today(),
+        suites: vec![],
+    };
+    let mut files = std::fs::read_dir(dir)?.collect::<io::Result<vec::Vec<_>>>()?;
+    // Run the suites in a fixed order, so that runs are easy to compare.
+    files.sort_by_key(|file| file.file_name());
     for file in files {
-        let file = file?;
-        let file_name = file.file_name().into_string().unwrap();
+        let Ok(file_name) = file.file_name().into_string() else {
+            continue;
+        };
         if !file_name.starts_with("bench") {
             continue;
         }
-        let file_name = file_name.strip_prefix("bench_").unwrap();
-        let params: vec::Vec<usize> = file_name
+        let invalid_benchmark = || Error::InvalidBenchmark(file_name.clone());
+        let params = file_name
+            .strip_prefix("bench_")
+            .ok_or_else(invalid_benchmark)?
             .split('_')
-            .flat_map(|s| s.split('-').map(|n| n.parse::<usize>().unwrap()))
-            .collect();
-        assert!(params.len() == 4);
+            .flat_map(|s| s.split('-').map(|n| n.parse::<usize>()))
+            .collect::<std::result::Result<vec::Vec<usize>, _>>()
+            .map_err(|_| invalid_benchmark())?;
+        if params.len() != 4 {
+            return Err(invalid_benchmark());
+        }
         let min_moves = params[0];
         let max_moves = params[1];
         let min_depth = params[2];
         let max_depth = params[3];
         let file = std::fs::read_to_string(file.path())?;
         let positions: vec::Vec<_> = file.lines().collect();
-        println!(
-            "\nStarting benchmark with {} positions.\n\
+        let mut start_positions = vec::Vec::with_capacity(positions.len());
+        for position in &positions {
+            let mut start_position = Position::default();
+            let moves = position.split_whitespace().map(|s| s.to_string()).collect();
+            start_position
+                .parse_and_play_moves(moves)
+                .map_err(|_| invalid_benchmark())?;
+            start_positions.push(start_position);
+        }
+        messages(format!(
+            "Starting benchmark with {} positions.\n\
             number of moves: {min_moves}..{max_moves}\n\
-            solution depth: {min_depth}..{max_depth}\n",
+            solution depth: {min_depth}..{max_depth}",
             positions.len()
-        );
+        ));
         let mut thread_handlers = vec![];
         for thread_id in 0..num_threads {
             let mut thread_positions = vec![];
             for position_id in (thread_id..(positions).len()).step_by(num_threads) {
-                thread_positions.push(positions[position_id].to_string());
+                thread_positions.push((
+                    position_id,
+                    positions[position_id].to_string(),
+                    start_positions[position_id].clone(),
+                ));
             }
             let abort = abort.clone();
-            let main_thread = thread_id == 0;
+            let messages = messages.clone();
+            let mut progress = progress.clone().filter(|_| thread_id == 0).map(|handler| {
+                ProgressReporter::new("benchmark", Some(thread_positions.len()), handler)
+            });
 
             thread_handlers.push(
                 std::thread::Builder::new()
@@ -183,20 +554,20 @@ pub fn run_benchmarks(abort: Arc<AtomicBool>, num_threads: usize) -> io::Result<
                     .stack_size(5_000_000)
                     .spawn(move || {
                         let mut solver = solver::Solver::new(abort);
+                        solver.options = options;
+                        solver.eval_params = eval_params;
+                        let mut results = vec![];
                         let mut total_nodes = 0;
-                        let mut total_time = 0;
-                        for (i, position) in thread_positions.iter().enumerate() {
-                            if main_thread {
-                                print!(
-                                    "\rRunning benchmark: {:.2}%",
-                                    (i as f64 + 1.0) / thread_positions.len() as f64 * 100.
-                                );
-                                io::stdout().flush().unwrap();
+                        for (i, (position_id, position, start_position)) in
+                            thread_positions.into_iter().enumerate()
+                        {
+                            if let Some(progress) = &mut progress {
+                                progress.update(i, total_nodes);
                             }
-                            solver.position = Position::default();
-                            let moves =
-                                position.split_whitespace().map(|s| s.to_string()).collect();
-                            solver.position.parse_and_play_moves(moves).unwrap();
+                            solver.position = start_position;
+                            // Every position starts from an empty table, so that the
+                            // number of nodes doesn't depend on the positions before it.
+                            solver.clear_tt();
                             let now = std::time::Instant::now();
                             // Add extra depth, in case the solver needs it.
                             let eval = solver.search(max_depth);
@@ -205,41 +576,122 @@ pub fn run_benchmarks(abort: Arc<AtomicBool>, num_threads: usize) -> io::Result<
                                 eval::decode_eval(eval, solver.position.ply() as isize),
                                 ExplainableEval::Undetermined(_)
                             ) {
-                                println!("\n Failed position {}", position);
+                                messages(format!("Failed position {position}"));
                                 break;
                             }
                             if solver.abort_search() {
                                 break;
                             }
-                            total_time += now.elapsed().as_micros();
-                            total_nodes += solver.nodes();
+                            let time = now.elapsed().as_micros();
+                            let nodes = solver.nodes();
+                            total_nodes += nodes;
+                            results.push((
+                                position_id,
+                                PositionResult {
+                                    position,
+                                    nodes,
+                                    time,
+                                },
+                            ));
                         }
-                        if main_thread {
-                            // Add a newline after the progress print
-                            println!("\nWaiting for all threads to finish...\n");
+                        if progress.is_some() {
+                            messages("Waiting for all threads to finish...".to_string());
                         }
 
-                        (total_nodes, total_time)
+                        results
                     }),
             )
         }
 
-        let mut total_nodes = 0;
-        let mut total_time = 0;
+        let mut suite_positions = vec![];
         for handler in thread_handlers {
-            let (nodes, time) = handler?.join().unwrap();
-            total_nodes += nodes;
-            total_time += time;
+            suite_positions.extend(handler?.join().map_err(|_| Error::ThreadStopped)?);
         }
-        println!(
+        if abort.load(std::sync::atomic::Ordering::Relaxed) {
+            messages("Benchmark stopped, the results are not saved.".to_string());
+            return Ok(());
+        }
+        suite_positions.sort_by_key(|(position_id, _)| *position_id);
+        let suite = SuiteResult {
+            name: file_name,
+            positions: suite_positions
+                .into_iter()
+                .map(|(_, result)| result)
+                .collect(),
+        };
+        let total_nodes: usize = suite.positions.iter().map(|p| p.nodes).sum();
+        let total_time: u128 = suite.positions.iter().map(|p| p.time).sum();
+        messages(format!(
             "Finished benchmark:\n\
             Average time: {:.4}s\n\
             Average number of nodes searched: {:.2}\n\
-            Average knps: {:.2} knps\n",
-            total_time as f64 / 1_000_000.0 / positions.len() as f64,
-            total_nodes as f64 / positions.len() as f64,
+            Average knps: {:.2} knps",
+            suite.average_time(),
+            suite.average_nodes(),
             total_nodes as f64 * 1000. / total_time as f64
-        );
+        ));
+        results.suites.push(suite);
+    }
+    let path = results.save(dir)?;
+    messages(format!("Saved results to {path:?}"));
+    if let Some(baseline) = baseline {
+        results.report_comparison(&baseline, &messages);
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_positions() {
+        let generate = |seed| {
+            let abort = Arc::new(AtomicBool::new(false));
+            generate_positions(abort, 3, &(4..12), &(1..3), seed, None).unwrap()
+        };
+        let positions = generate(42);
+        assert_eq!(positions.len(), 3);
+        assert_eq!(positions, generate(42));
+        assert_ne!(positions, generate(43));
+    }
+
+    #[test]
+    fn difficulty() {
+        assert_eq!(Difficulty::of_depth(2), Some(Difficulty::Easy));
+        assert_eq!(Difficulty::of_depth(5), Some(Difficulty::Medium));
+        assert_eq!(Difficulty::of_depth(13), None);
+        for name in Difficulty::NAMES {
+            assert_eq!(name.parse::<Difficulty>().unwrap().to_string(), name);
+        }
+
+        let dir = std::env::temp_dir();
+        let path = dir.join("second_best_difficulty_test.txt");
+        std::fs::write(
+            &path,
+            "# Won for black\n2 2 0 0 0 1 4 2 6 5 4 7 5 5 1 6 0-7 0-7 0-1 5-4 5-6\n\n0\n",
+        )
+        .unwrap();
+        let mut solver = solver::Solver::default();
+        let estimates = estimate_difficulty(&mut solver, &path, 5, None).unwrap();
+        assert_eq!(estimates.len(), 2);
+        let decided_depth = estimates[0].decided_depth.unwrap();
+        assert!(decided_depth <= 5);
+        assert_eq!(estimates[0].difficulty(), Some(Difficulty::Easy));
+        assert_eq!(estimates[1].decided_depth, None);
+        assert!(estimates[1].nodes > 0);
+
+        let labels = dir.join("second_best_difficulty_test.labels.txt");
+        write_difficulty_labels(&estimates, &labels).unwrap();
+        let text = std::fs::read_to_string(&labels).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            format!(
+                "2 2 0 0 0 1 4 2 6 5 4 7 5 5 1 6 0-7 0-7 0-1 5-4 5-6; depth {decided_depth}; nodes {}; difficulty easy",
+                estimates[0].nodes
+            )
+        );
+        assert!(lines[1].ends_with("difficulty unsolved"));
+    }
+}
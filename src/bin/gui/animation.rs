@@ -0,0 +1,205 @@
+//! Animations of the moves on the board, so that going through a game or a
+//! line is easy to follow. The stone of a move slides from its stack to the
+//! one it goes to, or drops onto its stack in the first phase. A move which
+//! is taken back slides back, and so does the move banned by "Second Best!",
+//! which glows in the color of the banned move while it is rewound.
+
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use second_best::position::{self, PlayerMove, Position};
+
+use crate::settings::Settings;
+use crate::{Game, Orientation, Spot, CYLINDER_HEIGHT, STACK_RADIUS};
+
+/// How long a move takes.
+const SLIDE_TIME: Duration = Duration::from_millis(250);
+/// How long the banned move takes to go back after "Second Best!".
+const REWIND_TIME: Duration = Duration::from_millis(600);
+/// How high a stone is lifted halfway a move between two stacks.
+const LIFT: f32 = 0.5;
+/// How far above its spot a stone placed in the first phase comes from.
+const DROP_HEIGHT: f32 = 1.0;
+
+/// The stone which is moved by the animations. It is hidden while no move
+/// is animated.
+#[derive(Component)]
+pub struct MovingStone;
+
+/// A spot on the board, as the stack and the height in it.
+type Place = (usize, usize);
+
+/// A stone moving from one place to another. `None` is above the other
+/// place, for a stone which is placed or taken off the board.
+struct Slide {
+    from: Option<Place>,
+    to: Option<Place>,
+    player: position::Color,
+    /// The move banned by "Second Best!" going back.
+    rewind: bool,
+    timer: Timer,
+}
+
+impl Slide {
+    /// The move `pmove` played in the position `before`, or taken back
+    /// to it. The stone is of the player to move there.
+    fn new(before: &Position, pmove: PlayerMove, backwards: bool, rewind: bool) -> Option<Self> {
+        let PlayerMove::StoneMove { from, to } = pmove else {
+            return None;
+        };
+        let from = from.map(|from| (from, before.stack_height(from) - 1));
+        let to = Some((to, before.stack_height(to)));
+        let (from, to) = match backwards {
+            true => (to, from),
+            false => (from, to),
+        };
+        let time = if rewind { REWIND_TIME } else { SLIDE_TIME };
+        Some(Self {
+            from,
+            to,
+            player: before.current_player(),
+            rewind,
+            timer: Timer::new(time, TimerMode::Once),
+        })
+    }
+
+    /// The animation of going from the position `old` to `new`, if that is
+    /// one move forward or back.
+    fn between(old: &Position, new: &Position) -> Option<Self> {
+        let (old_moves, new_moves) = (old.history(), new.history());
+        if new_moves.len() == old_moves.len() + 1 && new_moves.starts_with(&old_moves) {
+            match *new_moves.last()? {
+                // "Second Best!" takes the banned move back.
+                PlayerMove::SecondBest => Self::new(new, *old_moves.last()?, true, true),
+                pmove => Self::new(old, pmove, false, false),
+            }
+        } else if old_moves.len() == new_moves.len() + 1 && old_moves.starts_with(&new_moves) {
+            match *old_moves.last()? {
+                // Undoing "Second Best!" puts the banned move back.
+                PlayerMove::SecondBest => Self::new(old, *new_moves.last()?, false, false),
+                pmove => Self::new(new, pmove, true, false),
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Where the stone is at the given place, or above the other place.
+    fn point(&self, place: Option<Place>, orientation: &Orientation) -> Vec3 {
+        let Some((stack_i, height)) = place.or(self.from).or(self.to) else {
+            return Vec3::ZERO;
+        };
+        let (x, z) = orientation.stack_place(stack_i, STACK_RADIUS);
+        let point = Vec3::new(x, CYLINDER_HEIGHT * (height as f32 + 0.5), z);
+        match place {
+            Some(_) => point,
+            None => point + DROP_HEIGHT * Vec3::Y,
+        }
+    }
+}
+
+/// Start an animation when the position on the board goes a move forward or
+/// back, and move the stone of the running one. The stone at the end of the
+/// move is hidden until the moving stone gets there.
+#[allow(clippy::too_many_arguments)]
+pub fn animate_moves(
+    game: Res<Game>,
+    settings: Res<Settings>,
+    orientation: Res<Orientation>,
+    time: Res<Time>,
+    mut shown: Local<Option<Position>>,
+    mut slide: Local<Option<Slide>>,
+    mut moving: Query<
+        (&mut Transform, &mut Visibility, &Handle<StandardMaterial>),
+        With<MovingStone>,
+    >,
+    spots: Query<(&Spot, &Handle<StandardMaterial>)>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let (mut transform, mut visibility, handle) = moving.single_mut();
+    let position = game.is_changed().then(|| game.shown_position());
+    let moved = match (&position, &*shown) {
+        (Some(new), Some(old)) => new.history() != old.history() || new.to_fen() != old.to_fen(),
+        (new, _) => new.is_some(),
+    };
+    if let Some(position) = position.filter(|_| moved) {
+        let started = match (&*shown, settings.animations) {
+            (Some(old), true) => Slide::between(old, &position),
+            _ => None,
+        };
+        // A move which is still animated is finished right away.
+        if let Some(running) = slide.take() {
+            show_place(running.to, &position, &spots, &mut materials);
+        }
+        *shown = Some(position);
+        *slide = started;
+    }
+    let Some(running) = &mut *slide else {
+        visibility.set_if_neq(Visibility::Hidden);
+        return;
+    };
+    running.timer.tick(time.delta());
+    if running.timer.finished() {
+        show_place(
+            running.to,
+            (*shown).as_ref().unwrap(),
+            &spots,
+            &mut materials,
+        );
+        *slide = None;
+        visibility.set_if_neq(Visibility::Hidden);
+        return;
+    }
+    // The stone at the end of the move is only shown when it is finished.
+    if let Some(place) = running.to {
+        set_alpha(place, 0.0, &spots, &mut materials);
+    }
+    // Start and end slowly, and go over the stones in between.
+    let t = running.timer.percent();
+    let t = t * t * (3.0 - 2.0 * t);
+    let (start, end) = (
+        running.point(running.from, &orientation),
+        running.point(running.to, &orientation),
+    );
+    let lift = match running.from.is_some() && running.to.is_some() {
+        true => LIFT * (PI * t).sin(),
+        false => 0.0,
+    };
+    transform.translation = start.lerp(end, t) + lift * Vec3::Y;
+    let material = materials.get_mut(handle).unwrap();
+    material.base_color = settings.stone_color(running.player);
+    material.emissive = match running.rewind {
+        true => settings.palette.banned(),
+        false => Color::BLACK,
+    };
+    visibility.set_if_neq(Visibility::Inherited);
+}
+
+/// Show the stone at the end of a move again, if it is still on the board.
+fn show_place(
+    place: Option<Place>,
+    position: &Position,
+    spots: &Query<(&Spot, &Handle<StandardMaterial>)>,
+    materials: &mut Assets<StandardMaterial>,
+) {
+    if let Some((stack_i, height)) = place {
+        if position.stone_at(stack_i, height).is_some() {
+            set_alpha((stack_i, height), 1.0, spots, materials);
+        }
+    }
+}
+
+fn set_alpha(
+    place: Place,
+    alpha: f32,
+    spots: &Query<(&Spot, &Handle<StandardMaterial>)>,
+    materials: &mut Assets<StandardMaterial>,
+) {
+    for (spot, handle) in spots.iter() {
+        if (spot.stack_i, spot.height) == place {
+            let material = materials.get_mut(handle).unwrap();
+            material.base_color = material.base_color.with_a(alpha);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Clocks for the players, with a base time and an increment for every move.
+//! The time left is worked out from the time spent on the moves of the game,
+//! so it is also right after undoing moves or going to another variation.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use second_best::gamefile::GameRecord;
+use second_best::position::{Color, Position};
+use serde::{Deserialize, Serialize};
+
+use crate::moves::game_record;
+use crate::settings::Settings;
+use crate::sound::Sound;
+use crate::Game;
+
+/// The time each player gets for the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeControl {
+    /// The time at the start of the game.
+    pub base: Duration,
+    /// The time added after every move of a player.
+    pub increment: Duration,
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_secs(10 * 60),
+            increment: Duration::from_secs(5),
+        }
+    }
+}
+
+impl TimeControl {
+    /// The time left of each player, black first, after the moves of the
+    /// record. The player to move has been thinking for `thinking` already.
+    pub fn remaining(&self, record: &GameRecord, thinking: Duration) -> [Duration; 2] {
+        let mut left = [self.base; 2];
+        let mut pos = Position::default();
+        for (move_i, &pmove) in record.moves.iter().enumerate() {
+            let time = record.times.get(&move_i).copied().unwrap_or_default();
+            let player = &mut left[index(pos.current_player())];
+            *player = player.saturating_sub(time) + self.increment;
+            if pos.try_make_move(pmove).is_err() {
+                break;
+            }
+        }
+        let player = &mut left[index(pos.current_player())];
+        *player = player.saturating_sub(thinking);
+        left
+    }
+}
+
+/// The index of a player in [`TimeControl::remaining`].
+pub fn index(color: Color) -> usize {
+    match color {
+        Color::Black => 0,
+        Color::White => 1,
+    }
+}
+
+/// The time left on a clock, as "m:ss", with tenths of seconds in the last
+/// ten seconds.
+pub fn format_clock(time: Duration) -> String {
+    let secs = time.as_secs();
+    match secs < 10 {
+        true => format!("0:{:02}.{}", secs, time.subsec_millis() / 100),
+        false => format!("{}:{:02}", secs / 60, secs % 60),
+    }
+}
+
+/// The time left of each player in the game, if it is played with a clock.
+/// The clock of the player to move only runs while the game goes on.
+pub fn remaining(game: &Game, settings: &Settings) -> Option<[Duration; 2]> {
+    let time_control = settings.time_control?;
+    let position = game.main_game.as_ref().unwrap_or(&game.position);
+    let thinking = match game.flagged.is_some() || position.game_over() {
+        true => Duration::ZERO,
+        false => game.move_started.elapsed(),
+    };
+    Some(time_control.remaining(&game_record(game), thinking))
+}
+
+/// End the game when the player to move runs out of time.
+pub fn flag_fall(mut game: ResMut<Game>, settings: Res<Settings>) {
+    let position = game.main_game.as_ref().unwrap_or(&game.position);
+    if game.flagged.is_some() || position.game_over() {
+        return;
+    }
+    let player = position.current_player();
+    let Some(left) = remaining(&game, &settings) else {
+        return;
+    };
+    if left[index(player)].is_zero() {
+        game.flagged = Some(player);
+        game.message = format!("{player} ran out of time");
+        game.sound = Some(Sound::GameOver);
+    }
+}
@@ -0,0 +1,162 @@
+//! A console with everything the engine reports, where commands of the
+//! command line interface (see `help`) can be typed, for debugging.
+//! The typed commands act on the position on the board, and use the
+//! transposition table of the engine. Moves they play or undo are shown
+//! on the board, if they are on the standard board.
+
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use second_best::cli::Cli;
+use second_best::position::Variant;
+
+use crate::{Engine, Game};
+
+/// The lines shown in the console, shared with the threads writing them.
+#[derive(Default)]
+pub struct ConsoleLines(Mutex<VecDeque<String>>);
+
+impl ConsoleLines {
+    /// The number of lines which are kept, the oldest lines are removed first.
+    const MAX_LINES: usize = 2000;
+
+    /// Add every line of `text` to the console.
+    pub fn push(&self, text: impl Display) {
+        let mut lines = self.0.lock().unwrap();
+        for line in text.to_string().lines() {
+            if lines.len() == Self::MAX_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+    }
+}
+
+/// Writes the output of the command line interface to the console,
+/// once a line is complete.
+struct ConsoleWriter {
+    lines: Arc<ConsoleLines>,
+    partial_line: Vec<u8>,
+}
+
+impl Write for ConsoleWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.partial_line.extend_from_slice(buf);
+        while let Some(end) = self.partial_line.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial_line.drain(..=end).collect();
+            self.lines.push(String::from_utf8_lossy(&line[..end]));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Resource)]
+pub struct Console {
+    /// The same lines as [`Engine::console`].
+    lines: Arc<ConsoleLines>,
+    /// The command being typed.
+    input: String,
+    /// Runs the typed commands. It is started with the first command.
+    cli: Option<Cli>,
+}
+
+impl FromWorld for Console {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            lines: world.resource::<Engine>().console.clone(),
+            input: String::new(),
+            cli: None,
+        }
+    }
+}
+
+impl Console {
+    /// Run the typed command. Its output is shown in the console.
+    fn run(&mut self, game: &mut Game, engine: &Engine) {
+        let command = std::mem::take(&mut self.input);
+        if command.trim().is_empty() {
+            return;
+        }
+        self.lines.push(format!("$ {command}"));
+        let lines = self.lines.clone();
+        let cli = self.cli.get_or_insert_with(|| {
+            let mut cli = Cli::with_writer(ConsoleWriter {
+                lines,
+                partial_line: vec![],
+            });
+            cli.share_tt(engine.tt.clone());
+            cli
+        });
+        // While a command is still searching, its position is left alone.
+        let idle = !cli.is_busy();
+        if idle {
+            cli.set_position(game.position.clone());
+        }
+        match cli.execute_command(&command) {
+            // After `quit`, the next command starts a new cli.
+            Ok(true) => {
+                self.cli = None;
+                return;
+            }
+            Ok(false) => (),
+            Err(e) => self.lines.push(e),
+        }
+        if idle && !cli.is_busy() {
+            let position = cli.position();
+            if position.variant() != Variant::STANDARD {
+                game.message = format!("The board can't show the {} board", position.variant());
+            } else if position.history() != game.position.history()
+                || position.to_fen() != game.position.to_fen()
+            {
+                game.set_position(position);
+                game.message = format!("Changed by `{command}` in the console");
+            }
+        }
+    }
+}
+
+pub fn console_ui(
+    mut console: ResMut<Console>,
+    mut game: ResMut<Game>,
+    engine: Res<Engine>,
+    mut ctx: EguiContexts,
+) {
+    egui::Window::new("Console")
+        .default_open(false)
+        .show(ctx.ctx_mut(), |ui| {
+            {
+                let lines = console.lines.0.lock().unwrap();
+                let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                egui::ScrollArea::both()
+                    .max_height(300.0)
+                    .stick_to_bottom(true)
+                    .show_rows(ui, row_height, lines.len(), |ui, rows| {
+                        for line in lines.range(rows) {
+                            ui.monospace(line);
+                        }
+                    });
+            }
+            ui.horizontal(|ui| {
+                let input = ui.add(
+                    egui::TextEdit::singleline(&mut console.input)
+                        .font(egui::TextStyle::Monospace)
+                        .hint_text("A command, e.g. `eval 8`"),
+                );
+                if input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    console.run(&mut game, &engine);
+                    input.request_focus();
+                }
+                if ui.button("Clear").clicked() {
+                    console.lines.0.lock().unwrap().clear();
+                }
+            });
+        });
+}
@@ -0,0 +1,306 @@
+//! Searching every move of the game in the background, to show the moves
+//! with their scores and annotations, and a graph of the scores.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use second_best::analysis::{self, AnnotatedMove};
+use second_best::eval::{self, ExplainableEval};
+use second_best::position::{PlayerMove, Position};
+use second_best::solver::Solver;
+
+use crate::settings::Settings;
+use crate::{Game, Preview};
+
+/// How much worse than the best move a move has to be to be a mistake,
+/// as with `annotate` in the CLI.
+const MISTAKE_THRESHOLD: isize = 3;
+/// The largest score shown in the graph. Wins and losses are shown as this.
+const GRAPH_LIMIT: isize = 20;
+
+/// A move searched by the background thread. The run and the index of
+/// the move tell which game and which move of it the result is for, since
+/// a new run can be started before the results of the last one are used.
+struct SearchedMove {
+    run: usize,
+    index: usize,
+    /// The key of the move in the cache.
+    key: (String, String, usize),
+    annotated: AnnotatedMove,
+}
+
+#[derive(Resource)]
+pub struct GameAnalysis {
+    /// The depth every position is searched to.
+    depth: usize,
+    /// The game which is analyzed: the start and the moves.
+    start: Position,
+    moves: Vec<PlayerMove>,
+    /// The results of the moves of the game, by their index.
+    annotated: Vec<Option<AnnotatedMove>>,
+    /// All moves searched in this session, by the position before the
+    /// move, the move and the depth, so they aren't searched again.
+    cache: HashMap<(String, String, usize), AnnotatedMove>,
+    /// Counts the runs, to ignore the results of earlier runs.
+    run: usize,
+    /// Filled by the background thread.
+    searched: Arc<Mutex<Vec<SearchedMove>>>,
+    abort: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Default for GameAnalysis {
+    fn default() -> Self {
+        Self {
+            depth: 6,
+            start: Position::default(),
+            moves: vec![],
+            annotated: vec![],
+            cache: HashMap::new(),
+            run: 0,
+            searched: Arc::new(Mutex::new(vec![])),
+            abort: Arc::new(AtomicBool::new(false)),
+            handle: None,
+        }
+    }
+}
+
+impl GameAnalysis {
+    /// The key of a move in the cache.
+    fn cache_key(before: &Position, pmove: PlayerMove, depth: usize) -> (String, String, usize) {
+        (before.to_fen(), pmove.to_string(), depth)
+    }
+
+    fn is_running(&self) -> bool {
+        self.handle
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+    }
+
+    /// Search every move of the game leading to `position` which isn't
+    /// in the cache yet, on a background thread.
+    fn start(&mut self, position: &Position) {
+        self.stop();
+        self.run += 1;
+        self.moves = position.history();
+        self.start = position.clone();
+        while self.start.undo_last_player_move().is_some() {}
+
+        let mut before = self.start.clone();
+        let mut to_search = vec![];
+        self.annotated = vec![];
+        for (index, &pmove) in self.moves.iter().enumerate() {
+            let key = Self::cache_key(&before, pmove, self.depth);
+            let cached = self.cache.get(&key).cloned();
+            if cached.is_none() {
+                to_search.push((index, before.clone(), pmove));
+            }
+            self.annotated.push(cached);
+            before.try_make_move(pmove).unwrap();
+        }
+
+        self.abort.store(false, Ordering::Relaxed);
+        let abort = self.abort.clone();
+        let searched = self.searched.clone();
+        let (run, depth, rules) = (self.run, self.depth, self.start.rules());
+        let handle = std::thread::Builder::new()
+            .name("Game analysis".to_string())
+            .stack_size(5_000_000)
+            .spawn(move || {
+                let mut solver = Solver::new(abort.clone());
+                solver.position.set_rules(rules);
+                for (index, before, pmove) in to_search {
+                    let result = analysis::annotate_game(
+                        &mut solver,
+                        &before,
+                        &[pmove],
+                        depth,
+                        MISTAKE_THRESHOLD,
+                        None,
+                    );
+                    if abort.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    // The moves of the game are legal, so only a stopped search has no result.
+                    let Some(annotated) = result.ok().and_then(|mut moves| moves.pop()) else {
+                        return;
+                    };
+                    searched.lock().unwrap().push(SearchedMove {
+                        run,
+                        index,
+                        key: Self::cache_key(&before, pmove, depth),
+                        annotated,
+                    });
+                }
+            });
+        self.handle = handle.ok();
+    }
+
+    fn stop(&mut self) {
+        self.abort.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+
+    /// Put the moves searched by the background thread in the table and the cache.
+    fn collect_searched(&mut self) {
+        let searched = std::mem::take(&mut *self.searched.lock().unwrap());
+        for SearchedMove {
+            run,
+            index,
+            key,
+            annotated,
+        } in searched
+        {
+            if run == self.run {
+                self.annotated[index] = Some(annotated.clone());
+            }
+            self.cache.insert(key, annotated);
+        }
+    }
+}
+
+/// The score after a move from the perspective of black, for the graph.
+fn graph_score(annotated: &AnnotatedMove) -> isize {
+    let score = match eval::decode_eval(annotated.score, annotated.ply as isize) {
+        ExplainableEval::Win(_) => GRAPH_LIMIT,
+        ExplainableEval::Loss(_) => -GRAPH_LIMIT,
+        ExplainableEval::Undetermined(score) => score.clamp(-GRAPH_LIMIT, GRAPH_LIMIT),
+    };
+    // Black plays the moves at the even plies.
+    match annotated.ply % 2 {
+        0 => score,
+        _ => -score,
+    }
+}
+
+/// Draw the scores of the moves as a line, with black's advantage upwards.
+fn graph_ui(ui: &mut egui::Ui, annotated: &[Option<AnnotatedMove>]) {
+    let size = egui::vec2(ui.available_width().max(200.0), 80.0);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let stroke = ui.visuals().widgets.noninteractive.fg_stroke;
+    let painter = ui.painter();
+    painter.line_segment([rect.left_center(), rect.right_center()], stroke);
+    let step = rect.width() / annotated.len().max(1) as f32;
+    let points: Vec<egui::Pos2> = annotated
+        .iter()
+        .enumerate()
+        .filter_map(|(i, annotated)| {
+            let score = graph_score(annotated.as_ref()?) as f32 / GRAPH_LIMIT as f32;
+            let x = rect.left() + (i as f32 + 0.5) * step;
+            Some(egui::pos2(x, rect.center().y - score * rect.height() / 2.0))
+        })
+        .collect();
+    painter.add(egui::Shape::line(points, stroke));
+}
+
+pub fn game_analysis_ui(
+    mut game_analysis: ResMut<GameAnalysis>,
+    mut game: ResMut<Game>,
+    settings: Res<Settings>,
+    mut ctx: EguiContexts,
+) {
+    game_analysis.collect_searched();
+    // The moves of the scratch board are not part of the game.
+    let position = game
+        .main_game
+        .clone()
+        .unwrap_or_else(|| game.position.clone());
+    egui::Window::new("Game analysis")
+        .default_open(false)
+        .show(ctx.ctx_mut(), |ui| {
+            let running = game_analysis.is_running();
+            ui.horizontal(|ui| {
+                ui.add_enabled(
+                    !running,
+                    egui::Slider::new(&mut game_analysis.depth, 1..=16).text("Depth"),
+                );
+                if running {
+                    if ui.button("Stop").clicked() {
+                        game_analysis.stop();
+                    }
+                } else if ui
+                    .add_enabled(position.ply() > 0, egui::Button::new("Analyze game"))
+                    .on_hover_text("Search every move of the game, and mark the mistakes")
+                    .clicked()
+                {
+                    game_analysis.start(&position);
+                }
+            });
+            let done = game_analysis.annotated.iter().flatten().count();
+            if running {
+                ui.label(format!(
+                    "Searched {done} of {} moves",
+                    game_analysis.annotated.len()
+                ));
+            }
+            if game_analysis.annotated.is_empty() {
+                return;
+            }
+            if game_analysis.moves != position.history() {
+                ui.label("This is the analysis of another game.");
+            }
+            graph_ui(ui, &game_analysis.annotated);
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("game analysis")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for (i, annotated) in game_analysis.annotated.iter().enumerate() {
+                                let pmove = game_analysis.moves[i];
+                                let clicked = ui
+                                    .selectable_label(false, format!("{}. {pmove}", i + 1))
+                                    .on_hover_text("Show the position after this move on the board")
+                                    .clicked();
+                                if clicked {
+                                    game.preview = Some(Preview::new(
+                                        &game_analysis.start,
+                                        &game_analysis.moves,
+                                    ));
+                                    if let Some(preview) = &mut game.preview {
+                                        preview.shown = preview.shown.min(i + 1);
+                                    }
+                                }
+                                let Some(annotated) = annotated else {
+                                    ui.end_row();
+                                    continue;
+                                };
+                                let symbol = annotated.annotation.map_or("", |a| a.symbol());
+                                match annotated.annotation {
+                                    Some(analysis::Annotation::Blunder) => {
+                                        ui.colored_label(settings.palette.error(), symbol)
+                                    }
+                                    _ => ui.label(symbol),
+                                };
+                                let ply = annotated.ply as isize;
+                                ui.label(analysis::explain_score(annotated.score, ply));
+                                if annotated.best_move != pmove {
+                                    ui.label(format!(
+                                        "best was {}: {}",
+                                        annotated.best_move,
+                                        analysis::explain_score(annotated.best_score, ply)
+                                    ));
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+        });
+}
+
+/// Stop the background searches when the app is closed.
+pub fn stop_game_analysis_on_exit(
+    mut exit: EventReader<AppExit>,
+    game_analysis: Res<GameAnalysis>,
+) {
+    if exit.read().next().is_some() {
+        game_analysis.abort.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,99 @@
+//! Playing moves with the keyboard: the number of a stack plays a stone there
+//! in the first phase. In the second phase the first number chooses the stack
+//! to move from, which is highlighted as with a click, and the second the
+//! stack to move to. Escape cancels a chosen stack or a move to confirm.
+//!
+//! Enter analyzes the position of the game, like "Analyze", and Space
+//! stops the search. The arrow keys go back and forward through the line
+//! which is shown from the analysis, or else through the moves of the game
+//! like "Undo" and "Redo".
+
+use std::sync::atomic::Ordering;
+
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+use second_best::position::Variant;
+
+use crate::settings::Settings;
+use crate::{Engine, Game};
+
+/// The keys of the stacks, in the number row and on the numpad.
+const STACK_KEYS: [[KeyCode; 2]; Variant::STANDARD.stacks()] = [
+    [KeyCode::Key0, KeyCode::Numpad0],
+    [KeyCode::Key1, KeyCode::Numpad1],
+    [KeyCode::Key2, KeyCode::Numpad2],
+    [KeyCode::Key3, KeyCode::Numpad3],
+    [KeyCode::Key4, KeyCode::Numpad4],
+    [KeyCode::Key5, KeyCode::Numpad5],
+    [KeyCode::Key6, KeyCode::Numpad6],
+    [KeyCode::Key7, KeyCode::Numpad7],
+];
+
+pub fn keyboard_input(
+    keys: Res<Input<KeyCode>>,
+    mut game: ResMut<Game>,
+    mut engine: ResMut<Engine>,
+    settings: Res<Settings>,
+    mut ctx: EguiContexts,
+) {
+    // Keys typed in a text field, like the console, are not moves.
+    if ctx.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        if let Some(staged) = game.staged.take() {
+            game.message = format!("Cancelled {staged}");
+        } else if let Some(from) = game.selected.take() {
+            game.message = format!("Cancelled the move from {from}");
+        }
+        return;
+    }
+    if keys.any_just_pressed([KeyCode::Return, KeyCode::NumpadEnter]) {
+        // The move of the game which is on the board, also after going
+        // back to an earlier one.
+        if !engine.thinking && !game.position.game_over() {
+            engine.start_analysis(&game.position);
+        }
+        return;
+    }
+    if keys.just_pressed(KeyCode::Space) {
+        if engine.thinking {
+            engine.abort.store(true, Ordering::Relaxed);
+        }
+        return;
+    }
+    let (back, forward) = (
+        keys.just_pressed(KeyCode::Left),
+        keys.just_pressed(KeyCode::Right),
+    );
+    if back || forward {
+        if let Some(preview) = &mut game.preview {
+            if back && preview.shown > 0 {
+                preview.shown -= 1;
+            } else if forward && preview.shown < preview.line.len() {
+                preview.shown += 1;
+            }
+        } else if back {
+            game.undo();
+        } else if game.main_game.is_none() {
+            // The moves of the scratch board are not kept.
+            game.redo();
+        }
+        return;
+    }
+    let Some(stack_i) = STACK_KEYS
+        .iter()
+        .position(|stack_keys| keys.any_just_pressed(*stack_keys))
+    else {
+        return;
+    };
+    // As with clicks, a line which is shown or a move to confirm has to be
+    // closed first.
+    if game.preview.is_some() || game.staged.is_some() {
+        return;
+    }
+    if engine.thinking || game.position.game_over() {
+        return;
+    }
+    game.choose_stack(stack_i, settings.confirm_moves);
+}
@@ -1,9 +1,46 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use bevy::app::AppExit;
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts, EguiPlugin};
 use bevy_mod_picking::prelude::*;
 use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
+use console::{Console, ConsoleLines};
+use game_analysis::GameAnalysis;
+use second_best::analysis::{self, SecondBestAdvice, SecondBestMapEntry};
+use second_best::gamefile::GameRecord;
+use second_best::log::Log;
+use second_best::position::{self, GameStatus, PlayerMove, Position, Variant};
+use second_best::solver::{CurrMoveInfo, RootMoveLine, SearchLimits, Solver};
+use second_best::SharedTranspositionTable;
+use session::Autosave;
+use settings::{Board, Settings};
+use sound::Sound;
+use tabs::Tabs;
+use variations::Variations;
+
+mod animation;
+mod clock;
+mod console;
+mod game_analysis;
+mod keyboard;
+mod moves;
+mod session;
+mod settings;
+mod sound;
+mod tabs;
+mod variations;
 
 const CYLINDER_HEIGHT: f32 = 0.25;
+/// The distance of the stacks from the center of the board.
+const STACK_RADIUS: f32 = 2.0;
+/// The distance of the stack labels from the center of the board.
+const LABEL_RADIUS: f32 = 2.6;
+/// The directory with the engine logs, in the working directory.
+const LOG_DIR: &str = "second-best-logs";
 
 fn main() {
     App::new()
@@ -15,47 +52,767 @@ fn main() {
                 .disable::<DefaultHighlightingPlugin>(),
             PanOrbitCameraPlugin,
         ))
+        .init_resource::<Game>()
+        .init_resource::<Engine>()
+        .init_resource::<Console>()
+        .init_resource::<GameAnalysis>()
+        .init_resource::<Autosave>()
+        .init_resource::<Tabs>()
+        .init_resource::<Orientation>()
+        .init_resource::<HoveredSpot>()
+        .insert_resource(Settings::load())
         .add_systems(Startup, setup_system)
-        .add_systems(Update, camera_control_ui)
+        .add_systems(
+            Update,
+            (
+                tabs::tabs_ui,
+                camera_control_ui,
+                game_ui,
+                analysis_ui,
+                engine_reply,
+                update_stones,
+                update_orientation,
+                update_stack_labels,
+                settings::settings_ui,
+                settings::apply_settings,
+                update_engine_log,
+                console::console_ui,
+                keyboard::keyboard_input,
+                clock::flag_fall,
+                game_analysis::game_analysis_ui,
+                moves::moves_ui,
+                session::autosave_ui,
+                sound::play_sounds,
+            ),
+        )
+        // The stones have to be updated before the one at the end of a move
+        // is hidden.
+        .add_systems(Update, animation::animate_moves.after(update_stones))
+        // What is drawn on the board on top of the stones.
+        .add_systems(
+            Update,
+            (
+                draw_banned_move,
+                draw_second_best_map,
+                draw_best_line,
+                draw_destinations,
+            ),
+        )
+        .add_systems(
+            Last,
+            (
+                stop_engine_on_exit,
+                tabs::stop_hidden_engines_on_exit,
+                game_analysis::stop_game_analysis_on_exit,
+                settings::save_settings_on_exit,
+                session::save_session_on_exit,
+            ),
+        )
         .run();
 }
 
+/// The game being played, and the state of the move input.
+#[derive(Resource)]
+struct Game {
+    position: Position,
+    /// The stack selected to move a stone from, in the second phase.
+    selected: Option<usize>,
+    /// Feedback about the last move that was tried.
+    message: String,
+    /// A line from the analysis which is shown on the board instead of the game.
+    preview: Option<Preview>,
+    /// A move made on the board which is shown, but only played once it
+    /// is confirmed.
+    staged: Option<PlayerMove>,
+    /// The sound of the last thing which happened, until it is played.
+    sound: Option<Sound>,
+    /// The position of the game while moves are tried on the scratch
+    /// board. It is put back when the scratch board is closed.
+    main_game: Option<Position>,
+    /// The time spent on each move of the game. Moves of the scratch
+    /// board are not timed.
+    times: Vec<Duration>,
+    /// When the last move of the game was played, or the game started.
+    move_started: Instant,
+    /// The lines played in the game, including the ones which were taken
+    /// back. Like the times, this doesn't have the moves of the scratch board.
+    variations: Variations,
+    /// The player whose time ran out, which ends the game.
+    flagged: Option<position::Color>,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self {
+            position: Position::default(),
+            selected: None,
+            message: String::new(),
+            preview: None,
+            staged: None,
+            sound: None,
+            main_game: None,
+            times: vec![],
+            move_started: Instant::now(),
+            variations: Variations::default(),
+            flagged: None,
+        }
+    }
+}
+
+impl Game {
+    fn play(&mut self, pmove: PlayerMove) {
+        self.selected = None;
+        self.preview = None;
+        self.staged = None;
+        if let Some(player) = self.flagged.filter(|_| self.main_game.is_none()) {
+            self.message = format!("Can't play {pmove}: {player} ran out of time");
+            return;
+        }
+        match self.position.try_make_move(pmove) {
+            Ok(()) => {
+                if self.main_game.is_none() {
+                    let time = self.move_started.elapsed();
+                    self.times.push(time);
+                    self.variations.play(pmove, time);
+                    self.move_started = Instant::now();
+                }
+                self.message = format!("Played {pmove}");
+                self.sound = Some(match pmove {
+                    _ if self.position.game_over() => Sound::GameOver,
+                    PlayerMove::SecondBest => Sound::SecondBest,
+                    PlayerMove::StoneMove { .. } => Sound::StonePlaced,
+                });
+            }
+            Err(e) => self.message = format!("Can't play {pmove}: {e:?}"),
+        }
+    }
+
+    /// Play a move made on the board, or only show it if it has to be confirmed.
+    fn play_or_stage(&mut self, pmove: PlayerMove, confirm: bool) {
+        if !confirm {
+            self.play(pmove);
+            return;
+        }
+        self.selected = None;
+        match self.position.is_legal(pmove) {
+            Ok(()) => {
+                self.staged = Some(pmove);
+                self.message = format!("Confirm {pmove} to play it");
+            }
+            Err(e) => self.message = format!("Can't play {pmove}: {e:?}"),
+        }
+    }
+
+    /// Play a move on the chosen stack, clicked or typed. In the second phase
+    /// the first stack is the one to move from, and the second the one to
+    /// move to. Choosing the first stack again cancels the move.
+    fn choose_stack(&mut self, stack_i: usize, confirm: bool) {
+        if !self.position.is_second_phase() {
+            self.play_or_stage(
+                PlayerMove::StoneMove {
+                    from: None,
+                    to: stack_i,
+                },
+                confirm,
+            );
+            return;
+        }
+        let ours = self.top_stone(stack_i) == Some(self.position.current_player());
+        match self.selected {
+            Some(from) if from == stack_i => {
+                self.selected = None;
+                self.message = format!("Cancelled the move from {from}");
+            }
+            Some(from) if !ours => self.play_or_stage(
+                PlayerMove::StoneMove {
+                    from: Some(from),
+                    to: stack_i,
+                },
+                confirm,
+            ),
+            _ if ours => {
+                self.selected = Some(stack_i);
+                self.message = format!("Moving from {stack_i}, choose the stack to move to");
+            }
+            _ => self.message = "Select one of your stones to move first".to_string(),
+        }
+    }
+
+    /// Use a position which was changed outside the board, e.g. by a command
+    /// typed in the console. The times of the moves which are still the
+    /// same are kept.
+    fn set_position(&mut self, position: Position) {
+        let (old, new) = (self.position.history(), position.history());
+        let same = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        if self.main_game.is_none() {
+            self.times.truncate(same);
+            self.times.resize(new.len(), Duration::ZERO);
+            self.variations.set_line(&new, &self.times);
+            self.move_started = Instant::now();
+            self.flagged = None;
+        }
+        self.position = position;
+        self.selected = None;
+        self.preview = None;
+        self.staged = None;
+    }
+
+    /// Start again from a saved or pasted game, with the times of its moves.
+    /// The board only has the standard size.
+    fn load_game(&mut self, record: &GameRecord) -> Result<(), String> {
+        if record.variant != Variant::STANDARD {
+            return Err(format!("the game is played on a {} board", record.variant));
+        }
+        let position = record.to_position().map_err(|e| e.to_string())?;
+        *self = Game::default();
+        self.times = (0..record.moves.len())
+            .map(|move_i| record.times.get(&move_i).copied().unwrap_or_default())
+            .collect();
+        self.variations.set_line(&record.moves, &self.times);
+        self.position = position;
+        Ok(())
+    }
+
+    /// Take back the last move. In the game it is kept, so it can be redone.
+    fn undo(&mut self) {
+        self.selected = None;
+        self.preview = None;
+        self.staged = None;
+        self.message = match self.position.undo_last_player_move() {
+            Some(pmove) => {
+                if self.main_game.is_none() {
+                    self.times.pop();
+                    self.variations.back();
+                    self.move_started = Instant::now();
+                    self.flagged = None;
+                }
+                format!("Undid {pmove}")
+            }
+            None => "Nothing to undo".to_string(),
+        };
+    }
+
+    /// Play the last move which was taken back again, with the time it took.
+    fn redo(&mut self) {
+        let Some((pmove, time)) = self.variations.redo_move() else {
+            return;
+        };
+        self.selected = None;
+        self.preview = None;
+        self.staged = None;
+        match self.position.try_make_move(pmove) {
+            Ok(()) => {
+                self.times.push(time);
+                self.variations.forward();
+                self.move_started = Instant::now();
+                self.flagged = None;
+                self.message = format!("Redid {pmove}");
+            }
+            Err(e) => self.message = format!("Can't redo {pmove}: {e:?}"),
+        }
+    }
+
+    /// Go to a move of one of the lines of the game, see [`Variations`].
+    fn go_to_variation(&mut self, node: usize) {
+        let (moves, times) = self.variations.go_to(node);
+        let mut position = Position::default();
+        for &pmove in &moves {
+            // The moves of the tree were all played from the start.
+            position.try_make_move(pmove).unwrap();
+        }
+        self.position = position;
+        self.times = times;
+        self.move_started = Instant::now();
+        self.flagged = None;
+        self.selected = None;
+        self.preview = None;
+        self.staged = None;
+        self.message = match moves.last() {
+            Some(pmove) => format!("Went to move {}, {pmove}", moves.len()),
+            None => "Went to the start of the game".to_string(),
+        };
+    }
+
+    /// Try moves from the current position, which can be analyzed
+    /// and discarded without changing the game.
+    fn open_scratch_board(&mut self) {
+        self.main_game = Some(self.position.clone());
+        self.message = "Moves are played on the scratch board".to_string();
+    }
+
+    /// Go back to the game, and forget the moves of the scratch board.
+    fn close_scratch_board(&mut self) {
+        let Some(position) = self.main_game.take() else {
+            return;
+        };
+        self.position = position;
+        self.selected = None;
+        self.preview = None;
+        self.staged = None;
+        self.message = "Back to the game".to_string();
+    }
+
+    /// The position shown on the board.
+    fn shown_position(&self) -> Position {
+        if let Some(preview) = &self.preview {
+            return preview.position();
+        }
+        let mut position = self.position.clone();
+        if let Some(staged) = self.staged {
+            position.try_make_move(staged).unwrap();
+        }
+        position
+    }
+
+    /// The color of the stone on top of the given stack, if there is one.
+    fn top_stone(&self, stack_i: usize) -> Option<position::Color> {
+        let height = self.position.stack_height(stack_i).checked_sub(1)?;
+        self.position.stone_at(stack_i, height)
+    }
+}
+
+/// A line which is played on the board, without changing the game.
+struct Preview {
+    /// The position the line starts from.
+    start: Position,
+    /// The legal moves of the line.
+    line: Vec<PlayerMove>,
+    /// The number of moves of the line which are played on the board.
+    shown: usize,
+}
+
+impl Preview {
+    fn new(start: &Position, line: &[PlayerMove]) -> Self {
+        // A line from the transposition table might end in a move which
+        // is not legal, if a different position had the same key.
+        let mut position = start.clone();
+        let line: Vec<PlayerMove> = line
+            .iter()
+            .copied()
+            .take_while(|&pmove| position.try_make_move(pmove).is_ok())
+            .collect();
+        Self {
+            start: start.clone(),
+            shown: line.len(),
+            line,
+        }
+    }
+
+    fn position(&self) -> Position {
+        let mut position = self.start.clone();
+        for &pmove in &self.line[..self.shown] {
+            position.try_make_move(pmove).unwrap();
+        }
+        position
+    }
+}
+
+/// What the engine found in its search.
+enum EngineResult {
+    /// The move to play, or `None` if the game is over,
+    /// and the reply the engine expects if it knows it.
+    BestMove {
+        pmove: Option<PlayerMove>,
+        reply: Option<PlayerMove>,
+    },
+    /// Whether to call "Second Best!", or `None` if it can't be called.
+    Advice(Option<SecondBestAdvice>),
+    /// Whether the opponent should call "Second Best!" on every move.
+    SecondBestMap(Vec<SecondBestMapEntry>),
+    /// The position was analyzed, the results are in the analysis table.
+    Analyzed,
+}
+
+/// What the engine found so far about the moves in a position,
+/// shown in the analysis table.
+#[derive(Default)]
+struct Analysis {
+    /// The id of the search, see [`Engine::search_id`].
+    search_id: u64,
+    /// The searched position.
+    position: Position,
+    /// The depth of the last completed iteration.
+    depth: usize,
+    /// The largest number of plies searched from the position.
+    seldepth: usize,
+    /// The number of nodes searched, and thousands of nodes per second.
+    nodes: usize,
+    knps: u128,
+    /// How full the transposition table is, in per mille.
+    ttfull: usize,
+    /// The time spent on the search.
+    time: Duration,
+    /// The moves sorted from best to worst, with their lines.
+    root_moves: Vec<RootMoveLine>,
+    /// The move being searched, if the current iteration takes long.
+    currmove: Option<CurrMoveInfo>,
+}
+
+/// The solver, which searches in the background so the board stays responsive.
+#[derive(Resource)]
+struct Engine {
+    depth: usize,
+    thinking: bool,
+    abort: Arc<AtomicBool>,
+    /// Counts the searches. A search reports with its id, as with `id <n>`
+    /// in the CLI, so that what a search which was replaced still reports
+    /// is ignored.
+    search_id: u64,
+    /// Set by the search thread once the search is done, with its id.
+    result: Arc<Mutex<Option<(u64, EngineResult)>>>,
+    /// The thread doing the current search.
+    handle: Option<JoinHandle<()>>,
+    /// Set if the search thread stopped without a result.
+    error: Option<String>,
+    /// Updated by the search thread during searches for a move, or analysis.
+    analysis: Arc<Mutex<Analysis>>,
+    /// The log of this session, with the searches and what they found.
+    log: Option<Arc<Log>>,
+    /// Everything which is logged is also shown in the console.
+    console: Arc<ConsoleLines>,
+    /// The position after the reply the engine expects to its last move,
+    /// and the depth, while the engine searches it on the time of the player
+    /// (see [`Settings::ponder`]). If the reply is played, the search is
+    /// used for the next engine move (a "ponderhit").
+    ponder: Option<(Position, usize)>,
+    /// The position of the last "Second Best!" map, and the moves of it,
+    /// which are shown on the board while it is the position of the game.
+    second_best_map: Option<(Position, Vec<SecondBestMapEntry>)>,
+    /// The transposition table of the searches, which is kept between them
+    /// and shared with the engines of the other tabs.
+    tt: Arc<SharedTranspositionTable>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self {
+            depth: 8,
+            thinking: false,
+            abort: Arc::new(AtomicBool::new(false)),
+            search_id: 0,
+            result: Arc::new(Mutex::new(None)),
+            handle: None,
+            error: None,
+            analysis: Arc::new(Mutex::new(Analysis::default())),
+            log: None,
+            console: Arc::new(ConsoleLines::default()),
+            ponder: None,
+            second_best_map: None,
+            tt: Arc::new(SharedTranspositionTable::default()),
+        }
+    }
+}
+
+/// Search for the best move, and the reply to it the engine expects.
+fn search_best_move(solver: &mut Solver, limits: SearchLimits) -> EngineResult {
+    let pmove = solver.choose_move(limits);
+    let reply = pmove.and_then(|pmove| solver.expected_reply(pmove));
+    EngineResult::BestMove { pmove, reply }
+}
+
+impl Engine {
+    /// Search for the best move in the position.
+    fn start(&mut self, position: &Position) {
+        let ponderhit = self.ponder.as_ref().is_some_and(|(pondered, depth)| {
+            pondered.to_fen() == position.to_fen() && *depth == self.depth
+        });
+        if ponderhit {
+            // The search of this position is running or done already.
+            self.ponder = None;
+            self.thinking = true;
+            self.log(format!("id {} ponderhit", self.search_id));
+            self.show_analysis(self.search_id, position);
+            return;
+        }
+        let limits = SearchLimits::depth(self.depth);
+        let request = format!("best move, depth {}", self.depth);
+        self.spawn(position, &request, true, move |solver| {
+            search_best_move(solver, limits)
+        });
+    }
+
+    /// Search the position after the reply the engine expects to its move,
+    /// while the player thinks (see [`Engine::ponder`]).
+    fn start_ponder(&mut self, position: &Position) {
+        let limits = SearchLimits::depth(self.depth);
+        let request = format!("ponder, depth {}", self.depth);
+        self.spawn(position, &request, false, move |solver| {
+            search_best_move(solver, limits)
+        });
+        // The player can move while the engine ponders.
+        self.thinking = false;
+        if self.error.is_none() {
+            self.ponder = Some((position.clone(), self.depth));
+        }
+    }
+
+    /// Stop the search which ponders, and wait for it, so that it stops
+    /// before the next search clears the abort flag.
+    fn stop_ponder(&mut self) {
+        self.abort.store(true, std::sync::atomic::Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        *self.result.lock().unwrap() = None;
+    }
+
+    /// Show what the search with the given id finds in the analysis table.
+    fn show_analysis(&self, search_id: u64, position: &Position) {
+        *self.analysis.lock().unwrap() = Analysis {
+            search_id,
+            position: position.clone(),
+            ..Default::default()
+        };
+    }
+
+    /// Search the position without playing a move, to fill the analysis table.
+    fn start_analysis(&mut self, position: &Position) {
+        let limits = SearchLimits::depth(self.depth);
+        let request = format!("analysis, depth {}", self.depth);
+        self.spawn(position, &request, true, move |solver| {
+            solver.search_with_limits(limits);
+            EngineResult::Analyzed
+        });
+    }
+
+    /// Search whether calling "Second Best!" is better than allowing the last move.
+    fn start_advice(&mut self, position: &Position) {
+        let limits = SearchLimits::depth(self.depth);
+        let request = format!("\"Second Best!\" advice, depth {}", self.depth);
+        self.spawn(position, &request, false, move |solver| {
+            EngineResult::Advice(analysis::second_best_advice(solver, limits))
+        });
+    }
+
+    /// Search for every move whether the opponent should call "Second Best!" on it.
+    fn start_second_best_map(&mut self, position: &Position) {
+        let limits = SearchLimits::depth(self.depth);
+        let request = format!("\"Second Best!\" map, depth {}", self.depth);
+        self.spawn(position, &request, false, move |solver| {
+            EngineResult::SecondBestMap(analysis::second_best_map(solver, limits))
+        });
+        self.second_best_map = Some((position.clone(), vec![]));
+    }
+
+    /// Run the search on its own thread. If `analyze` is set, the search
+    /// of the position is shown in the analysis table while it runs.
+    /// The `request` describes the search in the log. A search which
+    /// ponders is stopped first.
+    fn spawn(
+        &mut self,
+        position: &Position,
+        request: &str,
+        analyze: bool,
+        search: impl FnOnce(&mut Solver) -> EngineResult + Send + 'static,
+    ) {
+        if self.ponder.take().is_some() {
+            self.stop_ponder();
+        }
+        self.thinking = true;
+        self.error = None;
+        self.abort
+            .store(false, std::sync::atomic::Ordering::Relaxed);
+        let mut solver = Solver::new(self.abort.clone());
+        solver.share_tt(self.tt.clone());
+        solver.position = position.clone();
+        self.search_id += 1;
+        let id = self.search_id;
+        self.log(format!("> id {id} {request} in {}", position.to_fen()));
+        if analyze {
+            self.show_analysis(id, position);
+        }
+        let analysis = self.analysis.clone();
+        let log = self.log.clone();
+        let console = self.console.clone();
+        solver.set_info_handler(move |info| {
+            let line = format!("id {id} {info}");
+            console.push(&line);
+            if let Some(log) = &log {
+                log.line(&line);
+            }
+            // Other searches, e.g. for advice, are not shown in the table.
+            let mut analysis = analysis.lock().unwrap();
+            if analysis.search_id != id {
+                return;
+            }
+            analysis.depth = info.depth;
+            analysis.seldepth = info.seldepth;
+            analysis.nodes = info.nodes;
+            analysis.knps = info.knps;
+            analysis.ttfull = info.ttfull;
+            analysis.time = info.time;
+            analysis.root_moves = info.root_moves;
+            analysis.currmove = None;
+        });
+        let analysis = self.analysis.clone();
+        let log = self.log.clone();
+        let console = self.console.clone();
+        solver.set_currmove_handler(move |info| {
+            let line = format!("id {id} {info}");
+            console.push(&line);
+            if let Some(log) = &log {
+                log.line(&line);
+            }
+            let mut analysis = analysis.lock().unwrap();
+            if analysis.search_id == id {
+                analysis.currmove = Some(info);
+            }
+        });
+        let result = self.result.clone();
+        let handle = std::thread::Builder::new()
+            .name("Engine".to_string())
+            .stack_size(5_000_000)
+            .spawn(move || {
+                let found = search(&mut solver);
+                *result.lock().unwrap() = Some((id, found));
+            });
+        match handle {
+            Ok(handle) => self.handle = Some(handle),
+            Err(e) => {
+                self.thinking = false;
+                let error = format!("Failed to start the engine: {e}");
+                self.log(&error);
+                self.error = Some(error);
+            }
+        }
+    }
+
+    /// Add a line to the console, and to the log if the engine is logged.
+    fn log(&self, text: impl std::fmt::Display) {
+        self.console.push(&text);
+        if let Some(log) = &self.log {
+            log.line(text);
+        }
+    }
+
+    /// Whether the search thread stopped without setting a result,
+    /// e.g. because it panicked.
+    fn crashed(&self) -> bool {
+        self.thinking
+            && self.result.lock().unwrap().is_none()
+            && self
+                .handle
+                .as_ref()
+                .is_some_and(|handle| handle.is_finished())
+    }
+}
+
+/// A spot on the board where a stone can be placed.
+#[derive(Component)]
+struct Spot {
+    stack_i: usize,
+    height: usize,
+}
+
+/// The spot under the pointer, if there is one.
+#[derive(Resource, Default)]
+struct HoveredSpot(Option<Entity>);
+
+/// The index of a stack, shown next to it on the board.
+#[derive(Component)]
+struct StackLabel {
+    stack_i: usize,
+}
+
+/// How the board is shown, to match the orientation of a physical board.
+/// Only the places of the stacks change, so clicks still go to the
+/// stack which is shown under the pointer.
+#[derive(Resource, Default, PartialEq, Eq)]
+struct Orientation {
+    /// The number of 45° steps the board is rotated clockwise.
+    rotation: usize,
+    /// Whether the stacks go around the other way.
+    mirrored: bool,
+}
+
+impl Orientation {
+    /// The place of a stack on the board, at the given distance from the center.
+    fn stack_place(&self, stack_i: usize, radius: f32) -> (f32, f32) {
+        let shown_i = match self.mirrored {
+            true => Variant::STANDARD.stacks() - stack_i,
+            false => stack_i,
+        };
+        let shown_i = (shown_i + self.rotation) % Variant::STANDARD.stacks();
+        // Opposite stacks are on opposite sides of the board.
+        let angle = shown_i as f32 * std::f32::consts::TAU / Variant::STANDARD.stacks() as f32;
+        (angle.cos() * radius, angle.sin() * radius)
+    }
+}
+
 fn setup_system(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    settings: Res<Settings>,
 ) {
     // Circular base
-    commands.spawn((PbrBundle {
-        mesh: meshes.add(shape::Circle::new(3.0).into()),
-        material: materials.add(Color::BEIGE.with_l(0.4).into()),
-        transform: Transform::from_rotation(Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2)),
-        ..default()
-    },));
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(shape::Circle::new(3.0).into()),
+            material: materials.add(settings.board_color().into()),
+            transform: Transform::from_rotation(Quat::from_rotation_x(
+                -std::f32::consts::FRAC_PI_2,
+            )),
+            ..default()
+        },
+        Board,
+    ));
 
     // Stones
-    for stack in 0..8 {
-        let angle = stack as f32 * std::f32::consts::FRAC_PI_4;
-        let x = angle.cos() * 2.0;
-        let y = angle.sin() * 2.0;
+    let stone_mesh = meshes.add(Mesh::from(shape::Cylinder {
+        height: CYLINDER_HEIGHT,
+        resolution: 64,
+        ..default()
+    }));
+    for stack_i in 0..Variant::STANDARD.stacks() {
+        let (x, y) = Orientation::default().stack_place(stack_i, STACK_RADIUS);
+        for height in 0..Variant::STANDARD.height() {
+            commands.spawn((
+                PbrBundle {
+                    mesh: stone_mesh.clone(),
+                    // Every spot gets its own material, so it can be colored separately.
+                    material: materials.add(Color::BLACK.with_a(0.0).into()),
+                    transform: Transform::from_xyz(x, CYLINDER_HEIGHT * (height as f32 + 0.5), y),
+                    ..default()
+                },
+                Spot { stack_i, height },
+                PickableBundle::default(),
+                On::<Pointer<Over>>::run(decrease_transparency),
+                On::<Pointer<Out>>::run(reset_transparency),
+                On::<Pointer<Click>>::run(click_spot),
+            ));
+        }
+        // The labels are placed by `update_stack_labels`.
         commands.spawn((
-            PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Cylinder {
-                    height: CYLINDER_HEIGHT,
-                    resolution: 64,
+            TextBundle::from_section(
+                stack_i.to_string(),
+                TextStyle {
+                    font_size: 24.0,
+                    color: Color::WHITE,
                     ..default()
-                })),
-                material: materials.add(Color::BLACK.with_a(0.2).into()),
-                transform: Transform::from_xyz(x, CYLINDER_HEIGHT / 2.0, y),
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
                 ..default()
-            },
-            PickableBundle::default(),
-            On::<Pointer<Over>>::run(decrease_transparency),
-            On::<Pointer<Out>>::run(reset_transparency),
-            On::<Pointer<Click>>::run(remove_transparency),
+            }),
+            StackLabel { stack_i },
         ));
     }
 
+    // The stone of the move which is animated.
+    commands.spawn((
+        PbrBundle {
+            mesh: stone_mesh,
+            material: materials.add(Color::WHITE.into()),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        animation::MovingStone,
+        // It shouldn't block the clicks on the spots behind it.
+        Pickable::IGNORE,
+    ));
+
     // light
     commands.spawn(PointLightBundle {
         point_light: PointLight {
@@ -81,7 +838,11 @@ fn setup_system(
     ));
 }
 
-fn camera_control_ui(mut camera_query: Query<&mut PanOrbitCamera>, mut ctx: EguiContexts) {
+fn camera_control_ui(
+    mut camera_query: Query<&mut PanOrbitCamera>,
+    mut orientation: ResMut<Orientation>,
+    mut ctx: EguiContexts,
+) {
     let mut cam = camera_query.single_mut();
     egui::Window::new("Camera Controls").show(ctx.ctx_mut(), |ui| {
         if ui.button("Reset camera").clicked() {
@@ -92,28 +853,752 @@ fn camera_control_ui(mut camera_query: Query<&mut PanOrbitCamera>, mut ctx: Egui
             cam.force_update = true;
         }
         ui.checkbox(&mut cam.enabled, "Camera controls");
+        ui.separator();
+        // The orientation is only changed when asked, so the stacks
+        // are not moved every frame.
+        ui.horizontal(|ui| {
+            if ui.button("Rotate left").clicked() {
+                orientation.rotation = (orientation.rotation + Variant::STANDARD.stacks() - 1)
+                    % Variant::STANDARD.stacks();
+            }
+            if ui.button("Rotate right").clicked() {
+                orientation.rotation = (orientation.rotation + 1) % Variant::STANDARD.stacks();
+            }
+        });
+        let mut mirrored = orientation.mirrored;
+        if ui.checkbox(&mut mirrored, "Mirror board").changed() {
+            orientation.mirrored = mirrored;
+        }
+        if *orientation != Orientation::default() && ui.button("Reset orientation").clicked() {
+            *orientation = Orientation::default();
+        }
     });
 }
 
-fn remove_transparency(
-    event: Listener<Pointer<Click>>,
+fn game_ui(
+    mut game: ResMut<Game>,
+    mut engine: ResMut<Engine>,
+    settings: Res<Settings>,
+    mut ctx: EguiContexts,
+) {
+    egui::Window::new("Game").show(ctx.ctx_mut(), |ui| {
+        if game.main_game.is_some() {
+            ui.strong("Scratch board: the moves played here don't change the game");
+        }
+        let pos = &game.position;
+        let status = pos.game_status();
+        if let Some(player) = game.flagged {
+            ui.label(format!("Game over, {player} ran out of time"));
+        } else if status != GameStatus::OnGoing {
+            ui.label(format!("Game over, {status}"));
+        } else {
+            ui.label(format!("{} to move", pos.current_player()));
+        }
+        if let Some(left) = clock::remaining(&game, &settings) {
+            clocks_ui(ui, &game, left);
+        }
+        let pos = &game.position;
+        if let Some(banned_move) = pos.board_state().banned_move {
+            ui.label(format!("Banned move: {banned_move}"));
+        }
+        if pos.has_alignment(false) {
+            ui.label(format!("{} has an alignment", pos.current_player().other()));
+        }
+        let shown = game.shown_position();
+        if !shown.is_second_phase() {
+            stones_in_hand_ui(ui, &shown, &settings);
+        }
+        ui.label(game.message.as_str());
+        if let Some(staged) = game.staged {
+            ui.horizontal(|ui| {
+                if ui.button(format!("Confirm {staged}")).clicked() {
+                    game.play(staged);
+                }
+                if ui.button("Cancel").clicked() {
+                    game.staged = None;
+                    game.message = format!("Cancelled {staged}");
+                }
+            });
+        }
+        if let Some(error) = engine.error.clone() {
+            ui.colored_label(settings.palette.error(), error);
+            if ui.button("Restart engine").clicked() {
+                let position = game.position.clone();
+                engine.start(&position);
+            }
+        }
+
+        ui.add_enabled_ui(!engine.thinking, |ui| {
+            let can_second_best = game.position.can_second_best();
+            if ui
+                .add_enabled(can_second_best, egui::Button::new("Second Best!"))
+                .clicked()
+            {
+                game.play(PlayerMove::SecondBest);
+            }
+            if ui
+                .add_enabled(can_second_best, egui::Button::new("Should I call it?"))
+                .on_hover_text("Compare calling \"Second Best!\" with allowing the last move")
+                .clicked()
+            {
+                engine.start_advice(&game.position);
+            }
+            if ui
+                .add_enabled(
+                    !game.position.game_over(),
+                    egui::Button::new("Second Best! map"),
+                )
+                .on_hover_text(
+                    "Show on the board which moves the opponent should call \"Second Best!\" on",
+                )
+                .clicked()
+            {
+                engine.start_second_best_map(&game.position);
+            }
+            if engine.second_best_map.is_some() && ui.button("Hide map").clicked() {
+                engine.second_best_map = None;
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Undo").clicked() {
+                    game.undo();
+                }
+                // The moves of the scratch board are not kept.
+                let can_redo = game.main_game.is_none() && game.variations.redo_move().is_some();
+                if ui
+                    .add_enabled(can_redo, egui::Button::new("Redo"))
+                    .clicked()
+                {
+                    game.redo();
+                }
+            });
+            if game.main_game.is_none() {
+                if ui.button("New game").clicked() {
+                    *game = Game::default();
+                }
+                if ui
+                    .button("Scratch board")
+                    .on_hover_text("Try and analyze moves without changing the game")
+                    .clicked()
+                {
+                    game.open_scratch_board();
+                }
+            } else if ui
+                .button("Back to the game")
+                .on_hover_text("Forget the moves of the scratch board")
+                .clicked()
+            {
+                game.close_scratch_board();
+            }
+            ui.add(egui::Slider::new(&mut engine.depth, 1..=20).text("Depth"));
+            if ui
+                .add_enabled(!game.position.game_over(), egui::Button::new("Engine move"))
+                .clicked()
+            {
+                engine.start(&game.position);
+            }
+            if ui
+                .add_enabled(!game.position.game_over(), egui::Button::new("Analyze"))
+                .on_hover_text("Search every move, without playing one")
+                .clicked()
+            {
+                engine.start_analysis(&game.position);
+            }
+        });
+        if engine.thinking {
+            ui.label("Engine is thinking...");
+            if ui.button("Stop").clicked() {
+                engine
+                    .abort
+                    .store(true, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+    });
+}
+
+/// Show the time left of both players, with the clock which runs in bold.
+fn clocks_ui(ui: &mut egui::Ui, game: &Game, left: [Duration; 2]) {
+    let to_move = game
+        .main_game
+        .as_ref()
+        .unwrap_or(&game.position)
+        .current_player();
+    ui.horizontal(|ui| {
+        for color in [position::Color::Black, position::Color::White] {
+            let text = format!(
+                "{color}: {}",
+                clock::format_clock(left[clock::index(color)])
+            );
+            match color == to_move {
+                true => ui.strong(text),
+                false => ui.label(text),
+            };
+        }
+    });
+}
+
+/// Show the stones each player still has to place, as a row of stones.
+fn stones_in_hand_ui(ui: &mut egui::Ui, position: &Position, settings: &Settings) {
+    const RADIUS: f32 = 6.0;
+    egui::Grid::new("stones in hand").show(ui, |ui| {
+        for color in [position::Color::Black, position::Color::White] {
+            let in_hand = position.stones_in_hand(color);
+            ui.label(format!("{color}: {in_hand}"));
+            let size = egui::vec2(
+                Variant::STANDARD.stones_per_player() as f32 * 2.5 * RADIUS,
+                2.0 * RADIUS,
+            );
+            let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+            let outline = ui.visuals().widgets.noninteractive.fg_stroke;
+            for i in 0..in_hand {
+                let center = rect.left_center() + egui::vec2((2.5 * i as f32 + 1.0) * RADIUS, 0.0);
+                ui.painter()
+                    .circle(center, RADIUS, settings.stone_color32(color), outline);
+            }
+            ui.end_row();
+        }
+    });
+}
+
+/// Play the move found by the engine once its search is done,
+/// or show its advice.
+fn engine_reply(mut game: ResMut<Game>, mut engine: ResMut<Engine>, settings: Res<Settings>) {
+    if !engine.thinking {
+        return;
+    }
+    if engine.crashed() {
+        engine.thinking = false;
+        let error = "The engine stopped unexpectedly".to_string();
+        engine.log(&error);
+        engine.error = Some(error);
+        return;
+    }
+    let Some((id, result)) = engine.result.lock().unwrap().take() else {
+        return;
+    };
+    if id != engine.search_id {
+        engine.log(format!("id {id} is out of date, ignored"));
+        return;
+    }
+    engine.thinking = false;
+    match result {
+        EngineResult::BestMove {
+            pmove: Some(pmove),
+            reply,
+        } => {
+            match reply {
+                Some(reply) => engine.log(format!("bestmove {pmove} ponder {reply}")),
+                None => engine.log(format!("bestmove {pmove}")),
+            }
+            game.play(pmove);
+            if let Some(reply) = reply.filter(|_| settings.ponder) {
+                let mut position = game.position.clone();
+                if position.try_make_move(reply).is_ok() && !position.game_over() {
+                    engine.start_ponder(&position);
+                }
+            }
+        }
+        EngineResult::BestMove { pmove: None, .. } => engine.log("bestmove none"),
+        EngineResult::Advice(Some(advice)) => {
+            engine.log(&advice);
+            game.message = advice.to_string();
+        }
+        EngineResult::Advice(None) => {
+            game.message = "\"Second Best!\" can't be called".to_string();
+            engine.log(&game.message);
+        }
+        EngineResult::SecondBestMap(map) => {
+            let refuted: Vec<String> = map
+                .iter()
+                .filter(|entry| entry.refuted())
+                .map(|entry| entry.pmove.to_string())
+                .collect();
+            game.message = match map.is_empty() {
+                true => "\"Second Best!\" can't be called on any move".to_string(),
+                false => format!(
+                    "\"Second Best!\" should be called on {} of {} moves: {}",
+                    refuted.len(),
+                    map.len(),
+                    refuted.join(", ")
+                ),
+            };
+            for entry in &map {
+                engine.log(entry);
+            }
+            if let Some((_, shown)) = &mut engine.second_best_map {
+                *shown = map;
+            }
+        }
+        EngineResult::Analyzed => engine.log("analysis done"),
+    }
+}
+
+/// Start or stop logging the engine when the setting is changed. Every
+/// session gets its own log file in [`LOG_DIR`].
+fn update_engine_log(settings: Res<Settings>, mut engine: ResMut<Engine>) {
+    if !settings.is_changed() || settings.engine_log == engine.log.is_some() {
+        return;
+    }
+    if !settings.engine_log {
+        engine.log = None;
+        return;
+    }
+    match Log::session(std::path::Path::new(LOG_DIR), "gui") {
+        Ok(log) => engine.log = Some(Arc::new(log)),
+        Err(e) => engine.error = Some(format!("Could not create the engine log: {e}")),
+    }
+}
+
+/// A column of the analysis table which the moves can be sorted by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum SortColumn {
+    Move,
+    /// The order of the search, from best to worst.
+    #[default]
+    Score,
+}
+
+/// How the rows of the analysis table are sorted.
+#[derive(Default)]
+struct TableSort {
+    column: SortColumn,
+    reversed: bool,
+}
+
+impl TableSort {
+    /// Sort by the clicked column, or reverse the order if it is already sorted by it.
+    fn click(&mut self, column: SortColumn) {
+        self.reversed = self.column == column && !self.reversed;
+        self.column = column;
+    }
+}
+
+/// A table with the moves of the analyzed position, their scores, and the
+/// start of their lines. Clicking a row shows its line on the board.
+fn analysis_ui(
+    mut game: ResMut<Game>,
+    engine: Res<Engine>,
+    mut sort: Local<TableSort>,
+    mut ctx: EguiContexts,
+) {
+    /// The number of moves of a line shown in the table.
+    const LINE_PREVIEW_MOVES: usize = 6;
+    let table = engine.analysis.lock().unwrap();
+    egui::Window::new("Analysis").show(ctx.ctx_mut(), |ui| {
+        if engine.thinking {
+            match &table.currmove {
+                Some(info) => {
+                    ui.label(format!(
+                        "Depth {}: searching {} ({}/{})",
+                        info.depth, info.currmove, info.currmovenumber, info.total
+                    ));
+                }
+                None if table.depth > 0 => {
+                    ui.label(format!("Depth {} done", table.depth));
+                }
+                None => (),
+            }
+        }
+        if table.depth > 0 {
+            ui.label(format!(
+                "Depth {}/{}, {} nodes, {} knps, table {}.{}% full, {} ms",
+                table.depth,
+                table.seldepth,
+                table.nodes,
+                table.knps,
+                table.ttfull / 10,
+                table.ttfull % 10,
+                table.time.as_millis()
+            ));
+        }
+        if table.root_moves.is_empty() {
+            ui.label("Use \"Analyze\" to see how good every move is.");
+            return;
+        }
+        if table.position.to_fen() != game.position.to_fen() {
+            ui.label("This is the analysis of an earlier position.");
+        }
+        let mut rows: Vec<&RootMoveLine> = table.root_moves.iter().collect();
+        if sort.column == SortColumn::Move {
+            rows.sort_by_key(|line| line.pmove.to_string());
+        }
+        if sort.reversed {
+            rows.reverse();
+        }
+        let ply = table.position.ply() as isize;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("root_moves").striped(true).show(ui, |ui| {
+                for (column, name) in [(SortColumn::Move, "Move"), (SortColumn::Score, "Score")] {
+                    let arrow = match (sort.column == column, sort.reversed) {
+                        (false, _) => "",
+                        (true, false) => " ⬇",
+                        (true, true) => " ⬆",
+                    };
+                    if ui.button(format!("{name}{arrow}")).clicked() {
+                        sort.click(column);
+                    }
+                }
+                ui.label("Depth");
+                ui.label("Line");
+                ui.end_row();
+                for line in rows {
+                    let (score, depth) = match line.score {
+                        Some(score) => {
+                            (analysis::explain_score(score, ply), table.depth.to_string())
+                        }
+                        // Only known to be worse than the best move.
+                        None => ("worse".to_string(), "-".to_string()),
+                    };
+                    let mut pv =
+                        PlayerMove::to_long_form(&line.pv[..line.pv.len().min(LINE_PREVIEW_MOVES)]);
+                    if line.pv.len() > LINE_PREVIEW_MOVES {
+                        pv.push_str(" ...");
+                    }
+                    let selected = game
+                        .preview
+                        .as_ref()
+                        .is_some_and(|preview| preview.line.first() == Some(&line.pmove));
+                    let move_clicked = ui
+                        .selectable_label(selected, line.pmove.to_string())
+                        .clicked();
+                    ui.label(score);
+                    ui.label(depth);
+                    let line_clicked = ui.selectable_label(selected, pv).clicked();
+                    if move_clicked || line_clicked {
+                        game.selected = None;
+                        game.preview = Some(Preview::new(&table.position, &line.pv));
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+        // Only change the game when a button is clicked, so that
+        // the stones are only updated when something changed.
+        let Some(preview) = &game.preview else {
+            return;
+        };
+        ui.separator();
+        ui.label(format!(
+            "Showing {}",
+            PlayerMove::to_long_form(&preview.line[..preview.shown])
+        ));
+        let (mut previous, mut next, mut back) = (false, false, false);
+        ui.horizontal(|ui| {
+            previous = ui
+                .add_enabled(preview.shown > 0, egui::Button::new("<"))
+                .clicked();
+            next = ui
+                .add_enabled(preview.shown < preview.line.len(), egui::Button::new(">"))
+                .clicked();
+            back = ui.button("Back to the game").clicked();
+        });
+        if back {
+            game.preview = None;
+        } else if previous || next {
+            if let Some(preview) = &mut game.preview {
+                match previous {
+                    true => preview.shown -= 1,
+                    false => preview.shown += 1,
+                }
+            }
+        }
+    });
+}
+
+/// Stop the search when the app is closed, so that it can exit right away.
+fn stop_engine_on_exit(mut exit: EventReader<AppExit>, engine: Res<Engine>) {
+    if exit.read().next().is_some() {
+        engine
+            .abort
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+/// Color the stones according to the current position.
+fn update_stones(
+    game: Res<Game>,
+    settings: Res<Settings>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    stones: Query<&Handle<StandardMaterial>>,
+    spots: Query<(&Spot, &Handle<StandardMaterial>)>,
+) {
+    if !game.is_changed() && !settings.is_changed() {
+        return;
+    }
+    let position = game.shown_position();
+    for (spot, handle) in spots.iter() {
+        let material = materials.get_mut(handle).unwrap();
+        material.base_color = match position.stone_at(spot.stack_i, spot.height) {
+            Some(color) => settings.stone_color(color),
+            None => Color::BLACK.with_a(0.0),
+        };
+        let selected = game.selected == Some(spot.stack_i)
+            && game
+                .position
+                .stone_at(spot.stack_i, spot.height + 1)
+                .is_none();
+        material.emissive = match selected {
+            true => settings.palette.selected(),
+            false => Color::BLACK,
+        };
+    }
+}
+
+/// Move the stacks to their places for the current orientation.
+fn update_orientation(orientation: Res<Orientation>, mut spots: Query<(&Spot, &mut Transform)>) {
+    if !orientation.is_changed() {
+        return;
+    }
+    for (spot, mut transform) in spots.iter_mut() {
+        let (x, y) = orientation.stack_place(spot.stack_i, STACK_RADIUS);
+        transform.translation.x = x;
+        transform.translation.z = y;
+    }
+}
+
+/// Keep the stack labels next to their stacks on the screen, which
+/// changes when the camera moves or the board is rotated.
+fn update_stack_labels(
+    orientation: Res<Orientation>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    mut labels: Query<(&StackLabel, &Node, &mut Style, &mut Visibility)>,
+) {
+    let (camera, camera_transform) = camera_query.single();
+    for (label, node, mut style, mut visibility) in labels.iter_mut() {
+        let (x, y) = orientation.stack_place(label.stack_i, LABEL_RADIUS);
+        match camera.world_to_viewport(camera_transform, Vec3::new(x, 0.0, y)) {
+            Some(place) => {
+                // Center the label on its place.
+                let size = node.size();
+                style.left = Val::Px(place.x - size.x / 2.0);
+                style.top = Val::Px(place.y - size.y / 2.0);
+                visibility.set_if_neq(Visibility::Inherited);
+            }
+            None => {
+                visibility.set_if_neq(Visibility::Hidden);
+            }
+        }
+    }
+}
+
+/// Draw the move which is banned by a "Second Best!" call on the board:
+/// a cross on the spot it would go to, with an arrow from the stone it
+/// would move in the second phase.
+fn draw_banned_move(
+    game: Res<Game>,
+    orientation: Res<Orientation>,
+    settings: Res<Settings>,
+    mut gizmos: Gizmos,
+) {
+    let position = game.shown_position();
+    let Some(PlayerMove::StoneMove { from, to }) = position.board_state().banned_move else {
+        return;
+    };
+    let color = settings.palette.banned();
+    let spot = |stack_i: usize, height: usize| {
+        let (x, z) = orientation.stack_place(stack_i, STACK_RADIUS);
+        Vec3::new(x, CYLINDER_HEIGHT * (height as f32 + 0.5), z)
+    };
+    let target = spot(to, position.stack_height(to));
+    for diagonal in [Vec3::new(0.3, 0.0, 0.3), Vec3::new(0.3, 0.0, -0.3)] {
+        gizmos.line(target - diagonal, target + diagonal, color);
+    }
+    let Some(from) = from else {
+        return;
+    };
+    let start = spot(from, position.stack_height(from).saturating_sub(1));
+    let direction = (target - start).normalize();
+    let side = direction.cross(Vec3::Y).normalize();
+    gizmos.line(start, target, color);
+    for head_side in [side, -side] {
+        gizmos.line(target, target - 0.3 * direction + 0.15 * head_side, color);
+    }
+}
+
+/// Draw the last "Second Best!" map on the board while it is of the shown
+/// position: a circle on the spot every move goes to, from the color of
+/// the banned move if the opponent should call "Second Best!" on it, to
+/// the color of the moves which should be allowed. Stone moves of the
+/// second phase get a line from the stone they move.
+fn draw_second_best_map(
+    game: Res<Game>,
+    engine: Res<Engine>,
+    orientation: Res<Orientation>,
+    settings: Res<Settings>,
+    mut gizmos: Gizmos,
+) {
+    /// The difference between calling and allowing which gets the full color.
+    const FULL_DIFFERENCE: isize = 5;
+    let Some((position, map)) = &engine.second_best_map else {
+        return;
+    };
+    if position.to_fen() != game.shown_position().to_fen() {
+        return;
+    }
+    let [called, allowed] = [settings.palette.banned(), settings.palette.allowed()]
+        .map(|color| Vec4::from(color.as_rgba_f32()));
+    let spot = |stack_i: usize, height: usize| {
+        let (x, z) = orientation.stack_place(stack_i, STACK_RADIUS);
+        Vec3::new(x, CYLINDER_HEIGHT * (height as f32 + 0.5), z)
+    };
+    for entry in map {
+        let PlayerMove::StoneMove { from, to } = entry.pmove else {
+            continue;
+        };
+        // How much the opponent prefers the call, from 0 to 1.
+        let heat = entry.advice.difference().map_or(1.0, |difference| {
+            let difference = difference.clamp(-FULL_DIFFERENCE, FULL_DIFFERENCE);
+            (difference + FULL_DIFFERENCE) as f32 / (2 * FULL_DIFFERENCE) as f32
+        });
+        let color = Color::from(allowed.lerp(called, heat));
+        let target = spot(to, position.stack_height(to));
+        gizmos.circle(target, Vec3::Y, 0.3, color);
+        if let Some(from) = from {
+            let start = spot(from, position.stack_height(from).saturating_sub(1));
+            gizmos.line(start, target, color);
+        }
+    }
+}
+
+/// Draw a ring on the spots the stone of the selected stack can move to, or
+/// else of the stack under the pointer if its top stone is of the player to
+/// move. Only the moves of the game are shown, not a line from the analysis.
+fn draw_destinations(
+    game: Res<Game>,
+    hovered: Res<HoveredSpot>,
+    spots: Query<&Spot>,
+    orientation: Res<Orientation>,
+    settings: Res<Settings>,
+    mut gizmos: Gizmos,
 ) {
-    let material = materials
-        .get_mut(stones.get(event.target).unwrap())
-        .unwrap();
-    material.base_color = material.base_color.with_a(1.0);
+    let position = &game.position;
+    if !position.is_second_phase() || position.game_over() {
+        return;
+    }
+    if game.preview.is_some() || game.staged.is_some() {
+        return;
+    }
+    let hovered = hovered
+        .0
+        .and_then(|entity| spots.get(entity).ok())
+        .map(|spot| spot.stack_i)
+        .filter(|&stack_i| game.top_stone(stack_i) == Some(position.current_player()));
+    let Some(stack_i) = game.selected.or(hovered) else {
+        return;
+    };
+    let color = settings.palette.destination();
+    // The banned move is not one of the legal moves.
+    for pmove in position.legal_moves() {
+        let PlayerMove::StoneMove { from, to } = pmove else {
+            continue;
+        };
+        if from != Some(stack_i) {
+            continue;
+        }
+        let (x, z) = orientation.stack_place(to, STACK_RADIUS);
+        let height = position.stack_height(to) as f32 + 0.5;
+        gizmos.circle(
+            Vec3::new(x, CYLINDER_HEIGHT * height, z),
+            Vec3::Y,
+            0.3,
+            color,
+        );
+    }
+}
+
+/// Draw the first moves of the best line of the analysis on the board while
+/// it is of the shown position, so they don't have to be looked up by their
+/// stack numbers: an arrow from the stone which is moved to the spot it goes
+/// to, or a ring on the spot of a stone placed in the first phase. Later
+/// moves are drawn smaller and fainter.
+fn draw_best_line(
+    game: Res<Game>,
+    engine: Res<Engine>,
+    orientation: Res<Orientation>,
+    settings: Res<Settings>,
+    mut gizmos: Gizmos,
+) {
+    /// The number of moves of the line which are drawn.
+    const ARROW_MOVES: usize = 3;
+    if !settings.best_line_arrows {
+        return;
+    }
+    let table = engine.analysis.lock().unwrap();
+    let Some(best) = table.root_moves.first() else {
+        return;
+    };
+    if table.position.to_fen() != game.shown_position().to_fen() {
+        return;
+    }
+    let spot = |stack_i: usize, height: usize| {
+        let (x, z) = orientation.stack_place(stack_i, STACK_RADIUS);
+        Vec3::new(x, CYLINDER_HEIGHT * (height as f32 + 0.5), z)
+    };
+    let mut position = table.position.clone();
+    for (move_i, &pmove) in best.pv.iter().take(ARROW_MOVES).enumerate() {
+        let scale = 1.0 - 0.25 * move_i as f32;
+        let color = settings.palette.best_line().with_a(scale);
+        if let PlayerMove::StoneMove { from, to } = pmove {
+            let target = spot(to, position.stack_height(to));
+            match from {
+                Some(from) => {
+                    let start = spot(from, position.stack_height(from).saturating_sub(1));
+                    let direction = (target - start).normalize();
+                    let side = direction.cross(Vec3::Y).normalize();
+                    gizmos.line(start, target, color);
+                    for head_side in [side, -side] {
+                        let head = 0.3 * scale * direction - 0.15 * scale * head_side;
+                        gizmos.line(target, target - head, color);
+                    }
+                }
+                None => {
+                    gizmos.circle(target, Vec3::Y, 0.25 * scale, color);
+                }
+            }
+        }
+        // A line from the transposition table can end in an illegal move.
+        if position.try_make_move(pmove).is_err() {
+            break;
+        }
+    }
+}
+
+/// Play a move on the clicked stack. In the second phase the first click
+/// selects the stack to move from, and the second click the stack to move to.
+fn click_spot(
+    event: Listener<Pointer<Click>>,
+    spots: Query<&Spot>,
+    mut game: ResMut<Game>,
+    engine: Res<Engine>,
+    settings: Res<Settings>,
+) {
+    if game.preview.is_some() {
+        // Go back to the game first.
+        game.preview = None;
+        return;
+    }
+    if let Some(staged) = game.staged.take() {
+        // A click on the board cancels the move waiting for confirmation.
+        game.message = format!("Cancelled {staged}");
+        return;
+    }
+    if engine.thinking || game.position.game_over() {
+        return;
+    }
+    let stack_i = spots.get(event.target).unwrap().stack_i;
+    game.choose_stack(stack_i, settings.confirm_moves);
 }
 
 fn reset_transparency(
     event: Listener<Pointer<Out>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     stones: Query<&Handle<StandardMaterial>>,
+    mut hovered: ResMut<HoveredSpot>,
 ) {
-    let material = materials
-        .get_mut(stones.get(event.target).unwrap())
-        .unwrap();
+    // The pointer can already be over the next spot.
+    if hovered.0 == Some(event.target) {
+        hovered.0 = None;
+    }
+    let handle = stones.get(event.target).unwrap();
+    let material = materials.get_mut(handle).unwrap();
     if material.base_color.a() >= 1.0 {
         return;
     }
@@ -124,10 +1609,11 @@ fn decrease_transparency(
     event: Listener<Pointer<Over>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     stones: Query<&Handle<StandardMaterial>>,
+    mut hovered: ResMut<HoveredSpot>,
 ) {
-    let material = materials
-        .get_mut(stones.get(event.target).unwrap())
-        .unwrap();
+    hovered.0 = Some(event.target);
+    let handle = stones.get(event.target).unwrap();
+    let material = materials.get_mut(handle).unwrap();
     if material.base_color.a() >= 1.0 {
         return;
     }
@@ -0,0 +1,189 @@
+//! The moves of the game with the time spent on each of them, which can
+//! be saved as a game record (see `load-game` in the CLI). Clicking a move
+//! goes to it, and the other lines which were played from there can be
+//! picked at the moves with variations.
+//!
+//! The moves can also be copied, and a game can be loaded from pasted moves
+//! or a game record, or from a file.
+
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use second_best::gamefile::{self, GameRecord};
+use second_best::position::Color;
+
+use crate::{Engine, Game};
+
+/// The directory the games are saved in.
+const GAMES_DIR: &str = "second-best-games";
+
+/// The record of the game, without the moves of the scratch board.
+pub fn game_record(game: &Game) -> GameRecord {
+    let position = game.main_game.as_ref().unwrap_or(&game.position);
+    let mut record = GameRecord::from_position(position);
+    record.times = game.times.iter().copied().enumerate().collect();
+    if let Some(player) = game.flagged {
+        record.winner = Some(player.other());
+    }
+    record
+}
+
+/// The text fields of the window.
+#[derive(Default)]
+pub struct Import {
+    /// Pasted moves, or a pasted game record.
+    pasted: String,
+    /// The file to save the game to or load it from.
+    path: String,
+}
+
+/// Load a game from its record, or only the moves, as in `3 5! 6`.
+fn load(game: &mut Game, text: &str, from: &str) {
+    game.message = match GameRecord::parse(text) {
+        Ok(record) if record.moves.is_empty() => format!("There are no moves in {from}"),
+        Ok(record) => match game.load_game(&record) {
+            Ok(()) => format!("Loaded {} moves from {from}", record.moves.len()),
+            Err(e) => format!("Could not load {from}: {e}"),
+        },
+        Err(e) => format!("Could not load {from}: {e}"),
+    };
+}
+
+pub fn moves_ui(
+    mut game: ResMut<Game>,
+    engine: Res<Engine>,
+    mut import: Local<Import>,
+    mut ctx: EguiContexts,
+) {
+    egui::Window::new("Moves")
+        .default_open(false)
+        .show(ctx.ctx_mut(), |ui| {
+            let record = game_record(&game);
+            let [black, white] = record.time_used();
+            ui.label(format!(
+                "Time used: {} {}, {} {}",
+                Color::Black,
+                gamefile::format_time(black),
+                Color::White,
+                gamefile::format_time(white)
+            ));
+            let variations = &game.variations;
+            let current = variations.current();
+            let mut go_to = None;
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .show(ui, |ui| {
+                    // The lines are only kept for the game, not the scratch board.
+                    ui.set_enabled(game.main_game.is_none());
+                    egui::Grid::new("moves").striped(true).show(ui, |ui| {
+                        if ui.selectable_label(current == 0, "Start").clicked() {
+                            go_to = Some(0);
+                        }
+                        ui.end_row();
+                        let mut redo = false;
+                        for (move_i, node) in variations.shown_line().into_iter().enumerate() {
+                            let text =
+                                format!("{}. {}", move_i + 1, variations.pmove(node).unwrap());
+                            // The moves after the one on the board can be redone.
+                            let text = match redo {
+                                true => egui::RichText::new(text).weak(),
+                                false => egui::RichText::new(text),
+                            };
+                            if ui.selectable_label(node == current, text).clicked() {
+                                go_to = Some(node);
+                            }
+                            redo |= node == current;
+                            ui.label(gamefile::format_time(variations.time(node)));
+                            let alternatives = variations.alternatives(node);
+                            if alternatives.len() > 1 {
+                                ui.menu_button(format!("{} lines", alternatives.len()), |ui| {
+                                    for &other in alternatives {
+                                        let pmove = variations.pmove(other).unwrap();
+                                        if ui
+                                            .selectable_label(other == node, pmove.to_string())
+                                            .clicked()
+                                        {
+                                            go_to = Some(other);
+                                            ui.close_menu();
+                                        }
+                                    }
+                                });
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+            if let Some(node) = go_to {
+                game.go_to_variation(node);
+            }
+            if ui
+                .add_enabled(!record.moves.is_empty(), egui::Button::new("Save game"))
+                .on_hover_text(format!(
+                    "Save the moves and their times to a new file in `{GAMES_DIR}`"
+                ))
+                .clicked()
+            {
+                game.message = match record.save_new(Path::new(GAMES_DIR)) {
+                    Ok(path) => format!("Saved the game to {}", path.display()),
+                    Err(e) => format!("Could not save the game: {e}"),
+                };
+            }
+            if ui
+                .button("Copy moves")
+                .on_hover_text("Copy the moves on the board, e.g. to paste them in the CLI")
+                .clicked()
+            {
+                let moves = game.position.clone().serialize();
+                ui.output_mut(|output| output.copied_text = moves);
+                game.message = "Copied the moves".to_string();
+            }
+            // A loaded game would get the move of a search which is still running.
+            ui.add_enabled_ui(!engine.thinking, |ui| {
+                ui.separator();
+                ui.add(
+                    egui::TextEdit::multiline(&mut import.pasted)
+                        .hint_text("Paste moves or a game record")
+                        .desired_rows(2),
+                );
+                if ui
+                    .add_enabled(
+                        !import.pasted.trim().is_empty(),
+                        egui::Button::new("Load moves"),
+                    )
+                    .clicked()
+                {
+                    load(&mut game, &import.pasted, "the pasted text");
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut import.path);
+                });
+                let path = import.path.trim();
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(
+                            !path.is_empty() && !record.moves.is_empty(),
+                            egui::Button::new("Save"),
+                        )
+                        .clicked()
+                    {
+                        game.message = match record.save(path) {
+                            Ok(()) => format!("Saved the game to {path}"),
+                            Err(e) => format!("Could not save the game: {e}"),
+                        };
+                    }
+                    if ui
+                        .add_enabled(!path.is_empty(), egui::Button::new("Load"))
+                        .clicked()
+                    {
+                        match std::fs::read_to_string(path) {
+                            Ok(text) => load(&mut game, &text, path),
+                            Err(e) => game.message = format!("Could not load {path}: {e}"),
+                        }
+                    }
+                });
+            });
+        });
+}
@@ -0,0 +1,164 @@
+//! Saving the game and the engine depth regularly and on exit, so that they
+//! can be restored when the GUI is started again, e.g. after a crash.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use second_best::gamefile::GameRecord;
+use second_best::position::{PlayerMove, Position};
+use serde::{Deserialize, Serialize};
+
+use crate::moves::game_record;
+use crate::{Engine, Game};
+
+/// The file the session is saved in, in the working directory.
+const SESSION_FILE: &str = "second-best-session.json";
+/// How often the session is saved.
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What is saved of a session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Session {
+    /// The game record of the game, with the times of the moves.
+    game: String,
+    /// The moves from the start to the position of the scratch board, if it is open.
+    scratch: Option<String>,
+    /// The depth of the engine.
+    depth: usize,
+}
+
+impl Session {
+    fn new(game: &Game, engine: &Engine) -> Self {
+        Self {
+            game: game_record(game).to_string(),
+            scratch: game
+                .main_game
+                .as_ref()
+                .map(|_| PlayerMove::to_long_form(&game.position.history())),
+            depth: engine.depth,
+        }
+    }
+
+    fn load(path: &Path) -> Option<Self> {
+        let json = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&json).ok()
+    }
+
+    /// Write to a temporary file first, so that a crash while saving
+    /// doesn't leave half a session behind.
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::from)?;
+        let temporary = path.with_extension("json.tmp");
+        std::fs::write(&temporary, json)?;
+        std::fs::rename(temporary, path)
+    }
+
+    /// Put the saved game and depth back.
+    fn restore(&self, game: &mut Game, engine: &mut Engine) -> Result<(), String> {
+        let record = GameRecord::parse(&self.game).map_err(|e| e.to_string())?;
+        let scratch = match &self.scratch {
+            Some(moves) => {
+                let mut scratch = Position::default();
+                let moves = moves.split_whitespace().map(String::from).collect();
+                scratch
+                    .parse_and_play_moves(moves)
+                    .map_err(|e| format!("{e:?}"))?;
+                Some(scratch)
+            }
+            None => None,
+        };
+        game.load_game(&record)?;
+        if let Some(scratch) = scratch {
+            game.main_game = Some(std::mem::replace(&mut game.position, scratch));
+        }
+        game.message = "Restored the last session".to_string();
+        engine.depth = self.depth;
+        Ok(())
+    }
+}
+
+#[derive(Resource)]
+pub struct Autosave {
+    /// The session of the last run, until it is restored or discarded.
+    /// Nothing is saved until then, so that it isn't overwritten.
+    last_session: Option<Session>,
+    /// What was saved last, to only save when something changed.
+    saved: Option<Session>,
+    last_save: Instant,
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        // Only offer to restore a session with moves.
+        let last_session = Session::load(Path::new(SESSION_FILE)).filter(|session| {
+            GameRecord::parse(&session.game).is_ok_and(|record| !record.moves.is_empty())
+        });
+        Self {
+            last_session,
+            saved: None,
+            last_save: Instant::now(),
+        }
+    }
+}
+
+impl Autosave {
+    fn save(&mut self, session: Session) {
+        self.last_save = Instant::now();
+        if self.saved.as_ref() == Some(&session) {
+            return;
+        }
+        match session.save(Path::new(SESSION_FILE)) {
+            Ok(()) => self.saved = Some(session),
+            Err(e) => eprintln!("Could not save the session: {e}"),
+        }
+    }
+}
+
+/// Offer to restore the last session, and save the session every
+/// [`SAVE_INTERVAL`] once that is decided.
+pub fn autosave_ui(
+    mut autosave: ResMut<Autosave>,
+    mut game: ResMut<Game>,
+    mut engine: ResMut<Engine>,
+    mut ctx: EguiContexts,
+) {
+    let Some(last_session) = autosave.last_session.clone() else {
+        if autosave.last_save.elapsed() >= SAVE_INTERVAL {
+            autosave.save(Session::new(&game, &engine));
+        }
+        return;
+    };
+    let (mut restore, mut discard) = (false, false);
+    egui::Window::new("Restore session").show(ctx.ctx_mut(), |ui| {
+        ui.label("The game of the last session was saved. Do you want to continue it?");
+        ui.horizontal(|ui| {
+            restore = ui.button("Restore").clicked();
+            discard = ui.button("Discard").clicked();
+        });
+    });
+    if restore {
+        if let Err(e) = last_session.restore(&mut game, &mut engine) {
+            game.message = format!("Could not restore the last session: {e}");
+        }
+    }
+    if restore || discard {
+        autosave.last_session = None;
+        autosave.save(Session::new(&game, &engine));
+    }
+}
+
+/// Save the session when the app is closed, unless the last
+/// session wasn't restored or discarded yet.
+pub fn save_session_on_exit(
+    mut exit: EventReader<AppExit>,
+    mut autosave: ResMut<Autosave>,
+    game: Res<Game>,
+    engine: Res<Engine>,
+) {
+    if exit.read().next().is_some() && autosave.last_session.is_none() {
+        autosave.save(Session::new(&game, &engine));
+    }
+}
@@ -0,0 +1,295 @@
+//! The look of the board and the interface, and how moves are made, which
+//! can be changed in the settings window and is kept in a file for the next time.
+
+use std::path::Path;
+use std::time::Duration;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts, EguiSettings};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::TimeControl;
+
+/// The file the settings are kept in, in the working directory.
+const SETTINGS_FILE: &str = "second-best-gui.json";
+
+/// The colors used to highlight things.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Palette {
+    /// Orange and red.
+    #[default]
+    Default,
+    /// Blue and orange, which can be told apart with every
+    /// common kind of colorblindness.
+    ColorblindSafe,
+    /// Yellow, which stands out against both black and white stones.
+    HighContrast,
+}
+
+impl Palette {
+    const ALL: [Self; 3] = [Self::Default, Self::ColorblindSafe, Self::HighContrast];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Default => "Default",
+            Self::ColorblindSafe => "Colorblind safe",
+            Self::HighContrast => "High contrast",
+        }
+    }
+
+    /// The glow of the selected stone.
+    pub fn selected(self) -> Color {
+        match self {
+            Self::Default => Color::ORANGE_RED,
+            Self::ColorblindSafe => Color::rgb_u8(0, 114, 178),
+            Self::HighContrast => Color::YELLOW,
+        }
+    }
+
+    /// The color of the banned move on the board.
+    pub fn banned(self) -> Color {
+        match self {
+            Self::Default => Color::RED,
+            Self::ColorblindSafe => Color::rgb_u8(230, 159, 0),
+            Self::HighContrast => Color::FUCHSIA,
+        }
+    }
+
+    /// The color of the moves on which "Second Best!" shouldn't be called,
+    /// in the "Second Best!" map.
+    pub fn allowed(self) -> Color {
+        match self {
+            Self::Default => Color::LIME_GREEN,
+            Self::ColorblindSafe => Color::rgb_u8(86, 180, 233),
+            Self::HighContrast => Color::CYAN,
+        }
+    }
+
+    /// The color of the best line of the analysis on the board.
+    pub fn best_line(self) -> Color {
+        match self {
+            Self::Default => Color::rgb(0.25, 0.55, 1.0),
+            Self::ColorblindSafe => Color::rgb_u8(0, 158, 115),
+            Self::HighContrast => Color::GREEN,
+        }
+    }
+
+    /// The rings on the spots the selected stone can move to.
+    pub fn destination(self) -> Color {
+        match self {
+            Self::Default => Color::CYAN,
+            Self::ColorblindSafe => Color::rgb_u8(204, 121, 167),
+            Self::HighContrast => Color::WHITE,
+        }
+    }
+
+    /// The color of error messages.
+    pub fn error(self) -> egui::Color32 {
+        match self {
+            Self::Default => egui::Color32::RED,
+            Self::ColorblindSafe => egui::Color32::from_rgb(230, 159, 0),
+            Self::HighContrast => egui::Color32::YELLOW,
+        }
+    }
+}
+
+/// The settings, see the [module documentation](self).
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// The colors are in sRGB.
+    pub board_color: [f32; 3],
+    pub black_stone_color: [f32; 3],
+    pub white_stone_color: [f32; 3],
+    pub palette: Palette,
+    /// How much larger the interface is drawn.
+    pub ui_scale: f32,
+    /// Play a sound when a stone is placed, "Second Best!" is called,
+    /// or the game is over.
+    pub sounds: bool,
+    /// Moves made on the board have to be confirmed before they are played.
+    pub confirm_moves: bool,
+    /// Write the searches of the engine, and what they found, to a new
+    /// log file for every session.
+    pub engine_log: bool,
+    /// After a move of the engine, search the position after the reply it
+    /// expects while the player thinks, so it answers sooner if the reply
+    /// is played.
+    pub ponder: bool,
+    /// Draw the start of the best line of the analysis on the board.
+    pub best_line_arrows: bool,
+    /// Animate the moves on the board, see [`crate::animation`].
+    pub animations: bool,
+    /// Play the games with clocks, see [`crate::clock`].
+    pub time_control: Option<TimeControl>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let [r, g, b, _] = Color::BEIGE.with_l(0.4).as_rgba_f32();
+        Self {
+            board_color: [r, g, b],
+            black_stone_color: [0.0, 0.0, 0.0],
+            white_stone_color: [1.0, 1.0, 1.0],
+            palette: Palette::default(),
+            ui_scale: 1.0,
+            sounds: false,
+            confirm_moves: false,
+            engine_log: true,
+            ponder: false,
+            best_line_arrows: true,
+            animations: true,
+            time_control: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Load the settings from the settings file, or use the
+    /// default settings if there is no valid settings file.
+    pub fn load() -> Self {
+        std::fs::read_to_string(SETTINGS_FILE)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::from)?;
+        std::fs::write(path, json)
+    }
+
+    pub fn board_color(&self) -> Color {
+        let [r, g, b] = self.board_color;
+        Color::rgb(r, g, b)
+    }
+
+    pub fn stone_color(&self, color: second_best::position::Color) -> Color {
+        let [r, g, b] = match color {
+            second_best::position::Color::Black => self.black_stone_color,
+            second_best::position::Color::White => self.white_stone_color,
+        };
+        Color::rgb(r, g, b)
+    }
+
+    /// The color of the stones, for drawing them in the interface.
+    pub fn stone_color32(&self, color: second_best::position::Color) -> egui::Color32 {
+        let [r, g, b, _] = self.stone_color(color).as_rgba_u8();
+        egui::Color32::from_rgb(r, g, b)
+    }
+}
+
+/// The circular base of the board.
+#[derive(Component)]
+pub struct Board;
+
+pub fn settings_ui(mut settings: ResMut<Settings>, mut ctx: EguiContexts) {
+    // Edit a copy, so the settings are only changed when something is changed.
+    let mut edited = settings.clone();
+    egui::Window::new("Settings")
+        .default_open(false)
+        .show(ctx.ctx_mut(), |ui| {
+            egui::Grid::new("settings").show(ui, |ui| {
+                ui.label("Board");
+                ui.color_edit_button_rgb(&mut edited.board_color);
+                ui.end_row();
+                ui.label("Black stones");
+                ui.color_edit_button_rgb(&mut edited.black_stone_color);
+                ui.end_row();
+                ui.label("White stones");
+                ui.color_edit_button_rgb(&mut edited.white_stone_color);
+                ui.end_row();
+                ui.label("Highlights");
+                egui::ComboBox::from_id_source("palette")
+                    .selected_text(edited.palette.name())
+                    .show_ui(ui, |ui| {
+                        for palette in Palette::ALL {
+                            ui.selectable_value(&mut edited.palette, palette, palette.name());
+                        }
+                    });
+                ui.end_row();
+                ui.label("Interface scale");
+                ui.add(egui::Slider::new(&mut edited.ui_scale, 0.5..=3.0));
+                ui.end_row();
+            });
+            ui.checkbox(&mut edited.sounds, "Sounds");
+            ui.checkbox(&mut edited.confirm_moves, "Confirm moves")
+                .on_hover_text("Moves made on the board are only played when they are confirmed");
+            ui.checkbox(&mut edited.engine_log, "Log the engine").on_hover_text(
+                "Write what the engine searches and finds to a new file in `second-best-logs` for every session",
+            );
+            ui.checkbox(&mut edited.ponder, "Ponder").on_hover_text(
+                "Let the engine think about the reply it expects while it's your move",
+            );
+            ui.checkbox(&mut edited.best_line_arrows, "Best line on the board")
+                .on_hover_text("Draw the first moves of the best line of the analysis as arrows");
+            ui.checkbox(&mut edited.animations, "Animate moves").on_hover_text(
+                "Slide the stones of the moves, and take the move back when \"Second Best!\" is called",
+            );
+            let mut clock = edited.time_control.is_some();
+            ui.checkbox(&mut clock, "Clock").on_hover_text(
+                "Give both players a base time and an increment per move; a player whose time runs out loses",
+            );
+            edited.time_control = clock.then(|| edited.time_control.unwrap_or_default());
+            if let Some(time_control) = &mut edited.time_control {
+                ui.horizontal(|ui| {
+                    let mut minutes = time_control.base.as_secs_f64() / 60.0;
+                    ui.label("Base time");
+                    if ui
+                        .add(
+                            egui::DragValue::new(&mut minutes)
+                                .clamp_range(0.5..=180.0)
+                                .speed(0.5)
+                                .suffix(" min"),
+                        )
+                        .changed()
+                    {
+                        time_control.base = Duration::from_secs_f64(minutes * 60.0);
+                    }
+                    let mut seconds = time_control.increment.as_secs();
+                    ui.label("Increment");
+                    if ui
+                        .add(
+                            egui::DragValue::new(&mut seconds)
+                                .clamp_range(0..=60)
+                                .suffix(" s"),
+                        )
+                        .changed()
+                    {
+                        time_control.increment = Duration::from_secs(seconds);
+                    }
+                });
+            }
+            if ui.button("Reset settings").clicked() {
+                edited = Settings::default();
+            }
+        });
+    if edited != *settings {
+        *settings = edited;
+    }
+}
+
+/// Apply the settings which are not used by other systems.
+pub fn apply_settings(
+    settings: Res<Settings>,
+    mut egui_settings: ResMut<EguiSettings>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    board: Query<&Handle<StandardMaterial>, With<Board>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    egui_settings.scale_factor = settings.ui_scale as f64;
+    for handle in board.iter() {
+        materials.get_mut(handle).unwrap().base_color = settings.board_color();
+    }
+}
+
+pub fn save_settings_on_exit(mut exit: EventReader<AppExit>, settings: Res<Settings>) {
+    if exit.read().next().is_some() {
+        if let Err(e) = settings.save(Path::new(SETTINGS_FILE)) {
+            eprintln!("Could not save the settings: {e}");
+        }
+    }
+}
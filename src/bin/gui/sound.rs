@@ -0,0 +1,52 @@
+//! Short tones for what happens in the game, so the board doesn't
+//! have to be watched to notice the engine's moves.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::settings::Settings;
+use crate::Game;
+
+/// Something in the game which makes a sound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sound {
+    StonePlaced,
+    SecondBest,
+    GameOver,
+}
+
+impl Sound {
+    /// The frequency and length of the tone.
+    fn tone(self) -> (f32, Duration) {
+        match self {
+            Self::StonePlaced => (440.0, Duration::from_millis(80)),
+            Self::SecondBest => (880.0, Duration::from_millis(250)),
+            Self::GameOver => (220.0, Duration::from_millis(600)),
+        }
+    }
+}
+
+/// Play the sound of the last thing which happened in the game, if sounds are on.
+pub fn play_sounds(
+    mut commands: Commands,
+    mut game: ResMut<Game>,
+    settings: Res<Settings>,
+    mut pitches: ResMut<Assets<Pitch>>,
+) {
+    if game.sound.is_none() {
+        return;
+    }
+    // Taking the sound doesn't change what is shown.
+    let Some(sound) = game.bypass_change_detection().sound.take() else {
+        return;
+    };
+    if !settings.sounds {
+        return;
+    }
+    let (frequency, duration) = sound.tone();
+    commands.spawn(PitchBundle {
+        source: pitches.add(Pitch::new(frequency, duration)),
+        settings: PlaybackSettings::DESPAWN,
+    });
+}
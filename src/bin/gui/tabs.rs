@@ -0,0 +1,159 @@
+//! Several games in tabs, to compare openings or lines side by side.
+//! The game and the engine of the shown tab are the [`Game`] and [`Engine`]
+//! resources, so that the other windows work on the shown tab. The other
+//! tabs keep theirs here, and their engines keep searching in the background:
+//! what they find is used once their tab is shown again. All the engines
+//! use one transposition table, so more tabs don't use more memory.
+
+use std::sync::atomic::Ordering;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{Engine, Game};
+
+struct Tab {
+    name: String,
+    /// The game and the engine of the tab, or `None` for the shown tab.
+    hidden: Option<(Game, Engine)>,
+}
+
+#[derive(Resource)]
+pub struct Tabs {
+    tabs: Vec<Tab>,
+    /// The index of the shown tab.
+    shown: usize,
+    /// The number in the name of the next new tab.
+    next_number: usize,
+}
+
+impl Default for Tabs {
+    fn default() -> Self {
+        Self {
+            tabs: vec![Tab {
+                name: "Game 1".to_string(),
+                hidden: None,
+            }],
+            shown: 0,
+            next_number: 2,
+        }
+    }
+}
+
+impl Tabs {
+    /// Show another tab, and keep the game and engine of the shown one.
+    fn show(&mut self, tab_i: usize, game: &mut Game, engine: &mut Engine) {
+        if tab_i == self.shown {
+            return;
+        }
+        let (mut new_game, mut new_engine) = self.tabs[tab_i].hidden.take().unwrap();
+        // The log can have been turned on or off since the tab was hidden.
+        new_engine.log = engine.log.clone();
+        std::mem::swap(game, &mut new_game);
+        std::mem::swap(engine, &mut new_engine);
+        game.message = format!("Showing {}", self.tabs[tab_i].name);
+        self.tabs[self.shown].hidden = Some((new_game, new_engine));
+        self.shown = tab_i;
+    }
+
+    /// Open a new tab with the position of the shown tab, and show it.
+    fn open(&mut self, game: &mut Game, engine: &mut Engine) {
+        let new_game = Game {
+            position: game.position.clone(),
+            main_game: game.main_game.clone(),
+            times: game.times.clone(),
+            variations: game.variations.clone(),
+            ..Default::default()
+        };
+        // The tabs share the console, the log and the transposition table.
+        let new_engine = Engine {
+            depth: engine.depth,
+            log: engine.log.clone(),
+            console: engine.console.clone(),
+            tt: engine.tt.clone(),
+            ..Default::default()
+        };
+        self.tabs.push(Tab {
+            name: format!("Game {}", self.next_number),
+            hidden: Some((new_game, new_engine)),
+        });
+        self.next_number += 1;
+        self.show(self.tabs.len() - 1, game, engine);
+    }
+
+    /// Close a tab and stop its engine. The last tab can't be closed.
+    fn close(&mut self, tab_i: usize, game: &mut Game, engine: &mut Engine) {
+        if self.tabs.len() == 1 {
+            return;
+        }
+        if tab_i == self.shown {
+            let other = if tab_i == 0 { 1 } else { tab_i - 1 };
+            self.show(other, game, engine);
+        }
+        let (_, closed_engine) = self.tabs.remove(tab_i).hidden.unwrap();
+        closed_engine.abort.store(true, Ordering::Relaxed);
+        if tab_i < self.shown {
+            self.shown -= 1;
+        }
+    }
+}
+
+pub fn tabs_ui(
+    mut tabs: ResMut<Tabs>,
+    mut game: ResMut<Game>,
+    mut engine: ResMut<Engine>,
+    mut ctx: EguiContexts,
+) {
+    let (mut show, mut close, mut open) = (None, None, false);
+    egui::TopBottomPanel::top("tabs").show(ctx.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            for (tab_i, tab) in tabs.tabs.iter().enumerate() {
+                // The engines of hidden tabs can still be searching.
+                let thinking = match &tab.hidden {
+                    Some((_, engine)) => engine.thinking,
+                    None => engine.thinking,
+                };
+                let name = match thinking {
+                    true => format!("{} (thinking)", tab.name),
+                    false => tab.name.clone(),
+                };
+                if ui.selectable_label(tab_i == tabs.shown, name).clicked() {
+                    show = Some(tab_i);
+                }
+                if tabs.tabs.len() > 1
+                    && ui
+                        .small_button("x")
+                        .on_hover_text("Close the tab, and stop its engine")
+                        .clicked()
+                {
+                    close = Some(tab_i);
+                }
+                ui.separator();
+            }
+            open = ui
+                .button("+")
+                .on_hover_text("Open a new tab with the position of this one")
+                .clicked();
+        });
+    });
+    if let Some(tab_i) = show {
+        tabs.show(tab_i, &mut game, &mut engine);
+    }
+    if let Some(tab_i) = close {
+        tabs.close(tab_i, &mut game, &mut engine);
+    }
+    if open {
+        tabs.open(&mut game, &mut engine);
+    }
+}
+
+/// Stop the engines of the hidden tabs when the app is closed, as
+/// [`crate::stop_engine_on_exit`] does for the shown tab.
+pub fn stop_hidden_engines_on_exit(mut exit: EventReader<AppExit>, tabs: Res<Tabs>) {
+    if exit.read().next().is_some() {
+        for (_, engine) in tabs.tabs.iter().filter_map(|tab| tab.hidden.as_ref()) {
+            engine.abort.store(true, Ordering::Relaxed);
+        }
+    }
+}
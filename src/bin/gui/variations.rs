@@ -0,0 +1,161 @@
+//! The lines played in a game, as a tree. Going back and playing another
+//! move keeps the old line as a variation, which can be gone back to, and
+//! moves which were taken back can be played again with "Redo".
+
+use std::time::Duration;
+
+use second_best::position::PlayerMove;
+
+/// A move of the tree, with the time spent on it.
+#[derive(Debug, Clone)]
+struct Node {
+    /// `None` for the start of the game.
+    pmove: Option<PlayerMove>,
+    time: Duration,
+    parent: usize,
+    /// The moves played after this one, in the order they were first played.
+    children: Vec<usize>,
+    /// The child which was played last, which "Redo" goes to.
+    next: Option<usize>,
+}
+
+/// The tree of the lines of a game, and the move of it which is on the board.
+/// Nodes are never removed, so their indices can be kept.
+#[derive(Debug, Clone)]
+pub struct Variations {
+    nodes: Vec<Node>,
+    current: usize,
+}
+
+impl Default for Variations {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node {
+                pmove: None,
+                time: Duration::ZERO,
+                parent: 0,
+                children: vec![],
+                next: None,
+            }],
+            current: 0,
+        }
+    }
+}
+
+impl Variations {
+    /// Play a move from the current node. If it was played here before,
+    /// its node is used again with the new time.
+    pub fn play(&mut self, pmove: PlayerMove, time: Duration) {
+        let parent = self.current;
+        let existing = self.nodes[parent]
+            .children
+            .iter()
+            .copied()
+            .find(|&child| self.nodes[child].pmove == Some(pmove));
+        let child = match existing {
+            Some(child) => {
+                self.nodes[child].time = time;
+                child
+            }
+            None => {
+                self.nodes.push(Node {
+                    pmove: Some(pmove),
+                    time,
+                    parent,
+                    children: vec![],
+                    next: None,
+                });
+                let child = self.nodes.len() - 1;
+                self.nodes[parent].children.push(child);
+                child
+            }
+        };
+        self.nodes[parent].next = Some(child);
+        self.current = child;
+    }
+
+    /// Go back one move, keeping it to be redone.
+    pub fn back(&mut self) {
+        self.current = self.nodes[self.current].parent;
+    }
+
+    /// The move "Redo" plays, with the time spent on it.
+    pub fn redo_move(&self) -> Option<(PlayerMove, Duration)> {
+        let next = self.nodes[self.current].next?;
+        Some((self.nodes[next].pmove?, self.nodes[next].time))
+    }
+
+    /// Go forward to the move of [`Variations::redo_move`].
+    pub fn forward(&mut self) {
+        if let Some(next) = self.nodes[self.current].next {
+            self.current = next;
+        }
+    }
+
+    /// Go to the end of the given line from the start of the game, adding
+    /// the moves which are not in the tree yet.
+    pub fn set_line(&mut self, moves: &[PlayerMove], times: &[Duration]) {
+        self.current = 0;
+        for (move_i, &pmove) in moves.iter().enumerate() {
+            self.play(pmove, times.get(move_i).copied().unwrap_or_default());
+        }
+    }
+
+    /// Go to the given node, and return the moves and times of its line.
+    pub fn go_to(&mut self, node: usize) -> (Vec<PlayerMove>, Vec<Duration>) {
+        self.current = node;
+        // Going back to an earlier move keeps the line after it for "Redo".
+        let mut child = node;
+        while child != 0 {
+            let parent = self.nodes[child].parent;
+            self.nodes[parent].next = Some(child);
+            child = parent;
+        }
+        let path = self.path(node);
+        let moves = path.iter().filter_map(|&i| self.nodes[i].pmove).collect();
+        let times = path.iter().map(|&i| self.nodes[i].time).collect();
+        (moves, times)
+    }
+
+    /// The nodes from the first move to the given node.
+    fn path(&self, mut node: usize) -> Vec<usize> {
+        let mut path = vec![];
+        while node != 0 {
+            path.push(node);
+            node = self.nodes[node].parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// The node of the move on the board, 0 at the start of the game.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// The nodes of the line on the board, followed by the moves which
+    /// can be redone.
+    pub fn shown_line(&self) -> Vec<usize> {
+        let mut line = self.path(self.current);
+        let mut node = self.current;
+        while let Some(next) = self.nodes[node].next {
+            line.push(next);
+            node = next;
+        }
+        line
+    }
+
+    /// The moves which were played instead of the given one, including itself.
+    pub fn alternatives(&self, node: usize) -> &[usize] {
+        &self.nodes[self.nodes[node].parent].children
+    }
+
+    /// The move of a node. Only the start of the game has none.
+    pub fn pmove(&self, node: usize) -> Option<PlayerMove> {
+        self.nodes[node].pmove
+    }
+
+    pub fn time(&self, node: usize) -> Duration {
+        self.nodes[node].time
+    }
+}
@@ -0,0 +1,219 @@
+//! Named positions, with the results of their analysis, to switch quickly
+//! between the lines being analyzed. They are kept in a JSON file, so they
+//! are still there in the next session.
+
+use crate::position::{InvalidFen, PlayerMove, Position, Rules, Variant};
+use crate::solver::RootMoveLine;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// A move of a bookmarked position, with what the search found about it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnalyzedMove {
+    #[serde(rename = "move")]
+    pub pmove: String,
+    /// The score of the move, `None` if it wasn't searched fully.
+    pub score: Option<isize>,
+    /// The best line starting with the move.
+    pub pv: Vec<String>,
+}
+
+impl Display for AnalyzedMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "move {} score ", self.pmove)?;
+        match self.score {
+            Some(score) => write!(f, "{score}")?,
+            None => write!(f, "-")?,
+        }
+        write!(f, " pv {}", self.pv.join(" "))
+    }
+}
+
+/// A saved position.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bookmark {
+    /// The position in compact notation.
+    pub fen: String,
+    /// The size of the board, e.g. "6x4".
+    #[serde(default, with = "variant_string")]
+    pub variant: Variant,
+    /// The moves leading to the position, as with `set-pos`. Empty
+    /// if the position was set up with a fen.
+    pub moves: String,
+    /// The moves from the last search of the position, best first.
+    #[serde(default)]
+    pub analysis: Vec<AnalyzedMove>,
+}
+
+impl Bookmark {
+    /// Bookmark a position, with the lines of its root moves if it was analyzed.
+    pub fn new(pos: &Position, analysis: &[RootMoveLine]) -> Self {
+        let to_strings = |moves: &[PlayerMove]| -> Vec<String> {
+            moves.iter().map(ToString::to_string).collect()
+        };
+        Self {
+            fen: pos.to_fen(),
+            variant: pos.variant(),
+            moves: to_strings(&pos.history()).join(" "),
+            analysis: analysis
+                .iter()
+                .map(|line| AnalyzedMove {
+                    pmove: line.pmove.to_string(),
+                    score: line.score,
+                    pv: to_strings(&line.pv),
+                })
+                .collect(),
+        }
+    }
+
+    /// The bookmarked position, played with the given house rules. The
+    /// moves are played again if possible, so they can be undone.
+    pub fn position(&self, rules: Rules) -> Result<Position, InvalidFen> {
+        let mut pos = Position::with_variant(self.variant);
+        pos.set_rules(rules);
+        let moves = self.moves.split_whitespace().map(String::from).collect();
+        if pos.parse_and_play_moves(moves).is_ok() && pos.to_fen() == self.fen {
+            return Ok(pos);
+        }
+        let mut pos = Position::from_fen_with_variant(&self.fen, self.variant)?;
+        pos.set_rules(rules);
+        Ok(pos)
+    }
+}
+
+/// A [`Variant`] in the file as its size, e.g. "6x4".
+mod variant_string {
+    use crate::position::Variant;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(variant: &Variant, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(variant)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Variant, D::Error> {
+        let size = String::deserialize(deserializer)?;
+        size.parse()
+            .map_err(|e| D::Error::custom(format!("invalid variant {size}: {e:?}")))
+    }
+}
+
+/// The bookmarks by name, and the file they are kept in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bookmarks {
+    path: PathBuf,
+    bookmarks: BTreeMap<String, Bookmark>,
+}
+
+impl Bookmarks {
+    /// The file the bookmarks are kept in, in the working directory.
+    pub const DEFAULT_PATH: &'static str = "second-best-bookmarks.json";
+
+    /// Load the bookmarks kept in the given file. There are no
+    /// bookmarks yet if the file doesn't exist.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let bookmarks = match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).map_err(std::io::Error::from)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            bookmarks,
+        })
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(&self.bookmarks).map_err(std::io::Error::from)?;
+        std::fs::write(&self.path, json)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Bookmark> {
+        self.bookmarks.get(name)
+    }
+
+    /// The bookmarks, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Bookmark)> {
+        self.bookmarks.iter()
+    }
+
+    /// Add a bookmark, replacing the one with the same name, and save the bookmarks.
+    pub fn insert(&mut self, name: &str, bookmark: Bookmark) -> std::io::Result<()> {
+        self.bookmarks.insert(name.to_string(), bookmark);
+        self.save()
+    }
+
+    /// Remove a bookmark, and save the bookmarks.
+    /// Returns `false` if there is no bookmark with the name.
+    pub fn remove(&mut self, name: &str) -> std::io::Result<bool> {
+        if self.bookmarks.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bookmarks() {
+        let path = std::env::temp_dir().join("second_best_bookmarks_test.json");
+        let _ = std::fs::remove_file(&path);
+        let mut bookmarks = Bookmarks::load(&path).unwrap();
+        assert_eq!(bookmarks.iter().count(), 0);
+
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(["0", "1", "!", "2"].map(String::from).to_vec())
+            .unwrap();
+        let line = RootMoveLine {
+            pmove: PlayerMove::StoneMove { from: None, to: 3 },
+            score: Some(2),
+            pv: vec![PlayerMove::StoneMove { from: None, to: 3 }],
+        };
+        bookmarks
+            .insert("main", Bookmark::new(&pos, &[line]))
+            .unwrap();
+        let fen_pos = Position::from_fen("O/X/XX/O/XO/././O X 3 -").unwrap();
+        bookmarks
+            .insert("fen", Bookmark::new(&fen_pos, &[]))
+            .unwrap();
+        let small = Variant::new(6, 4).unwrap();
+        let mut small_pos = Position::with_variant(small);
+        small_pos
+            .parse_and_play_moves(["5", "5"].map(String::from).to_vec())
+            .unwrap();
+        bookmarks
+            .insert("small", Bookmark::new(&small_pos, &[]))
+            .unwrap();
+
+        // The bookmarks are kept in the file.
+        let mut loaded = Bookmarks::load(&path).unwrap();
+        assert_eq!(loaded, bookmarks);
+        let names: Vec<_> = loaded.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["fen", "main", "small"]);
+        let main = loaded.get("main").unwrap();
+        assert_eq!(main.moves, "0 1 ! 2");
+        assert_eq!(main.analysis[0].to_string(), "move 3 score 2 pv 3");
+        // The moves are played again, with the history.
+        let restored = main.position(Rules::default()).unwrap();
+        assert_eq!(restored.history(), pos.history());
+        let restored = loaded
+            .get("fen")
+            .unwrap()
+            .position(Rules::default())
+            .unwrap();
+        assert_eq!(restored.to_fen(), fen_pos.to_fen());
+        let restored = loaded.get("small").unwrap().position(Rules::default());
+        assert_eq!(restored.unwrap().variant(), small);
+
+        assert!(loaded.remove("main").unwrap());
+        assert!(!loaded.remove("main").unwrap());
+        assert!(Bookmarks::load(&path).unwrap().get("main").is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,14 +1,44 @@
-use clap::{Args, Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt::Display;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::mpsc::{self, Sender};
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 
-use crate::position::{MoveFailed, Position};
-use crate::solver::Solver;
-use crate::{bench, eval};
+use crate::analysis::{
+    AnnotatedMove, Deduplicated, ExpectedResult, Line, SecondBestAdvice, SecondBestMapEntry,
+};
+use crate::bench::Difficulty;
+use crate::bookmarks::{Bookmark, Bookmarks};
+use crate::config::Config;
+use crate::error::Error;
+use crate::eval::{EvalParams, ExplainableEval};
+use crate::gamefile::GameRecord;
+use crate::log::Log;
+use crate::pns::{Proof, ProofSolver};
+use crate::position::{
+    Color, GameStatus, InvalidFen, InvalidVariant, MoveFailed, PlayerMove, Position, Rules, Variant,
+};
+use crate::progress::{MessageHandler, Progress, ProgressHandler};
+use crate::rng::Rng;
+use crate::solver::{
+    CurrMoveInfo, Engine, InvalidOption, SearchDriver, SearchInfo, SearchLimits, Skill, Solver,
+    SolverOptions,
+};
+use crate::time_control::TimeControl;
+use crate::trace::SearchTrace;
+use crate::verify::Verification;
+use crate::SharedTranspositionTable;
+use crate::{analysis, bench, eval, verify};
 
 #[derive(Subcommand, Debug, PartialEq, Eq)]
 #[command(author, version, about, multicall = true)]
@@ -19,31 +49,433 @@ enum Command {
     /// Print the current position
     #[command(alias("display"))]
     Show,
+    /// Start a new game. If an engine color is given, the engine automatically
+    /// replies with its best move after each move played with `play`.
+    #[command(name = "newgame", alias("new-game"))]
+    NewGame {
+        /// The color played by the engine: "black" (X) or "white" (O).
+        #[arg(long, value_parser = parse_color)]
+        engine_color: Option<Color>,
+        /// The depth to which the engine searches its moves.
+        #[arg(long, default_value_t = 10)]
+        depth: usize,
+    },
     /// Set the position using a sequence of moves
     SetPos {
         /// The moves to be played from the starting position
         moves: Vec<String>,
     },
+    /// Set the position using the compact notation (see `fen`)
+    SetFen {
+        /// The position in compact notation, e.g. `O/X/XX/O/XO/././O X 3 -`
+        fen: Vec<String>,
+    },
+    /// Print the current position in compact notation.
+    /// The notation is: stacks from bottom to top separated by '/',
+    /// the side to move, the last move if "Second Best!" can be
+    /// called on it (or '-'), and the banned move (or '-').
+    Fen,
     /// Play a sequence of moves from the current position
     Play {
         /// The moves to be played from the current position.
         moves: Vec<String>,
     },
+    /// Take back the last moves played, including "Second Best!" calls.
+    Undo {
+        /// The number of moves to take back.
+        #[arg(default_value_t = 1)]
+        n: usize,
+    },
+    /// Play moves taken back with `undo` again, as long as the
+    /// position wasn't changed in another way since.
+    Redo {
+        /// The number of moves to play again.
+        #[arg(default_value_t = 1)]
+        n: usize,
+    },
+    /// Save the moves leading to the current position to a game file.
+    SaveGame {
+        /// The file to save the game to.
+        path: PathBuf,
+        /// Name of the black (X) player.
+        #[arg(long, default_value = "")]
+        black: String,
+        /// Name of the white (O) player.
+        #[arg(long, default_value = "")]
+        white: String,
+    },
+    /// Load a game file, and set the position to the end of the game.
+    LoadGame {
+        /// The file to load the game from.
+        path: PathBuf,
+    },
+    /// Save, load and list bookmarked positions, which are kept in
+    /// `second-best-bookmarks.json` for the next session.
+    Bookmark {
+        #[command(subcommand)]
+        action: BookmarkAction,
+    },
+    /// Save the transposition table of the solver to a file, to continue
+    /// a long search later with `tt-load`.
+    TtSave {
+        /// The file to save the table to.
+        path: PathBuf,
+    },
+    /// Load a transposition table saved with `tt-save`, replacing the
+    /// current one. The table should be saved with the same house rules.
+    TtLoad {
+        /// The file to load the table from.
+        path: PathBuf,
+    },
     /// Evaluate the current position to the given depth.
     Eval {
+        /// The depth to which to evaluate the given position
+        /// (`depth` in the config, 5 by default).
+        depth: Option<usize>,
+        /// The same as the depth argument, e.g. `eval --depth 12`.
+        #[arg(long = "depth", value_name = "DEPTH", conflicts_with = "depth")]
+        depth_option: Option<usize>,
+        /// Stop the search after this many milliseconds.
+        #[arg(long)]
+        time: Option<u64>,
+        /// Stop the search after approximately this many nodes.
+        /// Unlike `--time`, this gives the same result on every machine.
+        #[arg(long)]
+        nodes: Option<usize>,
+        /// Only search these moves in the current position, e.g.
+        /// `--searchmoves 0 3-4 !`, to see how good they are.
+        #[arg(long, num_args = 1..)]
+        searchmoves: Vec<String>,
+        /// Search every move except these, e.g. `--exclude 3`, to find the
+        /// best alternative when a move is banned by "Second Best!".
+        #[arg(long, num_args = 1.., conflicts_with = "searchmoves")]
+        exclude: Vec<String>,
+    },
+    /// Compare allowing the opponent's last move with calling "Second Best!"
+    /// on it, and show the best line for both.
+    AdviseSecondBest {
+        #[arg(default_value_t = 5)]
+        /// The depth to which to search both lines.
+        depth: usize,
+        /// Stop each search after this many milliseconds.
+        #[arg(long)]
+        time: Option<u64>,
+        /// Stop each search after approximately this many nodes.
+        #[arg(long)]
+        nodes: Option<usize>,
+    },
+    /// For every move in the current position, whether the opponent should
+    /// call "Second Best!" on it, as with `advise-second-best` after the move.
+    SecondBestMap {
+        #[arg(default_value_t = 5)]
+        /// The depth to which to search both lines after every move.
+        depth: usize,
+        /// Stop each search after this many milliseconds.
+        #[arg(long)]
+        time: Option<u64>,
+        /// Stop each search after approximately this many nodes.
+        #[arg(long)]
+        nodes: Option<usize>,
+    },
+    /// Suggest the best moves in the current position, with their scores.
+    Hint {
+        #[arg(default_value_t = 3)]
+        /// The number of moves to suggest.
+        n: usize,
+        /// The depth to which to search every move.
+        #[arg(long, default_value_t = 5)]
+        depth: usize,
+        /// Stop the search for each move after this many milliseconds.
+        #[arg(long)]
+        time: Option<u64>,
+        /// Stop the search for each move after approximately this many nodes.
+        #[arg(long)]
+        nodes: Option<usize>,
+    },
+    /// Search the current position until it is solved, or one of the limits
+    /// is reached.
+    Solve {
+        /// The algorithm to solve the position with.
+        #[arg(long, value_enum, default_value_t = Algorithm::Negamax)]
+        algorithm: Algorithm,
+        /// Stop the search after this many milliseconds.
+        #[arg(long)]
+        time: Option<u64>,
+        /// Stop the search after approximately this many nodes.
+        #[arg(long)]
+        nodes: Option<usize>,
+    },
+    /// Search the current position to the given depth, and record the tree
+    /// searched in the last iteration: the moves, the windows, the scores,
+    /// and how the transposition table was used. For debugging the search.
+    DebugSearch {
+        /// The depth to search to.
+        depth: usize,
+        /// The file to write the tree to as JSON. The tree is printed if
+        /// no file is given.
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// The maximal number of nodes recorded.
+        #[arg(long, default_value_t = 10_000)]
+        max_nodes: usize,
+    },
+    /// Print a search tree written by `debug-search --out`.
+    ShowTrace {
+        /// The file with the tree.
+        path: PathBuf,
+    },
+    /// Search the current position, and play the best move found.
+    /// Weaker moves are played if the skill level is lowered (see `set-skill`).
+    PlayBest {
         #[arg(default_value_t = 5)]
-        /// The depth to which to evaluate the given position.
+        /// The depth to which to search the position.
         depth: usize,
+        /// Stop the search after this many milliseconds.
+        #[arg(long)]
+        time: Option<u64>,
+        /// Stop the search after approximately this many nodes.
+        #[arg(long)]
+        nodes: Option<usize>,
+    },
+    /// Search the current position under a clock, and print the best move
+    /// without playing it, e.g. for engine matches. The time spent on the move
+    /// depends on the time left, e.g. `go btime 60000 wtime 60000 binc 500 winc 500`.
+    /// The best move comes with the reply the engine expects, if it knows it.
+    Go {
+        /// Ponder: search the position after the expected reply while the
+        /// opponent thinks. The clock only starts with `ponderhit`, when the
+        /// opponent played that reply. Until then, the best move is not printed.
+        #[arg(long)]
+        ponder: bool,
+        /// The time left for black and white (`btime` and `wtime`), and the time
+        /// they get after each move (`binc` and `winc`), in milliseconds.
+        clock: Vec<String>,
     },
+    /// The opponent played the reply which is pondered on with `go --ponder`:
+    /// the search continues as a normal search under the clock.
+    #[command(name = "ponderhit")]
+    PonderHit,
+    /// Play a random legal move in the current position.
+    PlayRandom,
     /// Generate a benchmark file with the given parameters
     GenBench(GenBenchArgs),
     /// Run benchmarks
+    /// The results are saved in `results/` in the benchmarks directory.
     Bench {
-        /// The number of threads to run the benchmarks on.
-        num_threads: usize,
+        /// The number of threads to run the benchmarks on
+        /// (`threads` in the config, 1 by default).
+        num_threads: Option<usize>,
+        /// A results file of an earlier run, to compare the speed and
+        /// number of nodes searched to.
+        #[arg(long)]
+        compare: Option<PathBuf>,
+    },
+    /// Solve every position in a file, and write the results to a report.
+    /// The file has one position per line, either in compact notation (as
+    /// with `set-fen`) or as moves from the start (as with `set-pos`).
+    AnalyzeFile {
+        /// The file with the positions.
+        path: PathBuf,
+        #[arg(default_value_t = 10)]
+        /// The depth to which to search every position.
+        depth: usize,
+        /// The file to write the report to: JSON if it ends in `.json`, CSV otherwise.
+        /// Defaults to the positions file with a `.report.csv` extension.
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Read a file of move sequences, one per line, and write the positions
+    /// they lead to without duplicates. Positions which are rotations or
+    /// mirror images of each other are the same, and the shortest sequence
+    /// leading to a position is kept.
+    DedupePositions {
+        /// The file with the move sequences.
+        input: PathBuf,
+        /// The file to write the remaining sequences to.
+        output: PathBuf,
+    },
+    /// Estimate how hard every position in a file is to solve: the depth at
+    /// which its result is first known, and the nodes needed for it. The
+    /// positions are written with these labels and their difficulty (easy,
+    /// medium or hard, see `gen-bench --difficulty`).
+    EstimateDifficulty {
+        /// The file with the positions, as with `analyze-file`.
+        path: PathBuf,
+        /// The maximal depth to search every position to.
+        #[arg(default_value_t = 12)]
+        max_depth: usize,
+        /// The file to write the labeled positions to. Defaults to the
+        /// positions file with a `.labels.txt` extension.
+        #[arg(long)]
+        labels: Option<PathBuf>,
+    },
+    /// Evaluate every move of a game, and mark the mistakes (`?`), the moves
+    /// which lose or miss a win (`??`), and the only winning moves (`!`).
+    /// Annotates the game leading to the current position if no moves are given.
+    Annotate {
+        /// The moves of the game, from the starting position.
+        moves: Vec<String>,
+        /// The depth to which to search every position of the game.
+        #[arg(long, default_value_t = 5)]
+        depth: usize,
+        /// How much worse than the best move a move has to be to be a mistake.
+        #[arg(long, default_value_t = 3)]
+        threshold: isize,
+    },
+    /// Verify a claimed result, e.g. `prove 0 1 2 3 --expect win-in-5`. The
+    /// position is searched to the depth of the result, and the line found is
+    /// checked with a slow reference search without any pruning. Verifies the
+    /// current position if no moves are given.
+    Prove {
+        /// The moves of the position, from the starting position.
+        moves: Vec<String>,
+        /// The result for the player to move: `win-in-<moves>` or `loss-in-<moves>`.
+        #[arg(long, value_parser = parse_expected_result)]
+        expect: ExpectedResult,
+        /// The maximal number of nodes of the reference search for each
+        /// position of the line. Positions which need more are skipped.
+        #[arg(long, default_value_t = 1_000_000)]
+        reference_nodes: usize,
+    },
+    /// Run a test suite: positions with their expected result and best moves,
+    /// e.g. `0 1 4 5; result loss 4; bm 5 7; depth 9` on every line.
+    /// Fails with a nonzero exit code if any position fails.
+    TestSuite {
+        /// The file with the test positions.
+        path: PathBuf,
+        #[arg(default_value_t = 10)]
+        /// The depth to search positions to, if not given in the file.
+        depth: usize,
+    },
+    /// Run the commands in a file one after the other, one command per
+    /// line. Every command is finished before the next one starts, and the
+    /// script stops at the first command which fails. Empty lines and lines
+    /// starting with `#` are skipped.
+    RunScript {
+        /// The file with the commands.
+        path: PathBuf,
     },
-    /// Stop any currently running searches.
+    /// Stop any currently running searches, and wait until they have stopped.
     Stop,
+    /// Check that the cli is responsive, answered with `readyok`.
+    #[command(name = "isready")]
+    IsReady,
+    /// Accept connections on the given port, and run the commands sent over
+    /// them. Every connection gets its own solver. Commands are sent as one
+    /// JSON object per line, e.g. `{"command": "eval 5"}`, and the output is
+    /// sent back in JSON (see `set-output`). A command with an "id" field,
+    /// e.g. `{"command": "eval 5", "id": 3}`, gets it back in its output.
+    Serve {
+        /// The port to listen on. Only connections from this machine are accepted.
+        #[arg(long)]
+        port: u16,
+    },
+    /// Set the strength of the moves chosen by the solver.
+    SetSkill {
+        /// From 1 (weakest) to 10 (full strength).
+        #[arg(value_parser = clap::value_parser!(u8).range(Skill::MIN as i64..=Skill::MAX as i64))]
+        level: u8,
+    },
+    /// Set the algorithm which chooses the moves played by `play-best` and
+    /// the engine in a game: `negamax` (alpha-beta search), or `mcts`
+    /// (Monte Carlo tree search), which gives reasonable moves quickly.
+    /// With `mcts`, `--nodes` is the number of playouts.
+    SetEngine {
+        #[arg(value_parser = Engine::NAMES)]
+        engine: String,
+    },
+    /// Set the format of the output: plain text, or one JSON object per line
+    /// which is easier to read for other programs.
+    SetOutput {
+        #[arg(value_enum)]
+        format: OutputFormat,
+    },
+    /// Write everything which is output to a log file as well, with the
+    /// time of every line, together with the commands which are received.
+    /// Lines are added to the end of the file. Stops logging if no file is given.
+    SetLog {
+        /// The log file.
+        path: Option<PathBuf>,
+    },
+    /// Show or change the defaults of the engine, which are loaded from
+    /// `second-best.toml` at startup.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Set an option of the solver, e.g. `set-option pvs true`,
+    /// or a weight of the evaluation, e.g. `set-option mobility 2`
+    SetOption {
+        /// The name of the option.
+        name: String,
+        /// The new value of the option.
+        value: String,
+    },
+    /// Set how every iteration of the search is done: with a single
+    /// alpha-beta search, or with MTD(f), which uses a sequence of null
+    /// window searches around the score of the previous iteration.
+    SetSearchDriver {
+        #[arg(value_parser = SearchDriver::NAMES)]
+        driver: String,
+    },
+    /// Set a house rule, e.g. `set-rule second-best-limit 2`.
+    /// Rules: `second-best-on-alignment` (true/false), and
+    /// `second-best-limit` (a number up to 7, or none).
+    SetRule {
+        /// The name of the rule.
+        name: String,
+        /// The new value of the rule.
+        value: String,
+    },
+    /// Start a new game on a board of another size, e.g. `set-variant 6 4`.
+    /// The standard board is `set-variant 8 3`.
+    SetVariant {
+        /// The number of stacks, even and at least 4.
+        stacks: usize,
+        /// The number of stones a stack can hold, at least 3.
+        height: usize,
+    },
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+enum BookmarkAction {
+    /// Bookmark the current position, replacing the bookmark with the same name.
+    Save {
+        name: String,
+        /// Also keep the scores and lines of the moves found by the last
+        /// search of the position, which are shown when it is loaded.
+        #[arg(long)]
+        analysis: bool,
+    },
+    /// Set the position to a bookmarked one.
+    Load { name: String },
+    /// List the bookmarks.
+    List,
+    /// Remove a bookmark.
+    Delete { name: String },
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+enum ConfigAction {
+    /// Show the config, in the format of the config file.
+    Show,
+    /// Change a value of the config for this session, e.g. `config set tt-size 256`
+    /// or `config set mobility 2` for an option of the solver.
+    Set { name: String, value: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Algorithm {
+    /// Alpha-beta search with iterative deepening, as used by `eval`.
+    Negamax,
+    /// Proof-number search, which can be faster for deep wins.
+    Pns,
 }
 
 #[derive(Debug, Args, PartialEq, Eq)]
@@ -55,9 +487,38 @@ struct GenBenchArgs {
     /// The maximal number of moves in each position.
     max_moves: usize,
     /// The minimal amount of depth needed to solve each position.
-    min_depth: usize,
+    #[arg(required_unless_present = "difficulty")]
+    min_depth: Option<usize>,
     /// The maximal amount of depth needed to solve each position.
-    max_depth: usize,
+    #[arg(required_unless_present = "difficulty")]
+    max_depth: Option<usize>,
+    /// Generate positions of this difficulty (as labeled by
+    /// `estimate-difficulty`) instead of giving the depths.
+    #[arg(long, value_parser = Difficulty::NAMES, conflicts_with_all = ["min_depth", "max_depth"])]
+    difficulty: Option<String>,
+    /// The seed of the random moves. The same seed and parameters always
+    /// give the same benchmark. A random seed is used if none is given.
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+fn parse_color(s: &str) -> Result<Color, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "black" | "x" => Ok(Color::Black),
+        "white" | "o" => Ok(Color::White),
+        _ => Err("expected \"black\" or \"white\"".to_string()),
+    }
+}
+
+fn parse_expected_result(s: &str) -> Result<ExpectedResult, String> {
+    let invalid = || "expected \"win-in-<moves>\" or \"loss-in-<moves>\"".to_string();
+    let (result, moves) = s.split_once("-in-").ok_or_else(invalid)?;
+    let moves = Some(moves.parse().map_err(|_| invalid())?);
+    match result.to_ascii_lowercase().as_str() {
+        "win" => Ok(ExpectedResult::Win(moves)),
+        "loss" => Ok(ExpectedResult::Loss(moves)),
+        _ => Err(invalid()),
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -67,35 +528,649 @@ struct CliArgs {
 }
 
 struct SearchRequest {
+    solver: Arc<Mutex<Solver>>,
+    limits: SearchLimits,
+    /// If not empty, only these moves are searched in the root position.
+    search_moves: Vec<PlayerMove>,
+}
+
+struct HintRequest {
+    solver: Arc<Mutex<Solver>>,
+    limits: SearchLimits,
+    n: usize,
+}
+
+struct ProofRequest {
+    abort: Arc<AtomicBool>,
+    solver: Arc<Mutex<Solver>>,
+    limits: SearchLimits,
+}
+
+struct DebugSearchRequest {
     solver: Arc<Mutex<Solver>>,
     depth: usize,
+    out: Option<PathBuf>,
+    max_nodes: usize,
 }
 
 struct GenBenchRequest {
     abort: Arc<AtomicBool>,
+    dir: PathBuf,
     bench_args: GenBenchArgs,
 }
 
 struct RunBenchRequest {
     abort: Arc<AtomicBool>,
+    dir: PathBuf,
     num_threads: usize,
+    options: SolverOptions,
+    eval_params: EvalParams,
+    compare: Option<PathBuf>,
+}
+
+struct EstimateDifficultyRequest {
+    abort: Arc<AtomicBool>,
+    path: PathBuf,
+    max_depth: usize,
+    labels: PathBuf,
+    options: SolverOptions,
+    eval_params: EvalParams,
+    rules: Rules,
+    variant: Variant,
+}
+
+struct AnalyzeFileRequest {
+    abort: Arc<AtomicBool>,
+    path: PathBuf,
+    depth: usize,
+    report: PathBuf,
+    options: SolverOptions,
+    eval_params: EvalParams,
+    rules: Rules,
+    variant: Variant,
+}
+
+struct AnnotateRequest {
+    abort: Arc<AtomicBool>,
+    solver: Arc<Mutex<Solver>>,
+    /// The moves from the starting position, or `None` for
+    /// the game leading to the current position.
+    moves: Option<Vec<PlayerMove>>,
+    depth: usize,
+    threshold: isize,
+}
+
+struct VerifyRequest {
+    abort: Arc<AtomicBool>,
+    position: Box<Position>,
+    options: SolverOptions,
+    eval_params: EvalParams,
+    expected: ExpectedResult,
+    reference_nodes: usize,
 }
 
 enum ThreadRequest {
     Search(SearchRequest),
+    PlayBest(SearchRequest),
+    Go(SearchRequest),
+    Prove(ProofRequest),
+    Advise(SearchRequest),
+    SecondBestMap(SearchRequest),
+    Hint(HintRequest),
+    DebugSearch(DebugSearchRequest),
     GenBench(GenBenchRequest),
     RunBench(RunBenchRequest),
+    AnalyzeFile(AnalyzeFileRequest),
+    EstimateDifficulty(EstimateDifficultyRequest),
+    Annotate(AnnotateRequest),
+    Verify(VerifyRequest),
     Quit,
 }
 
+/// Where the output of the cli is written to. It is shared with
+/// the background thread, so that results of searches end up in
+/// the same place as the output of the commands.
+///
+/// In JSON mode every piece of output is written as a JSON object on
+/// a single line, with a "type" field telling what kind of output it is.
+///
+/// A command can start with `id <n>`, e.g. `id 7 eval 10`. All output for
+/// it then starts with `id 7`, or has an "id" field in JSON, so that a
+/// program can tell it apart from the output of earlier commands, such as
+/// the last info of a search which was stopped.
+#[derive(Clone)]
+struct Output {
+    writer: Arc<Mutex<dyn Write + Send>>,
+    json: Arc<AtomicBool>,
+    /// The id of the command the output is for, if it was given one.
+    id: Option<u64>,
+    /// Whether an error was reported, for the exit code of a command
+    /// run from the shell (see [`Cli::run_once`]).
+    failed: Arc<AtomicBool>,
+    /// The log file everything which is output is written to as well (see `set-log`).
+    log: Arc<Mutex<Option<Log>>>,
+}
+
+impl Output {
+    fn new(writer: Arc<Mutex<dyn Write + Send>>, json: bool) -> Self {
+        Self {
+            writer,
+            json: Arc::new(AtomicBool::new(json)),
+            id: None,
+            failed: Arc::new(AtomicBool::new(false)),
+            log: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn is_json(&self) -> bool {
+        self.json.load(Ordering::Relaxed)
+    }
+
+    /// The same output, for the command with the given id.
+    fn with_id(&self, id: Option<u64>) -> Self {
+        Self { id, ..self.clone() }
+    }
+
+    fn write(&self, text: impl Display, json: impl FnOnce() -> serde_json::Value) {
+        let line = match (self.is_json(), self.id) {
+            (true, id) => {
+                let mut value = json();
+                if let (Some(id), Some(object)) = (id, value.as_object_mut()) {
+                    object.insert("id".to_string(), id.into());
+                }
+                value.to_string()
+            }
+            (false, Some(id)) => format!("id {id} {text}"),
+            (false, None) => text.to_string(),
+        };
+        self.log(&line);
+        let mut writer = self.writer.lock().unwrap();
+        // The output can't be reported anywhere if writing it fails
+        // (e.g. because a client disconnected), so errors are ignored.
+        let _ = writeln!(writer, "{line}");
+        let _ = writer.flush();
+    }
+
+    /// Write to the log file only, if there is one.
+    fn log(&self, text: impl Display) {
+        if let Some(log) = &*self.log.lock().unwrap() {
+            log.line(text);
+        }
+    }
+
+    fn text(&self, text: impl Display) {
+        let text = text.to_string();
+        self.write(&text, || json!({"type": "text", "text": text}));
+    }
+
+    /// Report an error. The code is a short name of the error, which
+    /// doesn't change, unlike the message.
+    fn error(&self, code: &str, message: impl Display) {
+        self.failed.store(true, Ordering::Relaxed);
+        let message = message.to_string();
+        self.write(
+            &message,
+            || json!({"type": "error", "code": code, "message": message}),
+        );
+    }
+
+    fn info(&self, info: &SearchInfo) {
+        self.write(info, || {
+            let moves =
+                |pv: &[PlayerMove]| pv.iter().map(|pmove| pmove.to_string()).collect::<Vec<_>>();
+            let root_moves: Vec<_> = info
+                .root_moves
+                .iter()
+                .map(|line| {
+                    json!({
+                        "move": line.pmove.to_string(),
+                        "score": line.score,
+                        "pv": moves(&line.pv),
+                    })
+                })
+                .collect();
+            json!({
+                "type": "info",
+                "depth": info.depth,
+                "seldepth": info.seldepth,
+                "score": info.score,
+                "nodes": info.nodes,
+                "knps": info.knps as u64,
+                "time_ms": info.time.as_millis() as u64,
+                "ttfull": info.ttfull,
+                "pv": moves(&info.pv),
+                "root_moves": root_moves,
+            })
+        });
+    }
+
+    /// The root move being searched.
+    fn currmove(&self, info: &CurrMoveInfo) {
+        self.write(info, || {
+            json!({
+                "type": "currmove",
+                "depth": info.depth,
+                "currmove": info.currmove.to_string(),
+                "currmovenumber": info.currmovenumber,
+                "total": info.total,
+                "nodes": info.nodes,
+            })
+        });
+    }
+
+    /// The result of a search.
+    fn eval(&self, side: Color, eval: isize, ply: isize) {
+        self.write(eval::explain_eval(side, eval, ply), || {
+            let (result, moves) = match eval::decode_eval(eval, ply) {
+                ExplainableEval::Win(moves) => ("win", Some(moves)),
+                ExplainableEval::Loss(moves) => ("loss", Some(moves)),
+                ExplainableEval::Undetermined(_) => ("undetermined", None),
+            };
+            json!({
+                "type": "eval",
+                "side": side.to_string(),
+                "score": eval,
+                "result": result,
+                "moves": moves,
+            })
+        });
+    }
+
+    fn bookmarks(&self, bookmarks: &Bookmarks) {
+        let mut text: Vec<String> = bookmarks
+            .iter()
+            .map(|(name, bookmark)| format!("{name}: {}", bookmark.fen))
+            .collect();
+        if text.is_empty() {
+            text.push("There are no bookmarks".to_string());
+        }
+        self.write(text.join("\n"), || {
+            let bookmarks: Vec<_> = bookmarks
+                .iter()
+                .map(|(name, bookmark)| {
+                    json!({
+                        "name": name,
+                        "fen": bookmark.fen,
+                        "moves": bookmark.moves,
+                        "analysis": bookmark.analysis,
+                    })
+                })
+                .collect();
+            json!({"type": "bookmarks", "bookmarks": bookmarks})
+        });
+    }
+
+    fn config(&self, config: &Config) {
+        self.write(
+            config.to_toml().trim_end(),
+            || json!({"type": "config", "config": config}),
+        );
+    }
+
+    fn advice(&self, advice: &SecondBestAdvice) {
+        self.write(advice, || {
+            let line = |line: &Line| {
+                json!({
+                    "score": line.score,
+                    "pv": line.pv.iter().map(|pmove| pmove.to_string()).collect::<Vec<_>>(),
+                })
+            };
+            json!({
+                "type": "advice",
+                "last_move": advice.last_move.to_string(),
+                "allow": advice.allow.as_ref().map(line),
+                "call": line(&advice.call),
+                "difference": advice.difference(),
+            })
+        });
+    }
+
+    /// Whether "Second Best!" should be called on every move, one per line.
+    fn second_best_map(&self, map: &[SecondBestMapEntry]) {
+        let text = match map.is_empty() {
+            true => "\"Second Best!\" can't be called on any move.".to_string(),
+            false => map
+                .iter()
+                .map(|entry| entry.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+        self.write(text, || {
+            let entries: Vec<_> = map
+                .iter()
+                .map(|entry| {
+                    json!({
+                        "move": entry.pmove.to_string(),
+                        "refuted": entry.refuted(),
+                        "allow": entry.advice.allow.as_ref().map(|line| line.score),
+                        "call": entry.advice.call.score,
+                        "difference": entry.advice.difference(),
+                    })
+                })
+                .collect();
+            json!({"type": "second_best_map", "moves": entries})
+        });
+    }
+
+    /// The moves of an annotated game, one per line.
+    fn annotations(&self, annotated: &[AnnotatedMove]) {
+        let text: Vec<String> = annotated.iter().map(|amove| amove.to_string()).collect();
+        self.write(text.join("\n"), || {
+            let moves: Vec<_> = annotated
+                .iter()
+                .map(|amove| {
+                    json!({
+                        "ply": amove.ply,
+                        "move": amove.pmove.to_string(),
+                        "annotation": amove.annotation.map(|annotation| annotation.symbol()),
+                        "score": amove.score,
+                        "best_move": amove.best_move.to_string(),
+                        "best_score": amove.best_score,
+                        "missed_win": amove.missed_win,
+                    })
+                })
+                .collect();
+            json!({"type": "annotation", "moves": moves})
+        });
+    }
+
+    /// The result of `prove`, and the positions checked with the reference search.
+    fn verification(&self, verification: &Verification) {
+        let mut text = format!(
+            "Search: {} ({} nodes), line {}",
+            verification.found,
+            verification.nodes,
+            PlayerMove::to_long_form(&verification.pv)
+        );
+        for check in &verification.checks {
+            let found = match check.found {
+                Some(found) if check.passed() => format!("{found}"),
+                Some(found) => format!("{found}, expected {}", check.expected),
+                None => "skipped, too many nodes".to_string(),
+            };
+            text.push_str(&format!(
+                "\nReference search after {} moves: {found} ({} nodes)",
+                check.moves_played, check.nodes
+            ));
+        }
+        self.write(text, || {
+            let checks: Vec<_> = verification
+                .checks
+                .iter()
+                .map(|check| {
+                    json!({
+                        "moves_played": check.moves_played,
+                        "expected": check.expected.to_string(),
+                        "found": check.found.map(|found| found.to_string()),
+                        "nodes": check.nodes,
+                    })
+                })
+                .collect();
+            json!({
+                "type": "verification",
+                "expected": verification.expected.to_string(),
+                "found": verification.found.to_string(),
+                "pv": verification.pv.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                "nodes": verification.nodes,
+                "checks": checks,
+                "passed": verification.passed(),
+            })
+        });
+        match verification.passed() {
+            true => self.text(format!("Verified: {}", verification.expected)),
+            false => self.error(
+                "ProofFailed",
+                format!("Could not verify {}", verification.expected),
+            ),
+        }
+    }
+
+    /// The best moves found, with their scores.
+    fn hints(&self, top: &[(PlayerMove, isize)], ply: isize) {
+        let text: Vec<String> = top
+            .iter()
+            .enumerate()
+            .map(|(i, &(pmove, score))| {
+                format!(
+                    "{}. {pmove}: {}",
+                    i + 1,
+                    analysis::explain_score(score, ply)
+                )
+            })
+            .collect();
+        self.write(text.join("\n"), || {
+            let moves: Vec<_> = top
+                .iter()
+                .map(|&(pmove, score)| {
+                    let (result, moves) = match eval::decode_eval(score, ply) {
+                        ExplainableEval::Win(moves) => ("win", Some(moves)),
+                        ExplainableEval::Loss(moves) => ("loss", Some(moves)),
+                        ExplainableEval::Undetermined(_) => ("undetermined", None),
+                    };
+                    json!({
+                        "move": pmove.to_string(),
+                        "score": score,
+                        "result": result,
+                        "moves": moves,
+                    })
+                })
+                .collect();
+            json!({"type": "hints", "moves": moves})
+        });
+    }
+
+    /// The result of a proof-number search.
+    fn proof(&self, proof: Proof, nodes: usize) {
+        self.write(
+            format!("Proof-number search: {proof} ({nodes} nodes)"),
+            || {
+                let (result, pmove) = match proof {
+                    Proof::Win(pmove) => ("win", Some(pmove.to_string())),
+                    Proof::Loss => ("loss", None),
+                    Proof::Unknown => ("unknown", None),
+                };
+                json!({
+                    "type": "proof",
+                    "result": result,
+                    "move": pmove,
+                    "nodes": nodes,
+                })
+            },
+        );
+    }
+
+    fn position(&self, pos: &Position) {
+        self.write(pos, || {
+            let (winner, reason) = match pos.game_status() {
+                GameStatus::Won { winner, reason } => {
+                    (Some(winner.to_string()), Some(format!("{reason:?}")))
+                }
+                _ => (None, None),
+            };
+            json!({
+                "type": "position",
+                "fen": pos.to_fen(),
+                "side_to_move": pos.current_player().to_string(),
+                "game_over": winner.is_some(),
+                "winner": winner,
+                "reason": reason,
+                "board": pos.to_string(),
+            })
+        });
+    }
+
+    /// The best of the moves searched, when not all moves were searched, or
+    /// the move found by `go`, with the expected reply to ponder on.
+    fn best_move(&self, pmove: PlayerMove, ponder: Option<PlayerMove>) {
+        let text = match ponder {
+            Some(reply) => format!("Best of the searched moves: {pmove}, expecting {reply}"),
+            None => format!("Best of the searched moves: {pmove}"),
+        };
+        self.write(text, || {
+            json!({
+                "type": "bestmove",
+                "move": pmove.to_string(),
+                "ponder": ponder.map(|reply| reply.to_string()),
+            })
+        });
+    }
+
+    /// A move which was played by the solver.
+    fn played(&self, pmove: PlayerMove) {
+        self.write(
+            format!("Played {pmove}"),
+            || json!({"type": "move", "move": pmove.to_string()}),
+        );
+    }
+
+    fn progress(&self, progress: &Progress) {
+        self.write(progress, || {
+            json!({
+                "type": "progress",
+                "task": progress.task,
+                "done": progress.done,
+                "total": progress.total,
+                "nodes": progress.nodes,
+                "time_ms": progress.elapsed.as_millis(),
+                "eta_ms": progress.eta().map(|eta| eta.as_millis()),
+            })
+        });
+    }
+
+    /// A handler which writes the progress of a task to this output.
+    fn progress_handler(&self) -> ProgressHandler {
+        let output = self.clone();
+        Arc::new(move |progress| output.progress(&progress))
+    }
+
+    /// A handler which writes the messages of a task to this output.
+    fn message_handler(&self) -> MessageHandler {
+        let output = self.clone();
+        Arc::new(move |message| output.text(message))
+    }
+
+    /// Write what the searches of the solver report to this output.
+    fn report_searches(&self, solver: &mut Solver) {
+        let info_output = self.clone();
+        solver.set_info_handler(move |info| info_output.info(&info));
+        let currmove_output = self.clone();
+        solver.set_currmove_handler(move |info| currmove_output.currmove(&info));
+        solver.set_progress_handler(self.progress_handler());
+    }
+}
+
+/// Split the `id <n>` off the start of a command, see [`Output`].
+fn split_id(command: &str) -> Result<(Option<u64>, &str), String> {
+    let Some(rest) = command.trim_start().strip_prefix("id ") else {
+        return Ok((None, command));
+    };
+    let rest = rest.trim_start();
+    let (id, command) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    match id.parse() {
+        Ok(id) => Ok((Some(id), command)),
+        Err(_) => Err(format!("Invalid id `{id}`, it should be a number")),
+    }
+}
+
+impl Command {
+    /// Whether the command needs the solver or the background thread,
+    /// so it can't be executed while a search is running.
+    fn needs_solver(&self) -> bool {
+        !matches!(
+            self,
+            Command::Quit
+                | Command::Stop
+                | Command::PonderHit
+                | Command::IsReady
+                | Command::Serve { .. }
+                | Command::SetOutput { .. }
+                | Command::SetLog { .. }
+                | Command::ShowTrace { .. }
+        )
+    }
+}
+
+/// Whether the background thread is working on a request.
+/// It is set when a request is sent, and cleared by the
+/// background thread once the request is handled.
+#[derive(Clone, Default)]
+struct Busy(Arc<(Mutex<bool>, Condvar)>);
+
+impl Busy {
+    fn set(&self, busy: bool) {
+        let (lock, condvar) = &*self.0;
+        *lock.lock().unwrap() = busy;
+        condvar.notify_all();
+    }
+
+    fn is_busy(&self) -> bool {
+        *self.0 .0.lock().unwrap()
+    }
+
+    /// Wait until the current request is handled.
+    fn wait_idle(&self) {
+        let (lock, condvar) = &*self.0;
+        let _idle = condvar
+            .wait_while(lock.lock().unwrap(), |busy| *busy)
+            .unwrap();
+    }
+}
+
+/// Moves taken back with `undo`, which can be played again with `redo`.
+struct UndoneMoves {
+    /// The position the moves can be played in, in compact notation.
+    fen: String,
+    /// The moves leading to that position.
+    history: Vec<PlayerMove>,
+    /// The moves, the one which was taken back first at the end.
+    moves: Vec<PlayerMove>,
+}
+
+impl UndoneMoves {
+    fn new(position: &Position, moves: Vec<PlayerMove>) -> Self {
+        Self {
+            fen: position.to_fen(),
+            history: position.history(),
+            moves,
+        }
+    }
+
+    /// Whether the moves can be played again in the given position.
+    fn is_valid_in(&self, position: &Position) -> bool {
+        self.fen == position.to_fen() && self.history == position.history()
+    }
+}
+
 /// A structure for parsing command line arguments
 /// and then executing them.
 /// Search is run in the background, so that new
 /// commands can be received while running.
 pub struct Cli {
     solver: Arc<Mutex<Solver>>,
+    output: Output,
     abort: Arc<AtomicBool>,
-    sender: Sender<ThreadRequest>,
+    /// The flag of the solver which is set while `go --ponder` searches.
+    ponder: Arc<AtomicBool>,
+    /// The requests, with the id of the command which sent them.
+    sender: Sender<(Option<u64>, ThreadRequest)>,
+    busy: Busy,
+    rng: Rng,
+    /// The color played by the engine in a game started with `newgame`.
+    engine_color: Option<Color>,
+    /// How deep the engine searches its moves.
+    engine_depth: usize,
+    /// The bookmarked positions, loaded when they are first used.
+    bookmarks: Option<Bookmarks>,
+    undone: Option<UndoneMoves>,
+    config: Config,
+}
+
+impl Drop for Cli {
+    fn drop(&mut self) {
+        // Stop the background thread, if it wasn't stopped with `quit` already.
+        self.abort.store(true, Ordering::Relaxed);
+        let _ = self.sender.send((None, ThreadRequest::Quit));
+    }
 }
 
 impl Default for Cli {
@@ -106,162 +1181,1785 @@ impl Default for Cli {
 
 impl Cli {
     pub fn new() -> Self {
+        Self::with_output(Output::new(Arc::new(Mutex::new(std::io::stdout())), false))
+    }
+
+    /// A cli which writes its output to `writer` instead of stdout,
+    /// e.g. to show it in the GUI.
+    pub fn with_writer(writer: impl Write + Send + 'static) -> Self {
+        Self::with_output(Output::new(Arc::new(Mutex::new(writer)), false))
+    }
+
+    fn with_output(output: Output) -> Self {
         let abort = Arc::new(AtomicBool::new(false));
-        let solver = Arc::new(Mutex::new(Solver::new(abort.clone())));
-        let (tx, rx) = mpsc::channel::<ThreadRequest>();
+        let mut solver = Solver::new(abort.clone());
+        output.report_searches(&mut solver);
+        let ponder = solver.ponder_flag();
+        let solver = Arc::new(Mutex::new(solver));
+        let (tx, rx) = mpsc::channel::<(Option<u64>, ThreadRequest)>();
+        let busy = Busy::default();
+        let thread_busy = busy.clone();
+        let thread_output = output.clone();
         std::thread::Builder::new()
             .name("Receiver".to_string())
             .stack_size(5_000_000)
             .spawn(move || loop {
-                if let Ok(request) = rx.recv() {
-                    match request {
-                        ThreadRequest::Quit => return,
-                        ThreadRequest::Search(req) => {
-                            let mut solver = req.solver.lock().unwrap();
-                            solver.be_noisy();
-                            let eval = solver.search(req.depth);
-                            solver.be_quiet();
-                            println!(
-                                "{}",
-                                eval::explain_eval(
-                                    solver.position.current_player(),
-                                    eval,
-                                    solver.position.ply() as isize
-                                )
-                            );
+                let Ok((id, request)) = rx.recv() else {
+                    return;
+                };
+                let output = &thread_output.with_id(id);
+                match request {
+                    ThreadRequest::Quit => return,
+                    ThreadRequest::Search(req) => {
+                        let mut solver = req.solver.lock().unwrap();
+                        // The moves were checked when the request was made.
+                        let eval = solver
+                            .search_moves_with_limits(&req.search_moves, req.limits)
+                            .unwrap();
+                        output.eval(
+                            solver.position.current_player(),
+                            eval,
+                            solver.position.ply() as isize,
+                        );
+                        if !req.search_moves.is_empty() {
+                            if let Some(&pmove) = solver.principal_variation().first() {
+                                output.best_move(pmove, None);
+                            }
                         }
-                        ThreadRequest::GenBench(GenBenchRequest {
-                            abort,
-                            bench_args:
-                                GenBenchArgs {
-                                    num_positions,
-                                    min_moves,
-                                    max_moves,
-                                    min_depth,
-                                    max_depth,
-                                },
-                        }) => {
-                            bench::generate_benchmark_file(
-                                abort,
+                    }
+                    ThreadRequest::PlayBest(req) => {
+                        let solver = &mut *req.solver.lock().unwrap();
+                        let best_move = solver.choose_move(req.limits);
+                        match best_move {
+                            Some(pmove) => {
+                                solver.position.try_make_move(pmove).unwrap();
+                                output.played(pmove);
+                                output.position(&solver.position);
+                            }
+                            None => output.error("GameOver", "The game is already over."),
+                        }
+                    }
+                    ThreadRequest::Go(req) => {
+                        let solver = &mut *req.solver.lock().unwrap();
+                        let best_move = solver.choose_move(req.limits);
+                        // While pondering, the move is only reported once the opponent moved.
+                        let ponder = solver.ponder_flag();
+                        while ponder.load(Ordering::Relaxed) && !solver.abort_search() {
+                            std::thread::sleep(Duration::from_millis(5));
+                        }
+                        match best_move {
+                            Some(pmove) => output.best_move(pmove, solver.expected_reply(pmove)),
+                            None => output.error("GameOver", "The game is already over."),
+                        }
+                    }
+                    ThreadRequest::Advise(req) => {
+                        let solver = &mut *req.solver.lock().unwrap();
+                        match analysis::second_best_advice(solver, req.limits) {
+                            Some(advice) => output.advice(&advice),
+                            None => output.error(
+                                "NoSecondBest",
+                                "\"Second Best!\" can't be called in this position.",
+                            ),
+                        }
+                    }
+                    ThreadRequest::SecondBestMap(req) => {
+                        let solver = &mut *req.solver.lock().unwrap();
+                        let map = analysis::second_best_map(solver, req.limits);
+                        if !solver.abort_search() {
+                            output.second_best_map(&map);
+                        }
+                    }
+                    ThreadRequest::Hint(req) => {
+                        let solver = &mut *req.solver.lock().unwrap();
+                        let top = solver.top_moves(req.n, req.limits);
+                        output.hints(&top, solver.position.ply() as isize);
+                    }
+                    ThreadRequest::DebugSearch(req) => {
+                        let solver = &mut *req.solver.lock().unwrap();
+                        let trace = solver.trace_search(req.depth, req.max_nodes);
+                        match req.out {
+                            Some(path) => match trace.save(&path) {
+                                Ok(()) => output.text(format!(
+                                    "Wrote {} nodes of the search tree to {:?}",
+                                    trace.recorded_nodes(),
+                                    path
+                                )),
+                                Err(e) => output.error(
+                                    "Trace",
+                                    format!("Failed to write the search tree: {e}"),
+                                ),
+                            },
+                            None => output.text(trace),
+                        }
+                    }
+                    ThreadRequest::Prove(req) => {
+                        let position = req.solver.lock().unwrap().position.clone();
+                        let mut solver = ProofSolver::new(req.abort);
+                        let proof = solver.solve(&position, req.limits);
+                        output.proof(proof, solver.nodes());
+                    }
+                    ThreadRequest::GenBench(GenBenchRequest {
+                        abort,
+                        dir,
+                        bench_args:
+                            GenBenchArgs {
                                 num_positions,
-                                min_moves..max_moves,
-                                min_depth..max_depth,
-                            )
-                            .unwrap();
+                                min_moves,
+                                max_moves,
+                                min_depth,
+                                max_depth,
+                                difficulty,
+                                seed,
+                            },
+                    }) => {
+                        let depth_range = match difficulty {
+                            // The value was already checked by the parser.
+                            Some(difficulty) => {
+                                difficulty.parse::<Difficulty>().unwrap().depth_range()
+                            }
+                            None => min_depth.unwrap()..max_depth.unwrap(),
+                        };
+                        if let Err(e) = bench::generate_benchmark_file(
+                            abort,
+                            &dir,
+                            num_positions,
+                            min_moves..max_moves,
+                            depth_range,
+                            seed.unwrap_or_else(|| Rng::from_time().next_u64()),
+                            Some(output.progress_handler()),
+                            output.message_handler(),
+                        ) {
+                            output.error("Bench", format!("Failed to generate benchmark: {e}"));
+                        }
+                    }
+                    ThreadRequest::RunBench(RunBenchRequest {
+                        abort,
+                        dir,
+                        num_threads,
+                        options,
+                        eval_params,
+                        compare,
+                    }) => {
+                        if let Err(e) = bench::run_benchmarks(
+                            abort,
+                            &dir,
+                            num_threads,
+                            options,
+                            eval_params,
+                            compare.as_deref(),
+                            Some(output.progress_handler()),
+                            output.message_handler(),
+                        ) {
+                            output.error("Bench", format!("Failed to run benchmarks: {e}"));
+                        }
+                    }
+                    ThreadRequest::AnalyzeFile(req) => {
+                        let mut solver = Solver::new(req.abort);
+                        solver.options = req.options;
+                        solver.eval_params = req.eval_params;
+                        solver.set_variant(req.variant);
+                        solver.position.set_rules(req.rules);
+                        let progress = Some(output.progress_handler());
+                        let result =
+                            analysis::analyze_file(&mut solver, &req.path, req.depth, progress)
+                                .and_then(|analyses| {
+                                    analysis::write_report(&analyses, &req.report)?;
+                                    Ok(analyses.len())
+                                });
+                        match result {
+                            Ok(num_positions) => output.text(format!(
+                                "Analyzed {num_positions} positions, report written to {:?}",
+                                req.report
+                            )),
+                            Err(e) => {
+                                output.error("Analysis", format!("Failed to analyze the file: {e}"))
+                            }
+                        }
+                    }
+                    ThreadRequest::EstimateDifficulty(req) => {
+                        let mut solver = Solver::new(req.abort);
+                        solver.options = req.options;
+                        solver.eval_params = req.eval_params;
+                        solver.set_variant(req.variant);
+                        solver.position.set_rules(req.rules);
+                        let progress = Some(output.progress_handler());
+                        let result = bench::estimate_difficulty(
+                            &mut solver,
+                            &req.path,
+                            req.max_depth,
+                            progress,
+                        )
+                        .and_then(|estimates| {
+                            bench::write_difficulty_labels(&estimates, &req.labels)?;
+                            Ok(estimates.len())
+                        });
+                        match result {
+                            Ok(num_positions) => output.text(format!(
+                                "Labeled {num_positions} positions, written to {:?}",
+                                req.labels
+                            )),
+                            Err(e) => output.error(
+                                "Difficulty",
+                                format!("Failed to estimate the difficulty: {e}"),
+                            ),
+                        }
+                    }
+                    ThreadRequest::Annotate(req) => {
+                        // A separate solver, so that the searches don't
+                        // report their progress or fill the table.
+                        let mut solver = Solver::new(req.abort);
+                        let position = {
+                            let shared = req.solver.lock().unwrap();
+                            solver.options = shared.options;
+                            solver.eval_params = shared.eval_params;
+                            shared.position.clone()
+                        };
+                        solver.position.set_rules(position.rules());
+                        let (start, moves) = match req.moves {
+                            Some(moves) => (Position::with_variant(position.variant()), moves),
+                            None => {
+                                let mut start = position.clone();
+                                while start.undo_last_player_move().is_some() {}
+                                (start, position.history())
+                            }
+                        };
+                        let result = analysis::annotate_game(
+                            &mut solver,
+                            &start,
+                            &moves,
+                            req.depth,
+                            req.threshold,
+                            Some(output.progress_handler()),
+                        );
+                        match result {
+                            Ok(annotated) => output.annotations(&annotated),
+                            Err(e) => output
+                                .error("Analysis", format!("Failed to annotate the game: {e}")),
                         }
-                        ThreadRequest::RunBench(RunBenchRequest { abort, num_threads }) => {
-                            bench::run_benchmarks(abort, num_threads).unwrap();
+                    }
+                    ThreadRequest::Verify(req) => {
+                        // A separate solver, so that nothing from earlier
+                        // searches is used, and the search info isn't printed.
+                        let mut solver = Solver::new(req.abort);
+                        solver.options = req.options;
+                        solver.eval_params = req.eval_params;
+                        solver.position = *req.position;
+                        match verify::verify(&mut solver, req.expected, req.reference_nodes) {
+                            Some(verification) => output.verification(&verification),
+                            None => output.error("Stopped", "The proof was stopped."),
                         }
                     }
                 }
+                thread_busy.set(false);
             })
             .unwrap();
         Self {
             solver,
+            output,
             abort,
+            ponder,
             sender: tx,
+            busy,
+            rng: Rng::from_time(),
+            engine_color: None,
+            engine_depth: 0,
+            bookmarks: None,
+            undone: None,
+            config: Config::default(),
+        }
+    }
+
+    /// The position the commands act on.
+    pub fn position(&self) -> Position {
+        self.solver.lock().unwrap().position.clone()
+    }
+
+    /// Set the position the commands act on, e.g. to the position on the
+    /// board of the GUI. Unlike with `set-fen`, the moves played to reach
+    /// it are kept. The house rules of the cli are used.
+    pub fn set_position(&mut self, position: Position) {
+        self.solver.lock().unwrap().set_position(position);
+    }
+
+    /// Use a transposition table shared with other solvers, see [`Solver::share_tt`].
+    pub fn share_tt(&mut self, table: Arc<SharedTranspositionTable>) {
+        self.solver.lock().unwrap().share_tt(table);
+    }
+
+    /// Whether a command is still running in the background, e.g. a search.
+    /// The position can't be used or set until it is done.
+    pub fn is_busy(&self) -> bool {
+        self.busy.is_busy()
+    }
+
+    /// Run a single command given as arguments from the shell, e.g.
+    /// `eval --pos "0 1 2 3" --depth 12`, and wait until it is done.
+    /// The position is set with `--pos` (moves from the start, as with
+    /// `set-pos`) or `--fen` (as with `set-fen`), and `--json` gives
+    /// JSON output. Returns whether the command succeeded, i.e. no
+    /// errors were reported.
+    pub fn run_once(&mut self, args: &[String]) -> Result<bool, Error> {
+        let mut commands = vec![];
+        let mut command = vec![];
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let setup = match arg.as_str() {
+                "--pos" => "set-pos",
+                "--fen" => "set-fen",
+                "--json" => {
+                    commands.push("set-output json".to_string());
+                    continue;
+                }
+                _ => {
+                    command.push(arg.as_str());
+                    continue;
+                }
+            };
+            let Some(value) = args.next() else {
+                self.output
+                    .error("InvalidCommand", format!("Missing value for {arg}"));
+                return Ok(false);
+            };
+            commands.push(format!("{setup} {value}"));
+        }
+        commands.push(command.join(" "));
+        for command in commands {
+            self.execute_command(&command)?;
+            self.busy.wait_idle();
+            if self.output.failed.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// The words which can come at the end of `line`, for completing it:
+    /// the names of the commands for the first word, and the legal moves
+    /// after the moves typed so far for `play` and `set-pos`.
+    pub fn completions(&self, line: &str) -> Vec<String> {
+        let mut words: Vec<&str> = line.split_ascii_whitespace().collect();
+        if !line.ends_with(|c: char| c.is_ascii_whitespace()) {
+            // The word being completed.
+            words.pop();
         }
+        let Some((&command, moves)) = words.split_first() else {
+            return CliArgs::command()
+                .get_subcommands()
+                .flat_map(|command| {
+                    std::iter::once(command.get_name()).chain(command.get_all_aliases())
+                })
+                .map(String::from)
+                .collect();
+        };
+        let mut position = match command {
+            // The position can't be used while a search is running.
+            "play" => match self.solver.try_lock() {
+                Ok(solver) => solver.position.clone(),
+                Err(_) => return vec![],
+            },
+            "set-pos" => match self.solver.try_lock() {
+                Ok(solver) => Position::with_variant(solver.position.variant()),
+                Err(_) => return vec![],
+            },
+            _ => return vec![],
+        };
+        let moves = moves.iter().map(|smove| smove.to_string()).collect();
+        if position.parse_and_play_moves(moves).is_err() {
+            return vec![];
+        }
+        position
+            .legal_moves()
+            .iter()
+            .map(|pmove| pmove.to_string())
+            .collect()
     }
 
     /// Parses and executes the command.
     /// On success: returns whether to quit the cli or not.
-    /// On failure: returns the io error that caused a failure.
+    /// On failure: returns the error that caused a failure, e.g. when
+    /// the output could not be written or the background thread stopped.
     ///
     /// NOTE: invalid commands are not returned as errors, since
     /// these are communicated with the user through the cli
-    pub fn execute_command(&mut self, command: &str) -> Result<bool, std::io::Error> {
+    pub fn execute_command(&mut self, command: &str) -> Result<bool, Error> {
+        self.output.log(format!("> {}", command.trim()));
+        let command = match split_id(command) {
+            Ok((id, command)) => {
+                self.output.id = id;
+                command
+            }
+            Err(message) => {
+                self.output.id = None;
+                self.output.error("InvalidId", message);
+                return Ok(false);
+            }
+        };
         let args = match CliArgs::try_parse_from(command.split_ascii_whitespace()) {
             Ok(args) => args,
             Err(e) => {
-                println!();
-                e.print()?;
-                // Parse error is bad input from user, but not an actual problem.
+                // Written to the output, so that e.g. the console of the GUI shows it.
+                let message = e.render().to_string();
+                let message = message.trim_end();
+                match e.kind() {
+                    ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => self.output.text(message),
+                    // Parse error is bad input from user, but not an actual problem.
+                    _ => self.output.error("InvalidCommand", message),
+                }
                 return Ok(false);
             }
         };
+        if args.command.needs_solver() && self.busy.is_busy() {
+            self.output.error(
+                "Busy",
+                "A search is running, wait for it to finish or use `stop` first.",
+            );
+            return Ok(false);
+        }
+        if args.command.needs_solver() {
+            // The searches started by this command report with its id.
+            self.output
+                .report_searches(&mut self.solver.lock().unwrap());
+        }
         match args.command {
             Command::Quit => {
                 self.abort.store(true, Ordering::Relaxed);
-                self.sender.send(ThreadRequest::Quit).unwrap();
+                // The thread may have stopped already, which is fine since we quit anyway.
+                let _ = self.sender.send((None, ThreadRequest::Quit));
                 return Ok(true);
             }
-            Command::Show => self.solver.lock().unwrap().position.show(),
+            Command::Show => self.output.position(&self.solver.lock().unwrap().position),
+            Command::NewGame {
+                engine_color,
+                depth,
+            } => {
+                self.engine_color = engine_color;
+                self.engine_depth = depth;
+                let mut solver = self.solver.lock().unwrap();
+                let variant = solver.position.variant();
+                solver.set_position(Position::with_variant(variant));
+                self.output.position(&solver.position);
+                if let Some(color) = engine_color {
+                    self.output.text(format!(
+                        "The engine plays as {color}, you play as {}",
+                        color.other()
+                    ));
+                }
+                drop(solver);
+                self.engine_reply()?;
+            }
             Command::SetPos { moves } => {
                 let solver = &mut *self.solver.lock().unwrap();
-                solver.position = Position::default();
+                solver.set_position(Position::with_variant(solver.position.variant()));
                 if let Err(e) = solver.position.parse_and_play_moves(moves) {
-                    Self::display_error_help(e);
+                    self.display_error_help(e);
                 } else {
-                    solver.position.show();
+                    self.output.position(&solver.position);
+                }
+            }
+            Command::SetFen { fen } => {
+                let solver = &mut *self.solver.lock().unwrap();
+                match Position::from_fen_with_variant(&fen.join(" "), solver.position.variant()) {
+                    Ok(position) => {
+                        solver.set_position(position);
+                        self.output.position(&solver.position);
+                    }
+                    Err(e) => self.display_fen_error(e, solver.position.variant()),
                 }
             }
+            Command::Fen => self
+                .output
+                .text(self.solver.lock().unwrap().position.to_fen()),
             Command::Play { moves } => {
                 self.abort.store(false, Ordering::Relaxed);
+                let mut solver = self.solver.lock().unwrap();
+                if self.engine_color == Some(solver.position.current_player())
+                    && !solver.position.game_over()
+                {
+                    self.output
+                        .error("EngineTurn", "It is the engine's turn to move.");
+                } else if let Err(e) = solver.position.parse_and_play_moves(moves) {
+                    self.display_error_help(e);
+                } else {
+                    self.output.position(&solver.position);
+                    drop(solver);
+                    self.engine_reply()?;
+                }
+            }
+            Command::Undo { n } => {
                 let solver = &mut *self.solver.lock().unwrap();
-                if let Err(e) = solver.position.parse_and_play_moves(moves) {
-                    Self::display_error_help(e);
+                let mut undone = match self.undone.take() {
+                    Some(undone) if undone.is_valid_in(&solver.position) => undone.moves,
+                    _ => vec![],
+                };
+                let mut moves = vec![];
+                while moves.len() < n {
+                    let Some(pmove) = solver.position.undo_last_player_move() else {
+                        break;
+                    };
+                    moves.push(pmove.to_string());
+                    undone.push(pmove);
+                }
+                self.undone = Some(UndoneMoves::new(&solver.position, undone));
+                if moves.is_empty() {
+                    self.output
+                        .error("NothingToUndo", "There are no moves to take back.");
+                } else {
+                    self.output.text(format!("Took back {}", moves.join(" ")));
+                    self.output.position(&solver.position);
+                }
+            }
+            Command::Redo { n } => {
+                let solver = &mut *self.solver.lock().unwrap();
+                let undone = match &mut self.undone {
+                    Some(undone) if undone.is_valid_in(&solver.position) => undone,
+                    _ => {
+                        self.output
+                            .error("NothingToRedo", "There are no moves to play again.");
+                        return Ok(false);
+                    }
+                };
+                let mut moves = vec![];
+                while moves.len() < n {
+                    let Some(pmove) = undone.moves.pop() else {
+                        break;
+                    };
+                    // The moves were played in this position before.
+                    solver.position.try_make_move(pmove).unwrap();
+                    moves.push(pmove.to_string());
+                }
+                *undone = UndoneMoves::new(&solver.position, std::mem::take(&mut undone.moves));
+                if moves.is_empty() {
+                    self.output
+                        .error("NothingToRedo", "There are no moves to play again.");
                 } else {
-                    solver.position.show();
+                    self.output.text(format!("Played {}", moves.join(" ")));
+                    self.output.position(&solver.position);
                 }
             }
-            Command::Eval { depth } => {
+            Command::SaveGame { path, black, white } => {
+                let solver = self.solver.lock().unwrap();
+                let mut record = GameRecord::from_position(&solver.position);
+                record.black = black;
+                record.white = white;
+                match record.save(&path) {
+                    Ok(()) => self.output.text(format!("Saved game to {:?}", path)),
+                    Err(e) => self
+                        .output
+                        .error("GameFile", format!("Failed to save the game: {e}")),
+                }
+            }
+            Command::LoadGame { path } => {
+                let solver = &mut *self.solver.lock().unwrap();
+                match GameRecord::load(&path).and_then(|record| record.to_position()) {
+                    Ok(position) => {
+                        solver.set_position(position);
+                        self.output.position(&solver.position);
+                    }
+                    Err(e) => self
+                        .output
+                        .error("GameFile", format!("Failed to load the game: {e}")),
+                }
+            }
+            Command::Bookmark { action } => self.bookmark(action),
+            Command::DedupePositions { input, output } => {
+                let (rules, variant) = {
+                    let solver = self.solver.lock().unwrap();
+                    (solver.position.rules(), solver.position.variant())
+                };
+                match analysis::dedupe_positions(&input, &output, rules, variant) {
+                    Ok(Deduplicated { read, kept }) => self.output.write(
+                        format!("Kept {kept} of {read} positions, written to {:?}", output),
+                        || json!({"type": "deduplicated", "read": read, "kept": kept}),
+                    ),
+                    Err(e) => self
+                        .output
+                        .error("DedupePositions", format!("Failed to deduplicate: {e}")),
+                }
+            }
+            Command::TtSave { path } => {
+                let solver = self.solver.lock().unwrap();
+                match solver.save_transposition_table(&path) {
+                    Ok(num_entries) => self
+                        .output
+                        .text(format!("Saved {num_entries} positions to {:?}", path)),
+                    Err(e) => self.output.error(
+                        "TranspositionTable",
+                        format!("Failed to save the transposition table: {e}"),
+                    ),
+                }
+            }
+            Command::TtLoad { path } => {
+                let solver = &mut *self.solver.lock().unwrap();
+                match solver.load_transposition_table(&path) {
+                    Ok(num_entries) => self
+                        .output
+                        .text(format!("Loaded {num_entries} positions from {:?}", path)),
+                    Err(e) => self.output.error(
+                        "TranspositionTable",
+                        format!("Failed to load the transposition table: {e}"),
+                    ),
+                }
+            }
+            Command::Eval {
+                depth,
+                depth_option,
+                time,
+                nodes,
+                searchmoves,
+                exclude,
+            } => {
+                let search_moves = match self.root_moves(&searchmoves, &exclude) {
+                    Ok(moves) => moves,
+                    Err(e) => {
+                        self.display_error_help(e);
+                        return Ok(false);
+                    }
+                };
+                if search_moves.is_empty() && !exclude.is_empty() {
+                    self.output.error("NoMovesLeft", "All moves are excluded.");
+                    return Ok(false);
+                }
                 self.abort.store(false, Ordering::Relaxed);
                 let solver = self.solver.clone();
-                let req = SearchRequest { solver, depth };
-                self.sender.send(ThreadRequest::Search(req)).unwrap();
+                let limits = SearchLimits {
+                    max_time: time.map(Duration::from_millis),
+                    max_nodes: nodes,
+                    ..SearchLimits::depth(depth.or(depth_option).unwrap_or(self.config.depth))
+                };
+                let req = SearchRequest {
+                    solver,
+                    limits,
+                    search_moves,
+                };
+                self.send(ThreadRequest::Search(req))?;
+            }
+            Command::AdviseSecondBest { depth, time, nodes } => {
+                self.abort.store(false, Ordering::Relaxed);
+                let limits = SearchLimits {
+                    max_time: time.map(Duration::from_millis),
+                    max_nodes: nodes,
+                    ..SearchLimits::depth(depth)
+                };
+                let req = SearchRequest {
+                    solver: self.solver.clone(),
+                    limits,
+                    search_moves: vec![],
+                };
+                self.send(ThreadRequest::Advise(req))?;
+            }
+            Command::SecondBestMap { depth, time, nodes } => {
+                self.abort.store(false, Ordering::Relaxed);
+                let limits = SearchLimits {
+                    max_time: time.map(Duration::from_millis),
+                    max_nodes: nodes,
+                    ..SearchLimits::depth(depth)
+                };
+                let req = SearchRequest {
+                    solver: self.solver.clone(),
+                    limits,
+                    search_moves: vec![],
+                };
+                self.send(ThreadRequest::SecondBestMap(req))?;
+            }
+            Command::Hint {
+                n,
+                depth,
+                time,
+                nodes,
+            } => {
+                self.abort.store(false, Ordering::Relaxed);
+                let limits = SearchLimits {
+                    max_time: time.map(Duration::from_millis),
+                    max_nodes: nodes,
+                    ..SearchLimits::depth(depth)
+                };
+                let req = HintRequest {
+                    solver: self.solver.clone(),
+                    limits,
+                    n,
+                };
+                self.send(ThreadRequest::Hint(req))?;
+            }
+            Command::Solve {
+                algorithm,
+                time,
+                nodes,
+            } => {
+                self.abort.store(false, Ordering::Relaxed);
+                let limits = SearchLimits {
+                    max_time: time.map(Duration::from_millis),
+                    max_nodes: nodes,
+                    ..Default::default()
+                };
+                match algorithm {
+                    Algorithm::Negamax => {
+                        let req = SearchRequest {
+                            solver: self.solver.clone(),
+                            limits,
+                            search_moves: vec![],
+                        };
+                        self.send(ThreadRequest::Search(req))?;
+                    }
+                    Algorithm::Pns => {
+                        let req = ProofRequest {
+                            abort: self.abort.clone(),
+                            solver: self.solver.clone(),
+                            limits,
+                        };
+                        self.send(ThreadRequest::Prove(req))?;
+                    }
+                }
+            }
+            Command::DebugSearch {
+                depth,
+                out,
+                max_nodes,
+            } => {
+                self.abort.store(false, Ordering::Relaxed);
+                let req = DebugSearchRequest {
+                    solver: self.solver.clone(),
+                    depth,
+                    out,
+                    max_nodes,
+                };
+                self.send(ThreadRequest::DebugSearch(req))?;
+            }
+            Command::ShowTrace { path } => match SearchTrace::load(&path) {
+                Ok(trace) => self.output.text(trace),
+                Err(e) => self
+                    .output
+                    .error("Trace", format!("Failed to read the search tree: {e}")),
+            },
+            Command::PlayBest { depth, time, nodes } => {
+                self.abort.store(false, Ordering::Relaxed);
+                let solver = self.solver.clone();
+                let limits = SearchLimits {
+                    max_time: time.map(Duration::from_millis),
+                    max_nodes: nodes,
+                    ..SearchLimits::depth(depth)
+                };
+                let req = SearchRequest {
+                    solver,
+                    limits,
+                    search_moves: vec![],
+                };
+                self.send(ThreadRequest::PlayBest(req))?;
+            }
+            Command::Go { ponder, clock } => {
+                let time_control = match TimeControl::parse(&clock) {
+                    Ok(time_control) => time_control,
+                    Err(e) => {
+                        self.output.error("InvalidTimeControl", e);
+                        return Ok(false);
+                    }
+                };
+                self.abort.store(false, Ordering::Relaxed);
+                self.ponder.store(ponder, Ordering::Relaxed);
+                let limits = time_control.limits(&self.solver.lock().unwrap().position);
+                let req = SearchRequest {
+                    solver: self.solver.clone(),
+                    limits,
+                    search_moves: vec![],
+                };
+                self.send(ThreadRequest::Go(req))?;
+            }
+            Command::PlayRandom => {
+                let solver = &mut *self.solver.lock().unwrap();
+                if solver.position.game_over() {
+                    self.output.error("GameOver", "The game is already over.");
+                } else {
+                    let moves = solver.position.legal_moves();
+                    let pmove = moves[self.rng.below(moves.len())];
+                    solver.position.try_make_move(pmove).unwrap();
+                    self.output.played(pmove);
+                    self.output.position(&solver.position);
+                }
             }
             Command::GenBench(gen_bench_args) => {
                 self.abort.store(false, Ordering::Relaxed);
                 let req = GenBenchRequest {
                     abort: self.abort.clone(),
+                    dir: self.config.benchmarks.clone(),
                     bench_args: gen_bench_args,
                 };
-                self.sender.send(ThreadRequest::GenBench(req)).unwrap();
+                self.send(ThreadRequest::GenBench(req))?;
             }
             Command::Bench {
                 num_threads: threads,
+                compare,
             } => {
                 self.abort.store(false, Ordering::Relaxed);
+                let solver = self.solver.lock().unwrap();
                 let req = RunBenchRequest {
                     abort: self.abort.clone(),
-                    num_threads: threads,
+                    dir: self.config.benchmarks.clone(),
+                    num_threads: threads.unwrap_or(self.config.threads),
+                    options: solver.options,
+                    eval_params: solver.eval_params,
+                    compare,
+                };
+                self.send(ThreadRequest::RunBench(req))?;
+            }
+            Command::AnalyzeFile {
+                path,
+                depth,
+                report,
+            } => {
+                self.abort.store(false, Ordering::Relaxed);
+                let solver = self.solver.lock().unwrap();
+                let req = AnalyzeFileRequest {
+                    abort: self.abort.clone(),
+                    report: report.unwrap_or_else(|| path.with_extension("report.csv")),
+                    path,
+                    depth,
+                    options: solver.options,
+                    eval_params: solver.eval_params,
+                    rules: solver.position.rules(),
+                    variant: solver.position.variant(),
+                };
+                self.send(ThreadRequest::AnalyzeFile(req))?;
+            }
+            Command::EstimateDifficulty {
+                path,
+                max_depth,
+                labels,
+            } => {
+                self.abort.store(false, Ordering::Relaxed);
+                let solver = self.solver.lock().unwrap();
+                let req = EstimateDifficultyRequest {
+                    abort: self.abort.clone(),
+                    labels: labels.unwrap_or_else(|| path.with_extension("labels.txt")),
+                    path,
+                    max_depth,
+                    options: solver.options,
+                    eval_params: solver.eval_params,
+                    rules: solver.position.rules(),
+                    variant: solver.position.variant(),
+                };
+                self.send(ThreadRequest::EstimateDifficulty(req))?;
+            }
+            Command::Annotate {
+                moves,
+                depth,
+                threshold,
+            } => {
+                let moves = match moves.is_empty() {
+                    true => None,
+                    false => {
+                        let mut end = {
+                            let solver = self.solver.lock().unwrap();
+                            let mut end = Position::with_variant(solver.position.variant());
+                            end.set_rules(solver.position.rules());
+                            end
+                        };
+                        let moves = PlayerMove::parse_moves(&moves.join(" ")).and_then(|moves| {
+                            moves
+                                .iter()
+                                .try_for_each(|&pmove| end.try_make_move(pmove))?;
+                            Ok(moves)
+                        });
+                        match moves {
+                            Ok(moves) => Some(moves),
+                            Err(e) => {
+                                self.display_error_help(e);
+                                return Ok(false);
+                            }
+                        }
+                    }
+                };
+                self.abort.store(false, Ordering::Relaxed);
+                let req = AnnotateRequest {
+                    abort: self.abort.clone(),
+                    solver: self.solver.clone(),
+                    moves,
+                    depth,
+                    threshold,
+                };
+                self.send(ThreadRequest::Annotate(req))?;
+            }
+            Command::Prove {
+                moves,
+                expect,
+                reference_nodes,
+            } => {
+                let solver = self.solver.lock().unwrap();
+                let mut position = Position::with_variant(solver.position.variant());
+                position.set_rules(solver.position.rules());
+                if moves.is_empty() {
+                    position = solver.position.clone();
+                } else if let Err(e) = position.parse_and_play_moves(moves) {
+                    self.display_error_help(e);
+                    return Ok(false);
+                }
+                self.abort.store(false, Ordering::Relaxed);
+                let req = VerifyRequest {
+                    abort: self.abort.clone(),
+                    position: Box::new(position),
+                    options: solver.options,
+                    eval_params: solver.eval_params,
+                    expected: expect,
+                    reference_nodes,
                 };
-                self.sender.send(ThreadRequest::RunBench(req)).unwrap();
+                drop(solver);
+                self.send(ThreadRequest::Verify(req))?;
             }
+            Command::TestSuite { path, depth } => match self.run_test_suite(&path, depth) {
+                Ok(()) => {}
+                Err(e @ Error::TestSuiteFailed { .. }) => self.output.error("TestSuiteFailed", e),
+                Err(e) => self
+                    .output
+                    .error("TestSuite", format!("Failed to run the test suite: {e}")),
+            },
+            Command::RunScript { path } => return self.run_script(&path),
+            Command::PonderHit => self.ponder.store(false, Ordering::Relaxed),
             Command::Stop => {
                 self.abort.store(true, Ordering::Relaxed);
+                self.ponder.store(false, Ordering::Relaxed);
+                // Wait for the search to stop, so that the next command
+                // can't be started before the old search sees the abort.
+                self.busy.wait_idle();
+            }
+            Command::IsReady => self.output.text("readyok"),
+            Command::Serve { port } => match serve(port) {
+                Ok(address) => self.output.text(format!("Listening on {address}")),
+                Err(e) => self
+                    .output
+                    .error("Io", format!("Failed to start the server: {e}")),
+            },
+            Command::SetSkill { level } => {
+                self.solver.lock().unwrap().skill = Skill::new(level).unwrap();
+                self.output.text(format!("Set skill level to {level}"));
+            }
+            Command::SetEngine { engine } => {
+                // The value was already checked by the parser.
+                self.solver.lock().unwrap().engine = engine.parse().unwrap();
+                self.output.text(format!("Set engine to {engine}"));
+            }
+            Command::SetOutput { format } => {
+                self.output
+                    .json
+                    .store(format == OutputFormat::Json, Ordering::Relaxed);
+                let name = format.to_possible_value().unwrap();
+                self.output
+                    .text(format!("Set output to {}", name.get_name()));
+            }
+            Command::SetLog { path: Some(path) } => match Log::open(&path) {
+                Ok(log) => {
+                    *self.output.log.lock().unwrap() = Some(log);
+                    self.output.text(format!("Logging to {}", path.display()));
+                }
+                Err(e) => self
+                    .output
+                    .error("Io", format!("Failed to open the log file: {e}")),
+            },
+            Command::SetLog { path: None } => {
+                self.output.text("Stopped logging");
+                *self.output.log.lock().unwrap() = None;
             }
+            Command::Config { action } => self.config(action),
+            Command::SetOption { name, value } => {
+                let solver = &mut *self.solver.lock().unwrap();
+                match solver.set_option(&name, &value) {
+                    Ok(()) => self.output.text(format!("Set option {name} to {value}")),
+                    Err(e @ InvalidOption::UnknownName) => self
+                        .output
+                        .error(&format!("{e:?}"), format!("Unknown option: {name}")),
+                    Err(e @ InvalidOption::InvalidValue) => self.output.error(
+                        &format!("{e:?}"),
+                        format!("Invalid value for option {name}: {value}"),
+                    ),
+                }
+            }
+            Command::SetSearchDriver { driver } => {
+                let solver = &mut *self.solver.lock().unwrap();
+                // The value was already checked by the parser.
+                solver.options.driver = driver.parse().unwrap();
+                self.output.text(format!("Set search driver to {driver}"));
+            }
+            Command::SetRule { name, value } => {
+                let solver = &mut *self.solver.lock().unwrap();
+                let mut rules = solver.position.rules();
+                match rules.set(&name, &value) {
+                    Ok(()) => {
+                        solver.set_rules(rules);
+                        self.output.text(format!("Set rule {name} to {value}"));
+                    }
+                    Err(e @ InvalidOption::UnknownName) => self
+                        .output
+                        .error(&format!("{e:?}"), format!("Unknown rule: {name}")),
+                    Err(e @ InvalidOption::InvalidValue) => self.output.error(
+                        &format!("{e:?}"),
+                        format!("Invalid value for rule {name}: {value}"),
+                    ),
+                }
+            }
+            Command::SetVariant { stacks, height } => match Variant::new(stacks, height) {
+                Ok(variant) => {
+                    let solver = &mut *self.solver.lock().unwrap();
+                    solver.set_variant(variant);
+                    self.output.text(format!("Set variant to {variant}"));
+                    self.output.position(&solver.position);
+                }
+                Err(e) => self.output.error(
+                    &format!("{e:?}"),
+                    match e {
+                        InvalidVariant::InvalidStacks => {
+                            "The number of stacks should be even and at least 4."
+                        }
+                        InvalidVariant::TooLow => "A stack should hold at least 3 stones.",
+                        InvalidVariant::TooLarge => {
+                            "The board is too large, it should have fewer or lower stacks."
+                        }
+                        InvalidVariant::ParseError => "The size should be written as e.g. 6x4.",
+                    },
+                ),
+            },
         }
         Ok(false)
     }
 
-    fn display_error_help(error: MoveFailed) {
-        match error {
-            MoveFailed::InvalidFromSpot => println!("Invalid \"from\" spot in the given move."),
-            MoveFailed::InvalidToSpot => println!("Invalid \"to\" spot in the given move."),
-            MoveFailed::InvalidSecondBest => {
-                println!("Second best can not be called anymore on this move.")
+    /// The moves to search in the current position, given the moves of
+    /// `--searchmoves` and `--exclude`. Empty if all moves are searched,
+    /// or if all of them are excluded.
+    fn root_moves(
+        &self,
+        searchmoves: &[String],
+        exclude: &[String],
+    ) -> Result<Vec<PlayerMove>, MoveFailed> {
+        let searchmoves = PlayerMove::parse_moves(&searchmoves.join(" "))?;
+        let exclude = PlayerMove::parse_moves(&exclude.join(" "))?;
+        let position = &self.solver.lock().unwrap().position;
+        for &pmove in searchmoves.iter().chain(&exclude) {
+            position.is_legal(pmove)?;
+        }
+        if exclude.is_empty() {
+            return Ok(searchmoves);
+        }
+        Ok(position
+            .legal_moves()
+            .into_iter()
+            .filter(|pmove| !exclude.contains(pmove))
+            .collect())
+    }
+
+    /// In a game against the engine (see `newgame`), let the engine
+    /// play its move if it is its turn.
+    fn engine_reply(&self) -> Result<(), Error> {
+        let Some(engine_color) = self.engine_color else {
+            return Ok(());
+        };
+        {
+            let position = &self.solver.lock().unwrap().position;
+            if position.game_over() || position.current_player() != engine_color {
+                return Ok(());
+            }
+        }
+        self.abort.store(false, Ordering::Relaxed);
+        let req = SearchRequest {
+            solver: self.solver.clone(),
+            limits: SearchLimits::depth(self.engine_depth),
+            search_moves: vec![],
+        };
+        self.send(ThreadRequest::PlayBest(req))
+    }
+
+    /// Run the test suite in the given file with the options of the cli.
+    /// Returns an error if any of the positions failed.
+    fn run_test_suite(&self, path: &Path, depth: usize) -> Result<(), Error> {
+        let cases = analysis::parse_test_suite(&std::fs::read_to_string(path)?)?;
+        self.abort.store(false, Ordering::Relaxed);
+        // A separate solver, so that the search info isn't printed, and
+        // the position of the cli isn't changed.
+        let mut solver = Solver::new(self.abort.clone());
+        {
+            let cli_solver = self.solver.lock().unwrap();
+            solver.options = cli_solver.options;
+            solver.eval_params = cli_solver.eval_params;
+            solver.position.set_rules(cli_solver.position.rules());
+        }
+        let start = Instant::now();
+        let mut failed = 0;
+        for case in &cases {
+            let now = Instant::now();
+            let failure = analysis::run_test_case(&mut solver, case, depth);
+            let time = now.elapsed().as_secs_f64();
+            match failure {
+                None => self
+                    .output
+                    .text(format!("Line {}: passed ({time:.3}s)", case.line)),
+                Some(reason) => {
+                    failed += 1;
+                    self.output
+                        .text(format!("Line {}: FAILED, {reason} ({time:.3}s)", case.line));
+                }
+            }
+        }
+        self.output.text(format!(
+            "Passed {}/{} positions in {:.3}s",
+            cases.len() - failed,
+            cases.len(),
+            start.elapsed().as_secs_f64()
+        ));
+        match failed {
+            0 => Ok(()),
+            _ => Err(Error::TestSuiteFailed {
+                failed,
+                total: cases.len(),
+            }),
+        }
+    }
+
+    /// Use the given config, e.g. loaded from the config file. The
+    /// transposition table is cleared if its size changes.
+    pub fn set_config(&mut self, config: Config) -> Result<(), Error> {
+        let mut solver = self.solver.lock().unwrap();
+        if solver.tt_size() != config.tt_size {
+            solver.set_tt_size(config.tt_size);
+        }
+        for (name, value) in &config.options {
+            solver.set_option(name, value)?;
+        }
+        drop(solver);
+        self.config = config;
+        Ok(())
+    }
+
+    fn config(&mut self, action: ConfigAction) {
+        let (name, value) = match action {
+            ConfigAction::Show => {
+                self.output.config(&self.config);
+                return;
+            }
+            ConfigAction::Set { name, value } => (name, value),
+        };
+        let mut config = self.config.clone();
+        match config.set(&name, &value) {
+            Ok(()) => (),
+            Err(e @ InvalidOption::UnknownName) => {
+                return self
+                    .output
+                    .error(&format!("{e:?}"), format!("Unknown config value: {name}"));
+            }
+            Err(e @ InvalidOption::InvalidValue) => {
+                return self.output.error(
+                    &format!("{e:?}"),
+                    format!("Invalid value for {name}: {value}"),
+                );
+            }
+        }
+        // Only apply the changed value, to keep the options set with `set-option`.
+        let mut solver = self.solver.lock().unwrap();
+        if name == "tt-size" {
+            solver.set_tt_size(config.tt_size);
+        } else if config.options.contains_key(&name) {
+            // The value was checked by the config.
+            solver.set_option(&name, &value).unwrap();
+        }
+        drop(solver);
+        self.config = config;
+        self.output.text(format!("Set {name} to {value}"));
+    }
+
+    fn bookmark(&mut self, action: BookmarkAction) {
+        if self.bookmarks.is_none() {
+            match Bookmarks::load(Path::new(Bookmarks::DEFAULT_PATH)) {
+                Ok(bookmarks) => self.bookmarks = Some(bookmarks),
+                Err(e) => {
+                    self.output
+                        .error("Io", format!("Failed to load the bookmarks: {e}"));
+                    return;
+                }
             }
-            MoveFailed::MissingFromSpot => {
-                println!("The \"from\" spot was not given for the given move.")
+        }
+        let bookmarks = self.bookmarks.as_mut().unwrap();
+        match action {
+            BookmarkAction::Save { name, analysis } => {
+                let mut solver = self.solver.lock().unwrap();
+                let lines = match analysis {
+                    true => solver.root_move_lines(),
+                    false => vec![],
+                };
+                let bookmark = Bookmark::new(&solver.position, &lines);
+                match bookmarks.insert(&name, bookmark) {
+                    Ok(()) => self
+                        .output
+                        .text(format!("Bookmarked the position as {name}")),
+                    Err(e) => self
+                        .output
+                        .error("Io", format!("Failed to save the bookmarks: {e}")),
+                }
             }
-            MoveFailed::MoveBanned => println!(
-                "The given move can not be played anymore, since \"Second Best!\" was called."
+            BookmarkAction::Load { name } => {
+                let Some(bookmark) = bookmarks.get(&name) else {
+                    self.output
+                        .error("Bookmark", format!("There is no bookmark named {name}"));
+                    return;
+                };
+                let variant = bookmark.variant;
+                let solver = &mut *self.solver.lock().unwrap();
+                match bookmark.position(solver.position.rules()) {
+                    Ok(position) => {
+                        solver.set_position(position);
+                        self.output.position(&solver.position);
+                        for line in &bookmark.analysis {
+                            self.output.text(line);
+                        }
+                    }
+                    Err(e) => self.display_fen_error(e, variant),
+                }
+            }
+            BookmarkAction::List => self.output.bookmarks(bookmarks),
+            BookmarkAction::Delete { name } => match bookmarks.remove(&name) {
+                Ok(true) => self.output.text(format!("Removed the bookmark {name}")),
+                Ok(false) => self
+                    .output
+                    .error("Bookmark", format!("There is no bookmark named {name}")),
+                Err(e) => self
+                    .output
+                    .error("Io", format!("Failed to save the bookmarks: {e}")),
+            },
+        }
+    }
+
+    /// Run the commands of a script, see [`Command::RunScript`].
+    /// Returns whether one of the commands quit the cli.
+    fn run_script(&mut self, path: &Path) -> Result<bool, Error> {
+        let script = match std::fs::read_to_string(path) {
+            Ok(script) => script,
+            Err(e) => {
+                self.output.error(
+                    "Io",
+                    format!("Failed to read the script {}: {e}", path.display()),
+                );
+                return Ok(false);
+            }
+        };
+        for (i, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            self.output.failed.store(false, Ordering::Relaxed);
+            let result = self.execute_command(line);
+            self.busy.wait_idle();
+            let failed = self.output.failed.load(Ordering::Relaxed);
+            if result.is_err() || failed {
+                self.output.error(
+                    "Script",
+                    format!(
+                        "{}:{}: `{line}` failed, the rest of the script is skipped.",
+                        path.display(),
+                        i + 1
+                    ),
+                );
+            }
+            if result? {
+                return Ok(true);
+            }
+            if failed {
+                return Ok(false);
+            }
+        }
+        Ok(false)
+    }
+
+    fn send(&self, request: ThreadRequest) -> Result<(), Error> {
+        self.busy.set(true);
+        self.sender.send((self.output.id, request)).map_err(|_| {
+            self.busy.set(false);
+            Error::ThreadStopped
+        })
+    }
+
+    fn display_fen_error(&self, error: InvalidFen, variant: Variant) {
+        let code = format!("{error:?}");
+        self.output.error(&code, match error {
+            InvalidFen::WrongNumberOfFields => "The notation should consist of four parts: stacks, side to move, last move and banned move.".to_string(),
+            InvalidFen::WrongNumberOfStacks => format!(
+                "There should be {} stacks separated by a '/'.",
+                variant.stacks()
+            ),
+            InvalidFen::InvalidStack(stack_i) => format!(
+                "Stack {stack_i} is invalid. It should be '.' or at most {} 'X' or 'O'.",
+                variant.height()
             ),
-            MoveFailed::SameFromAndTo => {
-                println!("The \"from\" and \"to\" spot in the given move are the same.")
+            InvalidFen::InvalidSide => "The side to move should be 'X' or 'O'.".to_string(),
+            InvalidFen::InvalidStoneCount => {
+                "The number of stones of each player doesn't match the side to move.".to_string()
             }
-            MoveFailed::ParseError => {
-                println!(
+            InvalidFen::InvalidLastMove => {
+                "The last move could not have been played in this position.".to_string()
+            }
+            InvalidFen::InvalidBannedMove => {
+                "The banned move is not a valid move in this position.".to_string()
+            }
+        });
+    }
+
+    fn display_error_help(&self, error: MoveFailed) {
+        let code = format!("{error:?}");
+        self.output.error(
+            &code,
+            match error {
+                MoveFailed::InvalidFromSpot => "Invalid \"from\" spot in the given move.",
+                MoveFailed::InvalidToSpot => "Invalid \"to\" spot in the given move.",
+                MoveFailed::InvalidSecondBest => {
+                    "Second best can not be called anymore on this move."
+                }
+                MoveFailed::MissingFromSpot => {
+                    "The \"from\" spot was not given for the given move."
+                }
+                MoveFailed::MoveBanned => {
+                    "The given move can not be played anymore, since \"Second Best!\" was called."
+                }
+                MoveFailed::SameFromAndTo => {
+                    "The \"from\" and \"to\" spot in the given move are the same."
+                }
+                MoveFailed::ParseError => {
                     "The given move could not be parsed into a move.\n\
                 It should be either a '!' (representing a \"Second Best!\" call),\n\
                 a single number indicating the stack to move to, \n\
                 or two numbers separated by a '-' indicating the stacks to move from and to."
-                )
+                }
+                MoveFailed::PositionWinning => {
+                    "The opponent has an alignment, so \"Second Best!\" should be called."
+                }
+            },
+        );
+    }
+}
+
+/// A command sent to the server.
+#[derive(Deserialize)]
+struct Request {
+    command: String,
+    /// Given back with the output of the command, as with `id <n>`.
+    id: Option<u64>,
+}
+
+/// Start listening for connections on the given port in the background.
+/// Returns the address that is listened on.
+fn serve(port: u16) -> std::io::Result<SocketAddr> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let address = listener.local_addr()?;
+    std::thread::Builder::new()
+        .name("Server".to_string())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = std::thread::Builder::new()
+                    .name("Client".to_string())
+                    .spawn(move || handle_client(stream));
             }
-            MoveFailed::PositionWinning => {
-                println!("The opponent has an alignment, so \"Second Best!\" should be called.")
+        })?;
+    Ok(address)
+}
+
+/// Run the commands sent by a client, until it quits or disconnects.
+fn handle_client(stream: TcpStream) -> Result<(), Error> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let output = Output::new(Arc::new(Mutex::new(stream)), true);
+    let mut cli = Cli::with_output(output.clone());
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let command = match request.id {
+                    Some(id) => format!("id {id} {}", request.command),
+                    None => request.command,
+                };
+                if cli.execute_command(&command)? {
+                    break;
+                }
             }
+            Err(e) => output.error("InvalidRequest", format!("Invalid request: {e}")),
         }
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn busy_while_searching() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), false));
+        let output = || String::from_utf8(std::mem::take(&mut *buffer.lock().unwrap())).unwrap();
+
+        cli.execute_command("eval 100").unwrap();
+        cli.execute_command("set-pos 0").unwrap();
+        cli.execute_command("isready").unwrap();
+        let text = output();
+        assert!(text.contains("A search is running"));
+        assert!(text.contains("readyok"));
+
+        cli.execute_command("stop").unwrap();
+        output();
+        cli.execute_command("set-pos 0").unwrap();
+        assert!(!output().contains("A search is running"));
+        assert!(cli.execute_command("quit").unwrap());
+    }
+
+    #[test]
+    fn completions() {
+        let mut cli = Cli::default();
+        let commands = cli.completions("");
+        for command in ["play", "set-pos", "quit", "exit", "newgame"] {
+            assert!(commands.contains(&command.to_string()));
+        }
+        assert_eq!(cli.completions("pl"), commands);
+        assert!(cli.completions("show ").is_empty());
+
+        // All stone moves in the first phase.
+        let moves: Vec<String> = (0..8).map(|i| i.to_string()).collect();
+        assert_eq!(cli.completions("play "), moves);
+        cli.execute_command("set-pos 0").unwrap();
+        let moves = cli.completions("play 1 ");
+        assert!(moves.contains(&"!".to_string()));
+        assert_eq!(cli.completions("set-pos 0 1 "), moves);
+        // No moves after an illegal move.
+        assert!(cli.completions("play 1 ! 1 ").is_empty());
+    }
+
+    #[test]
+    fn set_variant() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), false));
+        let output = || String::from_utf8(std::mem::take(&mut *buffer.lock().unwrap())).unwrap();
+
+        cli.execute_command("set-variant 6 4").unwrap();
+        assert!(output().contains("Set variant to 6x4"));
+        cli.execute_command("set-pos 5 5 5 5").unwrap();
+        output();
+        cli.execute_command("fen").unwrap();
+        assert!(output().contains("./././././XOXO X 5 -"));
+
+        cli.execute_command("set-variant 5 3").unwrap();
+        assert!(!output().contains("Set variant"));
+        cli.execute_command("set-variant 8 3").unwrap();
+        assert!(output().contains("Set variant to 8x3"));
+    }
+
+    #[test]
+    fn run_once() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), false));
+        let output = || String::from_utf8(std::mem::take(&mut *buffer.lock().unwrap())).unwrap();
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        // The search is done before it returns.
+        let ok = cli
+            .run_once(&args(&[
+                "eval", "--pos", "0 1 4 5", "--depth", "3", "--json",
+            ]))
+            .unwrap();
+        assert!(ok);
+        let text = output();
+        assert!(text.lines().any(|line| line.contains("\"type\":\"eval\"")));
+        assert_eq!(cli.solver.lock().unwrap().position.ply(), 4);
+
+        assert!(!cli.run_once(&args(&["play", "--pos", "0 0 0 0"])).unwrap());
+        assert!(output().contains("InvalidToSpot"));
+        assert!(!cli.run_once(&args(&["eval", "--fen"])).unwrap());
+    }
+
+    #[test]
+    fn go() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), false));
+        let output = || String::from_utf8(std::mem::take(&mut *buffer.lock().unwrap())).unwrap();
+        let args = |args: &str| {
+            args.split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+
+        let mut go = args("go btime 500 wtime 500 binc 10 winc 10 --pos");
+        go.push("0 1".to_string());
+        let ok = cli.run_once(&go).unwrap();
+        assert!(ok);
+        assert!(output().contains("Best of the searched moves"));
+        // The move isn't played.
+        assert_eq!(cli.solver.lock().unwrap().position.ply(), 2);
+
+        assert!(!cli.run_once(&args("go btime 500 movetime 100")).unwrap());
+        assert!(output().contains("Unknown time control `movetime`"));
+    }
+
+    #[test]
+    fn ponder() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), false));
+        let output = || String::from_utf8(std::mem::take(&mut *buffer.lock().unwrap())).unwrap();
+
+        // The clock doesn't run while pondering.
+        cli.execute_command("set-pos 0 1").unwrap();
+        cli.execute_command("go --ponder btime 100 wtime 100")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(cli.busy.is_busy());
+        assert!(!output().contains("Best of the searched moves"));
+        cli.execute_command("ponderhit").unwrap();
+        cli.busy.wait_idle();
+        assert!(output().contains("Best of the searched moves"));
+
+        // Stopping reports the move as well, and the next search doesn't ponder.
+        cli.execute_command("go --ponder btime 100 wtime 100")
+            .unwrap();
+        cli.execute_command("stop").unwrap();
+        assert!(output().contains("Best of the searched moves"));
+        cli.execute_command("go btime 100 wtime 100").unwrap();
+        cli.busy.wait_idle();
+        assert!(output().contains("Best of the searched moves"));
+    }
+
+    #[test]
+    fn request_ids() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), true));
+        let output = || String::from_utf8(std::mem::take(&mut *buffer.lock().unwrap())).unwrap();
+
+        // The output of a search which was stopped keeps its id, even
+        // when it is written after the next command.
+        cli.execute_command("id 1 eval 100").unwrap();
+        cli.execute_command("id 2 stop").unwrap();
+        cli.execute_command("id 3 eval 3").unwrap();
+        cli.busy.wait_idle();
+        let lines: Vec<serde_json::Value> = output()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let ids: Vec<_> = lines.iter().map(|line| line["id"].as_u64()).collect();
+        let first_of_3 = ids.iter().position(|&id| id == Some(3)).unwrap();
+        assert!(ids[..first_of_3].iter().all(|&id| id == Some(1)));
+        assert!(ids[first_of_3..].iter().all(|&id| id == Some(3)));
+        assert!(lines.last().unwrap()["type"] == "eval");
+
+        // Commands without an id have none in their output.
+        cli.execute_command("isready").unwrap();
+        assert!(!output().contains("\"id\""));
+        cli.execute_command("id two isready").unwrap();
+        assert!(output().contains("InvalidId"));
+
+        cli.execute_command("set-output text").unwrap();
+        output();
+        cli.execute_command("id 4 isready").unwrap();
+        assert_eq!(output(), "id 4 readyok\n");
+    }
+
+    #[test]
+    fn run_script() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), false));
+        let output = || String::from_utf8(std::mem::take(&mut *buffer.lock().unwrap())).unwrap();
+        let path = std::env::temp_dir().join("second_best_script_test.txt");
+
+        // Every search is done before the next command.
+        let script = "# Set up the position\nset-pos 0 1\n\neval 3\nplay 4\neval 2\nfen\n";
+        std::fs::write(&path, script).unwrap();
+        let command = format!("run-script {}", path.display());
+        assert!(!cli.execute_command(&command).unwrap());
+        let text = output();
+        assert!(!text.contains("A search is running"));
+        assert!(text.contains("X/O/././X/././. O 4 -"));
+        assert_eq!(cli.solver.lock().unwrap().position.ply(), 3);
+
+        // The script stops at the first failing command.
+        std::fs::write(&path, "set-pos 0\nplay 0 0 0\nplay 1\n").unwrap();
+        let args: Vec<String> = command.split(' ').map(String::from).collect();
+        assert!(!cli.run_once(&args).unwrap());
+        let text = output();
+        assert!(text.contains(":2: `play 0 0 0` failed"));
+        // The moves before the illegal one are played, but `play 1` isn't.
+        assert_eq!(cli.solver.lock().unwrap().position.ply(), 3);
+
+        std::fs::write(&path, "set-pos 0\nquit\nplay 1\n").unwrap();
+        assert!(cli.execute_command(&command).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn undo_redo() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), false));
+        let output = || String::from_utf8(std::mem::take(&mut *buffer.lock().unwrap())).unwrap();
+        let fen = |cli: &Cli| cli.solver.lock().unwrap().position.to_fen();
+
+        cli.execute_command("set-pos 0 1 2 ! 3 4").unwrap();
+        let end = fen(&cli);
+        cli.execute_command("undo 2").unwrap();
+        assert!(output().contains("Took back 4 3"));
+        // "Second Best!" calls are taken back as a move of their own.
+        cli.execute_command("undo").unwrap();
+        assert!(output().contains("Took back !"));
+        let before_call = fen(&cli);
+        cli.execute_command("redo 2").unwrap();
+        assert!(output().contains("Played ! 3"));
+        cli.execute_command("redo 5").unwrap();
+        assert_eq!(fen(&cli), end);
+        cli.execute_command("redo").unwrap();
+        assert!(output().contains("There are no moves to play again"));
+
+        cli.execute_command("undo 10").unwrap();
+        assert_eq!(cli.solver.lock().unwrap().position.ply(), 0);
+        cli.execute_command("undo").unwrap();
+        assert!(output().contains("There are no moves to take back"));
+        cli.execute_command("redo 3").unwrap();
+        assert_eq!(fen(&cli), before_call);
+        // The moves can't be played again after another move.
+        cli.execute_command("play 7").unwrap();
+        cli.execute_command("redo").unwrap();
+        assert!(output().contains("There are no moves to play again"));
+    }
+
+    #[test]
+    fn bookmarks() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), false));
+        let output = || String::from_utf8(std::mem::take(&mut *buffer.lock().unwrap())).unwrap();
+        let path = std::env::temp_dir().join("second_best_cli_bookmarks_test.json");
+        let _ = std::fs::remove_file(&path);
+        cli.bookmarks = Some(Bookmarks::load(&path).unwrap());
+
+        cli.execute_command("bookmark list").unwrap();
+        assert!(output().contains("There are no bookmarks"));
+        cli.execute_command("set-pos 0 1 4").unwrap();
+        cli.execute_command("eval 3").unwrap();
+        cli.busy.wait_idle();
+        cli.execute_command("bookmark save main --analysis")
+            .unwrap();
+        let fen = cli.solver.lock().unwrap().position.to_fen();
+        cli.execute_command("set-pos 2").unwrap();
+        cli.execute_command("bookmark save other").unwrap();
+        output();
+
+        cli.execute_command("bookmark load main").unwrap();
+        assert_eq!(cli.solver.lock().unwrap().position.ply(), 3);
+        // The analysis is shown with the position.
+        assert!(output().contains("score"));
+        cli.execute_command("bookmark list").unwrap();
+        let text = output();
+        assert!(text.contains(&format!("main: {fen}")));
+        assert!(text.contains("other: "));
+
+        cli.execute_command("bookmark delete other").unwrap();
+        cli.execute_command("bookmark load other").unwrap();
+        assert!(output().contains("There is no bookmark named other"));
+        // The bookmarks are kept in the file.
+        assert!(Bookmarks::load(&path).unwrap().get("main").is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn config() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), false));
+        let output = || String::from_utf8(std::mem::take(&mut *buffer.lock().unwrap())).unwrap();
+
+        let config = Config {
+            depth: 2,
+            tt_size: 4,
+            options: [("mobility".to_string(), "7".to_string())].into(),
+            ..Default::default()
+        };
+        cli.set_config(config).unwrap();
+        assert_eq!(cli.solver.lock().unwrap().tt_size(), 4);
+        assert_eq!(cli.solver.lock().unwrap().eval_params.mobility, 7);
+        // The depth of `eval` if none is given.
+        cli.execute_command("eval").unwrap();
+        cli.busy.wait_idle();
+        let text = output();
+        assert!(text.contains("info depth 2"));
+        assert!(!text.contains("info depth 3"));
+
+        cli.execute_command("config set tt-size 2").unwrap();
+        assert_eq!(output().trim(), "Set tt-size to 2");
+        assert_eq!(cli.solver.lock().unwrap().tt_size(), 2);
+        // Changing another value keeps the options set with `set-option`.
+        cli.execute_command("set-option mobility 3").unwrap();
+        cli.execute_command("config set depth 4").unwrap();
+        assert_eq!(cli.solver.lock().unwrap().eval_params.mobility, 3);
+        cli.execute_command("config set threads 0").unwrap();
+        assert!(output().contains("Invalid value for threads: 0"));
+        cli.execute_command("config set speed 1").unwrap();
+        assert!(output().contains("Unknown config value: speed"));
+
+        cli.execute_command("config show").unwrap();
+        let text = output();
+        assert!(text.contains("depth = 4\ntt-size = 2\n"));
+        assert!(text.contains("[options]\nmobility = \"7\""));
+    }
+
+    #[test]
+    fn shared_position() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), false));
+        let tt = Arc::new(SharedTranspositionTable::with_size_mb(1));
+        cli.share_tt(tt.clone());
+        let mut position = Position::default();
+        position.make_phase_one_move(3);
+        cli.set_position(position.clone());
+        cli.execute_command("play 4").unwrap();
+        position.make_phase_one_move(4);
+        assert_eq!(cli.position().history(), position.history());
+        cli.execute_command("eval 2").unwrap();
+        cli.busy.wait_idle();
+        assert!(!cli.is_busy());
+        // The search used the shared table.
+        assert!(tt.num_entries() > 0);
+    }
+
+    #[test]
+    fn test_suite() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), false));
+        let path = std::env::temp_dir().join("second_best_cli_test_suite.txt");
+        std::fs::write(&path, "0 1 0 2 0; result win 1\n").unwrap();
+        // A failing suite is reported, without ending the session.
+        let quit = cli.execute_command(&format!("test-suite {} 2", path.display()));
+        assert!(matches!(quit, Ok(false)));
+        assert!(cli.output.failed.load(Ordering::Relaxed));
+        let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("Line 1: FAILED"));
+        assert!(output.contains("1 of 1 test positions failed"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn log_file() {
+        let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut cli = Cli::with_output(Output::new(buffer.clone(), false));
+        let path = std::env::temp_dir().join("second_best_cli_log_test.log");
+        let _ = std::fs::remove_file(&path);
+
+        cli.execute_command("show").unwrap();
+        cli.execute_command(&format!("set-log {}", path.display()))
+            .unwrap();
+        cli.execute_command("eval 3").unwrap();
+        cli.busy.wait_idle();
+        cli.execute_command("play 0 0 0").unwrap();
+        cli.execute_command("not-a-command").unwrap();
+        cli.execute_command("set-log").unwrap();
+        cli.execute_command("show").unwrap();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = log
+            .lines()
+            .map(|line| line.split_once("] ").unwrap().1)
+            .collect();
+        assert_eq!(lines[0], format!("Logging to {}", path.display()));
+        // The commands, and everything which is output.
+        assert_eq!(lines[1], "> eval 3");
+        assert!(lines.iter().any(|line| line.starts_with("info depth 3")));
+        assert!(lines.contains(&"> play 0 0 0"));
+        assert!(lines.iter().any(|line| line.contains("not-a-command")));
+        assert_eq!(lines.last(), Some(&"Stopped logging"));
+        // Nothing is logged before or after.
+        assert!(!lines.contains(&"> show"));
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,198 @@
+//! The defaults of the engine, which can be set in a config file so that
+//! they don't have to be set again in every session, e.g.
+//!
+//! ```toml
+//! depth = 8
+//! tt-size = 256
+//! threads = 4
+//! benchmarks = "./benchmarks/"
+//!
+//! # Solver options and evaluation weights, as with `set-option`.
+//! [options]
+//! mobility = 2
+//! pvs = true
+//! ```
+//!
+//! Everything is optional, the values which aren't given keep their default.
+
+use crate::bench::BENCHMARKS_PATH;
+use crate::error::{Error, Result};
+use crate::eval::EvalParams;
+use crate::solver::{InvalidOption, Solver, SolverOptions};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// The depth of `eval` if no depth is given.
+    pub depth: usize,
+    /// The size of the transposition table in megabytes.
+    pub tt_size: usize,
+    /// The number of threads of `bench` if it isn't given.
+    pub threads: usize,
+    /// The directory with the benchmark files.
+    pub benchmarks: PathBuf,
+    /// Solver options and evaluation weights by name, as with `set-option`.
+    #[serde(deserialize_with = "deserialize_options")]
+    pub options: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            depth: 5,
+            tt_size: Solver::DEFAULT_TT_SIZE,
+            threads: 1,
+            benchmarks: PathBuf::from(BENCHMARKS_PATH),
+            options: BTreeMap::new(),
+        }
+    }
+}
+
+/// The options can be written as TOML numbers and booleans, but
+/// are kept as strings like the values of `set-option`.
+fn deserialize_options<'de, D>(
+    deserializer: D,
+) -> std::result::Result<BTreeMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let options = BTreeMap::<String, toml::Value>::deserialize(deserializer)?;
+    Ok(options
+        .into_iter()
+        .map(|(name, value)| match value {
+            toml::Value::String(value) => (name, value),
+            value => (name, value.to_string()),
+        })
+        .collect())
+}
+
+impl Config {
+    /// The config file which is loaded at startup, in the working directory.
+    pub const DEFAULT_PATH: &'static str = "second-best.toml";
+
+    /// Load the config from a file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let invalid =
+            |message: String| Error::InvalidConfig(format!("{}: {message}", path.display()));
+        let config: Self = toml::from_str(&text).map_err(|e| invalid(e.message().to_string()))?;
+        // Check the values the same way as when they are set one by one.
+        let mut checked = Self::default();
+        for (name, value) in config.values() {
+            checked
+                .set(&name, &value)
+                .map_err(|e| invalid(format!("{e:?} for {name}")))?;
+        }
+        Ok(config)
+    }
+
+    /// Load the config from [`Self::DEFAULT_PATH`], or use the
+    /// defaults if there is no such file.
+    pub fn load_default() -> Result<Self> {
+        match Self::load(Path::new(Self::DEFAULT_PATH)) {
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            result => result,
+        }
+    }
+
+    /// Set a value by its name in the config file, e.g. `tt-size`, or
+    /// the name of a solver option or evaluation weight.
+    pub fn set(&mut self, name: &str, value: &str) -> std::result::Result<(), InvalidOption> {
+        let parse = |value: &str| {
+            value
+                .parse::<usize>()
+                .map_err(|_| InvalidOption::InvalidValue)
+        };
+        match name {
+            "depth" => self.depth = parse(value)?,
+            "tt-size" => {
+                let size = parse(value)?;
+                if !(1..=Solver::MAX_TT_SIZE).contains(&size) {
+                    return Err(InvalidOption::InvalidValue);
+                }
+                self.tt_size = size;
+            }
+            "threads" => {
+                let threads = parse(value)?;
+                if threads == 0 {
+                    return Err(InvalidOption::InvalidValue);
+                }
+                self.threads = threads;
+            }
+            "benchmarks" => self.benchmarks = PathBuf::from(value),
+            _ => {
+                // Check the option, so that a wrong option in the config is noticed.
+                match SolverOptions::default().set(name, value) {
+                    Err(InvalidOption::UnknownName) => EvalParams::default().set(name, value)?,
+                    result => result?,
+                }
+                self.options.insert(name.to_string(), value.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// All the values by their name, as they can be given to [`Config::set`].
+    pub fn values(&self) -> Vec<(String, String)> {
+        let mut values = vec![
+            ("depth".to_string(), self.depth.to_string()),
+            ("tt-size".to_string(), self.tt_size.to_string()),
+            ("threads".to_string(), self.threads.to_string()),
+            (
+                "benchmarks".to_string(),
+                self.benchmarks.display().to_string(),
+            ),
+        ];
+        values.extend(self.options.clone());
+        values
+    }
+
+    /// The config in the format of the config file.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("the config can be written as TOML")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_and_set() {
+        let path = std::env::temp_dir().join("second_best_config_test.toml");
+        std::fs::write(
+            &path,
+            "depth = 7\ntt-size = 16\n\n[options]\nmobility = 2\npvs = false\n",
+        )
+        .unwrap();
+        let mut config = Config::load(&path).unwrap();
+        assert_eq!(config.depth, 7);
+        assert_eq!(config.tt_size, 16);
+        // The values which aren't given keep their default.
+        assert_eq!(config.threads, 1);
+        assert_eq!(config.options["mobility"], "2");
+        assert_eq!(config.options["pvs"], "false");
+
+        config.set("threads", "4").unwrap();
+        config.set("search-driver", "mtdf").unwrap();
+        assert_eq!(config.set("tt-size", "0"), Err(InvalidOption::InvalidValue));
+        assert_eq!(
+            config.set("mobility", "a lot"),
+            Err(InvalidOption::InvalidValue)
+        );
+        assert_eq!(config.set("speed", "1"), Err(InvalidOption::UnknownName));
+        // The written config can be loaded again.
+        std::fs::write(&path, config.to_toml()).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+
+        std::fs::write(&path, "[options]\nspeed = 1\n").unwrap();
+        assert!(matches!(Config::load(&path), Err(Error::InvalidConfig(_))));
+        std::fs::write(&path, "depth = \"deep\"\n").unwrap();
+        assert!(matches!(Config::load(&path), Err(Error::InvalidConfig(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
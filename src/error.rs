@@ -0,0 +1,98 @@
+use crate::gamefile::GameFileError;
+use crate::options::InvalidOption;
+use crate::position::{InvalidFen, MoveFailed};
+use std::fmt::Display;
+
+/// The errors which can be returned by the library, so that programs
+/// embedding the solver can handle them instead of panicking.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file or stream failed.
+    Io(std::io::Error),
+    /// The benchmark file with the given name could not be parsed.
+    InvalidBenchmark(String),
+    /// The position on the given line of a file could not be parsed.
+    InvalidPositionLine(usize),
+    /// A thread doing work in the background has stopped.
+    ThreadStopped,
+    /// A move could not be played.
+    InvalidMove(MoveFailed),
+    /// A position in compact notation could not be parsed.
+    InvalidFen(InvalidFen),
+    /// A game file could not be read or written.
+    GameFile(GameFileError),
+    /// An option of the solver could not be set.
+    InvalidOption(InvalidOption),
+    /// Some positions of a test suite failed.
+    TestSuiteFailed { failed: usize, total: usize },
+    /// A saved transposition table could not be loaded.
+    InvalidTranspositionTable,
+    /// A transposition table shared between solvers can't be saved.
+    SharedTranspositionTable,
+    /// The config file could not be parsed, with the reason.
+    InvalidConfig(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::InvalidBenchmark(file_name) => write!(f, "Invalid benchmark file: {file_name}"),
+            Self::InvalidPositionLine(line) => write!(f, "Invalid position on line {line}"),
+            Self::ThreadStopped => write!(f, "A background thread has stopped"),
+            Self::InvalidMove(e) => write!(f, "Invalid move: {e:?}"),
+            Self::InvalidFen(e) => write!(f, "Invalid position: {e:?}"),
+            Self::GameFile(e) => write!(f, "{e}"),
+            Self::InvalidOption(e) => write!(f, "Invalid option: {e:?}"),
+            Self::TestSuiteFailed { failed, total } => {
+                write!(f, "{failed} of {total} test positions failed")
+            }
+            Self::InvalidTranspositionTable => write!(f, "Invalid transposition table file"),
+            Self::SharedTranspositionTable => {
+                write!(f, "A shared transposition table can't be saved")
+            }
+            Self::InvalidConfig(reason) => write!(f, "Invalid config file {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<MoveFailed> for Error {
+    fn from(e: MoveFailed) -> Self {
+        Self::InvalidMove(e)
+    }
+}
+
+impl From<InvalidFen> for Error {
+    fn from(e: InvalidFen) -> Self {
+        Self::InvalidFen(e)
+    }
+}
+
+impl From<GameFileError> for Error {
+    fn from(e: GameFileError) -> Self {
+        Self::GameFile(e)
+    }
+}
+
+impl From<InvalidOption> for Error {
+    fn from(e: InvalidOption) -> Self {
+        Self::InvalidOption(e)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::options::InvalidOption;
 use crate::position::{Color, Position};
 
 pub const WIN: isize = 1000;
@@ -5,6 +6,7 @@ pub const IS_WIN: isize = WIN - 2 * Position::MAX_MOVES as isize;
 pub const LOSS: isize = -WIN;
 pub const IS_LOSS: isize = -IS_WIN;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExplainableEval {
     /// A win, with how many moves needed to get there.
     Win(isize),
@@ -14,39 +16,190 @@ pub enum ExplainableEval {
     Undetermined(isize),
 }
 
-/// Return a static evaluation of the position.
-pub fn static_eval(pos: &Position) -> isize {
-    // For now just count how many stacks are controlled by each player.
-    let mut score = 0;
-    score += pos.controlled_stacks(true).count_ones() as isize;
-    score -= pos.controlled_stacks(false).count_ones() as isize;
+impl std::fmt::Display for ExplainableEval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Win(moves) => write!(f, "win in {moves}"),
+            Self::Loss(moves) => write!(f, "loss in {moves}"),
+            Self::Undetermined(_) => write!(f, "undetermined"),
+        }
+    }
+}
+
+/// The weights of the different terms in the static evaluation.
+/// Positive weights are good for the player with the term.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalParams {
+    /// Score per stack controlled.
+    pub controlled_stacks: isize,
+    /// Penalty if the opponent has an alignment, which we have to "Second Best!".
+    pub opponent_alignment: isize,
+    /// Score per free spot with two of the player's stones below it.
+    pub vertical_threats: isize,
+    /// Score per group of three adjacent stacks controlled by the player.
+    pub horizontal_threats: isize,
+    /// Score per possible stone move in the second phase.
+    pub mobility: isize,
+    /// Penalty if one of our moves is banned by a "Second Best!" call.
+    pub banned_move: isize,
+    /// Score if we can still call "Second Best!" on the opponent's last move.
+    pub second_best_available: isize,
+    /// Score per "Second Best!" call we have left more than the opponent,
+    /// if the house rules limit the calls.
+    pub call_budget: isize,
+    /// Score per vertical threat of a player whose next move can't be banned,
+    /// because the opponent has no calls left or it is played after a call.
+    pub unbannable_threats: isize,
+    /// Bonus for the player to move.
+    pub tempo: isize,
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        Self {
+            controlled_stacks: 1,
+            opponent_alignment: 10,
+            vertical_threats: 2,
+            horizontal_threats: 2,
+            mobility: 0,
+            banned_move: 1,
+            second_best_available: 1,
+            call_budget: 1,
+            unbannable_threats: 2,
+            tempo: 0,
+        }
+    }
+}
+
+impl EvalParams {
+    /// The largest weight which can be set, in absolute value. No term
+    /// counts more than 24 things (the stone moves in the second phase),
+    /// so that a single term can't reach the scores of proven results.
+    pub const MAX_WEIGHT: isize = 20;
+
+    /// Set the weight with the given name to the given value.
+    /// The names are the field names with '-' instead of '_'.
+    /// The value must be at most [`Self::MAX_WEIGHT`] in absolute value.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), InvalidOption> {
+        let weight = match name {
+            "controlled-stacks" => &mut self.controlled_stacks,
+            "opponent-alignment" => &mut self.opponent_alignment,
+            "vertical-threats" => &mut self.vertical_threats,
+            "horizontal-threats" => &mut self.horizontal_threats,
+            "mobility" => &mut self.mobility,
+            "banned-move" => &mut self.banned_move,
+            "second-best-available" => &mut self.second_best_available,
+            "call-budget" => &mut self.call_budget,
+            "unbannable-threats" => &mut self.unbannable_threats,
+            "tempo" => &mut self.tempo,
+            _ => return Err(InvalidOption::UnknownName),
+        };
+        let value: isize = value.parse().map_err(|_| InvalidOption::InvalidValue)?;
+        if value.abs() > Self::MAX_WEIGHT {
+            return Err(InvalidOption::InvalidValue);
+        }
+        *weight = value;
+        Ok(())
+    }
+}
+
+/// Return a static evaluation of the position, from the perspective
+/// of the player to move. The evaluation is never a win or a loss, since
+/// the position isn't solved.
+pub fn static_eval(pos: &Position, params: &EvalParams) -> isize {
+    let mut score = params.tempo;
     // Since the bitboards store two copies of the board,
     // we need to divide by 2.
-    score /= 2;
+    let stacks = pos.controlled_stacks(true).count_ones() as isize
+        - pos.controlled_stacks(false).count_ones() as isize;
+    score += params.controlled_stacks * stacks / 2;
     if pos.has_alignment(false) {
         // We don't check for us having an alignment, because that would already be a win.
-        score -= 10;
+        score -= params.opponent_alignment;
+    }
+    let our_threats = pos.vertical_threats(true).count_ones() as isize / 2;
+    let their_threats = pos.vertical_threats(false).count_ones() as isize / 2;
+    score += params.vertical_threats * (our_threats - their_threats);
+    // A threat which can't be banned on the next move has to be blocked.
+    if !pos.can_second_best_next(true) {
+        score += params.unbannable_threats * our_threats;
+    }
+    if !pos.can_second_best_next(false) {
+        score -= params.unbannable_threats * their_threats;
+    }
+    let horizontal_threats =
+        pos.horizontal_threats(true) as isize - pos.horizontal_threats(false) as isize;
+    score += params.horizontal_threats * horizontal_threats;
+    if pos.is_second_phase() {
+        let mobility = pos.mobility(true) as isize - pos.mobility(false) as isize;
+        score += params.mobility * mobility;
     }
-    score
+    if pos.banned_move().is_some() {
+        score -= params.banned_move;
+    }
+    if pos.can_second_best() {
+        score += params.second_best_available;
+    }
+    let calls_left = |color| pos.second_best_calls_left(color).unwrap_or(0) as isize;
+    let player = pos.current_player();
+    score += params.call_budget * (calls_left(player) - calls_left(player.other()));
+    // The terms add up, and the call budget isn't bounded by the board.
+    score.clamp(IS_LOSS + 1, IS_WIN - 1)
 }
 
 /// The evaluation of a loss at the given ply.
+///
+/// Wins and losses are scored by the ply at which the game ends, counted from
+/// the start of the game, so that faster wins and slower losses score better.
+/// Scores which are stored to be used at another ply have to be adjusted.
 #[inline]
 pub fn loss_score(ply: isize) -> isize {
     LOSS + ply
 }
 
+/// The evaluation of a win at the given ply, see [`loss_score`].
+#[inline]
+pub fn win_score(ply: isize) -> isize {
+    WIN - ply
+}
+
+/// Whether the evaluation is a win.
+#[inline]
+pub fn is_win(eval: isize) -> bool {
+    eval > IS_WIN
+}
+
+/// Whether the evaluation is a loss.
+#[inline]
+pub fn is_loss(eval: isize) -> bool {
+    eval < IS_LOSS
+}
+
 /// Turn the evaluation into a more digestible enum.
 pub fn decode_eval(eval: isize, ply: isize) -> ExplainableEval {
-    if eval < IS_LOSS {
+    if is_loss(eval) {
         ExplainableEval::Loss(eval - LOSS - ply)
-    } else if eval > IS_WIN {
+    } else if is_win(eval) {
         ExplainableEval::Win(WIN - eval - ply)
     } else {
         ExplainableEval::Undetermined(eval)
     }
 }
 
+/// Whether a search to `depth` which found `eval` has solved the position:
+/// it is a win or a loss, with the exact number of moves.
+///
+/// Some lines are searched deeper than `depth` (e.g. after "Second Best!"),
+/// so a win found further away than `depth` might not be the fastest win:
+/// a faster one could be just beyond the depth of another line. Similarly a
+/// loss further away might not be the slowest loss.
+pub fn is_solved(eval: isize, ply: isize, depth: usize) -> bool {
+    match decode_eval(eval, ply) {
+        ExplainableEval::Win(moves) | ExplainableEval::Loss(moves) => moves <= depth as isize,
+        ExplainableEval::Undetermined(_) => false,
+    }
+}
+
 /// Explain an evaluation in a human readable way.
 pub fn explain_eval(side: Color, eval: isize, ply: isize) -> String {
     match decode_eval(eval, ply) {
@@ -65,3 +218,68 @@ pub fn explain_eval(side: Color, eval: isize, ply: isize) -> String {
         ),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Rules;
+
+    /// How much the evaluation changes by a term, by turning it off.
+    fn term(pos: &Position, params: EvalParams, off: EvalParams) -> isize {
+        static_eval(pos, &params) - static_eval(pos, &off)
+    }
+
+    #[test]
+    fn bounded() {
+        let mut params = EvalParams::default();
+        assert_eq!(params.set("tempo", "900"), Err(InvalidOption::InvalidValue));
+        assert_eq!(params.set("tempo", "-21"), Err(InvalidOption::InvalidValue));
+        params.set("tempo", "-20").unwrap();
+        assert_eq!(params.tempo, -20);
+
+        // Weights set directly still don't give a proven result.
+        let pos = Position::default();
+        let params = EvalParams {
+            tempo: 900,
+            ..EvalParams::default()
+        };
+        assert_eq!(static_eval(&pos, &params), IS_WIN - 1);
+        assert!(!is_win(static_eval(&pos, &params)));
+        let params = EvalParams {
+            tempo: -900,
+            ..EvalParams::default()
+        };
+        assert!(!is_loss(static_eval(&pos, &params)));
+    }
+
+    #[test]
+    fn call_budget() {
+        let params = EvalParams::default();
+        let off = EvalParams {
+            call_budget: 0,
+            unbannable_threats: 0,
+            ..params
+        };
+        // Black has a threat on stack 0, and white is to move.
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(["0", "1", "0"].map(String::from).to_vec())
+            .unwrap();
+        assert_eq!(term(&pos, params, off), 0);
+        pos.set_rules(Rules {
+            second_best_limit: Some(0),
+            ..Default::default()
+        });
+        // White can't ban the move which completes the alignment.
+        assert_eq!(term(&pos, params, off), -params.unbannable_threats);
+
+        let mut pos = Position::default();
+        pos.set_rules(Rules {
+            second_best_limit: Some(1),
+            ..Default::default()
+        });
+        pos.parse_and_play_moves(["0", "!"].map(String::from).to_vec())
+            .unwrap();
+        // Black still has a call left, and white doesn't.
+        assert_eq!(term(&pos, params, off), params.call_budget);
+    }
+}
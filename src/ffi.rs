@@ -0,0 +1,300 @@
+//! A C API for embedding the solver in programs which are not written in Rust.
+//!
+//! The header is `include/second_best.h`, and is generated with
+//! `cbindgen --config cbindgen.toml --output include/second_best.h src/ffi.rs`.
+//!
+//! Positions and solvers are created with `sb_position_new` and
+//! `sb_solver_new`, and have to be freed with `sb_position_free` and
+//! `sb_solver_free`. Strings returned by the API have to be freed with
+//! `sb_string_free`.
+
+use crate::position::{PlayerMove, Position};
+use crate::solver::{SearchInfo, SearchLimits, Solver};
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::time::Duration;
+
+/// A position, see [`Position`].
+pub struct SbPosition(Position);
+
+/// The solver, see [`Solver`]. The transposition table is kept between searches.
+pub struct SbSolver(Solver);
+
+/// The result of a completed iteration of a search.
+/// The strings are only valid during the callback.
+#[repr(C)]
+pub struct SbSearchInfo {
+    pub depth: usize,
+    /// The largest number of plies searched from the position.
+    pub seldepth: usize,
+    /// The score from the perspective of the player to move.
+    pub score: isize,
+    pub nodes: usize,
+    pub time_ms: u64,
+    /// How full the transposition table is, in per mille.
+    pub ttfull: usize,
+    /// The principal variation, with the moves separated by spaces.
+    pub pv: *const c_char,
+}
+
+/// Called with the result of every completed iteration of a search.
+pub type SbInfoCallback = extern "C" fn(info: *const SbSearchInfo, user_data: *mut c_void);
+
+/// The user data passed to the info callback, which is only used
+/// on the thread doing the search.
+struct UserData(*mut c_void);
+
+// SAFETY: the caller of `sb_solver_set_info_callback` promises that
+// the user data can be used from the thread doing the search.
+unsafe impl Send for UserData {}
+
+impl UserData {
+    /// Use a method, so that closures capture the whole `UserData`, and not just the pointer.
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// Turn a string into a C string, which has to be freed with `sb_string_free`.
+fn into_c_string(s: String) -> *mut c_char {
+    // The strings never contain a nul byte.
+    CString::new(s).unwrap().into_raw()
+}
+
+/// # Safety
+///
+/// `s` has to be a valid nul terminated string, or null.
+unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+/// Free a string returned by one of the functions of this API.
+///
+/// # Safety
+///
+/// `s` has to be returned by this API, and not be freed before, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn sb_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Create the starting position.
+#[no_mangle]
+pub extern "C" fn sb_position_new() -> *mut SbPosition {
+    Box::into_raw(Box::new(SbPosition(Position::default())))
+}
+
+/// Create a position from the compact notation, see [`Position::from_fen`].
+/// Returns null if the notation is invalid.
+///
+/// # Safety
+///
+/// `fen` has to be a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sb_position_from_fen(fen: *const c_char) -> *mut SbPosition {
+    match to_str(fen).map(Position::from_fen) {
+        Some(Ok(pos)) => Box::into_raw(Box::new(SbPosition(pos))),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `pos` has to be created by this API, and not be freed before, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn sb_position_free(pos: *mut SbPosition) {
+    if !pos.is_null() {
+        drop(Box::from_raw(pos));
+    }
+}
+
+/// Play the given moves, e.g. "0 1 2" or "0-1!". Returns false if one of
+/// the moves is invalid or illegal, in which case none of them are played.
+///
+/// # Safety
+///
+/// `pos` has to be a valid position, and `moves` a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sb_position_play(pos: *mut SbPosition, moves: *const c_char) -> bool {
+    let pos = &mut (*pos).0;
+    let Some(Ok(moves)) = to_str(moves).map(PlayerMove::parse_moves) else {
+        return false;
+    };
+    let mut new_pos = pos.clone();
+    for pmove in moves {
+        if new_pos.try_make_move(pmove).is_err() {
+            return false;
+        }
+    }
+    *pos = new_pos;
+    true
+}
+
+/// Undo the last move. Returns false if there was nothing to undo.
+///
+/// # Safety
+///
+/// `pos` has to be a valid position.
+#[no_mangle]
+pub unsafe extern "C" fn sb_position_undo(pos: *mut SbPosition) -> bool {
+    (*pos).0.undo_last_player_move().is_some()
+}
+
+/// The position in the compact notation.
+///
+/// # Safety
+///
+/// `pos` has to be a valid position.
+#[no_mangle]
+pub unsafe extern "C" fn sb_position_fen(pos: *const SbPosition) -> *mut c_char {
+    into_c_string((*pos).0.to_fen())
+}
+
+/// The legal moves, separated by spaces.
+///
+/// # Safety
+///
+/// `pos` has to be a valid position.
+#[no_mangle]
+pub unsafe extern "C" fn sb_position_legal_moves(pos: *const SbPosition) -> *mut c_char {
+    into_c_string(PlayerMove::to_long_form(&(*pos).0.legal_moves()))
+}
+
+/// # Safety
+///
+/// `pos` has to be a valid position.
+#[no_mangle]
+pub unsafe extern "C" fn sb_position_game_over(pos: *const SbPosition) -> bool {
+    (*pos).0.game_over()
+}
+
+#[no_mangle]
+pub extern "C" fn sb_solver_new() -> *mut SbSolver {
+    Box::into_raw(Box::new(SbSolver(Solver::default())))
+}
+
+/// # Safety
+///
+/// `solver` has to be created by this API, and not be freed before, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn sb_solver_free(solver: *mut SbSolver) {
+    if !solver.is_null() {
+        drop(Box::from_raw(solver));
+    }
+}
+
+/// Set a function which gets called with the result of every completed
+/// iteration of a search, together with `user_data`.
+///
+/// # Safety
+///
+/// `solver` has to be a valid solver, and `user_data` has to stay valid
+/// and usable from the thread doing the searches, until the callback is
+/// replaced or the solver is freed.
+#[no_mangle]
+pub unsafe extern "C" fn sb_solver_set_info_callback(
+    solver: *mut SbSolver,
+    callback: SbInfoCallback,
+    user_data: *mut c_void,
+) {
+    let user_data = UserData(user_data);
+    (*solver).0.set_info_handler(move |info: SearchInfo| {
+        let pv = CString::new(PlayerMove::to_long_form(&info.pv)).unwrap();
+        let info = SbSearchInfo {
+            depth: info.depth,
+            seldepth: info.seldepth,
+            score: info.score,
+            nodes: info.nodes,
+            time_ms: info.time.as_millis() as u64,
+            ttfull: info.ttfull,
+            pv: pv.as_ptr(),
+        };
+        callback(&info, user_data.get());
+    });
+}
+
+/// Search the position until it is solved, or one of the limits is reached.
+/// A limit of 0 means there is no limit. Returns the score of the position
+/// from the perspective of the player to move.
+///
+/// # Safety
+///
+/// `solver` has to be a valid solver, and `pos` a valid position.
+#[no_mangle]
+pub unsafe extern "C" fn sb_solver_search(
+    solver: *mut SbSolver,
+    pos: *const SbPosition,
+    max_depth: usize,
+    max_time_ms: u64,
+) -> isize {
+    let solver = &mut (*solver).0;
+    let limits = SearchLimits {
+        max_depth: match max_depth {
+            0 => Position::MAX_MOVES,
+            depth => depth,
+        },
+        max_time: (max_time_ms > 0).then(|| Duration::from_millis(max_time_ms)),
+        ..Default::default()
+    };
+    solver.set_position((*pos).0.clone());
+    solver.search_with_limits(limits)
+}
+
+/// The principal variation of the last search, with the moves separated by spaces.
+///
+/// # Safety
+///
+/// `solver` has to be a valid solver.
+#[no_mangle]
+pub unsafe extern "C" fn sb_solver_pv(solver: *mut SbSolver) -> *mut c_char {
+    into_c_string(PlayerMove::to_long_form(&(*solver).0.principal_variation()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn count_iterations(_info: *const SbSearchInfo, user_data: *mut c_void) {
+        unsafe { *(user_data as *mut usize) += 1 };
+    }
+
+    #[test]
+    fn search_through_ffi() {
+        unsafe {
+            let fen = CString::new("O/X/XX/O/XO/././O X 3 -").unwrap();
+            let pos = sb_position_from_fen(fen.as_ptr());
+            assert!(!pos.is_null());
+            let invalid = CString::new("0 9").unwrap();
+            assert!(!sb_position_play(pos, invalid.as_ptr()));
+
+            let solver = sb_solver_new();
+            let mut iterations = 0usize;
+            sb_solver_set_info_callback(
+                solver,
+                count_iterations,
+                &mut iterations as *mut usize as *mut c_void,
+            );
+            sb_solver_search(solver, pos, 4, 0);
+            assert_eq!(iterations, 4);
+
+            let pv = sb_solver_pv(solver);
+            assert!(!CStr::from_ptr(pv).is_empty());
+
+            let pmove = CString::new("1").unwrap();
+            assert!(sb_position_play(pos, pmove.as_ptr()));
+            assert!(sb_position_undo(pos));
+            let fen_after = sb_position_fen(pos);
+            assert_eq!(CStr::from_ptr(fen_after), fen.as_c_str());
+
+            sb_string_free(pv);
+            sb_string_free(fen_after);
+            sb_solver_free(solver);
+            sb_position_free(pos);
+        }
+    }
+}
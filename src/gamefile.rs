@@ -0,0 +1,474 @@
+use crate::position::{Color, GameStatus, MoveFailed, PlayerMove, Position, Variant};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A record of a full game, which can be saved to and loaded from a text file.
+///
+/// The format is similar to PGN in chess. It starts with tags of the form
+/// `[Name "value"]`, one per line, followed by the moves in the same format
+/// as `set-pos`, with "Second Best!" attached to the move it was called on.
+/// Comments are placed between curly braces after a move, with a `}` or `\`
+/// in the comment escaped by a backslash, e.g. `{a \} in a comment}`.
+/// The time spent on a move is written at the start of its comment as
+/// `[%emt h:mm:ss]`, with milliseconds if there are any, as in PGN.
+/// A game on another board than the standard one has a tag with its size,
+/// e.g. `[Variant "6x4"]`.
+///
+/// Example:
+/// ```text
+/// [Black "Alice"]
+/// [White "Bob"]
+/// [Date "2024.01.31"]
+/// [Result "*"]
+///
+/// 1 0 4 {[%emt 0:00:12] Threatening an alignment} 7 2! 3 {[%emt 0:01:05.250]}
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GameRecord {
+    /// Name of the black (X) player.
+    pub black: String,
+    /// Name of the white (O) player.
+    pub white: String,
+    /// The date the game was played, as "YYYY.MM.DD".
+    pub date: String,
+    /// The winner of the game, or `None` if the game is not finished.
+    pub winner: Option<Color>,
+    /// The size of the board the game is played on.
+    pub variant: Variant,
+    /// The moves played in the game, including "Second Best!" calls.
+    pub moves: Vec<PlayerMove>,
+    /// Comments, keyed by the number of moves played before the comment.
+    pub comments: BTreeMap<usize, String>,
+    /// The time spent on the moves, keyed by the index of the move.
+    pub times: BTreeMap<usize, Duration>,
+}
+
+#[derive(Debug)]
+pub enum GameFileError {
+    /// The file could not be read or written.
+    Io(std::io::Error),
+    /// The tag on the given line is not of the form `[Name "value"]`.
+    InvalidTag(usize),
+    /// A comment was opened with '{' but never closed.
+    UnterminatedComment,
+    /// The move with the given index could not be played.
+    InvalidMove(usize, MoveFailed),
+    /// The time of the move with the given index is not of the form `h:mm:ss`.
+    InvalidTime(usize),
+}
+
+impl From<std::io::Error> for GameFileError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl Display for GameFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::InvalidTag(line) => write!(f, "Invalid tag on line {line}"),
+            Self::UnterminatedComment => write!(f, "A comment was not closed with '}}'"),
+            Self::InvalidMove(move_i, e) => write!(f, "Move {} is invalid: {e:?}", move_i + 1),
+            Self::InvalidTime(move_i) => write!(f, "The time of move {} is invalid", move_i + 1),
+        }
+    }
+}
+
+impl GameRecord {
+    /// Maximal length of a line of moves when writing a record.
+    const LINE_LENGTH: usize = 80;
+
+    /// Create a record of the moves that lead to the given position.
+    /// The date is set to today.
+    pub fn from_position(pos: &Position) -> Self {
+        Self {
+            date: today(),
+            winner: Self::winner(pos),
+            variant: pos.variant(),
+            moves: pos.history(),
+            ..Default::default()
+        }
+    }
+
+    /// Play all the moves of the game from the starting position.
+    pub fn to_position(&self) -> Result<Position, GameFileError> {
+        let mut pos = Position::with_variant(self.variant);
+        for (move_i, pmove) in self.moves.iter().enumerate() {
+            pos.try_make_move(*pmove)
+                .map_err(|e| GameFileError::InvalidMove(move_i, e))?;
+        }
+        Ok(pos)
+    }
+
+    /// The winner in the given position, if the game is over.
+    fn winner(pos: &Position) -> Option<Color> {
+        match pos.game_status() {
+            GameStatus::Won { winner, .. } => Some(winner),
+            _ => None,
+        }
+    }
+
+    /// Parse a game record from the text format.
+    /// The moves are checked to be valid.
+    pub fn parse(text: &str) -> Result<Self, GameFileError> {
+        let mut record = Self::default();
+        let mut movetext = String::new();
+        for (line_i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if !line.starts_with('[') {
+                movetext.push_str(line);
+                movetext.push('\n');
+                continue;
+            }
+            let (name, value) = line
+                .strip_prefix('[')
+                .and_then(|l| l.strip_suffix(']'))
+                .and_then(|l| l.split_once(' '))
+                .and_then(|(name, value)| Some((name, value.strip_prefix('"')?.strip_suffix('"')?)))
+                .ok_or(GameFileError::InvalidTag(line_i + 1))?;
+            match name {
+                "Black" => record.black = value.to_string(),
+                "White" => record.white = value.to_string(),
+                "Date" => record.date = value.to_string(),
+                "Variant" => {
+                    record.variant = value
+                        .parse()
+                        .map_err(|_| GameFileError::InvalidTag(line_i + 1))?
+                }
+                // The result is recomputed from the moves.
+                // Unknown tags are ignored.
+                _ => (),
+            }
+        }
+
+        let mut rest = movetext.as_str();
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(comment) = rest.strip_prefix('{') {
+                let (unescaped, end) =
+                    unescape_comment(comment).ok_or(GameFileError::UnterminatedComment)?;
+                let mut text = unescaped.trim();
+                if let Some(time) = text.strip_prefix("[%emt ") {
+                    let (time, comment) = time.split_once(']').unwrap_or((time, ""));
+                    let move_i = record.moves.len().saturating_sub(1);
+                    let time = parse_time(time.trim()).ok_or(GameFileError::InvalidTime(move_i))?;
+                    record.times.insert(move_i, time);
+                    text = comment;
+                }
+                let words = text.split_whitespace().collect::<Vec<_>>();
+                if !words.is_empty() {
+                    record.comments.insert(record.moves.len(), words.join(" "));
+                }
+                rest = &comment[end + 1..];
+                continue;
+            }
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == '{')
+                .unwrap_or(rest.len());
+            let pmoves = PlayerMove::parse_moves(&rest[..end])
+                .map_err(|e| GameFileError::InvalidMove(record.moves.len(), e))?;
+            record.moves.extend(pmoves);
+            rest = &rest[end..];
+        }
+        record.winner = Self::winner(&record.to_position()?);
+        Ok(record)
+    }
+
+    /// Save the record to the given file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), GameFileError> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Save the record to a new file in the directory `dir`, named after the
+    /// current time, e.g. "game-2024.03.01-153000.txt". Returns the path.
+    pub fn save_new(&self, dir: &Path) -> Result<PathBuf, GameFileError> {
+        std::fs::create_dir_all(dir)?;
+        let secs = unix_time().as_secs();
+        let (hours, minutes, seconds) = time_of_day(secs);
+        let path = dir.join(format!(
+            "game-{}-{hours:02}{minutes:02}{seconds:02}.txt",
+            date(secs)
+        ));
+        self.save(&path)?;
+        Ok(path)
+    }
+
+    /// Load a record from the given file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, GameFileError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// The total time spent on the moves of each player, black first.
+    pub fn time_used(&self) -> [Duration; 2] {
+        let mut used = [Duration::ZERO; 2];
+        let mut pos = Position::with_variant(self.variant);
+        for (move_i, &pmove) in self.moves.iter().enumerate() {
+            let player = match pos.current_player() {
+                Color::Black => 0,
+                Color::White => 1,
+            };
+            used[player] += self.times.get(&move_i).copied().unwrap_or_default();
+            if pos.try_make_move(pmove).is_err() {
+                break;
+            }
+        }
+        used
+    }
+}
+
+impl Display for GameRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "[Black \"{}\"]", self.black)?;
+        writeln!(f, "[White \"{}\"]", self.white)?;
+        writeln!(f, "[Date \"{}\"]", self.date)?;
+        if self.variant != Variant::STANDARD {
+            writeln!(f, "[Variant \"{}\"]", self.variant)?;
+        }
+        let result = match self.winner {
+            Some(color) => color.to_string(),
+            None => "*".to_string(),
+        };
+        writeln!(f, "[Result \"{result}\"]")?;
+        writeln!(f)?;
+
+        let mut tokens = vec![];
+        for move_i in 0..=self.moves.len() {
+            // The time of the last move goes in front of the comment.
+            let time = move_i
+                .checked_sub(1)
+                .and_then(|last| self.times.get(&last))
+                .map(|&time| format!("[%emt {}]", format_time(time)));
+            let comment = self.comments.get(&move_i).map(|c| escape_comment(c));
+            let comment = match (time, comment) {
+                (Some(time), Some(comment)) => Some(format!("{time} {comment}")),
+                (time, comment) => time.or(comment),
+            };
+            if let Some(comment) = comment {
+                tokens.push(format!("{{{comment}}}"));
+            }
+            match self.moves.get(move_i) {
+                // Attach "Second Best!" to the move it was called on.
+                Some(PlayerMove::SecondBest) => {
+                    if let Some(last) = tokens.last_mut().filter(|t| !t.ends_with('}')) {
+                        last.push('!');
+                    } else {
+                        tokens.push("!".to_string());
+                    }
+                }
+                Some(pmove) => tokens.push(pmove.to_string()),
+                None => (),
+            }
+        }
+        let mut line_length = 0;
+        for token in tokens {
+            if line_length > 0 && line_length + token.len() >= Self::LINE_LENGTH {
+                writeln!(f)?;
+                line_length = 0;
+            }
+            if line_length > 0 {
+                write!(f, " ")?;
+                line_length += 1;
+            }
+            write!(f, "{token}")?;
+            line_length += token.len();
+        }
+        writeln!(f)
+    }
+}
+
+/// Escape the characters of a comment which would end it or be read as an escape.
+fn escape_comment(comment: &str) -> String {
+    comment.replace('\\', "\\\\").replace('}', "\\}")
+}
+
+/// Read a comment up to the first `}` which is not escaped, see [`escape_comment`].
+/// Returns the comment without escapes and the index of the closing `}`,
+/// or `None` if the comment is not closed.
+fn unescape_comment(text: &str) -> Option<(String, usize)> {
+    let mut comment = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '}' => return Some((comment, i)),
+            '\\' => comment.push(chars.next()?.1),
+            c => comment.push(c),
+        }
+    }
+    None
+}
+
+/// A time spent on a move as "h:mm:ss", with milliseconds if there are any.
+pub fn format_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    let text = format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    match time.subsec_millis() {
+        0 => text,
+        millis => format!("{text}.{millis:03}"),
+    }
+}
+
+/// Parse a time written by [`format_time`].
+fn parse_time(text: &str) -> Option<Duration> {
+    let mut parts = text.split(':');
+    let hours: u64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || minutes >= 60 || !(0.0..60.0).contains(&seconds) {
+        return None;
+    }
+    let millis = (seconds * 1000.0).round() as u64;
+    Some(Duration::from_millis(
+        (hours * 60 + minutes) * 60_000 + millis,
+    ))
+}
+
+/// The current date (in UTC) as "YYYY.MM.DD".
+pub(crate) fn today() -> String {
+    date(unix_time().as_secs())
+}
+
+/// The time since 1970-01-01 (UTC).
+pub(crate) fn unix_time() -> std::time::Duration {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// The hours, minutes and seconds (in UTC) of a number of seconds since 1970-01-01.
+pub(crate) fn time_of_day(secs: u64) -> (u64, u64, u64) {
+    let secs = secs % 86400;
+    (secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// The date (in UTC) as "YYYY.MM.DD", of a number of seconds since 1970-01-01.
+pub(crate) fn date(secs: u64) -> String {
+    // Convert the number of days since 1970-01-01 to a date, see:
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut pos = Position::default();
+        let input_moves =
+            "3 1 1 0 6 2 3 7 6 6 7 0 5 7 0 2 5-4 7-3 0-1 3-4 3-4 0-7 4-0 4-3 4-5 7-0 7-3 6-7 ! 6-5 6-7";
+        pos.parse_and_play_moves(
+            input_moves
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        let mut record = GameRecord::from_position(&pos);
+        record.black = "Alice".to_string();
+        record.white = "Bob".to_string();
+        record.comments.insert(0, "A test game".to_string());
+        record.comments.insert(3, "Interesting {move".to_string());
+        record.times.insert(2, Duration::from_millis(5250));
+        record.times.insert(29, Duration::from_secs(3723));
+        let text = record.to_string();
+        println!("{text}");
+        assert!(text.contains("{[%emt 0:00:05.250] Interesting {move}"));
+        assert!(text.contains("6-7! 6-5 {[%emt 1:02:03]}"));
+        let parsed = GameRecord::parse(&text).unwrap();
+        assert_eq!(parsed, record);
+        let pos2 = parsed.to_position().unwrap();
+        assert_eq!(pos.to_fen(), pos2.to_fen());
+    }
+
+    #[test]
+    fn escaped_comments() {
+        let mut record = GameRecord::parse("0 1 2").unwrap();
+        record.comments.insert(1, "A } ends a comment".to_string());
+        record
+            .comments
+            .insert(3, r"Escaped with \ and \}".to_string());
+        let text = record.to_string();
+        assert!(text.contains(r"0 {A \} ends a comment} 1 2 {Escaped with \\ and \\\}}"));
+        assert_eq!(GameRecord::parse(&text).unwrap(), record);
+        assert!(matches!(
+            GameRecord::parse(r"0 {unfinished \}"),
+            Err(GameFileError::UnterminatedComment)
+        ));
+    }
+
+    #[test]
+    fn invalid_records() {
+        assert!(matches!(
+            GameRecord::parse("[Black Alice]\n0 1"),
+            Err(GameFileError::InvalidTag(1))
+        ));
+        assert!(matches!(
+            GameRecord::parse("0 1 {unfinished"),
+            Err(GameFileError::UnterminatedComment)
+        ));
+        assert!(matches!(
+            GameRecord::parse("0 1 0-1"),
+            Err(GameFileError::InvalidMove(2, MoveFailed::InvalidFromSpot))
+        ));
+        let record = GameRecord::parse("0{comment}1").unwrap();
+        assert_eq!(record.moves.len(), 2);
+        assert_eq!(record.comments.get(&1).unwrap(), "comment");
+        assert!(matches!(
+            GameRecord::parse("0 {[%emt 0:61:00]} 1"),
+            Err(GameFileError::InvalidTime(0))
+        ));
+    }
+
+    #[test]
+    fn variant() {
+        let record = GameRecord::parse("[Variant \"6x4\"]\n0 5 5 5 5").unwrap();
+        assert_eq!(record.variant, Variant::new(6, 4).unwrap());
+        let text = record.to_string();
+        assert!(text.contains("[Variant \"6x4\"]"));
+        assert_eq!(GameRecord::parse(&text).unwrap(), record);
+        assert_eq!(record.to_position().unwrap().stack_height(5), 4);
+        assert!(!GameRecord::parse("0 1")
+            .unwrap()
+            .to_string()
+            .contains("Variant"));
+        assert!(matches!(
+            GameRecord::parse("[Variant \"6x4\"]\n0 6"),
+            Err(GameFileError::InvalidMove(1, MoveFailed::InvalidToSpot))
+        ));
+        assert!(matches!(
+            GameRecord::parse("[Variant \"5x3\"]\n0 1"),
+            Err(GameFileError::InvalidTag(1))
+        ));
+    }
+
+    #[test]
+    fn times() {
+        let record =
+            GameRecord::parse("0 {[%emt 0:00:02]} 1 {[%emt 0:00:01.5]} 2 3 {[%emt 0:00:04]}")
+                .unwrap();
+        assert!(record.comments.is_empty());
+        assert_eq!(record.times.get(&1), Some(&Duration::from_millis(1500)));
+        assert_eq!(
+            record.time_used(),
+            [Duration::from_secs(2), Duration::from_millis(5500)]
+        );
+        assert_eq!(format_time(Duration::from_millis(61_005)), "0:01:01.005");
+    }
+}
@@ -1,7 +1,80 @@
+//! A solver for the board game ["Second Best!"](https://jelly2games.com/secondbest).
+//!
+//! This library is used by both the CLI and the GUI, and can be used
+//! directly by other programs. The main parts are:
+//! - [`Position`]: the state of the board, and playing moves on it.
+//! - [`Solver`]: searching positions for the best move and its score.
+//! - [`MoveGen`]: generating the legal moves in a position.
+//! - [`eval`]: static evaluation, and turning scores into wins and losses.
+//! - [`gamefile`]: saving and loading full games.
+//!
+//! # Example
+//!
+//! ```
+//! use second_best::{eval, Position, Solver};
+//! use std::sync::atomic::AtomicBool;
+//! use std::sync::Arc;
+//!
+//! let mut solver = Solver::new(Arc::new(AtomicBool::new(false)));
+//! solver.position = Position::from_fen("O/X/XX/O/XO/././O X 3 -").unwrap();
+//! let score = solver.search(4);
+//! let explanation = eval::explain_eval(
+//!     solver.position.current_player(),
+//!     score,
+//!     solver.position.ply() as isize,
+//! );
+//! println!("{explanation}");
+//! ```
+//!
+//! Limits and other settings of a search are set with [`Solver::searcher`]:
+//!
+//! ```
+//! # use second_best::{Position, Solver};
+//! # use std::sync::atomic::AtomicBool;
+//! # use std::sync::Arc;
+//! let mut solver = Solver::new(Arc::new(AtomicBool::new(false)));
+//! solver.position = Position::from_fen("O/X/XX/O/XO/././O X 3 -").unwrap();
+//! let result = solver.searcher().depth(8).nodes(100_000).multipv(2).run();
+//! for line in &result.lines {
+//!     println!("{}: {:?}", line.pmove, line.score);
+//! }
+//! ```
+//!
+//! Solvers which search at the same time, e.g. in different threads, can
+//! share one transposition table instead of each using their own, see
+//! [`Solver::share_tt`] and [`SharedTranspositionTable`].
+
+pub mod analysis;
 mod bench;
+pub mod bookmarks;
 pub mod cli;
+pub mod config;
+pub mod error;
 pub mod eval;
-mod movegen;
+pub mod ffi;
+pub mod gamefile;
+pub mod line_editor;
+pub mod log;
+pub mod mcts;
+pub mod movegen;
+pub mod options;
+pub mod pns;
 pub mod position;
+pub mod progress;
+#[cfg(feature = "python")]
+pub mod python;
+mod rng;
 pub mod solver;
+pub mod testing;
+pub mod time_control;
+pub mod trace;
 mod transposition_table;
+pub mod verify;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use error::Error;
+pub use movegen::MoveGen;
+pub use position::{BitboardMove, Color, PlayerMove, Position, Rules};
+pub use solver::{SearchLimits, SearchResult, Solver};
+pub use transposition_table::SharedTranspositionTable;
@@ -0,0 +1,537 @@
+//! Reading commands in the terminal, with editing, history and completion.
+//!
+//! When the input is a terminal, it is switched to raw mode while a line is
+//! read, so every key can be handled: the usual cursor movement and editing
+//! keys (arrows, home/end, `Ctrl-A`/`Ctrl-E`/`Ctrl-K`/`Ctrl-U`/`Ctrl-W`),
+//! up and down to go through the history, and tab to complete the word
+//! before the cursor. Otherwise, e.g. when commands are piped into the CLI,
+//! lines are read as they are.
+
+use std::io::{self, BufRead, Read, Write};
+
+/// A key, or a combination with control, which is handled by the editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    Tab,
+    /// Delete from the cursor to the end of the line.
+    KillToEnd,
+    /// Delete from the start of the line to the cursor.
+    KillToStart,
+    /// Delete the word before the cursor.
+    DeleteWord,
+    Interrupt,
+    /// End of input, or `Ctrl-D` on an empty line.
+    Eof,
+    /// A key which doesn't do anything.
+    Ignored,
+}
+
+/// What has to happen after a key was handled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Show the line again, and wait for the next key.
+    Redraw,
+    /// The line is done.
+    Submit(String),
+    /// The line was thrown away with `Ctrl-C`.
+    Interrupted,
+    /// The input is closed.
+    Eof,
+    /// The word can be completed in several ways, which are shown.
+    ShowCompletions(Vec<String>),
+}
+
+/// The line being edited, and the lines entered before.
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    /// The entry of the history which is shown, `None` for the new line.
+    history_i: Option<usize>,
+    /// The new line, while an entry of the history is shown.
+    new_line: Vec<char>,
+}
+
+impl LineEditor {
+    /// The maximal number of lines kept in the history.
+    pub const MAX_HISTORY: usize = 1000;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The line being edited.
+    pub fn line(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    /// The position of the cursor in the line, in characters.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// The lines entered so far, from old to new.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    fn set_line(&mut self, line: Vec<char>) {
+        self.cursor = line.len();
+        self.line = line;
+    }
+
+    fn delete_range(&mut self, range: std::ops::Range<usize>) {
+        self.cursor = range.start;
+        self.line.drain(range);
+    }
+
+    /// Handle a key. The `complete` function gives the ways to complete
+    /// the last word of the line it gets, see [`complete_word`].
+    pub fn handle_key(
+        &mut self,
+        key: Key,
+        complete: &mut impl FnMut(&str) -> Vec<String>,
+    ) -> Action {
+        match key {
+            Key::Char(c) => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Enter => {
+                let line = self.line();
+                if !line.trim().is_empty() && self.history.last() != Some(&line) {
+                    self.history.push(line.clone());
+                    if self.history.len() > Self::MAX_HISTORY {
+                        self.history.remove(0);
+                    }
+                }
+                self.history_i = None;
+                self.set_line(vec![]);
+                return Action::Submit(line);
+            }
+            Key::Backspace if self.cursor > 0 => self.delete_range(self.cursor - 1..self.cursor),
+            Key::Delete if self.cursor < self.line.len() => {
+                self.delete_range(self.cursor..self.cursor + 1)
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.line.len(),
+            Key::Up => {
+                let shown = match self.history_i {
+                    Some(i) => i.checked_sub(1),
+                    None => self.history.len().checked_sub(1),
+                };
+                if let Some(i) = shown {
+                    if self.history_i.is_none() {
+                        self.new_line = std::mem::take(&mut self.line);
+                    }
+                    self.history_i = Some(i);
+                    self.set_line(self.history[i].chars().collect());
+                }
+            }
+            Key::Down => match self.history_i {
+                Some(i) if i + 1 < self.history.len() => {
+                    self.history_i = Some(i + 1);
+                    self.set_line(self.history[i + 1].chars().collect());
+                }
+                Some(_) => {
+                    self.history_i = None;
+                    let new_line = std::mem::take(&mut self.new_line);
+                    self.set_line(new_line);
+                }
+                None => (),
+            },
+            Key::Tab => {
+                let before: String = self.line[..self.cursor].iter().collect();
+                let (extension, candidates) = complete_word(&before, complete);
+                for c in extension.chars().rev() {
+                    self.line.insert(self.cursor, c);
+                }
+                self.cursor += extension.chars().count();
+                // Show the candidates if nothing could be added.
+                if candidates.len() > 1 && extension.is_empty() {
+                    return Action::ShowCompletions(candidates);
+                }
+            }
+            Key::KillToEnd => self.delete_range(self.cursor..self.line.len()),
+            Key::KillToStart => self.delete_range(0..self.cursor),
+            Key::DeleteWord => {
+                let mut start = self.cursor;
+                while start > 0 && self.line[start - 1] == ' ' {
+                    start -= 1;
+                }
+                while start > 0 && self.line[start - 1] != ' ' {
+                    start -= 1;
+                }
+                self.delete_range(start..self.cursor);
+            }
+            Key::Interrupt => {
+                self.history_i = None;
+                self.set_line(vec![]);
+                return Action::Interrupted;
+            }
+            Key::Eof if self.line.is_empty() => return Action::Eof,
+            Key::Eof => return self.handle_key(Key::Delete, complete),
+            Key::Backspace | Key::Delete | Key::Ignored => (),
+        }
+        Action::Redraw
+    }
+
+    /// Read a line, with the given prompt if the input is a terminal.
+    /// Returns `None` at the end of the input.
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        mut complete: impl FnMut(&str) -> Vec<String>,
+    ) -> io::Result<Option<String>> {
+        let Some(_raw_mode) = RawMode::enable() else {
+            let mut line = String::new();
+            return match io::stdin().lock().read_line(&mut line)? {
+                0 => Ok(None),
+                _ => Ok(Some(line)),
+            };
+        };
+        let mut stdin = io::stdin().lock();
+        let mut stdout = io::stdout();
+        self.redraw(&mut stdout, prompt)?;
+        loop {
+            let key = read_key(&mut stdin)?;
+            match self.handle_key(key, &mut complete) {
+                Action::Redraw => (),
+                Action::Submit(line) => {
+                    write!(stdout, "\r\n")?;
+                    stdout.flush()?;
+                    return Ok(Some(line));
+                }
+                Action::Interrupted => write!(stdout, "^C\r\n")?,
+                Action::Eof => {
+                    write!(stdout, "\r\n")?;
+                    stdout.flush()?;
+                    return Ok(None);
+                }
+                Action::ShowCompletions(candidates) => {
+                    write!(stdout, "\r\n{}\r\n", candidates.join("  "))?
+                }
+            }
+            self.redraw(&mut stdout, prompt)?;
+        }
+    }
+
+    fn redraw(&self, stdout: &mut impl Write, prompt: &str) -> io::Result<()> {
+        // Go to the start of the line, write it, and clear what is left of the old line.
+        write!(stdout, "\r{prompt}{}\x1b[K", self.line())?;
+        let after_cursor = self.line.len() - self.cursor;
+        if after_cursor > 0 {
+            write!(stdout, "\x1b[{after_cursor}D")?;
+        }
+        stdout.flush()
+    }
+}
+
+/// Complete the last word of `line` with the `candidates` for it. Returns
+/// what can be added to the word because all candidates starting with it
+/// agree, and those candidates.
+pub fn complete_word(
+    line: &str,
+    candidates: &mut impl FnMut(&str) -> Vec<String>,
+) -> (String, Vec<String>) {
+    let word = match line.ends_with(' ') {
+        true => "",
+        false => line.rsplit(' ').next().unwrap_or(""),
+    };
+    let matching: Vec<String> = candidates(line)
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(word))
+        .collect();
+    let Some(first) = matching.first() else {
+        return (String::new(), matching);
+    };
+    let mut common = first.len();
+    for candidate in &matching[1..] {
+        common = first
+            .char_indices()
+            .zip(candidate.chars())
+            .take_while(|((_, a), b)| a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0)
+            .min(common);
+    }
+    let mut extension = first[word.len()..common].to_string();
+    if matching.len() == 1 {
+        extension.push(' ');
+    }
+    (extension, matching)
+}
+
+/// Read the bytes of a key from the terminal.
+fn read_key(input: &mut impl Read) -> io::Result<Key> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(Key::Eof);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x0b => Key::KillToEnd,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x15 => Key::KillToStart,
+        0x17 => Key::DeleteWord,
+        0x1b => read_escape_sequence(input)?,
+        byte if byte < 0x20 => Key::Ignored,
+        byte => {
+            // The other bytes of a character which is not ASCII.
+            let len = match byte {
+                0xf0.. => 4,
+                0xe0.. => 3,
+                0xc0.. => 2,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.extend(read_byte(input)?);
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Ignored,
+            }
+        }
+    };
+    Ok(key)
+}
+
+/// Read the rest of a key which starts with escape, like the arrow keys.
+fn read_escape_sequence(input: &mut impl Read) -> io::Result<Key> {
+    let (Some(b'[' | b'O'), Some(byte)) = (read_byte(input)?, read_byte(input)?) else {
+        return Ok(Key::Ignored);
+    };
+    // Keys like delete are sent as a number ending with '~', and keys
+    // combined with modifiers have more numbers separated by ';'.
+    let mut params = vec![];
+    let mut byte = byte;
+    while byte.is_ascii_digit() || byte == b';' {
+        params.push(byte);
+        let Some(next) = read_byte(input)? else {
+            return Ok(Key::Ignored);
+        };
+        byte = next;
+    }
+    let key = match (params.as_slice(), byte) {
+        (b"", b'A') => Key::Up,
+        (b"", b'B') => Key::Down,
+        (b"", b'C') => Key::Right,
+        (b"", b'D') => Key::Left,
+        (b"", b'H') | (b"1" | b"7", b'~') => Key::Home,
+        (b"", b'F') | (b"4" | b"8", b'~') => Key::End,
+        (b"3", b'~') => Key::Delete,
+        _ => Key::Ignored,
+    };
+    Ok(key)
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Keeps the terminal in raw mode until it is dropped.
+struct RawMode {
+    #[cfg(unix)]
+    original: libc::termios,
+}
+
+impl RawMode {
+    /// Switch the terminal to raw mode. Returns `None` if
+    /// the input is not a terminal.
+    #[cfg(unix)]
+    fn enable() -> Option<Self> {
+        // SAFETY: the terminal settings are only read into and
+        // written from a valid `termios` struct.
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 {
+                return None;
+            }
+            let mut original: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return None;
+            }
+            let mut raw = original;
+            // Get every key as it is typed, without echoing it. Output is
+            // still processed, so the output of searches shows up as usual.
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+            Some(Self { original })
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn enable() -> Option<Self> {
+        None
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in `RawMode::enable`.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_completions(_: &str) -> Vec<String> {
+        vec![]
+    }
+
+    fn type_keys(editor: &mut LineEditor, keys: &[Key]) -> Vec<Action> {
+        let mut complete = |line: &str| match line.starts_with("play") {
+            true => vec!["0-1".to_string(), "0-7".to_string(), "!".to_string()],
+            false => vec!["play".to_string(), "perft".to_string(), "show".to_string()],
+        };
+        keys.iter()
+            .map(|&key| editor.handle_key(key, &mut complete))
+            .collect()
+    }
+
+    fn chars(text: &str) -> Vec<Key> {
+        text.chars().map(Key::Char).collect()
+    }
+
+    #[test]
+    fn editing() {
+        let mut editor = LineEditor::new();
+        type_keys(&mut editor, &chars("pay 1"));
+        type_keys(&mut editor, &[Key::Home, Key::Right, Key::Char('l')]);
+        assert_eq!(editor.line(), "play 1");
+        assert_eq!(editor.cursor(), 2);
+        type_keys(&mut editor, &[Key::End, Key::Backspace, Key::Char('2')]);
+        assert_eq!(editor.line(), "play 2");
+        type_keys(&mut editor, &[Key::DeleteWord]);
+        assert_eq!(editor.line(), "play ");
+        type_keys(&mut editor, &[Key::Left, Key::Left, Key::KillToEnd]);
+        assert_eq!(editor.line(), "pla");
+        type_keys(&mut editor, &[Key::Left, Key::KillToStart]);
+        assert_eq!((editor.line().as_str(), editor.cursor()), ("a", 0));
+        type_keys(&mut editor, &[Key::Delete, Key::Delete, Key::Left]);
+        assert_eq!((editor.line().as_str(), editor.cursor()), ("", 0));
+        assert_eq!(
+            editor.handle_key(Key::Eof, &mut no_completions),
+            Action::Eof
+        );
+        type_keys(&mut editor, &chars("show"));
+        assert_eq!(
+            editor.handle_key(Key::Interrupt, &mut no_completions),
+            Action::Interrupted
+        );
+        assert_eq!(editor.line(), "");
+    }
+
+    #[test]
+    fn history() {
+        let mut editor = LineEditor::new();
+        for line in ["show", "fen", "fen", "  "] {
+            let mut keys = chars(line);
+            keys.push(Key::Enter);
+            let actions = type_keys(&mut editor, &keys);
+            assert_eq!(actions.last(), Some(&Action::Submit(line.to_string())));
+        }
+        // Empty lines and repeated lines are not kept.
+        assert_eq!(editor.history(), ["show", "fen"]);
+        type_keys(&mut editor, &chars("new"));
+        type_keys(&mut editor, &[Key::Up]);
+        assert_eq!(editor.line(), "fen");
+        type_keys(&mut editor, &[Key::Up, Key::Up]);
+        assert_eq!(editor.line(), "show");
+        type_keys(&mut editor, &[Key::Down]);
+        assert_eq!(editor.line(), "fen");
+        // The new line comes back after the history.
+        type_keys(&mut editor, &[Key::Down]);
+        assert_eq!(editor.line(), "new");
+        type_keys(&mut editor, &[Key::Down]);
+        assert_eq!(editor.line(), "new");
+    }
+
+    #[test]
+    fn completion() {
+        let mut editor = LineEditor::new();
+        let actions = type_keys(&mut editor, &[Key::Char('s'), Key::Tab]);
+        assert_eq!(actions[1], Action::Redraw);
+        assert_eq!(editor.line(), "show ");
+
+        let mut editor = LineEditor::new();
+        let actions = type_keys(&mut editor, &[Key::Char('p'), Key::Tab]);
+        assert_eq!(
+            actions[1],
+            Action::ShowCompletions(vec!["play".to_string(), "perft".to_string()])
+        );
+        assert_eq!(editor.line(), "p");
+        type_keys(&mut editor, &[Key::Char('l'), Key::Tab]);
+        assert_eq!(editor.line(), "play ");
+        // The common part of the candidates is added.
+        type_keys(&mut editor, &[Key::Char('0'), Key::Tab]);
+        assert_eq!(editor.line(), "play 0-");
+        type_keys(&mut editor, &[Key::Char('7'), Key::Tab]);
+        assert_eq!(editor.line(), "play 0-7 ");
+        // Only the part before the cursor is completed.
+        let actions = type_keys(&mut editor, &[Key::Home, Key::Tab]);
+        assert_eq!(
+            actions[1],
+            Action::ShowCompletions(vec![
+                "play".to_string(),
+                "perft".to_string(),
+                "show".to_string()
+            ])
+        );
+        assert_eq!(editor.line(), "play 0-7 ");
+    }
+
+    #[test]
+    fn keys() {
+        let key = |bytes: &[u8]| read_key(&mut &bytes[..]).unwrap();
+        assert_eq!(key(b"a"), Key::Char('a'));
+        assert_eq!(key("é".as_bytes()), Key::Char('é'));
+        assert_eq!(key(b"\r"), Key::Enter);
+        assert_eq!(key(b"\x1b[A"), Key::Up);
+        assert_eq!(key(b"\x1bOF"), Key::End);
+        assert_eq!(key(b"\x1b[3~"), Key::Delete);
+        assert_eq!(key(b"\x1b[1;5C"), Key::Ignored);
+        assert_eq!(key(b"\x1b[1~"), Key::Home);
+        assert_eq!(key(b"\x03"), Key::Interrupt);
+        assert_eq!(key(b""), Key::Eof);
+    }
+}
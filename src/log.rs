@@ -0,0 +1,102 @@
+//! Logging everything the engine reports to a file, with the time of each
+//! line, to find out afterwards what happened during a session.
+
+use crate::gamefile::{date, time_of_day, unix_time};
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A log file. Lines are added to the end of the file.
+#[derive(Debug)]
+pub struct Log {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Log {
+    /// Start logging to the given file, after what is already in it.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Start logging to a new file in the directory `dir`, named after
+    /// `name` and the current time, e.g. "gui-2024.03.01-153000.log".
+    pub fn session(dir: &Path, name: &str) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let secs = unix_time().as_secs();
+        let (hours, minutes, seconds) = time_of_day(secs);
+        let file_name = format!(
+            "{name}-{}-{hours:02}{minutes:02}{seconds:02}.log",
+            date(secs)
+        );
+        Self::open(&dir.join(file_name))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add a line with the current time. Every line of `text`
+    /// gets the time, so the log can be searched by line.
+    pub fn line(&self, text: impl Display) {
+        let time = timestamp();
+        let text = text.to_string();
+        let mut lines = String::new();
+        for line in text.trim_end().lines() {
+            lines.push_str(&format!("[{time}] {line}\n"));
+        }
+        let mut file = self.file.lock().unwrap();
+        // Nothing can be done if the log can't be written, so errors are ignored.
+        let _ = file.write_all(lines.as_bytes());
+        let _ = file.flush();
+    }
+}
+
+/// The current time (in UTC) as "YYYY.MM.DD HH:MM:SS.mmm".
+fn timestamp() -> String {
+    let time = unix_time();
+    let (hours, minutes, seconds) = time_of_day(time.as_secs());
+    format!(
+        "{} {hours:02}:{minutes:02}:{seconds:02}.{:03}",
+        date(time.as_secs()),
+        time.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_lines() {
+        assert_eq!(time_of_day(86400 * 3 + 3600 * 13 + 60 * 5 + 9), (13, 5, 9));
+
+        let path = std::env::temp_dir().join("second_best_log_test.log");
+        let _ = std::fs::remove_file(&path);
+        let log = Log::open(&path).unwrap();
+        log.line("> eval 5");
+        log.line("first\nsecond\n");
+        drop(log);
+        // Opening the log again adds to it.
+        Log::open(&path).unwrap().line("again");
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        for (line, expected) in lines.iter().zip(["> eval 5", "first", "second", "again"]) {
+            let (time, text) = line.split_once("] ").unwrap();
+            // E.g. "[2024.03.01 15:30:00.123"
+            assert_eq!(time.len(), 24);
+            assert!(time.starts_with('['));
+            assert_eq!(text, expected);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,21 +1,123 @@
 use second_best::cli::Cli;
+use second_best::config::Config;
+use second_best::line_editor::LineEditor;
+use std::path::Path;
+
+/// Printed for `--help`. The commands themselves are described by `help`.
+const USAGE: &str = "\
+A solver for \"Second-Best!\" by Wannes Malfait.
+
+Usage: second-best [OPTIONS] [COMMAND]...
+
+Without a command, commands are read interactively (type `help` to list them).
+A command given as arguments is run once, e.g. `second-best eval --pos \"0 1\" --depth 8`.
+
+Options:
+  --config <path>    Load the config from <path> instead of `second-best.toml`
+  -c <name>=<value>  Change a value of the config, e.g. `-c tt-size=256`
+  --script <path>    Run the commands in <path> at startup, then continue interactively
+  -h, --help         Print this help
+
+Options of a command given as arguments:
+  --pos <moves>      Set the position to the moves from the start first
+  --fen <fen>        Set the position in compact notation first
+  --json             Give the output as JSON
+";
+
+/// Load the config given with `--config <path>`, or the default config file,
+/// and change the values given with `-c <name>=<value>`. These arguments
+/// come first, and are removed from `args`.
+fn load_config(args: &mut Vec<String>) -> Result<Config, String> {
+    let mut path = None;
+    let mut changes = vec![];
+    while args.len() >= 2 {
+        match args[0].as_str() {
+            "--config" => path = Some(args.remove(1)),
+            "-c" => changes.push(args.remove(1)),
+            _ => break,
+        }
+        args.remove(0);
+    }
+    let mut config = match path {
+        Some(path) => Config::load(Path::new(&path))
+            .map_err(|e| format!("Could not load the config from {path}: {e}"))?,
+        None => Config::load_default().map_err(|e| e.to_string())?,
+    };
+    for change in changes {
+        let Some((name, value)) = change.split_once('=') else {
+            return Err(format!("Expected `-c <name>=<value>`, got `-c {change}`"));
+        };
+        config
+            .set(name, value)
+            .map_err(|e| format!("Could not set {name} to {value}: {e:?}"))?;
+    }
+    Ok(config)
+}
 
 fn main() {
-    let mut buf;
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if matches!(args.first().map(String::as_str), Some("--help" | "-h")) {
+        print!("{USAGE}");
+        return;
+    }
     let mut cli = Cli::default();
+    let config = load_config(&mut args).and_then(|config| {
+        cli.set_config(config)
+            .map_err(|e| format!("Could not use the config: {e}"))
+    });
+    if let Err(e) = config {
+        eprintln!("{e}");
+        std::process::exit(2);
+    }
+    // A script which is run at startup, before continuing interactively.
+    let mut startup_command = None;
+    if let [flag, path] = args.as_slice() {
+        if flag == "--script" {
+            startup_command = Some(format!("run-script {path}"));
+        }
+    }
+    // A single command given as arguments, e.g. `second-best eval --pos "0 1" --depth 8`.
+    if !args.is_empty() && startup_command.is_none() {
+        let code = match cli.run_once(&args) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                eprintln!("{e}");
+                2
+            }
+        };
+        // Stop the search thread before exiting.
+        drop(cli);
+        std::process::exit(code);
+    }
+    let mut editor = LineEditor::new();
     println!(
         "A solver for \"Second-Best!\" by Wannes Malfait.\nType `help` for usage information."
     );
     loop {
-        buf = "".to_string();
-        std::io::stdin().read_line(&mut buf).unwrap();
-        match cli.execute_command(&buf) {
+        let line = match startup_command.take() {
+            Some(command) => Ok(Some(command)),
+            None => editor.read_line("> ", |line| cli.completions(line)),
+        };
+        let line = match line {
+            Ok(Some(line)) => line,
+            // Stop the search thread properly at the end of the input.
+            Ok(None) => "quit".to_string(),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        };
+        match cli.execute_command(&line) {
             Ok(quit) => {
                 if quit {
                     return;
                 }
             }
-            Err(e) => return eprintln!("{e}"),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
         }
     }
 }
@@ -0,0 +1,282 @@
+//! Monte Carlo tree search, for choosing moves to play instead of solving.
+//!
+//! The tree is grown with UCT: moves which won often in the playouts so far,
+//! or which haven't been tried often, are explored first. The playouts follow
+//! the static evaluation, with some random moves, and are cut off after a
+//! few moves, in which case the static evaluation decides who is better.
+//!
+//! This gives reasonable moves with very little time, and plays differently
+//! from the alpha-beta search, which makes it a good sparring partner.
+
+use crate::eval::{self, EvalParams};
+use crate::movegen::{MoveGen, MoveList};
+use crate::position::{BitboardMove, PlayerMove, Position};
+use crate::rng::Rng;
+use crate::solver::SearchLimits;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use web_time as time;
+
+struct Node {
+    /// The move leading to this node, `None` for the root.
+    bmove: Option<BitboardMove>,
+    children: Vec<usize>,
+    /// The moves which don't have a child node yet.
+    unexpanded: Vec<BitboardMove>,
+    visits: u32,
+    /// The total reward of the playouts through this node, for the
+    /// player who played the move leading to it.
+    reward: f64,
+}
+
+impl Node {
+    fn new(bmove: Option<BitboardMove>, pos: &Position) -> Self {
+        Self {
+            bmove,
+            children: vec![],
+            unexpanded: MoveGen::new(pos, None).collect(),
+            visits: 0,
+            reward: 0.0,
+        }
+    }
+}
+
+/// A player using Monte Carlo tree search. See the [module documentation](self).
+pub struct Mcts {
+    nodes: Vec<Node>,
+    eval_params: EvalParams,
+    rng: Rng,
+    playouts: usize,
+    abort: Arc<AtomicBool>,
+}
+
+impl Mcts {
+    /// The number of playouts if there is no node or time limit.
+    pub const DEFAULT_PLAYOUTS: usize = 20_000;
+    /// How much unexplored moves are preferred over moves with good results.
+    const EXPLORATION: f64 = 1.4;
+    /// The maximal number of moves in a playout.
+    const PLAYOUT_LENGTH: usize = 30;
+    /// The chance in percent that a playout plays a random move, instead
+    /// of the move with the best static evaluation.
+    const RANDOM_MOVE_CHANCE: usize = 25;
+
+    pub fn new(abort: Arc<AtomicBool>, eval_params: EvalParams, seed: u64) -> Self {
+        Self {
+            nodes: vec![],
+            eval_params,
+            rng: Rng::new(seed),
+            playouts: 0,
+            abort,
+        }
+    }
+
+    /// The number of playouts done in the last call to [`Mcts::choose_move`].
+    pub fn playouts(&self) -> usize {
+        self.playouts
+    }
+
+    /// Choose a move to play in the given position. The search stops after
+    /// `max_nodes` playouts or `max_time`, and the depth is not used.
+    /// Returns `None` if the game is over.
+    pub fn choose_move(&mut self, pos: &Position, limits: SearchLimits) -> Option<PlayerMove> {
+        if pos.game_over() {
+            return None;
+        }
+        let start = time::Instant::now();
+        let max_playouts = match (limits.max_nodes, limits.max_time) {
+            (Some(max_nodes), _) => max_nodes,
+            (None, Some(_)) => usize::MAX,
+            (None, None) => Self::DEFAULT_PLAYOUTS,
+        };
+        let mut pos = pos.clone();
+        self.nodes = vec![Node::new(None, &pos)];
+        self.playouts = 0;
+        while self.playouts < max_playouts {
+            if self.abort.load(Ordering::Relaxed)
+                || matches!(limits.max_time, Some(max_time) if start.elapsed() >= max_time)
+            {
+                break;
+            }
+            self.iterate(&mut pos);
+            self.playouts += 1;
+        }
+        // The most visited move is the one which did best.
+        let best_move = match self.nodes[0]
+            .children
+            .iter()
+            .max_by_key(|&&child| self.nodes[child].visits)
+        {
+            Some(&child) => self.nodes[child].bmove,
+            // Not even a single playout was done.
+            None => self.nodes[0].unexpanded.first().copied(),
+        };
+        best_move.map(|bmove| bmove.to_player_move(&pos))
+    }
+
+    /// Select a leaf of the tree, add a child to it, do a playout
+    /// from there, and update the nodes on the way with the result.
+    fn iterate(&mut self, pos: &mut Position) {
+        let mut path = vec![0];
+        let mut node = 0;
+        while !pos.game_over() && pos.ply() + 1 < Position::MAX_MOVES {
+            if self.nodes[node].unexpanded.is_empty() && self.nodes[node].children.is_empty() {
+                // There are no legal moves.
+                break;
+            }
+            let unexpanded = &mut self.nodes[node].unexpanded;
+            if !unexpanded.is_empty() {
+                let bmove = unexpanded.swap_remove(self.rng.below(unexpanded.len()));
+                pos.make_move(bmove);
+                let child = self.nodes.len();
+                self.nodes.push(Node::new(Some(bmove), pos));
+                self.nodes[node].children.push(child);
+                path.push(child);
+                break;
+            }
+            node = self.select_child(node);
+            pos.make_move(self.nodes[node].bmove.unwrap());
+            path.push(node);
+        }
+        // The reward for the player to move in the leaf, which is the
+        // opponent of the player who played the move leading to it.
+        let mut reward = self.playout(pos);
+        for &node in path.iter().rev() {
+            reward = 1.0 - reward;
+            self.nodes[node].visits += 1;
+            self.nodes[node].reward += reward;
+        }
+        for _ in 1..path.len() {
+            pos.unmake_move();
+        }
+    }
+
+    /// The child with the best UCT score.
+    fn select_child(&self, node: usize) -> usize {
+        let log_visits = (self.nodes[node].visits as f64).ln();
+        let uct = |child: usize| {
+            let child = &self.nodes[child];
+            let visits = child.visits as f64;
+            child.reward / visits + Self::EXPLORATION * (log_visits / visits).sqrt()
+        };
+        *self.nodes[node]
+            .children
+            .iter()
+            .max_by(|&&a, &&b| uct(a).total_cmp(&uct(b)))
+            .unwrap()
+    }
+
+    /// Play the game on for a few moves, and return the reward for the
+    /// player to move: 1 for a win, 0 for a loss, and in between if the
+    /// game isn't over yet.
+    fn playout(&mut self, pos: &mut Position) -> f64 {
+        let mut plies = 0;
+        let reward = loop {
+            if pos.game_over() {
+                break 0.0;
+            }
+            if plies >= Self::PLAYOUT_LENGTH || pos.ply() + 1 >= Position::MAX_MOVES {
+                let eval = eval::static_eval(pos, &self.eval_params) as f64;
+                break 0.5 + 0.5 * (eval / 4.0).tanh();
+            }
+            let Some(bmove) = self.playout_move(pos) else {
+                // Without legal moves, we can't avoid losing.
+                break 0.0;
+            };
+            pos.make_move(bmove);
+            plies += 1;
+        };
+        for _ in 0..plies {
+            pos.unmake_move();
+        }
+        // Every move switches the player to move.
+        match plies % 2 {
+            0 => reward,
+            _ => 1.0 - reward,
+        }
+    }
+
+    /// The move played in a playout: a winning move if there is one,
+    /// otherwise usually the one with the best static evaluation, but
+    /// sometimes a random one. Returns `None` if there are no legal moves.
+    fn playout_move(&mut self, pos: &mut Position) -> Option<BitboardMove> {
+        let mut moves = MoveList::new();
+        MoveGen::new(pos, None).generate_into(&mut moves);
+        let mut scores = [0; MoveList::CAPACITY];
+        for (i, &bmove) in moves.iter().enumerate() {
+            pos.make_move(bmove);
+            let game_over = pos.game_over();
+            if !game_over {
+                scores[i] = -eval::static_eval(pos, &self.eval_params);
+            }
+            pos.unmake_move();
+            if game_over {
+                return Some(bmove);
+            }
+        }
+        if moves.is_empty() {
+            return None;
+        }
+        if self.rng.below(100) < Self::RANDOM_MOVE_CHANCE {
+            return Some(moves[self.rng.below(moves.len())]);
+        }
+        // Prefer the first of the moves with the best score,
+        // since the moves are generated from best to worst.
+        moves
+            .iter()
+            .zip(&scores[..moves.len()])
+            .rev()
+            .max_by_key(|&(_, score)| score)
+            .map(|(&bmove, _)| bmove)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::ExplainableEval;
+    use crate::Solver;
+
+    #[test]
+    fn playout_limit() {
+        let mut mcts = Mcts::new(Arc::new(AtomicBool::new(false)), EvalParams::default(), 0);
+        let limits = SearchLimits {
+            max_nodes: Some(500),
+            ..Default::default()
+        };
+        let pmove = mcts.choose_move(&Position::default(), limits).unwrap();
+        assert!(Position::default().is_legal(pmove).is_ok());
+        assert_eq!(mcts.playouts(), 500);
+        assert_eq!(mcts.nodes[0].visits, 500);
+    }
+
+    #[test]
+    fn finds_winning_moves() {
+        let mut mcts = Mcts::new(Arc::new(AtomicBool::new(false)), EvalParams::default(), 0);
+        let limits = SearchLimits {
+            max_nodes: Some(1000),
+            ..Default::default()
+        };
+        // Positions from the benchmark, which are won in 3 moves.
+        for moves in [
+            "1 1 3 6 3 7 5 1 2 6",
+            "0 0 6 2 6 4 7 2 2 4 4 3",
+            "5 7 4 2 4 5 1 4 0",
+        ] {
+            let mut pos = Position::default();
+            let moves = moves.split_whitespace().map(|s| s.to_string()).collect();
+            pos.parse_and_play_moves(moves).unwrap();
+            let pmove = mcts.choose_move(&pos, limits).unwrap();
+            pos.try_make_move(pmove).unwrap();
+            let mut solver = Solver::default();
+            solver.position = pos;
+            let eval = solver.search(6);
+            let ply = solver.position.ply() as isize;
+            assert!(matches!(
+                eval::decode_eval(eval, ply),
+                ExplainableEval::Loss(_)
+            ));
+        }
+    }
+}
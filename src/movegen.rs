@@ -2,7 +2,16 @@ use crate::position;
 use position::Bitboard;
 use position::BitboardMove;
 use position::Position;
+use position::Variant;
 
+use std::ops::Deref;
+
+/// Generates the legal moves in a position, as an iterator.
+/// Moves which are likely to be good are generated first: the given
+/// pv-move, then moves making a vertical alignment, the counter move (see
+/// [`MoveGen::with_counter_move`]), moves to stacks we don't control yet,
+/// "Second Best!", and finally the other moves.
+#[derive(Clone)]
 pub struct MoveGen {
     /// Spots which give us a vertical alignment.
     alignment_spots: Bitboard,
@@ -12,33 +21,103 @@ pub struct MoveGen {
     bad_to_spots: Bitboard,
     /// Possible "from" spots. These are the spots on top of stacks we control.
     possible_from_spots: Bitboard,
+    /// The stacks we can move to in the second phase, for every direction.
+    /// See [`Position::second_phase_moves_bb`].
+    moves_to: [Bitboard; 3],
+    /// The stacks we can move to in the second phase, from any direction.
+    reachable_stacks: Bitboard,
     /// Potential move that we are not allowed to play anymore.
     banned_move: Option<Bitboard>,
     /// Are we in the second phase of the game?
     second_phase: bool,
+    /// The size of the board.
+    variant: Variant,
     /// Current stack we are generating moves to.
     stack_i: usize,
     /// Current stage we are in to generate moves in the second phase.
     adjacent_stage: Adjacent,
     /// The pv-move from a previous iteration.
     pv_move: Option<BitboardMove>,
+    /// A legal move which refuted the previous move of the opponent elsewhere.
+    counter_move: Option<BitboardMove>,
     /// If we can play "Second Best"
     can_second_best: bool,
     /// The stage of move generation we are in.
     stage: Stage,
 }
 
-#[derive(PartialEq, Eq)]
+/// A list of moves in a buffer of fixed size, so that moves can be
+/// generated without allocating. See [`MoveGen::generate_into`].
+///
+/// The alpha-beta search uses [`MoveGen`] as an iterator instead, which
+/// doesn't allocate either, and doesn't generate the moves after a cutoff.
+#[derive(Clone)]
+pub struct MoveList {
+    moves: [BitboardMove; MoveList::CAPACITY],
+    len: usize,
+}
+
+impl MoveList {
+    /// The maximal number of moves in a position: a stone move in every
+    /// direction from every stack, "Second Best!", and the pv-move, which
+    /// is not checked for legality.
+    pub const CAPACITY: usize = 3 * Variant::MAX_STACKS + 2;
+
+    pub fn new() -> Self {
+        Self {
+            moves: [BitboardMove::SecondBest; Self::CAPACITY],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, bmove: BitboardMove) {
+        self.moves[self.len] = bmove;
+        self.len += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Only keep the moves for which `keep` returns true, in the same order.
+    pub fn retain(&mut self, mut keep: impl FnMut(&BitboardMove) -> bool) {
+        let mut len = 0;
+        for i in 0..self.len {
+            if keep(&self.moves[i]) {
+                self.moves[len] = self.moves[i];
+                len += 1;
+            }
+        }
+        self.len = len;
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [BitboardMove];
+
+    fn deref(&self) -> &Self::Target {
+        &self.moves[..self.len]
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
 enum Stage {
     PvMove,
     VerticalAlignments,
+    CounterMove,
     GoodToMoves,
     SecondBest,
     BadToMoves,
 }
 
 /// Used to keep track of which moves we generated already.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Adjacent {
     Left,
     Right,
@@ -54,12 +133,16 @@ impl MoveGen {
                 good_to_spots: 0,
                 bad_to_spots: 0,
                 possible_from_spots: 0,
+                moves_to: [0; 3],
+                reachable_stacks: 0,
                 banned_move: None,
                 second_phase: false,
+                variant: pos.variant(),
                 stack_i: 0,
                 adjacent_stage: Adjacent::Left,
                 can_second_best: pos.can_second_best(),
                 pv_move: None,
+                counter_move: None,
                 stage: Stage::SecondBest,
             };
         }
@@ -81,20 +164,111 @@ impl MoveGen {
         let bad_spots = possible_from_spots << 1;
         let good_to_spots = free_to_spots & !bad_spots;
         let bad_to_spots = free_to_spots & bad_spots;
+        let moves_to = match second_phase {
+            true => pos.second_phase_moves_bb(),
+            false => [0; 3],
+        };
         Self {
             alignment_spots,
             good_to_spots,
             bad_to_spots,
             possible_from_spots,
+            moves_to,
+            reachable_stacks: moves_to[0] | moves_to[1] | moves_to[2],
             banned_move,
             second_phase,
+            variant: pos.variant(),
             stack_i: 0,
             adjacent_stage: Adjacent::Left,
             can_second_best: pos.can_second_best(),
             pv_move,
+            counter_move: None,
             stage: Stage::PvMove,
         }
     }
+
+    /// Generate the given move just after the moves making a vertical
+    /// alignment, if it is a legal stone move in the second phase.
+    /// This is for a move which refuted the same move of the opponent in
+    /// another position, see [`CounterMoves`].
+    pub fn with_counter_move(mut self, counter_move: Option<BitboardMove>) -> Self {
+        let Some(BitboardMove::StoneMove(smove)) = counter_move else {
+            return self;
+        };
+        if !self.second_phase || counter_move == self.pv_move || self.banned_move == Some(smove) {
+            return self;
+        }
+        // The move is legal if one of its stacks is ours, and the other
+        // is free. The stacks are next to or opposite of each other,
+        // because it was legal in another position.
+        let from = smove & self.possible_from_spots;
+        let to = smove & (self.alignment_spots | self.good_to_spots | self.bad_to_spots);
+        if from != 0 && to != 0 && from | to == smove {
+            self.counter_move = counter_move;
+        }
+        self
+    }
+}
+
+/// For every move of the opponent, the reply which last caused a beta cutoff
+/// in the second phase, where the other ways of ordering the moves are weak.
+/// After "Second Best!", the reply to the banned move is kept separately.
+#[derive(Clone)]
+pub struct CounterMoves {
+    table: Vec<Option<BitboardMove>>,
+}
+
+impl CounterMoves {
+    /// The most bits the first copy of the board can have.
+    const NUM_SPOTS: usize = Bitboard::BITS as usize / 2;
+
+    pub fn new() -> Self {
+        Self {
+            table: vec![None; 2 * Self::NUM_SPOTS * Self::NUM_SPOTS],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.table.fill(None);
+    }
+
+    /// The counter move for the previous move of the opponent.
+    #[inline(always)]
+    pub fn get(&self, pos: &Position) -> Option<BitboardMove> {
+        Self::index(pos).and_then(|index| self.table[index])
+    }
+
+    /// Store a reply which caused a beta cutoff in the given position.
+    pub fn store(&mut self, pos: &Position, reply: BitboardMove) {
+        if let Some(index) = Self::index(pos) {
+            self.table[index] = Some(reply);
+        }
+    }
+
+    /// The index of the previous move of the opponent in the table, if we
+    /// are in the second phase. A move is given by the first and the last
+    /// spot it changes in the first copy of the board.
+    #[inline(always)]
+    fn index(pos: &Position) -> Option<usize> {
+        if !pos.is_second_phase() {
+            return None;
+        }
+        let last_move = pos.last_stone_move()?;
+        let spots = (last_move & pos.variant().first_copy()) as u32;
+        if spots == 0 {
+            return None;
+        }
+        let first = spots.trailing_zeros() as usize;
+        let last = (u32::BITS - 1 - spots.leading_zeros()) as usize;
+        let second_best = usize::from(pos.banned_move().is_some());
+        Some((second_best * Self::NUM_SPOTS + first) * Self::NUM_SPOTS + last)
+    }
+}
+
+impl Default for CounterMoves {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Iterator for MoveGen {
@@ -126,7 +300,13 @@ impl Iterator for MoveGen {
                 }
             }
             self.stack_i = 0;
+            self.stage = Stage::CounterMove;
+        }
+        if self.stage == Stage::CounterMove {
             self.stage = Stage::GoodToMoves;
+            if self.counter_move.is_some() {
+                return self.counter_move;
+            }
         }
         if self.stage == Stage::GoodToMoves {
             if self.good_to_spots != 0 {
@@ -151,10 +331,20 @@ impl Iterator for MoveGen {
 }
 
 impl MoveGen {
+    /// Replace the contents of `moves` with the moves this generator still
+    /// has to generate, in the same order.
+    pub fn generate_into(&mut self, moves: &mut MoveList) {
+        moves.clear();
+        for bmove in self {
+            moves.push(bmove);
+        }
+    }
+
     fn next_stone_move(&mut self, to_spots: Bitboard) -> Option<BitboardMove> {
+        let variant = self.variant;
         if !self.second_phase {
-            while self.stack_i < Position::NUM_STACKS {
-                let candidate = Position::column_mask(self.stack_i) & to_spots;
+            while self.stack_i < variant.stacks() {
+                let candidate = variant.column_mask(self.stack_i) & to_spots;
                 self.stack_i += 1;
                 if candidate != 0 {
                     // Checking for pv move and banned move is already handled.
@@ -162,38 +352,40 @@ impl MoveGen {
                 }
             }
         } else {
-            while self.stack_i < Position::NUM_STACKS {
-                let to = Position::column_mask(self.stack_i) & to_spots;
-                if to == 0 {
-                    // This stack was not free.
+            while self.stack_i < variant.stacks() {
+                let to = variant.column_mask(self.stack_i) & to_spots;
+                let to_stack = variant.column_bottom_mask(self.stack_i);
+                if to == 0 || self.reachable_stacks & to_stack == 0 {
+                    // This stack was not free, or none of our stones can move to it.
                     self.stack_i += 1;
                     continue;
                 }
-                let from = match self.adjacent_stage {
+                // The index in `Variant::directions` of the offset from the
+                // "from" stack to the "to" stack, and the "from" stack.
+                let (direction, from_stack) = match self.adjacent_stage {
                     Adjacent::Left => {
                         self.adjacent_stage = Adjacent::Right;
-                        Position::column_mask(self.stack_i + Position::LEFT)
-                            & self.possible_from_spots
+                        (0, self.stack_i + variant.left())
                     }
                     Adjacent::Right => {
                         self.adjacent_stage = Adjacent::Opposite;
-                        Position::column_mask(self.stack_i + Position::RIGHT)
-                            & self.possible_from_spots
+                        (2, self.stack_i + variant.right())
                     }
                     Adjacent::Opposite => {
                         self.adjacent_stage = Adjacent::Left;
                         self.stack_i += 1;
-                        Position::column_mask(self.stack_i - 1 + Position::OPPOSITE)
-                            & self.possible_from_spots
+                        (1, self.stack_i - 1 + variant.opposite())
                     }
                 };
-                if from != 0 {
+                let from_stack = from_stack % variant.stacks();
+                if self.moves_to[direction] & to_stack != 0 {
+                    let from = variant.column_mask(from_stack) & self.possible_from_spots;
                     let candidate = to | from;
                     if self.banned_move == Some(candidate) {
                         continue;
                     }
                     let candidate = Some(BitboardMove::StoneMove(candidate));
-                    if candidate != self.pv_move {
+                    if candidate != self.pv_move && candidate != self.counter_move {
                         return candidate;
                     }
                 }
@@ -266,4 +458,77 @@ mod tests {
         }
         assert_eq!(nodes, 2525);
     }
+
+    #[test]
+    fn counter_move() {
+        let mut pos = position::Position::default();
+        pos.parse_and_play_moves(
+            "0 0 1 1 2 3 2 3 4 4 0 1 6 6 6 7"
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        let expected: Vec<BitboardMove> = MoveGen::new(&pos, None).collect();
+        let counter_move = expected.iter().rev().find(|&&bmove| {
+            matches!(bmove, BitboardMove::StoneMove(smove) if smove & pos.vertical_alignment_spots() == 0)
+        });
+        let moves: Vec<BitboardMove> = MoveGen::new(&pos, None)
+            .with_counter_move(counter_move.copied())
+            .collect();
+        assert_eq!(moves.len(), expected.len());
+        let alignments = pos.vertical_alignment_spots();
+        let num_alignments = expected
+            .iter()
+            .filter(|&&bmove| matches!(bmove, BitboardMove::StoneMove(smove) if smove & alignments != 0))
+            .count();
+        assert_eq!(Some(&moves[num_alignments]), counter_move);
+        assert!(expected.iter().all(|bmove| moves.contains(bmove)));
+
+        // Our move is not legal for the opponent, so it is ignored.
+        let counter_move = *counter_move.unwrap();
+        pos.make_move(counter_move);
+        let expected: Vec<BitboardMove> = MoveGen::new(&pos, None).collect();
+        let with_counter_move: Vec<BitboardMove> = MoveGen::new(&pos, None)
+            .with_counter_move(Some(counter_move))
+            .collect();
+        assert_eq!(with_counter_move, expected);
+
+        // The counter moves depend on the previous move of the opponent.
+        let mut counter_moves = CounterMoves::new();
+        assert_eq!(counter_moves.get(&pos), None);
+        counter_moves.store(&pos, expected[1]);
+        assert_eq!(counter_moves.get(&pos), Some(expected[1]));
+        pos.make_move(expected[0]);
+        assert_eq!(counter_moves.get(&pos), None);
+        counter_moves.clear();
+        pos.unmake_move();
+        assert_eq!(counter_moves.get(&pos), None);
+    }
+
+    #[test]
+    fn move_list() {
+        let mut pos = position::Position::default();
+        pos.parse_and_play_moves(
+            "0 0 1 1 2 3 2 3 4 4 0 1 6 6 6 7"
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        let pv_move = MoveGen::new(&pos, None).last();
+        let expected: Vec<BitboardMove> = MoveGen::new(&pos, pv_move).collect();
+        let mut moves = MoveList::new();
+        moves.push(BitboardMove::SecondBest);
+        MoveGen::new(&pos, pv_move).generate_into(&mut moves);
+        assert_eq!(*moves, expected);
+        assert_eq!(moves.first().copied(), pv_move);
+
+        moves.retain(|&bmove| bmove != BitboardMove::SecondBest);
+        let expected: Vec<BitboardMove> = expected
+            .into_iter()
+            .filter(|&bmove| bmove != BitboardMove::SecondBest)
+            .collect();
+        assert_eq!(*moves, expected);
+    }
 }
@@ -0,0 +1,11 @@
+//! What is shared by everything which can be set by name: the options of
+//! the solver, the weights of the evaluation and the house rules.
+
+/// Why an option could not be set.
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidOption {
+    /// There is no option with the given name.
+    UnknownName,
+    /// The value could not be parsed for the given option, or is out of range.
+    InvalidValue,
+}
@@ -0,0 +1,337 @@
+//! Proof-number search, as an alternative to the alpha-beta search of the
+//! [`Solver`](crate::Solver) for proving wins and losses.
+//!
+//! Alpha-beta needs to search every line up to the same depth, while
+//! proof-number search always expands the most promising position: the one
+//! which is the easiest to prove or disprove. This works well for proving
+//! deep wins with few moves for the losing side.
+//!
+//! This is the depth-first variant (df-pn), which only keeps the proof and
+//! disproof numbers in a table instead of the full tree.
+//!
+//! Positions can repeat in the second phase of the game. A position which
+//! is already on the current line, or a line which gets too long, counts as
+//! a failure for the attacker: the player for whom a win is being proven.
+//! This makes the proofs for the attacker correct, but not the disproofs.
+//! So a loss is proven by a second search, with the other player attacking.
+
+use crate::movegen::{MoveGen, MoveList};
+use crate::position::{Color, PlayerMove, Position};
+use crate::solver::SearchLimits;
+use crate::transposition_table::TranspositionTable;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use web_time as time;
+
+/// A proof or disproof number which is larger than any real one.
+const INFINITY: u32 = u32::MAX / 2;
+
+/// The proof and disproof numbers of a position, from the perspective of the
+/// player to move: `phi` is the number of positions which still need to be
+/// proven to show that they win, and `delta` to show that they lose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ProofNumbers {
+    phi: u32,
+    delta: u32,
+}
+
+impl ProofNumbers {
+    /// The position is not solved yet.
+    const UNKNOWN: Self = Self { phi: 1, delta: 1 };
+    /// The player to move wins.
+    const WIN: Self = Self {
+        phi: 0,
+        delta: INFINITY,
+    };
+    /// The player to move loses.
+    const LOSS: Self = Self {
+        phi: INFINITY,
+        delta: 0,
+    };
+
+    fn is_solved(&self) -> bool {
+        self.phi == 0 || self.delta == 0
+    }
+}
+
+/// The key of a position in the table. Whether the attacker is to move is
+/// part of the key, because only the results for the attacker are correct.
+type Key = (u64, bool);
+
+/// The result of a proof-number search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Proof {
+    /// The player to move wins, by playing the given move.
+    Win(PlayerMove),
+    /// The player to move loses.
+    Loss,
+    /// The position could not be solved within the limits, or the game
+    /// can go on forever.
+    Unknown,
+}
+
+impl Display for Proof {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Win(pmove) => write!(f, "win, by playing {pmove}"),
+            Self::Loss => write!(f, "loss"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// A solver using proof-number search. See the [module documentation](self).
+pub struct ProofSolver {
+    table: HashMap<Key, ProofNumbers>,
+    /// The maximal number of positions in the table. When it is full, the
+    /// positions which are not solved are removed from it.
+    max_entries: usize,
+    /// The positions on the current line.
+    path: HashSet<Key>,
+    attacker: Color,
+    nodes: usize,
+    limits: SearchLimits,
+    deadline: Option<time::Instant>,
+    stopped: bool,
+    abort: Arc<AtomicBool>,
+}
+
+impl ProofSolver {
+    /// The default maximal number of positions in the table.
+    pub const DEFAULT_MAX_ENTRIES: usize = 1 << 22;
+
+    pub fn new(abort: Arc<AtomicBool>) -> Self {
+        Self {
+            table: HashMap::new(),
+            max_entries: Self::DEFAULT_MAX_ENTRIES,
+            path: HashSet::new(),
+            attacker: Color::Black,
+            nodes: 0,
+            limits: SearchLimits::default(),
+            deadline: None,
+            stopped: false,
+            abort,
+        }
+    }
+
+    /// Limit the number of positions in the table, to limit the memory used.
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        self.max_entries = max_entries;
+    }
+
+    /// The number of positions expanded in the last call to [`ProofSolver::solve`].
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    /// Try to prove whether the player to move wins or loses. Only the
+    /// `max_nodes` and `max_time` limits are used, the search has no depth.
+    pub fn solve(&mut self, pos: &Position, limits: SearchLimits) -> Proof {
+        self.nodes = 0;
+        self.stopped = false;
+        self.limits = limits;
+        self.deadline = limits
+            .max_time
+            .map(|max_time| time::Instant::now() + max_time);
+        if pos.game_over() {
+            return Proof::Loss;
+        }
+        let mut pos = pos.clone();
+        let us = pos.current_player();
+        if let Some(pmove) = self.prove_win(&mut pos, us) {
+            return Proof::Win(pmove);
+        }
+        if !self.stopped && self.prove_win(&mut pos, us.other()).is_some() {
+            return Proof::Loss;
+        }
+        Proof::Unknown
+    }
+
+    /// Try to prove that `attacker` wins. Returns the winning move if the
+    /// attacker is to move, and any move otherwise.
+    fn prove_win(&mut self, pos: &mut Position, attacker: Color) -> Option<PlayerMove> {
+        // The results depend on the attacker.
+        self.table.clear();
+        self.attacker = attacker;
+        let root = self.mid(pos, INFINITY, INFINITY);
+        let proven = match pos.current_player() == attacker {
+            true => root == ProofNumbers::WIN,
+            false => root == ProofNumbers::LOSS,
+        };
+        if !proven {
+            return None;
+        }
+        // The winning move is one after which the opponent is lost.
+        MoveGen::new(pos, None).find_map(|bmove| {
+            let pmove = bmove.to_player_move(pos);
+            pos.make_move(bmove);
+            let lost = self.child_numbers(pos) == ProofNumbers::LOSS;
+            pos.unmake_move();
+            (lost || pos.current_player() != attacker).then_some(pmove)
+        })
+    }
+
+    fn key(&self, pos: &Position) -> Key {
+        (
+            TranspositionTable::key(pos),
+            pos.current_player() == self.attacker,
+        )
+    }
+
+    /// The proof numbers of a position which is not being expanded.
+    fn child_numbers(&self, pos: &Position) -> ProofNumbers {
+        if pos.game_over() {
+            return ProofNumbers::LOSS;
+        }
+        let key = self.key(pos);
+        if self.path.contains(&key) || pos.ply() + 1 >= Position::MAX_MOVES {
+            // The attacker can't win by repeating positions or by
+            // making the game go on forever.
+            return match key.1 {
+                true => ProofNumbers::LOSS,
+                false => ProofNumbers::WIN,
+            };
+        }
+        self.table
+            .get(&key)
+            .copied()
+            .unwrap_or(ProofNumbers::UNKNOWN)
+    }
+
+    fn store(&mut self, key: Key, numbers: ProofNumbers) {
+        if self.table.len() >= self.max_entries {
+            // Recycle the table: keep only the solved positions, or start
+            // over if those already fill most of it.
+            self.table.retain(|_, numbers| numbers.is_solved());
+            if self.table.len() >= self.max_entries / 2 {
+                self.table.clear();
+            }
+        }
+        self.table.insert(key, numbers);
+    }
+
+    /// Check if the search should be stopped, because it was
+    /// aborted or one of the limits was reached.
+    fn check_limits(&mut self) -> bool {
+        let out_of_nodes =
+            matches!(self.limits.max_nodes, Some(max_nodes) if self.nodes >= max_nodes);
+        let out_of_time =
+            matches!(self.deadline, Some(deadline) if time::Instant::now() >= deadline);
+        self.stopped = self.abort.load(Ordering::Relaxed) || out_of_nodes || out_of_time;
+        self.stopped
+    }
+
+    /// Expand the current position until its proof or disproof number
+    /// reaches the given threshold. This is the "multiple iterative
+    /// deepening" function of df-pn.
+    fn mid(&mut self, pos: &mut Position, th_phi: u32, th_delta: u32) -> ProofNumbers {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(1024) {
+            self.check_limits();
+        }
+        let key = self.key(pos);
+        let mut moves = MoveList::new();
+        MoveGen::new(pos, None).generate_into(&mut moves);
+        self.path.insert(key);
+        let numbers = loop {
+            // The player to move wins if one of the moves wins,
+            // and loses if all moves lose.
+            let mut numbers = ProofNumbers {
+                phi: INFINITY,
+                delta: 0,
+            };
+            let mut best = (0, ProofNumbers::UNKNOWN);
+            let mut second_best_delta = INFINITY;
+            for (i, &bmove) in moves.iter().enumerate() {
+                pos.make_move(bmove);
+                let child = self.child_numbers(pos);
+                pos.unmake_move();
+                numbers.delta = numbers.delta.saturating_add(child.phi).min(INFINITY);
+                if child.delta < numbers.phi {
+                    second_best_delta = numbers.phi;
+                    numbers.phi = child.delta;
+                    best = (i, child);
+                } else if child.delta < second_best_delta {
+                    second_best_delta = child.delta;
+                }
+            }
+            if numbers.phi >= th_phi || numbers.delta >= th_delta || self.stopped {
+                break numbers;
+            }
+            let (best_i, child) = best;
+            let child_th_phi = (th_delta - numbers.delta)
+                .saturating_add(child.phi)
+                .min(INFINITY);
+            let child_th_delta = th_phi.min(second_best_delta + 1);
+            pos.make_move(moves[best_i]);
+            let child = self.mid(pos, child_th_phi, child_th_delta);
+            let child_key = self.key(pos);
+            pos.unmake_move();
+            self.store(child_key, child);
+        };
+        self.path.remove(&key);
+        numbers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{self, ExplainableEval};
+    use crate::Solver;
+
+    #[test]
+    fn same_result_as_negamax() {
+        let abort = Arc::new(AtomicBool::new(false));
+        let mut pns = ProofSolver::new(abort.clone());
+        let mut solver = Solver::new(abort);
+        // Positions from the benchmark, which are solved in 2 to 5 moves.
+        for moves in [
+            "2 2 0 0 0 1 4 2 6 5 4 7 5 5 1 6 0-7 0-7 0-1 5-4 5-6",
+            "1 1 3 6 3 7 5 1 2 6",
+            "0 0 6 2 6 4 7 2 2 4 4 3",
+            "6 3 7 7 7 1 2 3 3 2 2 0 5 5 0 1",
+            "5 7 4 2 4 5 1 4 0",
+        ] {
+            let mut pos = Position::default();
+            let moves = moves.split_whitespace().map(|s| s.to_string()).collect();
+            pos.parse_and_play_moves(moves).unwrap();
+            solver.position = pos.clone();
+            let eval = solver.search(20);
+            let proof = pns.solve(&pos, SearchLimits::default());
+            match eval::decode_eval(eval, pos.ply() as isize) {
+                ExplainableEval::Win(_) => {
+                    let Proof::Win(pmove) = proof else {
+                        panic!("Expected a win, found {proof}");
+                    };
+                    // The winning move found by proof-number search has to win.
+                    solver.position.try_make_move(pmove).unwrap();
+                    let eval = solver.search(20);
+                    let ply = solver.position.ply() as isize;
+                    assert!(matches!(
+                        eval::decode_eval(eval, ply),
+                        ExplainableEval::Loss(_)
+                    ));
+                }
+                ExplainableEval::Loss(_) => assert_eq!(proof, Proof::Loss),
+                ExplainableEval::Undetermined(_) => (),
+            }
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let mut pns = ProofSolver::new(Arc::new(AtomicBool::new(false)));
+        pns.set_max_entries(1000);
+        let limits = SearchLimits {
+            max_nodes: Some(5000),
+            ..Default::default()
+        };
+        assert_eq!(pns.solve(&Position::default(), limits), Proof::Unknown);
+        assert!(pns.nodes() <= 5000 + 1024);
+        assert!(pns.table.len() <= 1000);
+    }
+}
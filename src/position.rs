@@ -1,6 +1,8 @@
+use crate::movegen::MoveGen;
+use crate::options::InvalidOption;
 use std::fmt::Display;
 /// A bitboard is a way to efficiently store board state.
-/// The board has 8 stacks with a maximal height of 3.
+/// The standard board has 8 stacks with a maximal height of 3.
 /// In total there are hence 8 * 3 = 24 spots.
 /// So, technically we just need a u32. However, since the
 /// board is in a circle, we will place two copies of the
@@ -42,6 +44,8 @@ use std::fmt::Display;
 /// .1111....1111...
 ///
 /// 0123456701234567 -- column index
+///
+/// Other board sizes are laid out in the same way, see [`Variant`].
 pub type Bitboard = u64;
 
 #[derive(Clone)]
@@ -70,59 +74,277 @@ pub struct Position {
     move_history: [Option<Bitboard>; Self::MAX_MOVES],
     /// History of all the moves which were banned.
     banned_moves: [Option<Bitboard>; Self::MAX_MOVES],
+    /// For every turn, whether the player who made the last move has an alignment.
+    /// Updated in `make_stone_move`, since eval, movegen and `game_over`
+    /// all need it for the same position.
+    alignments: [bool; Self::MAX_MOVES],
+    /// The house rules the game is played with.
+    rules: Rules,
+    /// The size of the board.
+    variant: Variant,
+    /// The number of times each player called "Second Best!", indexed by [`Color`].
+    second_best_calls: [usize; 2],
 }
 
-impl Position {
-    pub const NUM_STACKS: usize = 8;
-    pub const STACK_HEIGHT: usize = 3;
-    pub const STONES_PER_PLAYER: usize = 8;
-    pub const MAX_MOVES: usize = 255;
-    // Offset to get to the right of the current stack.
-    pub const RIGHT: usize = 1;
-    // Offset to get to the left of the current stack.
-    pub const LEFT: usize = Self::NUM_STACKS - 1;
-    // Offset to get to the opposite of the current stack.
-    pub const OPPOSITE: usize = Self::NUM_STACKS / 2;
+/// Variations on the rules of the game.
+/// The default follows the official rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rules {
+    /// Whether "Second Best!" can be called on a move which gives
+    /// the opponent an alignment.
+    pub second_best_on_alignment: bool,
+    /// How many times each player can call "Second Best!" in a game.
+    /// No limit if `None`. At most [`Rules::MAX_SECOND_BEST_LIMIT`].
+    pub second_best_limit: Option<usize>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            second_best_on_alignment: true,
+            second_best_limit: None,
+        }
+    }
+}
+
+impl Rules {
+    /// The highest limit on "Second Best!" calls. The calls left are part of
+    /// the key of a position in the transposition table, which has room for
+    /// three bits per player.
+    pub const MAX_SECOND_BEST_LIMIT: usize = 7;
+
+    /// Set the rule with the given name to the given value.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), InvalidOption> {
+        match name {
+            "second-best-on-alignment" => {
+                self.second_best_on_alignment =
+                    value.parse().map_err(|_| InvalidOption::InvalidValue)?
+            }
+            "second-best-limit" => {
+                self.second_best_limit = match value {
+                    "none" => None,
+                    _ => Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|&limit| limit <= Self::MAX_SECOND_BEST_LIMIT)
+                            .ok_or(InvalidOption::InvalidValue)?,
+                    ),
+                }
+            }
+            _ => return Err(InvalidOption::UnknownName),
+        }
+        Ok(())
+    }
+}
 
+/// The size of the board: the number of stacks, and how many stones
+/// a stack can hold. The default is [`Variant::STANDARD`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Variant {
+    stacks: usize,
+    height: usize,
     /// Bitboard with the bottom row set to ones.
-    const BOTTOM: Bitboard = Self::bottom(0, 0);
-    /// Bitboard with a 1 on the bottom of the first four columns.
-    const BOTTOM_FOUR: Bitboard = 1
-        | (1 << (Self::STACK_HEIGHT + 1))
-        | (1 << ((Self::STACK_HEIGHT + 1) * 2))
-        | (1 << ((Self::STACK_HEIGHT + 1) * 3));
-    const COLUMN_MASKS: [Bitboard; Self::NUM_STACKS * 2] = Self::gen_column_masks(0);
-
-    /// Create a bitboard with the bottom row set to ones
-    /// recursively at compile time.
-    const fn bottom(mut bb: Bitboard, col: usize) -> Bitboard {
-        if col == Self::NUM_STACKS * 2 {
-            return bb;
-        }
-        bb |= 1 << ((Self::STACK_HEIGHT + 1) * col);
-        Self::bottom(bb, col + 1)
-    }
-
-    const fn gen_column_masks(col: usize) -> [Bitboard; Self::NUM_STACKS * 2] {
-        let mut other_masks = if col == Self::NUM_STACKS - 1 {
-            [0; Self::NUM_STACKS * 2]
-        } else {
-            Self::gen_column_masks(col + 1)
-        };
+    bottom: Bitboard,
+    /// Bitboard with all the spots of the first column, in both copies
+    /// of the board.
+    column: Bitboard,
+}
+
+/// Reasons why a board size can't be played, see [`Variant::new`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidVariant {
+    /// The number of stacks should be even, and at least 4.
+    InvalidStacks,
+    /// A stack should be able to hold at least 3 stones.
+    TooLow,
+    /// The board doesn't fit in a bitboard.
+    TooLarge,
+    /// The size should be written as the stacks and the height, e.g. "6x4".
+    ParseError,
+}
+
+impl Default for Variant {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+impl Variant {
+    /// The board of the official rules: 8 stacks of at most 3 stones.
+    pub const STANDARD: Self = match Self::new(8, 3) {
+        Ok(variant) => variant,
+        Err(_) => panic!("the standard board should fit in a bitboard"),
+    };
+    /// The most stacks a board can have.
+    pub const MAX_STACKS: usize = 8;
+    /// The number of bits the key of the transposition table stores on top
+    /// of the board: whether "Second Best!" can be called, and three bits
+    /// for the calls left of each player.
+    const SPARE_BITS: usize = 7;
+
+    /// A board with the given number of stacks, which hold at most `height`
+    /// stones. Every player has a stone for every third spot of the board.
+    ///
+    /// Every stack needs an opposite one, and a horizontal alignment needs
+    /// four stacks, so the number of stacks should be even and at least 4.
+    /// A vertical alignment needs stacks of at least 3 stones. Both copies
+    /// of the board, with an extra bit on top of every column, have to fit
+    /// in a bitboard, with [`Variant::SPARE_BITS`] left over for the key of
+    /// the transposition table.
+    pub const fn new(stacks: usize, height: usize) -> Result<Self, InvalidVariant> {
+        if stacks < 4 || !stacks.is_multiple_of(2) {
+            return Err(InvalidVariant::InvalidStacks);
+        }
+        if height < 3 {
+            return Err(InvalidVariant::TooLow);
+        }
+        let copy_bits = stacks * (height + 1);
+        // The unused tops of the columns of the second copy, and the bits
+        // above both copies.
+        if copy_bits > Bitboard::BITS as usize / 2
+            || stacks + Bitboard::BITS as usize - 2 * copy_bits < Self::SPARE_BITS
+        {
+            return Err(InvalidVariant::TooLarge);
+        }
+        let mut bottom = 0;
+        let mut col = 0;
+        while col < 2 * stacks {
+            bottom |= 1 << ((height + 1) * col);
+            col += 1;
+        }
         // Substracting 1 from 1000 gives 0111.
-        // We can then shift that to the correct column.
-        let mask = ((1 << Self::STACK_HEIGHT) - 1) << ((Self::STACK_HEIGHT + 1) * col)
-        // We have two copies of the column in our bitboards.
-        | (((1 << Self::STACK_HEIGHT) - 1)
-            << ((Self::STACK_HEIGHT + 1) * (col + Self::NUM_STACKS)));
-        other_masks[col] = mask;
-        other_masks[col + Self::NUM_STACKS] = mask;
-        other_masks
+        let column = ((1 << height) - 1) | (((1 << height) - 1) << copy_bits);
+        Ok(Self {
+            stacks,
+            height,
+            bottom,
+            column,
+        })
+    }
+
+    /// The number of stacks on the board.
+    pub const fn stacks(&self) -> usize {
+        self.stacks
+    }
+
+    /// The number of stones a stack can hold.
+    pub const fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The number of stones each player places in the first phase.
+    pub const fn stones_per_player(&self) -> usize {
+        self.stacks * self.height / 3
+    }
+
+    /// Offset to get to the right of the current stack.
+    pub const fn right(&self) -> usize {
+        1
+    }
+
+    /// Offset to get to the left of the current stack.
+    pub const fn left(&self) -> usize {
+        self.stacks - 1
+    }
+
+    /// Offset to get to the opposite of the current stack.
+    pub const fn opposite(&self) -> usize {
+        self.stacks / 2
+    }
+
+    /// The offsets from the "from" stack to the "to" stack of a stone move in the
+    /// second phase, in the order used by [`Position::second_phase_moves_bb`].
+    pub const fn directions(&self) -> [usize; 3] {
+        [self.right(), self.opposite(), self.left()]
+    }
+
+    /// The number of bits of one copy of the board.
+    #[inline(always)]
+    const fn copy_bits(&self) -> usize {
+        (self.height + 1) * self.stacks
+    }
+
+    /// Bitboard with all the bits of the first copy of the board set to ones.
+    #[inline(always)]
+    pub(crate) const fn first_copy(&self) -> Bitboard {
+        (1 << self.copy_bits()) - 1
+    }
+
+    /// Bitboard with the bottom row of the first copy of the board set to ones.
+    #[inline(always)]
+    const fn first_bottom(&self) -> Bitboard {
+        self.bottom & self.first_copy()
+    }
+
+    /// A mask with all the spots marked in the given column.
+    #[inline(always)]
+    pub const fn column_mask(&self, col: usize) -> Bitboard {
+        self.column << ((self.height + 1) * col)
+    }
+
+    /// A mask with a one at the bottom of the given column.
+    #[inline(always)]
+    pub const fn column_bottom_mask(&self, col: usize) -> Bitboard {
+        1 << ((self.height + 1) * col)
+    }
+
+    /// The bitboard with a one set of the given spot.
+    #[inline(always)]
+    pub const fn bb_of_spot(&self, stack_i: usize, height: usize) -> Bitboard {
+        self.column_mask(stack_i) & self.bottom << height
+    }
+
+    /// The column that the lowest bit set in the bitboard is in.
+    #[inline(always)]
+    fn column_of_bit(&self, bb: Bitboard) -> usize {
+        debug_assert!(bb != 0, "Some bit should have been set!");
+        bb.trailing_zeros() as usize / (self.height + 1) % self.stacks
+    }
+
+    /// Check if the "to" spot is adjacent or opposite to the "from" spot.
+    #[inline(always)]
+    fn valid_adjacent(&self, from: usize, to: usize) -> bool {
+        self.directions()
+            .into_iter()
+            .any(|offset| (from + offset) % self.stacks == to)
+    }
+
+    /// The bits which are never set in the spots of a position: the tops of
+    /// the columns of the second copy of the board, and the bits above both
+    /// copies. The key of the transposition table stores more in them.
+    #[inline(always)]
+    pub(crate) fn spare_bits(&self) -> impl Iterator<Item = Bitboard> {
+        let (copy_bits, height) = (self.copy_bits(), self.height);
+        (0..self.stacks)
+            .map(move |col| copy_bits + (height + 1) * col + height)
+            .chain(2 * copy_bits..Bitboard::BITS as usize)
+            .map(|bit| 1 << bit)
     }
 }
 
-impl Default for Position {
-    fn default() -> Self {
+impl Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.stacks, self.height)
+    }
+}
+
+impl std::str::FromStr for Variant {
+    type Err = InvalidVariant;
+
+    /// Parse a board size written as in [`Variant`]'s `Display`, e.g. "6x4".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (stacks, height) = s.split_once('x').ok_or(InvalidVariant::ParseError)?;
+        let parse = |n: &str| n.parse().map_err(|_| InvalidVariant::ParseError);
+        Self::new(parse(stacks)?, parse(height)?)
+    }
+}
+
+impl Position {
+    pub const MAX_MOVES: usize = 255;
+
+    /// The starting position on a board of the given size.
+    pub fn with_variant(variant: Variant) -> Self {
         Position {
             played_spots: 0,
             our_spots: 0,
@@ -130,8 +352,24 @@ impl Default for Position {
             ply: 0,
             move_history: [None; Self::MAX_MOVES],
             banned_moves: [None; Self::MAX_MOVES],
+            alignments: [false; Self::MAX_MOVES],
+            rules: Rules::default(),
+            variant,
+            second_best_calls: [0; 2],
         }
     }
+
+    /// The size of the board the game is played on.
+    #[inline(always)]
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::with_variant(Variant::STANDARD)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -162,14 +400,48 @@ impl Display for Color {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Whether the game is over, and if so who won and why.
+/// See [`Position::game_status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum GameStatus {
-    /// Current player has won.
-    Win,
-    /// Current player has lost.
-    Loss,
     /// The game is not yet over.
     OnGoing,
+    /// The game is over, and the given player has won.
+    Won {
+        winner: Color,
+        reason: GameOverReason,
+    },
+}
+
+/// Why a game is over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameOverReason {
+    /// The winner has a vertical alignment, and "Second Best!"
+    /// can't be called on it anymore.
+    Alignment,
+    /// The loser has no legal moves left.
+    NoLegalMoves,
+}
+
+impl Display for GameStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameStatus::OnGoing => write!(f, "The game is not over yet"),
+            GameStatus::Won { winner, reason } => {
+                write!(f, "({winner}) has won! ")?;
+                match reason {
+                    GameOverReason::Alignment => write!(
+                        f,
+                        "({winner}) has an alignment, and \"Second Best!\" can't be called"
+                    ),
+                    GameOverReason::NoLegalMoves => {
+                        write!(f, "({}) has no legal moves", winner.other())
+                    }
+                }
+            }
+        }
+    }
 }
 
 /// A type of move a player can make.
@@ -248,19 +520,47 @@ impl PlayerMove {
             _ => Err(MoveFailed::ParseError),
         }
     }
-}
 
-impl BitboardMove {
-    /// The column that the single bit set in the bitboard is in.
-    fn column_of_bit(bb: Bitboard) -> usize {
-        for col in 0..Position::NUM_STACKS {
-            if bb & Position::column_mask(col) != 0 {
-                return col;
+    /// Parse a list of moves separated by whitespace.
+    /// Besides the separate `!` token, "Second Best!" can also be written
+    /// directly after the move it is called on, as in `5-4!`.
+    pub fn parse_moves(text: &str) -> Result<Vec<Self>, MoveFailed> {
+        let mut moves = vec![];
+        for token in text.split_whitespace() {
+            let (pmove, second_best) = Self::from_long_form(token)?;
+            moves.push(pmove);
+            if second_best {
+                moves.push(Self::SecondBest);
+            }
+        }
+        Ok(moves)
+    }
+
+    /// Parse a single move, which may have a "!" attached to it.
+    /// Also returns whether "Second Best!" was called on the move.
+    fn from_long_form(smove: &str) -> Result<(Self, bool), MoveFailed> {
+        match smove.strip_suffix('!') {
+            Some(smove) if !smove.is_empty() => Ok((Self::from(smove.to_string())?, true)),
+            _ => Ok((Self::from(smove.to_string())?, false)),
+        }
+    }
+
+    /// Write the moves in the long form, where "Second Best!" is attached
+    /// to the move it was called on, e.g. `3 5! 6`.
+    /// An "inverse" to `parse_moves`.
+    pub fn to_long_form(moves: &[Self]) -> String {
+        let mut text = String::new();
+        for pmove in moves {
+            if *pmove != Self::SecondBest && !text.is_empty() {
+                text.push(' ');
             }
+            text.push_str(&pmove.to_string());
         }
-        unreachable!("Some bit should have been set!")
+        text
     }
+}
 
+impl BitboardMove {
     pub fn to_player_move(self, pos: &Position) -> PlayerMove {
         match self {
             Self::SecondBest => PlayerMove::SecondBest,
@@ -269,10 +569,10 @@ impl BitboardMove {
                 let from = if from_spot == 0 {
                     None
                 } else {
-                    Some(Self::column_of_bit(from_spot))
+                    Some(pos.variant.column_of_bit(from_spot))
                 };
                 let to_spot = smove & pos.free_spots();
-                let to = Self::column_of_bit(to_spot);
+                let to = pos.variant.column_of_bit(to_spot);
                 PlayerMove::StoneMove { from, to }
             }
         }
@@ -303,11 +603,123 @@ pub enum MoveFailed {
     PositionWinning,
 }
 
+/// Reasons why a string could not be parsed with [`Position::from_fen`].
+#[derive(PartialEq, Eq, Debug)]
+pub enum InvalidFen {
+    /// The notation should have exactly four fields separated by spaces.
+    WrongNumberOfFields,
+    /// The board should have as many stacks as the variant, separated by '/'.
+    WrongNumberOfStacks,
+    /// The stack at the given index contains an invalid character or too many stones.
+    InvalidStack(usize),
+    /// The side to move should be either 'X' or 'O'.
+    InvalidSide,
+    /// The number of stones of each color does not match the side to move.
+    InvalidStoneCount,
+    /// The last move is not a move that could have lead to this position.
+    InvalidLastMove,
+    /// The banned move is not a valid move in this position.
+    InvalidBannedMove,
+}
+
+/// The contents of the board, without the moves that lead to it.
+/// This is everything needed to continue a game, and can be used to
+/// set up a position directly, e.g. in a board editor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoardState {
+    /// The size of the board.
+    pub variant: Variant,
+    /// The stones on each stack, from bottom to top.
+    pub stacks: Vec<Vec<Color>>,
+    /// The player whose turn it is.
+    pub side_to_move: Color,
+    /// The last move played, if "Second Best!" can still be called on it.
+    pub last_move: Option<PlayerMove>,
+    /// The move which is banned by a "Second Best!" call, if any.
+    pub banned_move: Option<PlayerMove>,
+}
+
+impl Default for BoardState {
+    fn default() -> Self {
+        Self::with_variant(Variant::STANDARD)
+    }
+}
+
+impl BoardState {
+    /// An empty board of the given size, with black to move.
+    pub fn with_variant(variant: Variant) -> Self {
+        Self {
+            variant,
+            stacks: vec![vec![]; variant.stacks()],
+            side_to_move: Color::Black,
+            last_move: None,
+            banned_move: None,
+        }
+    }
+}
+
+/// Reasons why a [`BoardState`] is not a legal position,
+/// see [`Position::from_board_state`].
+#[derive(PartialEq, Eq, Debug)]
+pub enum InvalidBoardState {
+    /// The number of stacks doesn't match the variant.
+    WrongNumberOfStacks,
+    /// The stack at the given index has more stones than it can hold.
+    StackTooHigh(usize),
+    /// The number of stones of each color does not match the side to move.
+    InvalidStoneCount,
+    /// The last move is not a move that could have lead to this position.
+    InvalidLastMove,
+    /// The banned move is not a valid move in this position.
+    InvalidBannedMove,
+}
+
+/// Ways in which the internal state of a [`Position`] can be inconsistent,
+/// see [`Position::validate`]. These can only happen through a bug, or by
+/// playing moves with the unchecked methods which aren't legal.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum InvariantViolation {
+    /// A stone of the player to move is on a spot which isn't played.
+    StoneNotPlayed,
+    /// The two copies of the board in the bitboards are different.
+    CopiesDiffer,
+    /// The stack at the given index has more stones than it can hold.
+    StackTooHigh(usize),
+    /// The stack at the given index has an empty spot below a stone.
+    FloatingStone(usize),
+    /// The number of stones of the given player doesn't match the number of turns.
+    WrongStoneCount(Color),
+    /// The ply is smaller than the number of turns, or they differ by an odd
+    /// number: every "Second Best!" call adds two plies.
+    PlyMismatch,
+    /// A move is stored for a turn which wasn't played yet.
+    FutureMove(usize),
+    /// The last move doesn't match the board: the stone it moved isn't
+    /// the opponent's stone on top of the stack it moved to.
+    InvalidLastMove,
+    /// The banned move can't be played in the position.
+    InvalidBannedMove,
+    /// The stored alignment of the opponent doesn't match the board.
+    StaleAlignment,
+}
+
+impl From<InvalidBoardState> for InvalidFen {
+    fn from(e: InvalidBoardState) -> Self {
+        match e {
+            InvalidBoardState::WrongNumberOfStacks => Self::WrongNumberOfStacks,
+            InvalidBoardState::StackTooHigh(stack_i) => Self::InvalidStack(stack_i),
+            InvalidBoardState::InvalidStoneCount => Self::InvalidStoneCount,
+            InvalidBoardState::InvalidLastMove => Self::InvalidLastMove,
+            InvalidBoardState::InvalidBannedMove => Self::InvalidBannedMove,
+        }
+    }
+}
+
 impl Position {
     /// Get the color of the stone at the given location,
     /// if there is no stone, None is returned.
     pub fn stone_at(&self, stack_i: usize, height: usize) -> Option<Color> {
-        let spot_bb = Self::bb_of_spot(stack_i, height);
+        let spot_bb = self.variant.bb_of_spot(stack_i, height);
         if 0 == (self.played_spots & spot_bb) {
             // This spot hasn't been played yet.
             return None;
@@ -318,16 +730,26 @@ impl Position {
         }
     }
 
-    /// The bitboard with a one set of the given spot.
-    #[inline(always)]
-    pub fn bb_of_spot(stack_i: usize, height: usize) -> Bitboard {
-        Self::column_mask(stack_i) & Self::BOTTOM << height
+    /// The number of stones on the given stack.
+    pub fn stack_height(&self, stack_i: usize) -> usize {
+        // Both copies of the board are stored in the bitboards.
+        (self.played_spots & self.variant.column_mask(stack_i)).count_ones() as usize / 2
+    }
+
+    /// The number of stones the given player still has to place on the board.
+    pub fn stones_in_hand(&self, color: Color) -> usize {
+        let spots = match color == self.current_player() {
+            true => self.our_spots,
+            false => self.played_spots ^ self.our_spots,
+        };
+        self.variant.stones_per_player() - spots.count_ones() as usize / 2
     }
 
-    pub fn print_bb(bb: Bitboard) {
-        for row in (0..(Self::STACK_HEIGHT + 1)).rev() {
-            for col in 0..(Self::NUM_STACKS * 2) {
-                let mask = 1 << (col * (Self::STACK_HEIGHT + 1) + row);
+    pub fn print_bb(&self, bb: Bitboard) {
+        let height = self.variant.height();
+        for row in (0..(height + 1)).rev() {
+            for col in 0..(self.variant.stacks() * 2) {
+                let mask = 1 << (col * (height + 1) + row);
                 if 0 == (bb & mask) {
                     print!(".");
                 } else {
@@ -342,7 +764,7 @@ impl Position {
     pub fn stone_move(&self, from: Option<usize>, to: usize) -> Bitboard {
         let bb = self.phase_one_stone_move(to);
         if let Some(from) = from {
-            bb | (Position::column_mask(from) & (self.top_spots()))
+            bb | (self.variant.column_mask(from) & (self.top_spots()))
         } else {
             bb
         }
@@ -350,7 +772,7 @@ impl Position {
 
     #[inline(always)]
     fn phase_one_stone_move(&self, to: usize) -> Bitboard {
-        Position::column_mask(to) & self.free_spots()
+        self.variant.column_mask(to) & self.free_spots()
     }
 
     #[inline(always)]
@@ -368,29 +790,23 @@ impl Position {
         // By adding 0001 to this, we will make all the
         // bits flow over, and we end up with 0100,
         // as we wanted.
-        Self::BOTTOM + self.played_spots
+        self.variant.bottom + self.played_spots
     }
 
     /// The top spots of every stack.
     #[inline(always)]
     fn top_spots(&self) -> Bitboard {
-        // Shifting left drags the columns down.
-        // And-ing with self.played_spots ensures we don't overflow
-        // into the next column.
-        // The xor gives us the bits which did not have a bit above it.
-        self.played_spots ^ ((self.played_spots >> 1) & self.played_spots)
-    }
-
-    /// A mask with all the spots marked in the given column.
-    #[inline(always)]
-    pub fn column_mask(col: usize) -> Bitboard {
-        Self::COLUMN_MASKS[col]
+        Self::top_spots_of(self.played_spots)
     }
 
-    /// A mask with a one at the bottom of the given column.
+    /// The top spots of every stack, with the given spots played.
     #[inline(always)]
-    fn column_bottom_mask(col: usize) -> Bitboard {
-        1 << ((Self::STACK_HEIGHT + 1) * col)
+    fn top_spots_of(played_spots: Bitboard) -> Bitboard {
+        // Shifting left drags the columns down.
+        // And-ing with played_spots ensures we don't overflow
+        // into the next column.
+        // The xor gives us the bits which did not have a bit above it.
+        played_spots ^ ((played_spots >> 1) & played_spots)
     }
 
     /// Bitboard with a 1 on the bottom of each stack
@@ -403,11 +819,18 @@ impl Position {
             self.played_spots ^ self.our_spots
         };
         // Only look at stones on top of their stack.
-        let player_stones = player_stones & self.top_spots();
+        Self::stacks_of(self.variant, player_stones & self.top_spots())
+    }
+
+    /// Bitboard with a 1 on the bottom of each stack with one of the given spots.
+    #[inline(always)]
+    fn stacks_of(variant: Variant, spots: Bitboard) -> Bitboard {
         // Shift everything to the bottom row.
-        (player_stones & Self::BOTTOM)
-            | ((player_stones >> 1) & Self::BOTTOM)
-            | ((player_stones >> 2) & Self::BOTTOM)
+        let mut stacks = 0;
+        for height in 0..variant.height {
+            stacks |= (spots >> height) & variant.bottom;
+        }
+        stacks
     }
 
     /// Bitboard with columns set to 1 if the given player
@@ -415,7 +838,7 @@ impl Position {
     #[inline(always)]
     pub fn controlled_columns(&self, us: bool) -> Bitboard {
         let controlled_stacks = self.controlled_stacks(us);
-        (Self::BOTTOM << Self::STACK_HEIGHT) - controlled_stacks
+        (self.variant.bottom << self.variant.height) - controlled_stacks
     }
 
     /// Bitboard with a 1 set on the top of every stack controlled
@@ -428,7 +851,8 @@ impl Position {
     /// Bitboard with a 1 set on the bottom of the stacks which are still free.
     #[inline(always)]
     pub fn free_columns(&self) -> Bitboard {
-        Self::BOTTOM & !(self.played_spots >> 2)
+        // A stack is full when the spot just below its top bit is played.
+        self.variant.bottom & !(self.played_spots >> (self.variant.height - 1))
     }
 
     /// Bitboard with a 1 set on every free spot that would give us an alignment.
@@ -438,6 +862,167 @@ impl Position {
         (self.our_spots << 1) & (self.our_spots << 2) & self.free_spots()
     }
 
+    /// Bitboard with a 1 set on every free spot that would give the given
+    /// player a vertical alignment.
+    #[inline(always)]
+    pub fn vertical_threats(&self, us: bool) -> Bitboard {
+        let player_stones = if us {
+            self.our_spots
+        } else {
+            self.played_spots ^ self.our_spots
+        };
+        (player_stones << 1) & (player_stones << 2) & self.free_spots()
+    }
+
+    /// The number of groups of three adjacent stacks controlled by the given player.
+    /// One more controlled stack next to such a group gives an alignment.
+    #[inline(always)]
+    pub fn horizontal_threats(&self, us: bool) -> u32 {
+        let controlled = self.controlled_stacks(us);
+        let shift = self.variant.height + 1;
+        // Only count the groups starting in the first copy of the board.
+        (controlled
+            & (controlled >> shift)
+            & (controlled >> (2 * shift))
+            & self.variant.first_bottom())
+        .count_ones()
+    }
+
+    /// The number of stone moves the given player could make in the second phase,
+    /// not taking into account banned moves or alignments.
+    #[inline(always)]
+    pub fn mobility(&self, us: bool) -> u32 {
+        let [right, opposite, left] = self.stone_moves_to(us);
+        right.count_ones() + opposite.count_ones() + left.count_ones()
+    }
+
+    /// The stone moves the player to move could make in the second phase, not
+    /// taking into account banned moves or alignments. For every offset in
+    /// [`Variant::directions`], the bitboard has a 1 on the bottom of each
+    /// stack `to` in the first copy of the board, for which the move from the
+    /// stack `(to - offset) % stacks` is possible.
+    #[inline(always)]
+    pub fn second_phase_moves_bb(&self) -> [Bitboard; 3] {
+        let variant = self.variant;
+        // Fold the second copy of the board back onto the first one.
+        self.stone_moves_to(true)
+            .map(|to| (to | (to >> variant.copy_bits())) & variant.first_bottom())
+    }
+
+    /// The "to" stacks of the stone moves of the given player in the second
+    /// phase, for every offset in [`Variant::directions`]. Stacks reached by
+    /// wrapping around the board are in the second copy of the board.
+    #[inline(always)]
+    fn stone_moves_to(&self, us: bool) -> [Bitboard; 3] {
+        let variant = self.variant;
+        let from_columns = self.controlled_stacks(us) & variant.first_bottom();
+        let free_columns = self.free_columns();
+        // Because the board is stored twice, shifting the first copy
+        // wraps around to the second copy.
+        variant
+            .directions()
+            .map(|offset| (from_columns << ((variant.height + 1) * offset)) & free_columns)
+    }
+
+    /// The stones of the given player.
+    #[inline(always)]
+    fn player_spots(&self, us: bool) -> Bitboard {
+        match us {
+            true => self.our_spots,
+            false => self.played_spots ^ self.our_spots,
+        }
+    }
+
+    /// The stone moves the given player could make if it were their turn:
+    /// placing a stone while they have stones in hand, and moving one of
+    /// their stones after that. Banned moves are not left out.
+    fn stone_moves_of(&self, us: bool) -> Vec<Bitboard> {
+        let color = match us {
+            true => self.current_player(),
+            false => self.current_player().other(),
+        };
+        let variant = self.variant;
+        let free = |to: usize| self.free_spots() & variant.column_mask(to) != 0;
+        if self.stones_in_hand(color) > 0 {
+            return (0..variant.stacks)
+                .filter(|&to| free(to))
+                .map(|to| self.stone_move(None, to))
+                .collect();
+        }
+        let from_spots = self.top_spots() & self.player_spots(us);
+        let mut moves = vec![];
+        for from in 0..variant.stacks {
+            if from_spots & variant.column_mask(from) == 0 {
+                continue;
+            }
+            for offset in [variant.right(), variant.opposite(), variant.left()] {
+                let to = (from + offset) % variant.stacks;
+                if free(to) {
+                    moves.push(self.stone_move(Some(from), to));
+                }
+            }
+        }
+        moves
+    }
+
+    /// The stone moves with which the given player gets an alignment,
+    /// as if it were their turn. For the player to move, the move banned
+    /// by "Second Best!" is left out. The other player can still call
+    /// "Second Best!" on these moves, unless the rules forbid it.
+    pub fn immediate_winning_moves(&self, us: bool) -> Vec<PlayerMove> {
+        let player_stones = self.player_spots(us);
+        self.stone_moves_of(us)
+            .into_iter()
+            .filter(|&smove| !(us && self.is_move_banned(smove)))
+            .filter(|&smove| {
+                Self::is_alignment(
+                    self.variant,
+                    player_stones ^ smove,
+                    self.played_spots ^ smove,
+                )
+            })
+            .map(|smove| BitboardMove::StoneMove(smove).to_player_move(self))
+            .collect()
+    }
+
+    /// The stacks on which the opponent gets an alignment by playing a stone
+    /// there on their next move, sorted from low to high.
+    pub fn opponent_threats(&self) -> Vec<usize> {
+        let mut stacks: Vec<usize> = self
+            .immediate_winning_moves(false)
+            .into_iter()
+            .filter_map(|pmove| match pmove {
+                PlayerMove::StoneMove { to, .. } => Some(to),
+                PlayerMove::SecondBest => None,
+            })
+            .collect();
+        stacks.sort_unstable();
+        stacks.dedup();
+        stacks
+    }
+
+    /// If the opponent threatens to get an alignment on their next move, the
+    /// legal moves after which they can't. Every other move allows an alignment,
+    /// which can only be undone by calling "Second Best!" on it.
+    /// Returns `None` if there is no threat, or if the game is over.
+    pub fn forced_moves(&self) -> Option<Vec<PlayerMove>> {
+        if self.game_over() || self.opponent_threats().is_empty() {
+            return None;
+        }
+        let mut pos = self.clone();
+        let moves = MoveGen::new(self, None)
+            .filter(|&bmove| {
+                pos.make_move(bmove);
+                // The opponent is to move after every move, also after "Second Best!".
+                let safe = pos.immediate_winning_moves(true).is_empty();
+                pos.unmake_move();
+                safe
+            })
+            .map(|bmove| bmove.to_player_move(self))
+            .collect();
+        Some(moves)
+    }
+
     /// The current player to move.
     pub fn current_player(&self) -> Color {
         match self.num_turns % 2 {
@@ -461,6 +1046,42 @@ impl Position {
         }
     }
 
+    /// A key which is the same for positions that are rotations or mirror
+    /// images of each other, including the last stone move, which could
+    /// still be banned. Only the board and the last move are used, so this
+    /// is meant for comparing positions after different moves from the same
+    /// position, where everything else is the same.
+    pub fn symmetric_key(&self) -> u64 {
+        let variant = self.variant;
+        let (bits, column_bits) = (variant.copy_bits(), variant.height + 1);
+        let first_copy = |bb: Bitboard| (bb | bb >> bits) & variant.first_copy();
+        let board = first_copy(self.our_spots | self.free_spots());
+        let last_move = self.last_stone_move().map_or(0, first_copy);
+        // Reverse the order of the columns.
+        let mirror = |bb: Bitboard| {
+            let mut mirrored = 0;
+            for col in 0..variant.stacks {
+                let column = (bb >> (column_bits * col)) & ((1 << column_bits) - 1);
+                mirrored |= column << (column_bits * (variant.stacks - 1 - col));
+            }
+            mirrored
+        };
+        // Turn the board by the given number of bits.
+        let rotate = |bb: Bitboard, shift: usize| {
+            ((bb << shift) | (bb >> (bits - shift))) & variant.first_copy()
+        };
+        [(board, last_move), (mirror(board), mirror(last_move))]
+            .into_iter()
+            .flat_map(|(board, last_move)| {
+                (0..variant.stacks).map(move |col| {
+                    let shift = column_bits * col;
+                    rotate(last_move, shift) << 32 | rotate(board, shift)
+                })
+            })
+            .min()
+            .unwrap()
+    }
+
     /// The number of turns that have been played in the current position.
     #[inline(always)]
     pub fn num_turns(&self) -> usize {
@@ -475,15 +1096,7 @@ impl Position {
     /// Are we in the second phase of the game, where stones are no longer placed, but moved.
     #[inline(always)]
     pub fn is_second_phase(&self) -> bool {
-        self.num_turns >= 2 * Self::STONES_PER_PLAYER
-    }
-
-    /// Check if the "to" spot is adjacent or opposite to the "from" spot.
-    #[inline(always)]
-    fn valid_adjacent(from: usize, to: usize) -> bool {
-        (from + Self::RIGHT) % Self::NUM_STACKS == to
-            || (from + Self::OPPOSITE) % Self::NUM_STACKS == to
-            || (from + Self::LEFT) % Self::NUM_STACKS == to
+        self.num_turns >= 2 * self.variant.stones_per_player()
     }
 
     /// Is the given move banned due to a "Second Best!" call?
@@ -494,13 +1107,32 @@ impl Position {
 
     /// Make the move if it is valid, otherwise return why it wasn't valid.
     pub fn try_make_move(&mut self, pmove: PlayerMove) -> Result<(), MoveFailed> {
+        let bmove = self.check_move(pmove)?;
+        self.make_move(bmove);
+        Ok(())
+    }
+
+    /// Check if the move can be played, without playing it.
+    /// If not, the reason why it isn't valid is returned.
+    pub fn is_legal(&self, pmove: PlayerMove) -> Result<(), MoveFailed> {
+        self.check_move(pmove).map(|_| ())
+    }
+
+    /// All the moves which can be played in the current position.
+    pub fn legal_moves(&self) -> Vec<PlayerMove> {
+        MoveGen::new(self, None)
+            .map(|bmove| bmove.to_player_move(self))
+            .collect()
+    }
+
+    /// Check if the move is valid, and convert it to a bitboard move.
+    fn check_move(&self, pmove: PlayerMove) -> Result<BitboardMove, MoveFailed> {
         let (from, to) = match pmove {
             PlayerMove::SecondBest => {
                 if !self.can_second_best() {
                     return Err(MoveFailed::InvalidSecondBest);
                 } else {
-                    self.second_best();
-                    return Ok(());
+                    return Ok(BitboardMove::SecondBest);
                 }
             }
             PlayerMove::StoneMove { from, to } => (from, to),
@@ -515,10 +1147,10 @@ impl Position {
                 return Err(MoveFailed::MissingFromSpot);
             };
             // Check for out of bounds indices.
-            if from >= Self::NUM_STACKS {
+            if from >= self.variant.stacks {
                 return Err(MoveFailed::InvalidFromSpot);
             }
-            if to >= Self::NUM_STACKS {
+            if to >= self.variant.stacks {
                 return Err(MoveFailed::InvalidToSpot);
             }
 
@@ -530,14 +1162,16 @@ impl Position {
             // 1. There should be a stone at that spot
             // 2. The stone at that spot should be of the current
             //    player's color.
-            if 0 == (Self::column_mask(from) & self.top_spots() & self.our_spots) {
+            if 0 == (self.variant.column_mask(from) & self.top_spots() & self.our_spots) {
                 return Err(MoveFailed::InvalidFromSpot);
             }
 
             // We are moving a stone to the "to" spot, so:
             // 1. The "to" spot should be "adjacent" to the "from" spot
             // 2. The stack at the "to" spot should not be full.
-            if !Self::valid_adjacent(from, to) || 0 == (self.free_spots() & Self::column_mask(to)) {
+            if !self.variant.valid_adjacent(from, to)
+                || 0 == (self.free_spots() & self.variant.column_mask(to))
+            {
                 return Err(MoveFailed::InvalidToSpot);
             }
 
@@ -547,8 +1181,7 @@ impl Position {
                 return Err(MoveFailed::MoveBanned);
             }
 
-            self.make_stone_move(smove);
-            return Ok(());
+            return Ok(BitboardMove::StoneMove(smove));
         }
 
         // There should be no "from" spot.
@@ -557,21 +1190,20 @@ impl Position {
         }
 
         // Check for out of bounds indices.
-        if to >= Self::NUM_STACKS {
+        if to >= self.variant.stacks {
             return Err(MoveFailed::InvalidToSpot);
         }
 
         // We are moving a stone to the "to" spot, so:
         // the stack at the "to" spot should not be full.
-        if 0 == (self.free_spots() & Self::column_mask(to)) {
+        if 0 == (self.free_spots() & self.variant.column_mask(to)) {
             return Err(MoveFailed::InvalidToSpot);
         }
         let smove = self.stone_move(None, to);
         if self.is_move_banned(smove) {
             return Err(MoveFailed::MoveBanned);
         }
-        self.make_stone_move(smove);
-        Ok(())
+        Ok(BitboardMove::StoneMove(smove))
     }
 
     /// Make a move in the given position.
@@ -605,6 +1237,8 @@ impl Position {
         self.num_turns += 1;
         self.ply += 1;
         self.move_history[self.num_turns] = Some(smove);
+        self.alignments[self.num_turns] = self.compute_alignment(false);
+        debug_assert_eq!(self.validate(), Ok(()));
     }
 
     /// Unmake the last move played.
@@ -623,26 +1257,74 @@ impl Position {
         // xor-ing a second time undoes the first xor.
         self.played_spots ^= last_move;
         self.our_spots ^= self.played_spots;
+        debug_assert_eq!(self.validate(), Ok(()));
         last_move
     }
 
+    /// Pass the turn without moving a stone. This is not a move of the game,
+    /// but is used by the search for null move pruning: if passing is
+    /// already good enough, a real move probably is as well.
+    ///
+    /// Only possible in the second phase, when no move is banned. "Second
+    /// Best!" can't be called on the pass, and it has to be undone with
+    /// [`Position::unmake_null_move`].
+    pub fn make_null_move(&mut self) {
+        debug_assert!(self.is_second_phase() && self.banned_move().is_none());
+        self.our_spots ^= self.played_spots;
+        self.num_turns += 1;
+        self.ply += 1;
+        // Like a position from a fen without a last move, see `from_board_state`.
+        self.banned_moves[self.num_turns] = Some(0);
+        self.alignments[self.num_turns] = self.compute_alignment(false);
+        debug_assert_eq!(self.validate(), Ok(()));
+    }
+
+    /// Undo [`Position::make_null_move`].
+    pub fn unmake_null_move(&mut self) {
+        debug_assert_eq!(self.banned_moves[self.num_turns], Some(0));
+        self.banned_moves[self.num_turns] = None;
+        self.num_turns -= 1;
+        self.ply -= 1;
+        self.our_spots ^= self.played_spots;
+        debug_assert_eq!(self.validate(), Ok(()));
+    }
+
     /// Check if "Second Best!" can be called this move.
     /// 1. There should be at least one move played.
     /// 2. "Second Best!" should not have been called yet this turn.
     /// 3. "Second Best!" should not have been called previous turn.
+    /// 4. The house rules should allow it, see [`Rules`].
     #[inline(always)]
     pub fn can_second_best(&self) -> bool {
         self.num_turns > 0
             && self.banned_moves[self.num_turns].is_none()
             && self.banned_moves[self.num_turns + 1].is_none()
+            && (self.rules.second_best_on_alignment || !self.has_alignment(false))
+            && self.second_best_calls_left(self.current_player()) != Some(0)
+    }
+
+    /// Whether "Second Best!" can be called on the next move of a player:
+    /// by the opponent on our next move if `on_us`, or by us on the move
+    /// of the opponent after it otherwise. Our next move can't be banned if
+    /// it is already played again after a call. Whether the move gives an
+    /// alignment isn't known yet, so that house rule isn't checked.
+    #[inline(always)]
+    pub fn can_second_best_next(&self, on_us: bool) -> bool {
+        let caller = match on_us {
+            true => self.current_player().other(),
+            false => self.current_player(),
+        };
+        (!on_us || self.banned_move().is_none()) && self.second_best_calls_left(caller) != Some(0)
     }
 
     /// Opponent called "Second Best!"
     /// This should only be called if `can_second_best()` is true.
     pub fn second_best(&mut self) {
+        self.second_best_calls[self.current_player() as usize] += 1;
         let last_move = self.unmake_stone_move();
         self.ply += 2;
         self.banned_moves[self.num_turns + 1] = Some(last_move);
+        debug_assert_eq!(self.validate(), Ok(()));
     }
 
     /// Undo a "Second Best!" call.
@@ -652,6 +1334,27 @@ impl Position {
         self.banned_moves[self.num_turns + 1] = None;
         self.make_stone_move(banned_move);
         self.ply -= 2;
+        self.second_best_calls[self.current_player() as usize] -= 1;
+        debug_assert_eq!(self.validate(), Ok(()));
+    }
+
+    /// The house rules the game is played with.
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    /// Change the house rules of the game.
+    /// "Second Best!" calls made before the position was
+    /// set up from a fen or board state are not counted.
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.rules = rules;
+    }
+
+    /// How many more times the given player can call "Second Best!"
+    /// in this game. `None` if there is no limit.
+    pub fn second_best_calls_left(&self, color: Color) -> Option<usize> {
+        let limit = self.rules.second_best_limit?;
+        Some(limit.saturating_sub(self.second_best_calls[color as usize]))
     }
 
     /// Parses the moves and plays them one by one.
@@ -664,49 +1367,88 @@ impl Position {
     /// 2. A "!" representing a second best call
     /// 3. Two numbers representing a from and to spot for a move in the second part of the game,
     ///    separated by a "-", e.g. 0-2
+    /// 4. One of the above followed by a "!", if "Second Best!" was called on it, e.g. 0-2!
     pub fn parse_and_play_moves(&mut self, moves: Vec<String>) -> Result<(), MoveFailed> {
         for smove in moves {
-            self.try_make_move(PlayerMove::from(smove)?)?;
+            let (pmove, second_best) = PlayerMove::from_long_form(&smove)?;
+            self.try_make_move(pmove)?;
+            if second_best {
+                self.try_make_move(PlayerMove::SecondBest)?;
+            }
         }
         Ok(())
     }
 
-    #[inline(always)]
-    fn stone_move_to_string(&self, smove: Bitboard) -> String {
-        BitboardMove::StoneMove(smove)
-            .to_player_move(self)
-            .to_string()
-    }
-
     /// Serialize the position into a string of moves.
     /// An "inverse" to `parse_and_play_moves`.
-    pub fn serialize(mut self) -> String {
-        let mut moves = std::vec::Vec::<String>::new();
-        for move_i in (0..self.num_turns).rev() {
-            let smove = self.move_history[move_i + 1].unwrap();
-            self.unmake_stone_move();
-            let s = self.stone_move_to_string(smove);
-            moves.push(s);
-            if let Some(banned_move) = self.banned_moves[move_i + 1] {
-                let s = format!("{} !", self.stone_move_to_string(banned_move));
-                moves.push(s);
-            }
-        }
-        moves.reverse();
+    pub fn serialize(self) -> String {
+        let moves: Vec<String> = self.history().iter().map(|m| m.to_string()).collect();
         moves.join(" ")
     }
 
+    /// Undo the last move that was played: a stone move, or a "Second Best!" call.
+    /// After "Second Best!" the same player moves again, so undoing that
+    /// move leaves the "Second Best!" call in place.
+    ///
+    /// Returns the move which was undone, or `None` if there is nothing to undo.
+    /// This is the case at the start of the game. Positions created with
+    /// [`Position::from_fen`] only know their last move, so at most one move
+    /// can be undone there.
+    pub fn undo_last_player_move(&mut self) -> Option<PlayerMove> {
+        if self.banned_move().is_some() {
+            self.undo_second_best();
+            return Some(PlayerMove::SecondBest);
+        }
+        let smove = self.move_history[self.num_turns]?;
+        self.unmake_stone_move();
+        Some(BitboardMove::StoneMove(smove).to_player_move(self))
+    }
+
+    /// The moves that were played to reach this position, including
+    /// "Second Best!" calls. See [`Position::undo_last_player_move`].
+    pub fn history(&self) -> Vec<PlayerMove> {
+        let mut pos = self.clone();
+        let mut moves = vec![];
+        while let Some(pmove) = pos.undo_last_player_move() {
+            moves.push(pmove);
+        }
+        moves.reverse();
+        moves
+    }
+
+    /// The move that was banned by a "Second Best!" call at the given ply,
+    /// where the ply is an index in [`Position::history`].
+    /// `None` if "Second Best!" wasn't called at that ply.
+    pub fn banned_move_at(&self, ply: usize) -> Option<PlayerMove> {
+        let history = self.history();
+        match history.get(ply)? {
+            PlayerMove::SecondBest => history.get(ply.checked_sub(1)?).copied(),
+            PlayerMove::StoneMove { .. } => None,
+        }
+    }
+
     /// Check if the given player has an alignment on the board:
     /// Either:
     /// 1. There is a stack with three stones of the player's color.
     /// 2. There are 4 stones of the players colors next to each other
     ///    on the top of the stacks.
     pub fn has_alignment(&self, us: bool) -> bool {
-        let player_stones = if us {
-            self.our_spots
+        if us {
+            self.compute_alignment(true)
         } else {
-            self.our_spots ^ self.played_spots
-        };
+            self.alignments[self.num_turns]
+        }
+    }
+
+    /// Check for an alignment without using the cached value.
+    fn compute_alignment(&self, us: bool) -> bool {
+        Self::is_alignment(self.variant, self.player_spots(us), self.played_spots)
+    }
+
+    /// Check if the stones of a player give an alignment,
+    /// on a board with the given spots played.
+    #[inline(always)]
+    fn is_alignment(variant: Variant, player_stones: Bitboard, played_spots: Bitboard) -> bool {
         // Check for alignment in the columns:
         if (player_stones & (player_stones << 1) & (player_stones << 2)) != 0 {
             return true;
@@ -714,219 +1456,515 @@ impl Position {
 
         // Check for alignment on top of the stacks.
         // Step 1. "flatten" the top of the stacks to the bottom row.
-        let top_of_stacks = self.controlled_stacks(us);
-        // Step 2. Check for horizontal alignment.
-        let mut bottom_four_mask = Self::BOTTOM_FOUR;
-        for _ in 0..(Self::NUM_STACKS + 4) {
-            if bottom_four_mask == (bottom_four_mask & top_of_stacks) {
-                // All four stones are ours.
-                return true;
+        let top_of_stacks =
+            Self::stacks_of(variant, player_stones & Self::top_spots_of(played_spots));
+        // Step 2. Check for horizontal alignment, of four stacks next to each
+        // other. Because of the two copies of the board, this also finds the
+        // alignments which wrap around.
+        let shift = variant.height + 1;
+        (top_of_stacks
+            & (top_of_stacks >> shift)
+            & (top_of_stacks >> (2 * shift))
+            & (top_of_stacks >> (3 * shift)))
+            != 0
+    }
+
+    /// Check that the internal state of the position is consistent: the
+    /// stones on the board, the number of turns and plies, the last move and
+    /// the banned move. This is done after every move in debug builds, and
+    /// can be used to check a position after driving it with the unchecked
+    /// methods like [`Position::make_move`].
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        if self.our_spots & !self.played_spots != 0 {
+            return Err(InvariantViolation::StoneNotPlayed);
+        }
+        let variant = self.variant;
+        for bb in [self.played_spots, self.our_spots] {
+            if bb & variant.first_copy() != bb >> variant.copy_bits() {
+                return Err(InvariantViolation::CopiesDiffer);
             }
-            bottom_four_mask <<= Self::STACK_HEIGHT + 1;
         }
-        false
+        for stack_i in 0..variant.stacks {
+            let column = (self.played_spots >> ((variant.height + 1) * stack_i))
+                & ((1 << (variant.height + 1)) - 1);
+            if column >> variant.height != 0 {
+                return Err(InvariantViolation::StackTooHigh(stack_i));
+            }
+            // The stones of a stack are the lowest bits of its column.
+            if column & (column + 1) != 0 {
+                return Err(InvariantViolation::FloatingStone(stack_i));
+            }
+        }
+
+        // Black places a stone on the even turns of the first phase.
+        let stones_placed = self.num_turns.min(2 * variant.stones_per_player());
+        for color in [Color::Black, Color::White] {
+            let expected = match color {
+                Color::Black => stones_placed.div_ceil(2),
+                Color::White => stones_placed / 2,
+            };
+            let spots = self.player_spots(color == self.current_player());
+            // Both copies of the board are stored in the bitboards.
+            if spots.count_ones() as usize / 2 != expected {
+                return Err(InvariantViolation::WrongStoneCount(color));
+            }
+        }
+        if self.ply < self.num_turns || !(self.ply - self.num_turns).is_multiple_of(2) {
+            return Err(InvariantViolation::PlyMismatch);
+        }
+        // Undoing a move clears what was stored for its turn, so only the
+        // turn after the current one is checked.
+        let next_turn = self.num_turns + 1;
+        if next_turn + 1 < Self::MAX_MOVES
+            && (self.move_history[next_turn].is_some()
+                || self.banned_moves[next_turn + 1].is_some())
+        {
+            return Err(InvariantViolation::FutureMove(next_turn));
+        }
+
+        let opponent_spots = self.played_spots ^ self.our_spots;
+        if let Some(last_move) = self.move_history[self.num_turns] {
+            // The stone is on top of the stack it was moved to, and the
+            // stack it was moved from (if any) has an empty spot on top.
+            let to_spot = last_move & self.top_spots() & opponent_spots;
+            let from_spot = last_move & self.free_spots();
+            if to_spot.count_ones() != 2
+                || to_spot | from_spot != last_move
+                || from_spot.count_ones() > 2
+            {
+                return Err(InvariantViolation::InvalidLastMove);
+            }
+        }
+        if let Some(banned_move) = self.banned_move() {
+            let to_spot = banned_move & self.free_spots();
+            let from_spot = banned_move & self.top_spots() & self.our_spots;
+            if to_spot.count_ones() != 2
+                || to_spot | from_spot != banned_move
+                || from_spot.count_ones() > 2
+            {
+                return Err(InvariantViolation::InvalidBannedMove);
+            }
+        }
+        if self.alignments[self.num_turns] != self.compute_alignment(false) {
+            return Err(InvariantViolation::StaleAlignment);
+        }
+        Ok(())
     }
 
     /// Returns true if the current player is lost.
     pub fn game_over(&self) -> bool {
+        self.game_status() != GameStatus::OnGoing
+    }
+
+    /// Whether the game is over, who won, and why.
+    /// The game can only be over on the turn of the losing player.
+    pub fn game_status(&self) -> GameStatus {
         // TODO: Actually possible for us to be winning on our turn,
-        // so this should return an enum instead.
         // if self.has_alignment(true) {
         // return Win;
         // }
+        let lost = |reason| GameStatus::Won {
+            winner: self.current_player().other(),
+            reason,
+        };
         if self.can_second_best() {
-            return false;
+            return GameStatus::OnGoing;
         }
         if self.has_alignment(false) {
             // Opponent has an alignment, and we can't "Second Best!".
-            return true;
+            return lost(GameOverReason::Alignment);
         }
         // From now on we just check if we are lost (this turn), this can
         // only happen if we have no legal moves.
         if !self.is_second_phase() {
-            return false;
+            return GameStatus::OnGoing;
         }
-        // Free columns are those where the top spot is not played.
-        let free_columns = self.free_columns();
-        let our_columns = self.controlled_stacks(true);
-        if our_columns == 0 {
-            // No controlled columns means no moves.
-            return true;
-        }
-
-        let left = Self::column_bottom_mask(Self::LEFT);
-        let right = Self::column_bottom_mask(Self::RIGHT);
-        let opposite = Self::column_mask(Self::OPPOSITE);
-        let mut possible_to = left | right | opposite;
-        for from in 0..Self::NUM_STACKS {
-            let from_mask = Self::column_bottom_mask(from);
-            if (from_mask & our_columns) == 0 {
-                // This from spot is not controlled by us.
-                continue;
-            }
-            if (possible_to & free_columns) != 0 {
-                // Found a possible move.
-                return false;
-            }
-            // Move to the next column.
-            possible_to <<= Self::STACK_HEIGHT + 1;
+        // The move "Second Best!" was called on can't be played again.
+        let banned = self.banned_move().is_some() as u32;
+        if self.mobility(true) > banned {
+            // Found a possible move.
+            return GameStatus::OnGoing;
         }
 
         // No legal move, so the game is over.
-        true
+        lost(GameOverReason::NoLegalMoves)
     }
 
     /// Display the current state of the board.
     pub fn show(&self) {
-        //     .
-        // .   .   .
-        //  .  .  .
-        //   .   .
-        //...     ...
-        //   .   .
-        //  .  .  .
-        // .   .   .
-        //     .
-        let indices = [
-            [
-                (8, 8),
-                (8, 8),
-                (8, 8),
-                (8, 8),
-                (4, 2),
-                (8, 8),
-                (8, 8),
-                (8, 8),
-                (8, 8),
-            ],
-            [
-                (8, 8),
-                (5, 2),
-                (8, 8),
-                (8, 8),
-                (4, 1),
-                (8, 8),
-                (8, 8),
-                (3, 2),
-                (8, 8),
-            ],
-            [
-                (8, 8),
-                (8, 8),
-                (5, 1),
-                (8, 8),
-                (4, 0),
-                (8, 8),
-                (3, 1),
-                (8, 8),
-                (8, 8),
-            ],
-            [
-                (8, 8),
-                (8, 8),
-                (8, 8),
-                (5, 0),
-                (8, 8),
-                (3, 0),
-                (8, 8),
-                (8, 8),
-                (8, 8),
-            ],
-            [
-                (6, 2),
-                (6, 1),
-                (6, 0),
-                (8, 8),
-                (8, 8),
-                (8, 8),
-                (2, 0),
-                (2, 1),
-                (2, 2),
-            ],
-            [
-                (8, 8),
-                (8, 8),
-                (8, 8),
-                (7, 0),
-                (8, 8),
-                (1, 0),
-                (8, 8),
-                (8, 8),
-                (8, 8),
-            ],
-            [
-                (8, 8),
-                (8, 8),
-                (7, 1),
-                (8, 8),
-                (0, 0),
-                (8, 8),
-                (1, 1),
-                (8, 8),
-                (8, 8),
-            ],
-            [
-                (8, 8),
-                (7, 2),
-                (8, 8),
-                (8, 8),
-                (0, 1),
-                (8, 8),
-                (8, 8),
-                (1, 2),
-                (8, 8),
-            ],
-            [
-                (8, 8),
-                (8, 8),
-                (8, 8),
-                (8, 8),
-                (0, 2),
-                (8, 8),
-                (8, 8),
-                (8, 8),
-                (8, 8),
-            ],
-        ];
+        println!("{self}");
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stone = |stack_i: usize, height: usize| match self.stone_at(stack_i, height) {
+            Some(color) => color.to_string(),
+            None => ".".to_string(),
+        };
+        let variant = self.variant;
         let mut s = "".to_string();
-        for row in indices {
-            for coordinate in row {
-                if coordinate == (8, 8) {
+        if variant.stacks == 8 {
+            // The stacks go around the center, starting at the bottom:
+            //     .
+            // .   .   .
+            //  .  .  .
+            //   .   .
+            //...     ...
+            //   .   .
+            //  .  .  .
+            // .   .   .
+            //     .
+            // The direction of every stack from the center, as (column, row).
+            const DIRECTIONS: [(isize, isize); 8] = [
+                (0, 1),
+                (1, 1),
+                (1, 0),
+                (1, -1),
+                (0, -1),
+                (-1, -1),
+                (-1, 0),
+                (-1, 1),
+            ];
+            let spot_at = |col: isize, row: isize| {
+                DIRECTIONS
+                    .iter()
+                    .enumerate()
+                    .find_map(|(stack_i, &(dx, dy))| {
+                        // The diagonal stacks start closer to the center.
+                        let start = if dx != 0 && dy != 0 { 1 } else { 2 };
+                        (0..variant.height)
+                            .find(|&height| {
+                                let distance = start + height as isize;
+                                (dx * distance, dy * distance) == (col, row)
+                            })
+                            .map(|height| (stack_i, height))
+                    })
+            };
+            let radius = variant.height as isize + 1;
+            for row in -radius..=radius {
+                for col in -radius..=radius {
+                    match spot_at(col, row) {
+                        Some((stack_i, height)) => s += &stone(stack_i, height),
+                        None => s += " ",
+                    }
                     s += " ";
-                } else {
-                    let mask = (1 << coordinate.1) << (coordinate.0 * (Self::STACK_HEIGHT + 1));
-                    let c = if 0 != (mask & self.our_spots) {
-                        self.current_player().to_string()
-                    } else if 0 != (mask & self.played_spots) {
-                        self.current_player().other().to_string()
-                    } else {
-                        ".".to_string()
-                    };
-                    s += &c;
                 }
-                s += " ";
+                s += "\n";
+            }
+        } else {
+            // Every stack on its own line, from bottom to top.
+            for stack_i in 0..variant.stacks {
+                s += &format!("{stack_i}:");
+                for height in 0..variant.height {
+                    s += " ";
+                    s += &stone(stack_i, height);
+                }
+                s += "\n";
             }
-            s += "\n";
         }
-        println!("{s}");
-        if self.game_over() {
-            println!("Game over, ({}) has won!", self.current_player().other());
-            return;
+        writeln!(f, "{s}")?;
+        let status = self.game_status();
+        if status != GameStatus::OnGoing {
+            return write!(f, "Game over, {status}");
         }
-        println!(
+        write!(
+            f,
             "It is {} turn to move",
             match self.current_player() {
                 Color::Black => "black's (X)",
                 Color::White => "white's (O)",
             }
-        );
+        )?;
         if let Some(banned_move) = self.banned_move() {
             let banned_move = BitboardMove::StoneMove(banned_move).to_player_move(self);
-            println!("Banned move: {}", banned_move);
+            write!(f, "\nBanned move: {}", banned_move)?;
         }
         if self.has_alignment(false) {
-            println!("{} has an alignment", self.current_player().other());
+            write!(f, "\n{} has an alignment", self.current_player().other())?;
+        }
+        Ok(())
+    }
+}
+
+/// A compact notation of a position, similar to FEN in chess.
+/// Unlike [`Position::serialize`] this does not store the moves that
+/// lead to the position, only what is needed to continue the game.
+///
+/// The notation consists of four fields separated by spaces:
+/// 1. The stacks, separated by a '/'. Each stack lists its stones from
+///    bottom to top as 'X' (black) or 'O' (white), or '.' if it is empty.
+/// 2. The side to move: 'X' or 'O'.
+/// 3. The last move played, if "Second Best!" can still be called on it,
+///    or '-' otherwise.
+/// 4. The move which is banned by a "Second Best!" call, or '-' if there is none.
+///
+/// Example:
+/// play 1 0 4 7 2 4 2 3:
+/// O/X/XX/O/XO/././O X 3 -
+///
+/// The notation doesn't store the size of the board, which is given
+/// separately, see [`Position::from_fen_with_variant`].
+impl Position {
+    /// Export the position in the compact notation.
+    pub fn to_fen(&self) -> String {
+        let state = self.board_state();
+        let stacks: Vec<String> = state
+            .stacks
+            .iter()
+            .map(|stack| {
+                if stack.is_empty() {
+                    ".".to_string()
+                } else {
+                    stack.iter().map(|color| color.to_string()).collect()
+                }
+            })
+            .collect();
+        let or_dash = |pmove: Option<PlayerMove>| match pmove {
+            Some(pmove) => pmove.to_string(),
+            None => "-".to_string(),
+        };
+        format!(
+            "{} {} {} {}",
+            stacks.join("/"),
+            state.side_to_move,
+            or_dash(state.last_move),
+            or_dash(state.banned_move)
+        )
+    }
+
+    /// Create a position from the compact notation. See [`Position::to_fen`].
+    ///
+    /// NOTE: the moves that lead to the position are unknown, so the
+    /// position can not be serialized with [`Position::serialize`], and
+    /// moves before the given position can't be undone.
+    pub fn from_fen(fen: &str) -> Result<Self, InvalidFen> {
+        Self::from_fen_with_variant(fen, Variant::STANDARD)
+    }
+
+    /// Create a position on a board of the given size from the compact
+    /// notation, see [`Position::from_fen`].
+    pub fn from_fen_with_variant(fen: &str, variant: Variant) -> Result<Self, InvalidFen> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let [stacks, side, last_move, banned_move] = fields[..] else {
+            return Err(InvalidFen::WrongNumberOfFields);
+        };
+        let stacks: Vec<&str> = stacks.split('/').collect();
+        if stacks.len() != variant.stacks {
+            return Err(InvalidFen::WrongNumberOfStacks);
+        }
+        let mut state = BoardState::with_variant(variant);
+        for (stack_i, stack) in stacks.iter().enumerate() {
+            if *stack == "." {
+                continue;
+            }
+            if stack.is_empty() {
+                return Err(InvalidFen::InvalidStack(stack_i));
+            }
+            for c in stack.chars() {
+                state.stacks[stack_i].push(match c {
+                    'X' => Color::Black,
+                    'O' => Color::White,
+                    _ => return Err(InvalidFen::InvalidStack(stack_i)),
+                });
+            }
+        }
+        state.side_to_move = match side {
+            "X" => Color::Black,
+            "O" => Color::White,
+            _ => return Err(InvalidFen::InvalidSide),
+        };
+        if last_move != "-" {
+            state.last_move = Some(
+                PlayerMove::from(last_move.to_string()).map_err(|_| InvalidFen::InvalidLastMove)?,
+            );
+        }
+        if banned_move != "-" {
+            state.banned_move = Some(
+                PlayerMove::from(banned_move.to_string())
+                    .map_err(|_| InvalidFen::InvalidBannedMove)?,
+            );
         }
+        Ok(Self::from_board_state(&state)?)
+    }
+
+    /// The contents of the board, see [`BoardState`].
+    pub fn board_state(&self) -> BoardState {
+        let stacks = (0..self.variant.stacks)
+            .map(|stack_i| {
+                (0..self.variant.height)
+                    .map_while(|height| self.stone_at(stack_i, height))
+                    .collect()
+            })
+            .collect();
+        let last_move = match self.can_second_best() {
+            true => {
+                Some(self.played_move_to_player_move(self.move_history[self.num_turns].unwrap()))
+            }
+            false => None,
+        };
+        let banned_move = self
+            .banned_move()
+            .map(|banned_move| BitboardMove::StoneMove(banned_move).to_player_move(self));
+        BoardState {
+            variant: self.variant,
+            stacks,
+            side_to_move: self.current_player(),
+            last_move,
+            banned_move,
+        }
+    }
+
+    /// Create a position from the contents of the board, checking that it
+    /// could have been reached in a game.
+    ///
+    /// NOTE: the moves that lead to the position are unknown, so the
+    /// position can not be serialized with [`Position::serialize`], and
+    /// moves before the given position can't be undone.
+    pub fn from_board_state(state: &BoardState) -> Result<Self, InvalidBoardState> {
+        let variant = state.variant;
+        if state.stacks.len() != variant.stacks {
+            return Err(InvalidBoardState::WrongNumberOfStacks);
+        }
+        let mut black_spots = 0;
+        let mut white_spots = 0;
+        for (stack_i, stack) in state.stacks.iter().enumerate() {
+            if stack.len() > variant.height {
+                return Err(InvalidBoardState::StackTooHigh(stack_i));
+            }
+            for (height, color) in stack.iter().enumerate() {
+                match color {
+                    Color::Black => black_spots |= variant.bb_of_spot(stack_i, height),
+                    Color::White => white_spots |= variant.bb_of_spot(stack_i, height),
+                }
+            }
+        }
+        let side = state.side_to_move;
+
+        // Both copies of the board are stored in the bitboards.
+        let num_black = black_spots.count_ones() as usize / 2;
+        let num_white = white_spots.count_ones() as usize / 2;
+        let num_stones = num_black + num_white;
+        let stones_per_player = variant.stones_per_player();
+        if num_black > stones_per_player || num_white > stones_per_player {
+            return Err(InvalidBoardState::InvalidStoneCount);
+        }
+        let num_turns = if num_stones < 2 * stones_per_player {
+            // In the first phase black always has as many stones as white,
+            // or one more if it is white's turn.
+            let expected_black = match side {
+                Color::Black => num_white,
+                Color::White => num_white + 1,
+            };
+            if num_black != expected_black {
+                return Err(InvalidBoardState::InvalidStoneCount);
+            }
+            num_stones
+        } else {
+            // Black moves first in the second phase. If the last move was
+            // already one of the second phase, black moved before it.
+            let second_phase_move = matches!(
+                state.last_move,
+                Some(PlayerMove::StoneMove { from: Some(_), .. })
+            );
+            match side {
+                Color::Black if second_phase_move => num_stones + 2,
+                Color::Black => num_stones,
+                Color::White => num_stones + 1,
+            }
+        };
+
+        let mut pos = Position {
+            played_spots: black_spots | white_spots,
+            our_spots: match side {
+                Color::Black => black_spots,
+                Color::White => white_spots,
+            },
+            num_turns,
+            ply: num_turns,
+            ..Self::with_variant(variant)
+        };
+        pos.alignments[pos.num_turns] = pos.compute_alignment(false);
+
+        if let Some(pmove) = state.last_move {
+            let smove = pos
+                .played_stone_move(pmove)
+                .ok_or(InvalidBoardState::InvalidLastMove)?;
+            pos.move_history[pos.num_turns] = Some(smove);
+            // The last move can be undone, so also fill in the turn before it.
+            let mut before = pos.clone();
+            before.unmake_stone_move();
+            pos.alignments[before.num_turns] = before.compute_alignment(false);
+        } else if pos.num_turns > 0 {
+            // Mark that "Second Best!" was called on the previous turn,
+            // so that it can not be called this turn. Which move was
+            // banned doesn't matter anymore.
+            pos.banned_moves[pos.num_turns] = Some(0);
+        }
+
+        if let Some(pmove) = state.banned_move {
+            if state.last_move.is_some() {
+                // "Second Best!" can't be called twice in a row.
+                return Err(InvalidBoardState::InvalidBannedMove);
+            }
+            let PlayerMove::StoneMove { from, to } = pmove else {
+                return Err(InvalidBoardState::InvalidBannedMove);
+            };
+            // The banned move should be a valid move in the position.
+            if pos.clone().try_make_move(pmove).is_err() {
+                return Err(InvalidBoardState::InvalidBannedMove);
+            }
+            pos.banned_moves[pos.num_turns + 1] = Some(pos.stone_move(from, to));
+            pos.second_best_calls[side.other() as usize] = 1;
+        }
+        Ok(pos)
+    }
+
+    /// The stone move which lead to the current position, if the player
+    /// move could have been played by the opponent.
+    fn played_stone_move(&self, pmove: PlayerMove) -> Option<Bitboard> {
+        let PlayerMove::StoneMove { from, to } = pmove else {
+            return None;
+        };
+        let variant = self.variant;
+        if to >= variant.stacks {
+            return None;
+        }
+        // The stone on top of the "to" stack should be the opponent's.
+        let opponent_spots = self.played_spots ^ self.our_spots;
+        let to_spot = variant.column_mask(to) & self.top_spots() & opponent_spots;
+        if to_spot == 0 {
+            return None;
+        }
+        // The position after the last stone of the first phase is
+        // placed is already in the second phase.
+        let was_first_phase = self.num_turns <= 2 * variant.stones_per_player();
+        match (from, self.is_second_phase()) {
+            (None, _) if was_first_phase => Some(to_spot),
+            (Some(from), true) => {
+                if from >= variant.stacks || !variant.valid_adjacent(from, to) {
+                    return None;
+                }
+                // The "from" stack had a stone on it, which is now gone.
+                let from_spot = variant.column_mask(from) & self.free_spots();
+                if from_spot == 0 {
+                    return None;
+                }
+                Some(to_spot | from_spot)
+            }
+            _ => None,
+        }
+    }
+
+    /// Convert a stone move which was played to reach the current position.
+    fn played_move_to_player_move(&self, smove: Bitboard) -> PlayerMove {
+        let to = self.variant.column_of_bit(smove & self.top_spots());
+        let from_spot = smove & self.free_spots();
+        let from = match from_spot {
+            0 => None,
+            _ => Some(self.variant.column_of_bit(from_spot)),
+        };
+        PlayerMove::StoneMove { from, to }
     }
 }
 
@@ -941,11 +1979,11 @@ mod tests {
         assert_eq!(
             pos.try_make_move(PlayerMove::StoneMove {
                 from: None,
-                to: Position::NUM_STACKS
+                to: Variant::STANDARD.stacks()
             }),
             Err(MoveFailed::InvalidToSpot)
         );
-        for _ in 0..Position::STACK_HEIGHT {
+        for _ in 0..Variant::STANDARD.height() {
             assert_eq!(
                 pos.try_make_move(PlayerMove::StoneMove { from: None, to: 0 }),
                 Ok(())
@@ -955,10 +1993,10 @@ mod tests {
             pos.try_make_move(PlayerMove::StoneMove { from: None, to: 0 }),
             Err(MoveFailed::InvalidToSpot)
         );
-        assert_eq!(pos.num_turns, Position::STACK_HEIGHT);
+        assert_eq!(pos.num_turns, Variant::STANDARD.height());
         // Use make_stone_move the stones.
-        'outer: for stack in 1..Position::NUM_STACKS {
-            for _ in 0..Position::STACK_HEIGHT {
+        'outer: for stack in 1..Variant::STANDARD.stacks() {
+            for _ in 0..Variant::STANDARD.height() {
                 if pos.is_second_phase() {
                     break 'outer;
                 }
@@ -975,28 +2013,28 @@ mod tests {
         assert_eq!(
             pos.try_make_move(PlayerMove::StoneMove {
                 from: None,
-                to: Position::NUM_STACKS - 1
+                to: Variant::STANDARD.stacks() - 1
             }),
             Err(MoveFailed::MissingFromSpot)
         );
         assert_eq!(
             pos.try_make_move(PlayerMove::StoneMove {
-                from: Some(Position::NUM_STACKS),
-                to: Position::NUM_STACKS - 1
+                from: Some(Variant::STANDARD.stacks()),
+                to: Variant::STANDARD.stacks() - 1
             }),
             Err(MoveFailed::InvalidFromSpot)
         );
         assert_eq!(
             pos.try_make_move(PlayerMove::StoneMove {
                 from: Some(0),
-                to: Position::NUM_STACKS - 2
+                to: Variant::STANDARD.stacks() - 2
             }),
             Err(MoveFailed::InvalidToSpot)
         );
         assert_eq!(
             pos.try_make_move(PlayerMove::StoneMove {
                 from: Some(0),
-                to: Position::NUM_STACKS - 1
+                to: Variant::STANDARD.stacks() - 1
             }),
             Ok(()),
         );
@@ -1004,7 +2042,7 @@ mod tests {
         assert_eq!(
             pos.try_make_move(PlayerMove::StoneMove {
                 from: Some(0),
-                to: Position::NUM_STACKS - 1
+                to: Variant::STANDARD.stacks() - 1
             }),
             Err(MoveFailed::MoveBanned),
         );
@@ -1016,8 +2054,12 @@ mod tests {
         assert!(!pos.can_second_best());
         pos.make_phase_one_move(0);
         assert!(pos.can_second_best());
+        assert!(pos.can_second_best_next(true));
         pos.second_best();
         assert_eq!(pos.num_turns, 0);
+        // The move played instead can't be banned.
+        assert!(!pos.can_second_best_next(true));
+        assert!(pos.can_second_best_next(false));
         assert_eq!(
             pos.try_make_move(PlayerMove::StoneMove { from: None, to: 0 }),
             Err(MoveFailed::MoveBanned)
@@ -1085,6 +2127,54 @@ mod tests {
         assert!(pos.has_alignment(false));
     }
 
+    #[test]
+    fn symmetric_key() {
+        let key = |moves: &str| {
+            let mut pos = Position::default();
+            let moves = moves.split_whitespace().map(|s| s.to_string()).collect();
+            pos.parse_and_play_moves(moves).unwrap();
+            pos.symmetric_key()
+        };
+        // Rotations.
+        assert_eq!(key("0"), key("5"));
+        assert_eq!(key("0 2 4"), key("3 5 7"));
+        // Mirror images.
+        assert_eq!(key("0 1"), key("0 7"));
+        assert_eq!(key("2 3 3"), key("6 5 5"));
+        assert_ne!(key("0 1"), key("0 2"));
+        // The same board, but with a different last move.
+        assert_ne!(key("0 4 1 4"), key("4 1 4 0"));
+        // Every move in the second phase gives the same key as the mirrored
+        // move in the mirrored position, also across the first and last stack.
+        let mirror = |moves: &str| {
+            moves
+                .split(|c: char| !c.is_ascii_digit())
+                .map(|col| ((8 - col.parse::<usize>().unwrap()) % 8).to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let moves = "4 6 1 7 7 4 2 1 0 6 4 7 6 0 0 1";
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(moves.split_whitespace().map(|s| s.to_string()).collect())
+            .unwrap();
+        let mut keys = vec![];
+        for pmove in pos.legal_moves() {
+            let pmove = pmove.to_string();
+            if pmove.contains('!') {
+                continue;
+            }
+            let mirrored = mirror(&pmove).replace(' ', "-");
+            assert_eq!(
+                key(&format!("{moves} {pmove}")),
+                key(&format!("{} {mirrored}", mirror(moves)))
+            );
+            keys.push(key(&format!("{moves} {pmove}")));
+        }
+        keys.sort();
+        keys.dedup();
+        assert!(keys.len() > 1);
+    }
+
     #[test]
     fn game_over() {
         let mut pos = Position::default();
@@ -1109,6 +2199,13 @@ mod tests {
         .unwrap();
         // Can't second best.
         assert!(pos.game_over());
+        assert_eq!(
+            pos.game_status(),
+            GameStatus::Won {
+                winner: Color::Black,
+                reason: GameOverReason::Alignment
+            }
+        );
         pos.unmake_stone_move();
         pos.show();
 
@@ -1142,6 +2239,51 @@ mod tests {
         pos.show();
         // No legal moves.
         assert!(pos.game_over());
+        assert_eq!(
+            pos.game_status(),
+            GameStatus::Won {
+                winner: Color::Black,
+                reason: GameOverReason::NoLegalMoves
+            }
+        );
+
+        // Positions for which the check for legal moves
+        // used to look at the wrong "to" stacks.
+        for (moves, game_over) in [
+            (
+                "4 6 4 7 0 4 5 0 7 0 1 1 7 1 5 2 7-3 4-5 3-7 2-3 ! 0-4",
+                false,
+            ),
+            (
+                "4 6 4 7 0 4 5 0 7 0 1 1 7 1 5 2 7-3 2-3 7-6 1-2 ! 7-6",
+                true,
+            ),
+        ] {
+            let mut pos = Position::default();
+            pos.parse_and_play_moves(moves.split_whitespace().map(|s| s.to_string()).collect())
+                .unwrap();
+            assert_eq!(pos.game_over(), game_over);
+            assert_eq!(pos.mobility(true) == 0, game_over);
+        }
+
+        // The only move left is the one "Second Best!" was called on.
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(
+            "6 3 7 7 7 1 2 3 3 2 2 0 5 5 0 1 6-5 1-0 !"
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(pos.mobility(true), 1);
+        assert!(pos.legal_moves().is_empty());
+        assert_eq!(
+            pos.game_status(),
+            GameStatus::Won {
+                winner: Color::Black,
+                reason: GameOverReason::NoLegalMoves
+            }
+        );
     }
 
     #[test]
@@ -1224,5 +2366,635 @@ mod tests {
         let moves = pos.clone().serialize();
         println!("{moves}");
         assert_eq!(moves, input_moves);
+
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(vec!["0".to_string(), "!".to_string()])
+            .unwrap();
+        assert_eq!(pos.serialize(), "0 !");
+    }
+
+    #[test]
+    fn fen() {
+        let mut pos = Position::default();
+        assert_eq!(pos.to_fen(), "./././././././. X - -");
+        pos.parse_and_play_moves(
+            "1 0 4 7 2 4 2 3"
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(pos.to_fen(), "O/X/XX/O/XO/././O X 3 -");
+        pos.second_best();
+        assert_eq!(pos.to_fen(), "O/X/XX/./XO/././O O - 3");
+
+        let input_moves =
+            "3 1 1 0 6 2 3 7 6 6 7 0 5 7 0 2 5-4 7-3 0-1 3-4 3-4 0-7 4-0 4-3 4-5 7-0 7-3 6-7 ! 6-5";
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(
+            input_moves
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        for _ in 0..4 {
+            let fen = pos.to_fen();
+            let pos2 = Position::from_fen(&fen).unwrap();
+            assert_eq!(fen, pos2.to_fen());
+            assert_eq!(pos.our_spots, pos2.our_spots);
+            assert_eq!(pos.played_spots, pos2.played_spots);
+            assert_eq!(pos.banned_move(), pos2.banned_move());
+            assert_eq!(pos.can_second_best(), pos2.can_second_best());
+            assert_eq!(pos.current_player(), pos2.current_player());
+            pos.unmake_move();
+        }
+        // The last stone placed in the first phase.
+        while pos.num_turns() > 2 * Variant::STANDARD.stones_per_player() {
+            pos.unmake_move();
+        }
+        let fen = pos.to_fen();
+        assert!(fen.ends_with(" X 2 -"), "{fen}");
+        let pos2 = Position::from_fen(&fen).unwrap();
+        assert_eq!(fen, pos2.to_fen());
+        assert!(pos2.can_second_best());
+
+        assert_eq!(
+            Position::from_fen("./././././././. X -").err(),
+            Some(InvalidFen::WrongNumberOfFields)
+        );
+        assert_eq!(
+            Position::from_fen("./././././. X - -").err(),
+            Some(InvalidFen::WrongNumberOfStacks)
+        );
+        assert_eq!(
+            Position::from_fen("./XXXX/./././././. X - -").err(),
+            Some(InvalidFen::InvalidStack(1))
+        );
+        assert_eq!(
+            Position::from_fen("X/././././././. X - -").err(),
+            Some(InvalidFen::InvalidStoneCount)
+        );
+        assert_eq!(
+            Position::from_fen("X/././././././. O 1 -").err(),
+            Some(InvalidFen::InvalidLastMove)
+        );
+        assert_eq!(
+            Position::from_fen("X/././././././. O - 0-1").err(),
+            Some(InvalidFen::InvalidBannedMove)
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let mut pos = Position::default();
+        assert_eq!(pos.validate(), Ok(()));
+        pos.parse_and_play_moves(
+            "3 1 1 0 6 2 3 7 6 6 7 0 5 7 0 2 5-4 7-3 0-1 3-4 3-4 0-7 4-0 4-3 4-5 7-0 7-3 6-7 ! 6-5"
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(pos.validate(), Ok(()));
+        // Positions from a fen can undo their last move, here 6-7 by white.
+        let mut before_second_best = pos.clone();
+        before_second_best.unmake_move();
+        before_second_best.unmake_move();
+        let mut from_fen = Position::from_fen(&before_second_best.to_fen()).unwrap();
+        assert_eq!(from_fen.current_player(), Color::Black);
+        assert_eq!(from_fen.validate(), Ok(()));
+        from_fen.unmake_move();
+        assert_eq!(from_fen.validate(), Ok(()));
+
+        let broken = |change: fn(&mut Position)| {
+            let mut broken = pos.clone();
+            change(&mut broken);
+            broken.validate()
+        };
+        assert_eq!(
+            broken(|pos| pos.our_spots |= !pos.played_spots & Variant::STANDARD.column_mask(5)),
+            Err(InvariantViolation::StoneNotPlayed)
+        );
+        assert_eq!(
+            broken(|pos| pos.played_spots &= !(1 << 40)),
+            Err(InvariantViolation::CopiesDiffer)
+        );
+        assert_eq!(
+            broken(|pos| pos.played_spots |= Variant::STANDARD.column_mask(2) << 1),
+            Err(InvariantViolation::StackTooHigh(2))
+        );
+        let stack_i = (0..Variant::STANDARD.stacks())
+            .find(|&stack_i| pos.stack_height(stack_i) >= 2)
+            .unwrap();
+        let mut floating = pos.clone();
+        floating.played_spots &= !Variant::STANDARD.bb_of_spot(stack_i, 0);
+        floating.our_spots &= !Variant::STANDARD.bb_of_spot(stack_i, 0);
+        assert_eq!(
+            floating.validate(),
+            Err(InvariantViolation::FloatingStone(stack_i))
+        );
+        assert_eq!(
+            broken(|pos| pos.num_turns -= 2),
+            Err(InvariantViolation::FutureMove(pos.num_turns() - 1))
+        );
+        let mut first_phase = Position::default();
+        first_phase
+            .parse_and_play_moves(vec!["0".to_string(), "1".to_string(), "2".to_string()])
+            .unwrap();
+        // The stones of the player to move are now counted as black's.
+        first_phase.num_turns = 2;
+        assert_eq!(
+            first_phase.validate(),
+            Err(InvariantViolation::WrongStoneCount(Color::White))
+        );
+        assert_eq!(
+            broken(|pos| pos.ply -= 1),
+            Err(InvariantViolation::PlyMismatch)
+        );
+        assert_eq!(
+            broken(|pos| pos.move_history[pos.num_turns + 1] = Some(0)),
+            Err(InvariantViolation::FutureMove(pos.num_turns() + 1))
+        );
+        assert_eq!(
+            broken(|pos| pos.move_history[pos.num_turns] = Some(pos.our_spots)),
+            Err(InvariantViolation::InvalidLastMove)
+        );
+        assert_eq!(
+            broken(|pos| pos.banned_moves[pos.num_turns + 1] = Some(pos.free_spots())),
+            Err(InvariantViolation::InvalidBannedMove)
+        );
+        assert_eq!(
+            broken(|pos| pos.alignments[pos.num_turns] ^= true),
+            Err(InvariantViolation::StaleAlignment)
+        );
+    }
+
+    #[test]
+    fn board_inspection() {
+        let mut pos = Position::default();
+        assert_eq!(
+            pos.stones_in_hand(Color::Black),
+            Variant::STANDARD.stones_per_player()
+        );
+        pos.parse_and_play_moves(
+            "1 0 4 7 2 4 2 3"
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(pos.ply(), 8);
+        assert_eq!(pos.stack_height(2), 2);
+        assert_eq!(pos.stack_height(5), 0);
+        assert_eq!(pos.stone_at(4, 0), Some(Color::Black));
+        assert_eq!(pos.stone_at(4, 1), Some(Color::White));
+        assert_eq!(pos.stone_at(4, 2), None);
+        assert_eq!(pos.stones_in_hand(Color::Black), 4);
+        assert_eq!(pos.stones_in_hand(Color::White), 4);
+        assert_ne!(pos.played_spots & Variant::STANDARD.bb_of_spot(3, 0), 0);
+        assert_eq!(pos.played_spots & Variant::STANDARD.bb_of_spot(3, 1), 0);
+        // The stone is given back when "Second Best!" is called.
+        pos.second_best();
+        assert_eq!(pos.stack_height(3), 0);
+        assert_eq!(pos.stones_in_hand(Color::White), 5);
+    }
+
+    #[test]
+    fn undo_last_player_move() {
+        let input_moves =
+            "3 1 1 0 6 2 3 7 6 6 7 0 5 7 0 2 5-4 7-3 0-1 3-4 3-4 0-7 4-0 4-3 4-5 7-0 7-3 6-7 ! 6-5";
+        let moves: Vec<PlayerMove> = input_moves
+            .split_whitespace()
+            .map(|s| PlayerMove::from(s.to_string()).unwrap())
+            .collect();
+        let mut pos = Position::default();
+        assert_eq!(pos.undo_last_player_move(), None);
+        for &pmove in &moves {
+            pos.try_make_move(pmove).unwrap();
+        }
+        assert_eq!(pos.history(), moves);
+        // Undo the move after "Second Best!", and the call itself.
+        assert_eq!(pos.undo_last_player_move(), moves.last().copied());
+        assert!(pos.banned_move().is_some());
+        assert_eq!(pos.undo_last_player_move(), Some(PlayerMove::SecondBest));
+        assert!(pos.can_second_best());
+        for &pmove in moves.iter().rev().skip(2) {
+            assert_eq!(pos.undo_last_player_move(), Some(pmove));
+        }
+        assert_eq!(pos.undo_last_player_move(), None);
+        assert_eq!(pos.to_fen(), Position::default().to_fen());
+
+        let mut pos = Position::from_fen("O/X/XX/O/XO/././O X 3 -").unwrap();
+        let last_move = PlayerMove::StoneMove { from: None, to: 3 };
+        assert_eq!(pos.history(), vec![last_move]);
+        assert_eq!(pos.undo_last_player_move(), Some(last_move));
+        assert_eq!(pos.undo_last_player_move(), None);
+    }
+
+    #[test]
+    fn cached_alignment() {
+        let mut pos = Position::default();
+        let input_moves =
+            "3 1 1 0 6 2 3 7 6 6 7 0 5 7 0 2 5-4 7-3 0-1 3-4 3-4 0-7 4-0 4-3 4-5 7-0 7-3 6-7 ! 6-5";
+        for smove in input_moves.split_whitespace() {
+            pos.try_make_move(PlayerMove::from(smove.to_string()).unwrap())
+                .unwrap();
+            assert_eq!(pos.has_alignment(false), pos.compute_alignment(false));
+        }
+        while pos.undo_last_player_move().is_some() {
+            assert_eq!(pos.has_alignment(false), pos.compute_alignment(false));
+        }
+
+        // Undoing the last move of a position set up from a fen.
+        let mut pos = Position::from_fen("XXX/O/O/O/./X/./. O 0 -").unwrap();
+        assert!(pos.has_alignment(false));
+        pos.undo_last_player_move().unwrap();
+        assert!(!pos.has_alignment(false));
+    }
+
+    #[test]
+    fn null_move() {
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(
+            "3 1 1 0 6 2 3 7 6 6 7 0 5 7 0 2 5-4 7-3"
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        let before = pos.clone();
+        let moves = pos.legal_moves();
+        pos.make_null_move();
+        assert_eq!(pos.validate(), Ok(()));
+        assert_eq!(pos.current_player(), before.current_player().other());
+        assert_eq!(pos.ply(), before.ply() + 1);
+        assert!(pos.is_second_phase());
+        // The pass can't be banned, and the board is the same.
+        assert!(!pos.can_second_best());
+        assert_eq!(pos.last_stone_move(), None);
+        assert_eq!(
+            pos.to_fen().split_whitespace().next(),
+            before.to_fen().split_whitespace().next()
+        );
+        // The opponent moves as if it were their turn on this board.
+        for pmove in pos.legal_moves() {
+            assert!(!matches!(pmove, PlayerMove::SecondBest));
+            pos.try_make_move(pmove).unwrap();
+            assert!(pos.can_second_best());
+            pos.unmake_move();
+        }
+        pos.unmake_null_move();
+        assert_eq!(pos.to_fen(), before.to_fen());
+        assert_eq!(pos.history(), before.history());
+        assert_eq!(pos.legal_moves(), moves);
+        assert_eq!(pos.validate(), Ok(()));
+    }
+
+    #[test]
+    fn house_rules() {
+        let mut rules = Rules::default();
+        assert_eq!(rules.set("second-best-limit", "1"), Ok(()));
+        assert_eq!(
+            rules.set("second-best-limit", "x"),
+            Err(InvalidOption::InvalidValue)
+        );
+        assert_eq!(rules.set("limit", "1"), Err(InvalidOption::UnknownName));
+        // The transposition table only has room for 7 calls left.
+        assert_eq!(rules.set("second-best-limit", "7"), Ok(()));
+        assert_eq!(
+            rules.set("second-best-limit", "8"),
+            Err(InvalidOption::InvalidValue)
+        );
+        assert_eq!(rules.set("second-best-limit", "1"), Ok(()));
+        assert_eq!(rules.second_best_limit, Some(1));
+
+        let mut pos = Position::default();
+        pos.set_rules(rules);
+        pos.try_make_move(PlayerMove::StoneMove { from: None, to: 0 })
+            .unwrap();
+        assert_eq!(pos.second_best_calls_left(Color::White), Some(1));
+        pos.try_make_move(PlayerMove::SecondBest).unwrap();
+        assert_eq!(pos.second_best_calls_left(Color::White), Some(0));
+        pos.parse_and_play_moves(vec!["1".to_string(), "2".to_string()])
+            .unwrap();
+        // White has used their call, black hasn't.
+        assert!(pos.can_second_best());
+        assert!(pos.can_second_best_next(false));
+        assert!(!pos.can_second_best_next(true));
+        pos.parse_and_play_moves(vec!["3".to_string()]).unwrap();
+        assert!(!pos.can_second_best());
+        for _ in 0..4 {
+            pos.undo_last_player_move().unwrap();
+        }
+        assert_eq!(pos.second_best_calls_left(Color::White), Some(1));
+
+        // Black has an alignment after their last move.
+        let moves = "0 1 0 2 0".split_whitespace().map(|s| s.to_string());
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(moves.collect()).unwrap();
+        assert!(pos.can_second_best());
+        pos.set_rules(Rules {
+            second_best_on_alignment: false,
+            ..Default::default()
+        });
+        assert!(!pos.can_second_best());
+        assert_eq!(
+            pos.game_status(),
+            GameStatus::Won {
+                winner: Color::Black,
+                reason: GameOverReason::Alignment
+            }
+        );
+    }
+
+    #[test]
+    fn long_form() {
+        let moves = PlayerMove::parse_moves("3 1! 2 ! 0").unwrap();
+        let m = |to| PlayerMove::StoneMove { from: None, to };
+        assert_eq!(
+            moves,
+            vec![
+                m(3),
+                m(1),
+                PlayerMove::SecondBest,
+                m(2),
+                PlayerMove::SecondBest,
+                m(0)
+            ]
+        );
+        assert_eq!(PlayerMove::to_long_form(&moves), "3 1! 2! 0");
+        assert_eq!(PlayerMove::parse_moves("1-!"), Err(MoveFailed::ParseError));
+        assert_eq!(PlayerMove::to_long_form(&[]), "");
+
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(vec!["3".to_string(), "1!".to_string(), "2".to_string()])
+            .unwrap();
+        assert_eq!(
+            pos.history(),
+            vec![m(3), m(1), PlayerMove::SecondBest, m(2)]
+        );
+        assert_eq!(pos.banned_move_at(2), Some(m(1)));
+        assert_eq!(pos.banned_move_at(1), None);
+        assert_eq!(pos.banned_move_at(4), None);
+    }
+
+    #[test]
+    fn legal_moves() {
+        let input_moves =
+            "3 1 1 0 6 2 3 7 6 6 7 0 5 7 0 2 5-4 7-3 0-1 3-4 3-4 0-7 4-0 4-3 4-5 7-0 7-3 6-7 ! 6-5";
+        let mut pos = Position::default();
+        let mut candidates = vec![PlayerMove::SecondBest];
+        for to in 0..Variant::STANDARD.stacks() + 1 {
+            candidates.push(PlayerMove::StoneMove { from: None, to });
+            for from in 0..Variant::STANDARD.stacks() + 1 {
+                candidates.push(PlayerMove::StoneMove {
+                    from: Some(from),
+                    to,
+                });
+            }
+        }
+        for pmove in input_moves.split_whitespace() {
+            let mut legal_moves = pos.legal_moves();
+            let mut expected: Vec<_> = candidates
+                .iter()
+                .copied()
+                .filter(|&candidate| pos.is_legal(candidate).is_ok())
+                .collect();
+            legal_moves.sort_by_key(|pmove| pmove.to_string());
+            expected.sort_by_key(|pmove| pmove.to_string());
+            assert_eq!(legal_moves, expected);
+            pos.try_make_move(PlayerMove::from(pmove.to_string()).unwrap())
+                .unwrap();
+        }
+        assert_eq!(
+            pos.is_legal(PlayerMove::SecondBest),
+            Err(MoveFailed::InvalidSecondBest)
+        );
+    }
+
+    #[test]
+    fn board_state() {
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(
+            "1 0 4 7 2 4 2 3"
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        let state = pos.board_state();
+        assert_eq!(state.stacks[2], vec![Color::Black, Color::Black]);
+        assert_eq!(state.side_to_move, Color::Black);
+        assert_eq!(
+            state.last_move,
+            Some(PlayerMove::StoneMove { from: None, to: 3 })
+        );
+        let pos2 = Position::from_board_state(&state).unwrap();
+        assert_eq!(pos.to_fen(), pos2.to_fen());
+
+        let mut state = BoardState::default();
+        state.stacks[0] = vec![Color::White; Variant::STANDARD.height() + 1];
+        assert_eq!(
+            Position::from_board_state(&state).err(),
+            Some(InvalidBoardState::StackTooHigh(0))
+        );
+        state.stacks[0] = vec![Color::White];
+        assert_eq!(
+            Position::from_board_state(&state).err(),
+            Some(InvalidBoardState::InvalidStoneCount)
+        );
+        state.stacks[1] = vec![Color::Black];
+        state.banned_move = Some(PlayerMove::StoneMove { from: None, to: 2 });
+        assert!(Position::from_board_state(&state).is_ok());
+    }
+
+    #[test]
+    fn threats_and_mobility() {
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(
+            "0 1 0 2 3 7 4 7"
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        pos.show();
+        // Black (X) has two stones on stack 0, white (O) on stack 7.
+        assert_eq!(
+            pos.vertical_threats(true),
+            Variant::STANDARD.bb_of_spot(0, 2)
+        );
+        assert_eq!(
+            pos.vertical_threats(false),
+            Variant::STANDARD.bb_of_spot(7, 2)
+        );
+        // Black controls stacks 0, 3 and 4, white controls 1, 2 and 7.
+        assert_eq!(pos.horizontal_threats(true), 0);
+        assert_eq!(pos.horizontal_threats(false), 0);
+        pos.make_phase_one_move(5);
+        // Now black controls 3, 4 and 5.
+        assert_eq!(pos.horizontal_threats(false), 1);
+        assert_eq!(pos.horizontal_threats(true), 0);
+
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(
+            "0 1 2 3 4 5 6 7 1 2 3 4 5 6 7 0"
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        // Every stack has two stones, so every move is possible.
+        assert_eq!(pos.mobility(true), 3 * 4);
+        assert_eq!(pos.mobility(false), 3 * 4);
+    }
+
+    #[test]
+    fn second_phase_moves_bb() {
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(
+            "0 0 1 1 2 3 2 3 4 4 0 1 6 6 6 7"
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        for pmove in pos.legal_moves() {
+            pos.try_make_move(pmove).unwrap();
+            if pos.is_second_phase() && !pos.game_over() && !pos.has_alignment(false) {
+                let mut moves = vec![];
+                for (direction, offset) in Variant::STANDARD.directions().into_iter().enumerate() {
+                    let to_stacks = pos.second_phase_moves_bb()[direction];
+                    for to in 0..Variant::STANDARD.stacks() {
+                        if to_stacks & Variant::STANDARD.column_bottom_mask(to) != 0 {
+                            let from = (to + Variant::STANDARD.stacks() - offset)
+                                % Variant::STANDARD.stacks();
+                            moves.push(PlayerMove::StoneMove {
+                                from: Some(from),
+                                to,
+                            });
+                        }
+                    }
+                }
+                assert_eq!(moves.len() as u32, pos.mobility(true));
+                // The only stone move which is not legal is the banned move.
+                let mut legal_moves = pos.legal_moves();
+                legal_moves.retain(|&pmove| pmove != PlayerMove::SecondBest);
+                if let Some(banned_move) = pos.banned_move() {
+                    legal_moves.push(BitboardMove::StoneMove(banned_move).to_player_move(&pos));
+                }
+                moves.sort_by_key(|pmove| pmove.to_string());
+                legal_moves.sort_by_key(|pmove| pmove.to_string());
+                assert_eq!(moves, legal_moves);
+            }
+            pos.undo_last_player_move();
+        }
+    }
+
+    #[test]
+    fn threat_detection() {
+        assert_eq!(Position::default().forced_moves(), None);
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(
+            "0 1 0 2 4"
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        // Black (X) has two stones on stack 0.
+        let on_stack_0 = PlayerMove::StoneMove { from: None, to: 0 };
+        assert_eq!(pos.immediate_winning_moves(false), vec![on_stack_0]);
+        assert!(pos.immediate_winning_moves(true).is_empty());
+        assert_eq!(pos.opponent_threats(), vec![0]);
+        // Only blocking the stack stops the threat.
+        assert_eq!(pos.forced_moves(), Some(vec![on_stack_0]));
+        pos.try_make_move(on_stack_0).unwrap();
+        // Now white (O) controls stacks 0, 1 and 2, and threatens to get four in
+        // a row on both sides. Covering stack 1 or 2 stops both, and so does
+        // calling "Second Best!" on 0.
+        assert_eq!(pos.opponent_threats(), vec![3, 7]);
+        let forced = vec![
+            PlayerMove::StoneMove { from: None, to: 1 },
+            PlayerMove::StoneMove { from: None, to: 2 },
+            PlayerMove::SecondBest,
+        ];
+        assert_eq!(pos.forced_moves(), Some(forced));
+        // After the call, white can't block stack 0 anymore.
+        pos.try_make_move(PlayerMove::SecondBest).unwrap();
+        assert_eq!(pos.opponent_threats(), vec![0]);
+        assert_eq!(pos.forced_moves(), Some(vec![]));
+
+        // The moves found are exactly the legal moves after which
+        // the player who moved has an alignment.
+        for moves in [
+            "2 2 0 0 0 1 4 2 6 5 4 7 5 5 1 6 0-7 0-7 0-1 5-4 1-0",
+            "1 1 3 6 3 7 5 1 2 3 5",
+            "0 0 6 2 6 4 7 2 2 4 4",
+            "0 1 2 3 4 5 6 7 1 2 3 4 5 6 7 0",
+        ] {
+            let mut pos = Position::default();
+            pos.parse_and_play_moves(moves.split_whitespace().map(|s| s.to_string()).collect())
+                .unwrap();
+            let mut expected = vec![];
+            for pmove in pos.legal_moves() {
+                let mut after = pos.clone();
+                after.try_make_move(pmove).unwrap();
+                if pmove != PlayerMove::SecondBest && after.has_alignment(false) {
+                    expected.push(pmove);
+                }
+            }
+            assert_eq!(pos.immediate_winning_moves(true), expected);
+        }
+    }
+
+    #[test]
+    fn variants() {
+        assert_eq!(Variant::new(5, 3), Err(InvalidVariant::InvalidStacks));
+        assert_eq!(Variant::new(2, 3), Err(InvalidVariant::InvalidStacks));
+        assert_eq!(Variant::new(8, 2), Err(InvalidVariant::TooLow));
+        assert_eq!(Variant::new(8, 4), Err(InvalidVariant::TooLarge));
+        // The board fits, but there is no room left for the key.
+        assert_eq!(Variant::new(4, 7), Err(InvalidVariant::TooLarge));
+        assert_eq!("8x3".parse(), Ok(Variant::STANDARD));
+        assert_eq!("6 4".parse::<Variant>(), Err(InvalidVariant::ParseError));
+
+        let variant: Variant = "6x4".parse().unwrap();
+        assert_eq!(variant.to_string(), "6x4");
+        assert_eq!(variant.stones_per_player(), 8);
+        let play = |moves: &str| {
+            let mut pos = Position::with_variant(variant);
+            pos.parse_and_play_moves(moves.split_whitespace().map(|s| s.to_string()).collect())
+                .unwrap();
+            pos
+        };
+        let to = |to| PlayerMove::StoneMove { from: None, to };
+        let pos = play("5 5 5 5");
+        assert_eq!(pos.stack_height(5), 4);
+        assert_eq!(pos.is_legal(to(5)), Err(MoveFailed::InvalidToSpot));
+        assert_eq!(pos.is_legal(to(6)), Err(MoveFailed::InvalidToSpot));
+        // Three stones in a column, below the top of a stack.
+        let pos = play("1 0 0 1 0 1");
+        assert!(pos.is_legal(to(0)).is_ok());
+        assert_eq!(pos.immediate_winning_moves(true), vec![to(0)]);
+        // Four stacks in a row, around the end of the board.
+        let mut pos = play("4 3 0 2 1 3");
+        assert_eq!(pos.immediate_winning_moves(true), vec![to(5)]);
+        pos.try_make_move(to(5)).unwrap();
+        assert!(pos.has_alignment(false));
+
+        // The notation has as many stacks as the board.
+        let fen = pos.to_fen();
+        assert_eq!(fen, "X/X/O/OO/X/X O 5 -");
+        assert_eq!(
+            Position::from_fen(&fen).err(),
+            Some(InvalidFen::WrongNumberOfStacks)
+        );
+        let from_fen = Position::from_fen_with_variant(&fen, variant).unwrap();
+        assert_eq!(from_fen.to_fen(), fen);
+        assert_eq!(from_fen.variant(), variant);
+        // Boards without the star are shown a stack per line.
+        assert!(pos.to_string().starts_with("0: X . . .\n1: X . . .\n"));
     }
 }
@@ -0,0 +1,138 @@
+use std::fmt::Display;
+use std::sync::Arc;
+use web_time::{Duration, Instant};
+
+/// The progress of a long running task, like a search or a benchmark.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    /// What is being done, e.g. "search depth" or "benchmark".
+    pub task: String,
+    /// How much of the task is done, e.g. the depth being searched
+    /// or the number of positions solved.
+    pub done: usize,
+    /// The amount of work in the full task, if it is known.
+    pub total: Option<usize>,
+    /// The number of nodes searched so far.
+    pub nodes: usize,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// The percentage of the task which is done, if the total is known.
+    pub fn percentage(&self) -> Option<f64> {
+        let total = self.total.filter(|&total| total > 0)?;
+        Some(self.done as f64 / total as f64 * 100.0)
+    }
+
+    /// An estimate of the time left, assuming the rest of the
+    /// task goes as fast as what is done already.
+    pub fn eta(&self) -> Option<Duration> {
+        let total = self.total?;
+        if self.done == 0 {
+            return None;
+        }
+        let left = total.saturating_sub(self.done) as u32;
+        Some(self.elapsed / self.done as u32 * left)
+    }
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "info string {} {}", self.task, self.done)?;
+        if let (Some(total), Some(percentage)) = (self.total, self.percentage()) {
+            write!(f, "/{total} ({percentage:.1}%)")?;
+        }
+        write!(
+            f,
+            " nodes {} time {:.1}s",
+            self.nodes,
+            self.elapsed.as_secs_f64()
+        )?;
+        if let Some(eta) = self.eta() {
+            write!(f, " eta {:.1}s", eta.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+/// A function which gets the progress of a task.
+pub type ProgressHandler = Arc<dyn Fn(Progress) + Send + Sync>;
+
+/// A function which gets the messages of a long running task, like
+/// "Saved results to ...". They are meant to be shown to the user.
+pub type MessageHandler = Arc<dyn Fn(String) + Send + Sync>;
+
+/// Keeps track of a task, and reports its progress to a handler
+/// at most once every [`ProgressReporter::INTERVAL`].
+pub struct ProgressReporter {
+    task: String,
+    total: Option<usize>,
+    start: Instant,
+    last_report: Instant,
+    handler: ProgressHandler,
+}
+
+impl ProgressReporter {
+    pub const INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(task: impl Into<String>, total: Option<usize>, handler: ProgressHandler) -> Self {
+        let now = Instant::now();
+        Self {
+            task: task.into(),
+            total,
+            start: now,
+            last_report: now,
+            handler,
+        }
+    }
+
+    /// Report the progress, if the last report was long enough ago.
+    pub fn update(&mut self, done: usize, nodes: usize) {
+        let now = Instant::now();
+        if now.duration_since(self.last_report) >= Self::INTERVAL {
+            self.last_report = now;
+            self.report(done, nodes);
+        }
+    }
+
+    /// Report the progress right away.
+    pub fn report(&self, done: usize, nodes: usize) {
+        (self.handler)(Progress {
+            task: self.task.clone(),
+            done,
+            total: self.total,
+            nodes,
+            elapsed: self.start.elapsed(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eta() {
+        let mut progress = Progress {
+            task: "benchmark".to_string(),
+            done: 0,
+            total: Some(4),
+            nodes: 100,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(progress.eta(), None);
+        progress.done = 1;
+        assert_eq!(progress.percentage(), Some(25.0));
+        assert_eq!(progress.eta(), Some(Duration::from_secs(6)));
+        assert_eq!(
+            progress.to_string(),
+            "info string benchmark 1/4 (25.0%) nodes 100 time 2.0s eta 6.0s"
+        );
+        progress.total = None;
+        assert_eq!(progress.eta(), None);
+        assert_eq!(
+            progress.to_string(),
+            "info string benchmark 1 nodes 100 time 2.0s"
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Bindings for using the solver from Python.
+//!
+//! Build with `maturin develop --features python`, after which
+//! the module can be used as follows:
+//!
+//! ```python
+//! from second_best_py import Position, Solver
+//!
+//! pos = Position.from_fen("O/X/XX/O/XO/././O X 3 -")
+//! score, pv = Solver().search(pos, depth=6)
+//! pos.make_move(pv[0])
+//! ```
+
+// The code generated by `#[pymethods]` triggers this lint for every `PyResult`.
+#![allow(clippy::useless_conversion)]
+
+use crate::position::{PlayerMove, Position};
+use crate::solver::{SearchLimits, Solver};
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::time::Duration;
+
+fn to_py_error(e: impl std::fmt::Debug) -> PyErr {
+    PyValueError::new_err(format!("{e:?}"))
+}
+
+/// A position, see [`Position`].
+#[pyclass(name = "Position")]
+#[derive(Clone)]
+pub struct PyPosition(Position);
+
+#[pymethods]
+impl PyPosition {
+    /// The starting position.
+    #[new]
+    fn new() -> Self {
+        Self(Position::default())
+    }
+
+    /// Create a position from the compact notation, see [`Position::from_fen`].
+    #[staticmethod]
+    fn from_fen(fen: &str) -> PyResult<Self> {
+        Position::from_fen(fen).map(Self).map_err(to_py_error)
+    }
+
+    /// Create a position by playing the given moves from the start, e.g. "0 1 2".
+    #[staticmethod]
+    fn from_moves(moves: &str) -> PyResult<Self> {
+        let mut pos = Self::new();
+        pos.make_moves(moves)?;
+        Ok(pos)
+    }
+
+    /// The position in the compact notation.
+    fn fen(&self) -> String {
+        self.0.to_fen()
+    }
+
+    /// The moves leading to this position, see [`Position::serialize`].
+    fn serialize(&self) -> String {
+        self.0.clone().serialize()
+    }
+
+    /// Play a single move, e.g. "3", "0-4" or "!".
+    fn make_move(&mut self, pmove: &str) -> PyResult<()> {
+        let pmove = PlayerMove::from(pmove.to_string()).map_err(to_py_error)?;
+        self.0.try_make_move(pmove).map_err(to_py_error)
+    }
+
+    /// Play the given moves, e.g. "0 1 2" or "0-1!".
+    /// If one of the moves is illegal, none of them are played.
+    fn make_moves(&mut self, moves: &str) -> PyResult<()> {
+        let moves = PlayerMove::parse_moves(moves).map_err(to_py_error)?;
+        let mut pos = self.0.clone();
+        for pmove in moves {
+            pos.try_make_move(pmove).map_err(to_py_error)?;
+        }
+        self.0 = pos;
+        Ok(())
+    }
+
+    /// Undo the last move, and return it. Returns `None` if there is nothing to undo.
+    fn unmake_move(&mut self) -> Option<String> {
+        self.0
+            .undo_last_player_move()
+            .map(|pmove| pmove.to_string())
+    }
+
+    fn legal_moves(&self) -> Vec<String> {
+        self.0
+            .legal_moves()
+            .into_iter()
+            .map(|pmove| pmove.to_string())
+            .collect()
+    }
+
+    /// "X" or "O", the player to move.
+    fn current_player(&self) -> String {
+        self.0.current_player().to_string()
+    }
+
+    fn ply(&self) -> usize {
+        self.0.ply()
+    }
+
+    fn game_over(&self) -> bool {
+        self.0.game_over()
+    }
+
+    /// A description of the state of the game.
+    fn game_status(&self) -> String {
+        self.0.game_status().to_string()
+    }
+
+    fn copy(&self) -> Self {
+        self.clone()
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Position.from_fen(\"{}\")", self.0.to_fen())
+    }
+}
+
+impl Default for PyPosition {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The solver, see [`Solver`].
+/// The transposition table is kept between searches.
+#[pyclass(name = "Solver")]
+pub struct PySolver(Solver);
+
+#[pymethods]
+impl PySolver {
+    #[new]
+    fn new() -> Self {
+        Self(Solver::default())
+    }
+
+    /// Set one of the solver options, see [`crate::solver::SolverOptions::set`].
+    fn set_option(&mut self, name: &str, value: &str) -> PyResult<()> {
+        self.0.set_option(name, value).map_err(to_py_error)
+    }
+
+    /// Search the position until it is solved, or one of the limits is reached.
+    /// The time is in seconds. Returns the score of the position from the
+    /// perspective of the player to move, and the principal variation.
+    #[pyo3(signature = (position, depth=None, time=None, nodes=None))]
+    fn search(
+        &mut self,
+        py: Python<'_>,
+        position: &PyPosition,
+        depth: Option<usize>,
+        time: Option<f64>,
+        nodes: Option<usize>,
+    ) -> PyResult<(isize, Vec<String>)> {
+        let max_time = time
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(to_py_error)?;
+        let limits = SearchLimits {
+            max_depth: depth.unwrap_or(Position::MAX_MOVES),
+            max_nodes: nodes,
+            max_time,
+            ..Default::default()
+        };
+        self.0.set_position(position.0.clone());
+        let solver = &mut self.0;
+        let score = py.allow_threads(|| solver.search_with_limits(limits));
+        let pv = self.0.principal_variation();
+        Ok((score, pv.iter().map(|pmove| pmove.to_string()).collect()))
+    }
+
+    /// The number of nodes searched in the last search.
+    fn nodes(&self) -> usize {
+        self.0.nodes()
+    }
+}
+
+#[pymodule]
+fn second_best_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyPosition>()?;
+    m.add_class::<PySolver>()?;
+    Ok(())
+}
@@ -1,35 +1,579 @@
+use crate::error;
 use crate::eval;
+use crate::mcts::Mcts;
 use crate::movegen;
-use crate::position::Position;
+pub use crate::options::InvalidOption;
+use crate::position::{BitboardMove, MoveFailed, PlayerMove, Position, Rules, Variant};
+use crate::progress::{ProgressHandler, ProgressReporter};
+use crate::rng::Rng;
+use crate::trace::{SearchTrace, TtEvent};
 use crate::transposition_table::EntryType;
-use crate::transposition_table::TranspositionTable;
+use crate::transposition_table::{SharedTranspositionTable, SolverTable, TranspositionTable};
+use std::fmt::Display;
+use std::path::Path;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time;
+use web_time as time;
+
+/// Options which change how the search is performed.
+/// Some only change how fast the result is found, others (the extensions,
+/// reductions and pruning) can also change the score or the best move
+/// found at a given depth. Every option says which kind it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SolverOptions {
+    /// Use principal variation search instead of plain alpha-beta.
+    /// The first move is searched with a full window, and the other
+    /// moves with a null window, re-searching them if they fail high.
+    /// The score is the same as without it.
+    pub pvs: bool,
+    /// The maximal number of times a line is extended at depth 0, because
+    /// one of the players threatens an alignment. 0 disables the extension.
+    /// The extended lines are searched deeper, so the score can change.
+    pub threat_extensions: usize,
+    /// Don't decrease the depth when there is only one move possible.
+    /// The extended lines are searched deeper, so the score can change.
+    pub forced_extensions: bool,
+    /// Search late moves with a reduced depth first, and only search
+    /// them fully if they turn out to be better than the moves before.
+    /// A reduced search can miss a result, so the score can change.
+    pub lmr: bool,
+    /// In the second phase, first try the reply which refuted the previous
+    /// move of the opponent elsewhere, see [`movegen::CounterMoves`].
+    /// Only the move order changes, so the score is the same, but another
+    /// move with the same score can be found as the best one.
+    pub countermoves: bool,
+    /// In positions with fewer plies played than this, only search one of
+    /// the moves which lead to positions that are rotations or mirror images
+    /// of each other. The board is often symmetric early in the game, like
+    /// the starting position where all first moves are the same. 0 disables it,
+    /// which is the default until the benchmarks show that it helps.
+    /// The score is the same, but the best move can be a symmetric one.
+    pub symmetry_plies: usize,
+    /// How every iteration of the iterative deepening loop is searched.
+    /// Both drivers find the same score.
+    pub driver: SearchDriver,
+    /// Keep the transposition table between searches, so that a search
+    /// can use what earlier searches found. Without it, every search starts
+    /// from an empty table, which makes the number of nodes reproducible.
+    /// The table only cuts off with solved scores, so the scores found are
+    /// correct either way, but with the table a result beyond the depth can
+    /// be found.
+    pub keep_tt: bool,
+    /// In the second phase, first search what happens if we pass the turn,
+    /// with a reduced depth. If the opponent can't punish that, the node is
+    /// verified with a reduced search of our moves, and cut off if that is
+    /// good enough as well. Moving can be worse than passing, as it uncovers
+    /// stones, so this can miss wins and losses within the search depth,
+    /// which changes the score. The results it does find are still correct.
+    pub null_move: bool,
+}
+
+impl Default for SolverOptions {
+    fn default() -> Self {
+        Self {
+            pvs: false,
+            threat_extensions: 0,
+            forced_extensions: true,
+            lmr: false,
+            countermoves: true,
+            symmetry_plies: 0,
+            driver: SearchDriver::AlphaBeta,
+            keep_tt: true,
+            null_move: false,
+        }
+    }
+}
+
+/// How an iteration of the iterative deepening loop is searched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchDriver {
+    /// A single search with the full window.
+    AlphaBeta,
+    /// MTD(f): a sequence of null window searches, starting around the
+    /// score of the previous iteration, which narrow down the bounds on
+    /// the score until it is known.
+    Mtdf,
+}
+
+impl SearchDriver {
+    /// The names of the drivers, as used by [`SolverOptions::set`].
+    pub const NAMES: [&'static str; 2] = ["alphabeta", "mtdf"];
+}
+
+impl std::str::FromStr for SearchDriver {
+    type Err = InvalidOption;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alphabeta" => Ok(Self::AlphaBeta),
+            "mtdf" => Ok(Self::Mtdf),
+            _ => Err(InvalidOption::InvalidValue),
+        }
+    }
+}
+
+impl SolverOptions {
+    /// Set the option with the given name to the given value.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), InvalidOption> {
+        match name {
+            "pvs" => self.pvs = value.parse().map_err(|_| InvalidOption::InvalidValue)?,
+            "forced-extensions" => {
+                self.forced_extensions = value.parse().map_err(|_| InvalidOption::InvalidValue)?
+            }
+            "lmr" => self.lmr = value.parse().map_err(|_| InvalidOption::InvalidValue)?,
+            "countermoves" => {
+                self.countermoves = value.parse().map_err(|_| InvalidOption::InvalidValue)?
+            }
+            "symmetry-plies" => {
+                self.symmetry_plies = value.parse().map_err(|_| InvalidOption::InvalidValue)?
+            }
+            "search-driver" => self.driver = value.parse()?,
+            "threat-extensions" => {
+                self.threat_extensions = value.parse().map_err(|_| InvalidOption::InvalidValue)?
+            }
+            "keep-tt" => self.keep_tt = value.parse().map_err(|_| InvalidOption::InvalidValue)?,
+            "null-move" => {
+                self.null_move = value.parse().map_err(|_| InvalidOption::InvalidValue)?
+            }
+            _ => return Err(InvalidOption::UnknownName),
+        }
+        Ok(())
+    }
+}
+
+/// How strong the solver plays when choosing a move, from
+/// [`Skill::MIN`] (weakest) to [`Skill::MAX`] (full strength).
+/// Weaker levels search less deep, and add some randomness to
+/// the scores of the moves, so that they don't always play the
+/// best move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Skill(u8);
+
+impl Default for Skill {
+    fn default() -> Self {
+        Self(Self::MAX)
+    }
+}
+
+impl Skill {
+    pub const MIN: u8 = 1;
+    pub const MAX: u8 = 10;
+
+    /// Returns `None` if the level is not between [`Skill::MIN`] and [`Skill::MAX`].
+    pub fn new(level: u8) -> Option<Self> {
+        (Self::MIN..=Self::MAX)
+            .contains(&level)
+            .then_some(Self(level))
+    }
+
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+
+    pub fn is_full_strength(&self) -> bool {
+        self.0 == Self::MAX
+    }
+
+    /// The maximal depth searched at this level.
+    fn max_depth(&self) -> usize {
+        self.0 as usize
+    }
+
+    /// The maximal random amount added to the score of a move.
+    fn noise(&self) -> usize {
+        (Self::MAX - self.0) as usize
+    }
+}
+
+/// The algorithm used by [`Solver::choose_move`] to choose moves.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Alpha-beta search, which plays perfectly once a position is solved.
+    #[default]
+    Negamax,
+    /// Monte Carlo tree search, see [`crate::mcts`].
+    Mcts,
+}
+
+impl Engine {
+    /// The names of the engines, as parsed by [`Engine::from_str`](std::str::FromStr).
+    pub const NAMES: [&'static str; 2] = ["negamax", "mcts"];
+}
+
+impl std::str::FromStr for Engine {
+    type Err = InvalidOption;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "negamax" => Ok(Self::Negamax),
+            "mcts" => Ok(Self::Mcts),
+            _ => Err(InvalidOption::InvalidValue),
+        }
+    }
+}
 
 pub struct Solver {
     pub position: Position,
+    pub options: SolverOptions,
+    /// The weights used in the static evaluation.
+    pub eval_params: eval::EvalParams,
+    /// How strong the moves chosen by [`Solver::choose_move`] are.
+    pub skill: Skill,
+    /// The algorithm used by [`Solver::choose_move`].
+    pub engine: Engine,
+    rng: Rng,
     nodes: usize,
+    /// The largest number of plies from the root reached in the current search.
+    seldepth: usize,
+    /// The number of extensions in the current line.
+    extensions: usize,
+    /// The limits of the current search.
+    limits: SearchLimits,
+    /// The time at which the current search has to stop.
+    deadline: Option<time::Instant>,
+    /// Set when the current search was aborted or a hard limit was reached.
+    stopped: bool,
     abort: Arc<AtomicBool>,
+    /// See [`Solver::ponder_flag`].
+    ponder: Arc<AtomicBool>,
+    /// Whether the current search is pondering, so the time limits
+    /// haven't started yet.
+    pondering: bool,
     /// If true, don't print anything to stdout.
     quiet: bool,
-    t_table: TranspositionTable,
+    t_table: SolverTable,
+    counter_moves: movegen::CounterMoves,
+    /// Called with the results of every completed iteration.
+    info_handler: Option<Box<dyn Fn(SearchInfo) + Send>>,
+    /// Called with the root move being searched, during long searches.
+    currmove_handler: Option<Box<dyn Fn(CurrMoveInfo) + Send>>,
+    /// When the current search started.
+    start: time::Instant,
+    /// Called periodically during long searches.
+    progress_handler: Option<ProgressHandler>,
+    /// Reports the progress of the current search.
+    progress: Option<ProgressReporter>,
+    /// The depth of the current iteration.
+    depth: usize,
+    /// The moves searched in the root position, sorted by the results
+    /// of the last completed iteration.
+    root_moves: Vec<RootMove>,
+    /// Only some of the moves are searched in the root position.
+    restricted_root: bool,
+    /// The ply of the position the current search started from.
+    root_ply: usize,
+    /// The key of the position the last search started from.
+    root_key: u64,
+    /// Records the nodes searched, see [`Solver::trace_search`].
+    trace: Option<SearchTrace>,
+    /// Set before searching a node in which no null move may be made: the
+    /// node after a null move, or the verification of one. That node clears it.
+    skip_null_move: bool,
+}
+
+/// Limits on how much work a search can do.
+/// The search stops immediately when one of the hard limits
+/// (`max_nodes` and `max_time`) is reached, in which case the
+/// result of the last completed iteration is returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchLimits {
+    /// The maximal depth of the iterative deepening loop.
+    pub max_depth: usize,
+    /// Stop the search after approximately this many nodes.
+    pub max_nodes: Option<usize>,
+    /// Stop the search after this much time.
+    pub max_time: Option<time::Duration>,
+    /// Don't start a new iteration after this much time.
+    pub soft_time: Option<time::Duration>,
+    /// Don't start a new iteration after this much time if the best move
+    /// didn't change in the last [`Solver::STABLE_ITERATIONS`] iterations.
+    pub stable_time: Option<time::Duration>,
+    /// Like `stable_time`, for a stable best move which is also at least
+    /// [`Solver::EASY_MOVE_MARGIN`] better than the other moves.
+    pub easy_time: Option<time::Duration>,
+    /// Use this instead of `soft_time` if the best move changed in the last
+    /// iteration, to give the search time to check the new move.
+    pub unstable_time: Option<time::Duration>,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: Position::MAX_MOVES,
+            max_nodes: None,
+            max_time: None,
+            soft_time: None,
+            stable_time: None,
+            easy_time: None,
+            unstable_time: None,
+        }
+    }
+}
+
+impl SearchLimits {
+    /// Only limit the depth of the search.
+    pub fn depth(max_depth: usize) -> Self {
+        Self {
+            max_depth,
+            ..Default::default()
+        }
+    }
+}
+
+/// A search of the current position of a solver, set up by chaining the
+/// limits and settings, e.g.
+/// `solver.searcher().depth(12).nodes(5_000_000).time_ms(3000).multipv(2).run()`.
+/// Without any limits the search goes on until the position is solved.
+pub struct SearchBuilder<'a> {
+    solver: &'a mut Solver,
+    limits: SearchLimits,
+    multipv: usize,
+}
+
+impl SearchBuilder<'_> {
+    /// Use these limits, e.g. to also set the soft time limits
+    /// of [`SearchLimits`]. Replaces the limits set before.
+    pub fn limits(mut self, limits: SearchLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// The maximal depth of the iterative deepening loop.
+    pub fn depth(mut self, max_depth: usize) -> Self {
+        self.limits.max_depth = max_depth;
+        self
+    }
+
+    /// Stop the search after approximately this many nodes.
+    pub fn nodes(mut self, max_nodes: usize) -> Self {
+        self.limits.max_nodes = Some(max_nodes);
+        self
+    }
+
+    /// Stop the search after this many milliseconds.
+    pub fn time_ms(mut self, max_time: u64) -> Self {
+        self.limits.max_time = Some(time::Duration::from_millis(max_time));
+        self
+    }
+
+    /// Find the best `n` moves with their lines instead of only the best
+    /// one. Every line after the first is found with another search of the
+    /// moves which were not found yet, with the same limits.
+    pub fn multipv(mut self, n: usize) -> Self {
+        self.multipv = n.max(1);
+        self
+    }
+
+    /// Do the search. If the solver is aborted, the lines found so far are returned.
+    pub fn run(self) -> SearchResult {
+        self.solver.top_lines(self.multipv, self.limits)
+    }
+}
+
+/// What a search of a [`SearchBuilder`] found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    /// The score of the position from the perspective of the player to move,
+    /// see [`eval::decode_eval`].
+    pub score: isize,
+    /// The depth of the last completed iteration of the first search.
+    pub depth: usize,
+    /// The number of nodes searched by all the searches.
+    pub nodes: usize,
+    pub time: time::Duration,
+    /// The best lines, sorted from best to worst. There are fewer than asked
+    /// for with [`SearchBuilder::multipv`] if there are fewer legal moves or
+    /// the search was aborted, and none if the game is over.
+    pub lines: Vec<RootMoveLine>,
+}
+
+impl SearchResult {
+    /// The best move, or `None` if the game is over.
+    pub fn best_move(&self) -> Option<PlayerMove> {
+        self.lines.first().map(|line| line.pmove)
+    }
+
+    /// The principal variation, starting with the best move.
+    pub fn pv(&self) -> &[PlayerMove] {
+        self.lines.first().map_or(&[], |line| &line.pv)
+    }
+}
+
+/// Information about a completed iteration of the iterative deepening loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchInfo {
+    /// The depth that was searched.
+    pub depth: usize,
+    /// The largest number of plies from the searched position reached
+    /// so far, which is more than the depth because of extensions and
+    /// "Second Best!" calls.
+    pub seldepth: usize,
+    /// The score of the position at this depth.
+    pub score: isize,
+    /// The number of nodes searched so far.
+    pub nodes: usize,
+    /// Thousands of nodes searched per second.
+    pub knps: u128,
+    /// The time spent since the start of the search.
+    pub time: time::Duration,
+    /// How full the transposition table is, in per mille.
+    pub ttfull: usize,
+    /// The principal variation, starting from the searched position.
+    pub pv: Vec<PlayerMove>,
+    /// The moves in the searched position, sorted from best to worst.
+    pub root_moves: Vec<RootMoveLine>,
+}
+
+/// A root move with the best line starting with it, see [`Solver::root_move_lines`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootMoveLine {
+    pub pmove: PlayerMove,
+    /// The score of the move from our perspective, see [`RootMove::score`].
+    pub score: Option<isize>,
+    /// The line starting with the move, as far as it is in the transposition table.
+    pub pv: Vec<PlayerMove>,
+}
+
+impl Display for SearchInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "info depth {} seldepth {} score {} nodes {} knps {} ttfull {} time {}",
+            self.depth,
+            self.seldepth,
+            self.score,
+            self.nodes,
+            self.knps,
+            self.ttfull,
+            self.time.as_millis()
+        )?;
+        write!(f, "pv")?;
+        for pmove in &self.pv {
+            write!(f, " {pmove}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The root move which is being searched, reported during long searches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrMoveInfo {
+    /// The depth of the current iteration.
+    pub depth: usize,
+    pub currmove: PlayerMove,
+    /// The place of the move in the order the root moves are searched, starting from 1.
+    pub currmovenumber: usize,
+    /// The number of root moves.
+    pub total: usize,
+    /// The number of nodes searched so far.
+    pub nodes: usize,
+}
+
+impl Display for CurrMoveInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "info depth {} currmove {} currmovenumber {}/{} nodes {}",
+            self.depth, self.currmove, self.currmovenumber, self.total, self.nodes
+        )
+    }
+}
+
+/// A move in the root position of a search, with the results of searching it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RootMove {
+    pub pmove: PlayerMove,
+    bmove: BitboardMove,
+    /// The score of the move from our perspective in the last completed
+    /// iteration, if it was better than the moves searched before it.
+    /// The other moves are only known to be worse than the best move.
+    pub score: Option<isize>,
+    /// An upper bound on the score of the move in the last completed
+    /// iteration, if it wasn't better than the moves searched before it.
+    pub upper_bound: Option<isize>,
+    /// The score of the move in the iteration before.
+    pub previous_score: Option<isize>,
+    /// The number of nodes spent on this move in the last completed iteration.
+    pub nodes: usize,
+}
+
+impl RootMove {
+    fn new(bmove: BitboardMove, pos: &Position) -> Self {
+        Self {
+            pmove: bmove.to_player_move(pos),
+            bmove,
+            score: None,
+            upper_bound: None,
+            previous_score: None,
+            nodes: 0,
+        }
+    }
 }
 
 impl Default for Solver {
     fn default() -> Self {
         Self {
             position: Position::default(),
+            options: SolverOptions::default(),
+            eval_params: eval::EvalParams::default(),
+            skill: Skill::default(),
+            engine: Engine::default(),
+            rng: Rng::from_time(),
             nodes: 0,
+            seldepth: 0,
+            extensions: 0,
+            limits: SearchLimits::default(),
+            deadline: None,
+            stopped: false,
             abort: Arc::new(AtomicBool::new(false)),
+            ponder: Arc::new(AtomicBool::new(false)),
+            pondering: false,
             quiet: true,
-            t_table: TranspositionTable::default(),
+            t_table: SolverTable::default(),
+            counter_moves: movegen::CounterMoves::new(),
+            info_handler: None,
+            currmove_handler: None,
+            start: time::Instant::now(),
+            progress_handler: None,
+            progress: None,
+            depth: 0,
+            root_moves: vec![],
+            restricted_root: false,
+            root_ply: 0,
+            root_key: 0,
+            trace: None,
+            skip_null_move: false,
         }
     }
 }
 
 impl Solver {
+    /// The maximal number of extensions in a single line.
+    const MAX_EXTENSIONS: usize = 16;
+    /// The number of moves which are searched with full depth before
+    /// late move reductions are applied.
+    const LMR_MIN_MOVES: usize = 3;
+    /// The minimal depth at which late move reductions are applied.
+    const LMR_MIN_DEPTH: usize = 3;
+    /// How much less deep the search after a null move and its verification
+    /// are, see [`SolverOptions::null_move`].
+    const NULL_MOVE_REDUCTION: usize = 2;
+    /// The minimal depth at which a null move is tried.
+    const NULL_MOVE_MIN_DEPTH: usize = Self::NULL_MOVE_REDUCTION + 1;
+    /// The number of iterations in which the best move has to stay the same
+    /// to stop the search early, see [`SearchLimits::stable_time`].
+    pub const STABLE_ITERATIONS: usize = 3;
+    /// How much better than the other moves the best move has to be
+    /// to be an easy move, see [`SearchLimits::easy_time`].
+    pub const EASY_MOVE_MARGIN: isize = 10;
+    /// How long a search runs before the root moves being searched are reported.
+    pub const CURRMOVE_DELAY: time::Duration = time::Duration::from_secs(1);
+    /// The size of the transposition table in megabytes, if it isn't set.
+    pub const DEFAULT_TT_SIZE: usize = TranspositionTable::DEFAULT_SIZE_MB;
+    /// The largest size of the transposition table in megabytes.
+    pub const MAX_TT_SIZE: usize = TranspositionTable::MAX_SIZE_MB;
+
     pub fn new(abort: Arc<AtomicBool>) -> Self {
         Solver {
             abort,
@@ -37,37 +581,163 @@ impl Solver {
         }
     }
 
+    /// Set the position to search, keeping the house rules of the current position.
+    /// If the board has another size, the transposition table is cleared,
+    /// see [`Self::set_variant`].
+    pub fn set_position(&mut self, mut position: Position) {
+        if position.variant() != self.position.variant() {
+            self.clear_tt();
+        }
+        position.set_rules(self.position.rules());
+        self.position = position;
+    }
+
+    /// Change the house rules of the current position.
+    /// Scores found with the old rules may be wrong, so the transposition
+    /// table is cleared.
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.position.set_rules(rules);
+        self.clear_tt();
+    }
+
+    /// Start a new game on a board of the given size, with the same house
+    /// rules. The keys of the transposition table don't store the size of
+    /// the board, so the table is cleared.
+    pub fn set_variant(&mut self, variant: Variant) {
+        let rules = self.position.rules();
+        self.position = Position::with_variant(variant);
+        self.position.set_rules(rules);
+        self.clear_tt();
+    }
+
+    /// Remove everything from the transposition table, so that the next
+    /// search doesn't use what earlier searches found.
+    pub fn clear_tt(&mut self) {
+        self.t_table.clear();
+    }
+
+    /// Replace the transposition table by an empty one using about
+    /// `size_mb` megabytes, which must be between 1 and [`Self::MAX_TT_SIZE`].
+    /// A shared table is no longer used.
+    pub fn set_tt_size(&mut self, size_mb: usize) {
+        self.t_table = SolverTable::Own(TranspositionTable::with_size_mb(size_mb));
+    }
+
+    /// Use a transposition table shared with other solvers, instead of
+    /// a table of its own. The solvers can search at the same time, and
+    /// use what the others found. Clearing the table, with [`Self::clear_tt`]
+    /// or by searching without [`SolverOptions::keep_tt`], clears it for
+    /// all of them.
+    pub fn share_tt(&mut self, table: Arc<SharedTranspositionTable>) {
+        self.t_table = SolverTable::Shared(table);
+    }
+
+    /// The size of the transposition table in megabytes.
+    pub fn tt_size(&self) -> usize {
+        self.t_table.size_mb()
+    }
+
+    /// Set a search option or an evaluation weight by name.
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<(), InvalidOption> {
+        match self.options.set(name, value) {
+            Err(InvalidOption::UnknownName) => self.eval_params.set(name, value),
+            result => result,
+        }
+    }
+
     pub fn nodes(&self) -> usize {
         self.nodes
     }
 
+    /// Save the transposition table to a file, so that a long search can be
+    /// continued later. Returns the number of positions saved.
+    /// A shared transposition table can't be saved.
+    pub fn save_transposition_table(&self, path: &Path) -> error::Result<usize> {
+        match &self.t_table {
+            SolverTable::Own(table) => table.save(path),
+            SolverTable::Shared(_) => Err(error::Error::SharedTranspositionTable),
+        }
+    }
+
+    /// Replace the transposition table by one saved with
+    /// [`Solver::save_transposition_table`]. The table should be saved
+    /// with the same house rules. Returns the number of positions loaded.
+    /// A shared table is no longer used.
+    pub fn load_transposition_table(&mut self, path: &Path) -> error::Result<usize> {
+        self.t_table = SolverTable::Own(TranspositionTable::load(path)?);
+        Ok(self.t_table.num_entries())
+    }
+
     /// Do an alpha beta negamax search on the current position.
     /// Returns the score of the current position.
-    fn negamax(&mut self, depth: usize, mut alpha: isize, mut beta: isize) -> isize {
+    #[inline(always)]
+    fn negamax(&mut self, depth: usize, alpha: isize, beta: isize) -> isize {
+        let Some(trace) = &mut self.trace else {
+            return self.search_node(depth, alpha, beta);
+        };
+        trace.enter(depth, alpha, beta);
+        let score = self.search_node(depth, alpha, beta);
+        let stopped = self.stopped;
+        if let Some(trace) = &mut self.trace {
+            trace.exit((!stopped).then_some(score));
+        }
+        score
+    }
+
+    /// The search of a single node by [`Solver::negamax`].
+    fn search_node(&mut self, depth: usize, mut alpha: isize, mut beta: isize) -> isize {
+        let null_move_allowed = !std::mem::take(&mut self.skip_null_move);
         // Don't check this every node, but often often enough.
-        if self.nodes % 1024 == 0 && self.abort_search() {
+        if self.stopped || (self.nodes.is_multiple_of(1024) && self.check_limits()) {
             // Have to stop the search now.
             return 0;
         }
 
         self.nodes += 1;
+        self.seldepth = self.seldepth.max(self.position.ply() - self.root_ply);
         if self.position.game_over() {
             return eval::loss_score(self.position.ply() as isize);
         }
         if depth == 0 {
+            if self.extensions < self.options.threat_extensions && self.is_threatened() {
+                // Resolve the threat first, to avoid walking into a forced loss
+                // just after the horizon.
+                self.extensions += 1;
+                let eval = self.negamax(1, alpha, beta);
+                self.extensions -= 1;
+                return eval;
+            }
             // Return a static evaluation of the position.
-            let eval = eval::static_eval(&self.position);
+            let eval = eval::static_eval(&self.position, &self.eval_params);
             return eval;
         }
 
         let initial_alpha = alpha;
         let initial_beta = beta;
 
+        let at_root = self.position.ply() == self.root_ply;
+        // Only some of the moves are searched in the root position,
+        // so its score is not the real score of the position.
+        let restricted_root = at_root && self.restricted_root;
+
         let mut best_move = None;
         if let Some(tt_entry) = self.t_table.get(&self.position) {
             best_move = Some(tt_entry.best_move(&self.position));
             // If we find the entry in a direct way, the score can be used.
-            if tt_entry.ply() >= self.position.ply() {
+            // The root moves are always searched, to find their scores.
+            let usable = tt_entry.ply() >= self.position.ply() && !at_root;
+            if let Some(trace) = &mut self.trace {
+                trace.tt_event(TtEvent::Hit {
+                    entry_type: format!("{:?}", tt_entry.entry_type()),
+                    score: tt_entry.score(self.position.ply() as isize),
+                    best_move: tt_entry
+                        .best_move(&self.position)
+                        .to_player_move(&self.position)
+                        .to_string(),
+                    usable,
+                });
+            }
+            if usable {
                 let score = tt_entry.score(self.position.ply() as isize);
                 // if score.abs() == 968 {
                 //     println!(
@@ -77,88 +747,395 @@ impl Solver {
                 //         tt_entry.entry_type(),
                 //     );
                 // }
-                match tt_entry.entry_type() {
-                    EntryType::Undetermined => (),
-                    EntryType::Exact => {
-                        return score;
-                    }
+                let cutoff = match tt_entry.entry_type() {
+                    EntryType::Undetermined => false,
+                    EntryType::Exact => true,
                     EntryType::UpperBound => {
-                        if beta > score {
-                            beta = score;
-                            if score <= alpha {
-                                return score;
-                            }
-                        }
+                        beta = beta.min(score);
+                        score <= alpha
                     }
                     EntryType::LowerBound => {
-                        if alpha < score {
-                            alpha = score;
-                            if score >= beta {
-                                return score;
-                            }
-                        }
+                        alpha = alpha.max(score);
+                        score >= beta
+                    }
+                };
+                if cutoff {
+                    if let Some(trace) = &mut self.trace {
+                        trace.tt_event(TtEvent::Cutoff { score });
                     }
+                    return score;
                 }
             }
         }
 
         // Set the best score to the minimal value at first.
         // We can only be lost on our turn, so worst case we lose in 2 ply.
-        let mut best_score = eval::loss_score(self.position.ply() as isize + 2);
+        let mut best_score = match restricted_root {
+            // Make sure one of the root moves becomes the best move.
+            true => eval::LOSS,
+            false => eval::loss_score(self.position.ply() as isize + 2),
+        };
         if best_score >= beta {
             return best_score;
         }
+        if self.options.null_move
+            && null_move_allowed
+            && !at_root
+            && depth >= Self::NULL_MOVE_MIN_DEPTH
+        {
+            if let Some(score) = self.null_move_cutoff(depth, beta) {
+                return score;
+            }
+        }
 
         // Look at the child nodes:
-        let moves = movegen::MoveGen::new(&self.position, best_move);
-        for bmove in moves {
+        let counter_move = match self.options.countermoves {
+            true => self.counter_moves.get(&self.position),
+            false => None,
+        };
+        let moves =
+            movegen::MoveGen::new(&self.position, best_move).with_counter_move(counter_move);
+        // Extend the search if there is only one move.
+        let forced_reply = self.options.forced_extensions
+            && self.extensions < Self::MAX_EXTENSIONS
+            && moves.clone().take(2).count() == 1;
+        if at_root {
+            // The move from the table might not be one of the root moves.
+            best_move = None;
+        }
+        // The root moves are searched in the order of the last iteration.
+        let mut moves = (!at_root).then_some(moves);
+        let mut move_i = 0;
+        // The symmetric root moves are already removed.
+        let skip_symmetric = !at_root && self.position.ply() < self.options.symmetry_plies;
+        let mut symmetric_keys = [0; movegen::MoveList::CAPACITY];
+        let mut num_symmetric_keys = 0;
+        loop {
+            let bmove = match &mut moves {
+                Some(moves) => moves.next(),
+                None => self.root_moves.get(move_i).map(|root_move| root_move.bmove),
+            };
+            let Some(bmove) = bmove else {
+                break;
+            };
+            if at_root {
+                self.report_current_move(move_i);
+            }
             // Enable for testing purposes.
             // self.position
             //     .try_make_move(bmove.to_player_move(&self.position))
             //     .unwrap();
+            if let Some(trace) = &mut self.trace {
+                trace.play(bmove.to_player_move(&self.position));
+            }
             self.position.make_move(bmove);
-            let next_depth = if matches!(bmove, crate::position::BitboardMove::SecondBest) {
+            let is_second_best = matches!(bmove, crate::position::BitboardMove::SecondBest);
+            if skip_symmetric && !is_second_best {
+                let key = self.position.symmetric_key();
+                if symmetric_keys[..num_symmetric_keys].contains(&key) {
+                    // The same as one of the moves before.
+                    self.position.unmake_move();
+                    if let Some(trace) = &mut self.trace {
+                        trace.unplay();
+                    }
+                    continue;
+                }
+                symmetric_keys[num_symmetric_keys] = key;
+                num_symmetric_keys += 1;
+            }
+            let (next_depth, extended) = if is_second_best {
                 //  Search lines where we "Second Best!" a little longer.
-                depth
+                (depth, false)
+            } else if forced_reply {
+                (depth, true)
+            } else {
+                (depth - 1, false)
+            };
+            // Late moves are unlikely to be good, so search them less deep first.
+            let reduction = if self.options.lmr
+                && !is_second_best
+                && move_i >= Self::LMR_MIN_MOVES
+                && depth >= Self::LMR_MIN_DEPTH
+            {
+                1
             } else {
-                depth - 1
+                0
             };
-            let eval = -self.negamax(next_depth, -beta, -alpha);
+            if extended {
+                self.extensions += 1;
+            }
+            let nodes = self.nodes;
+            let eval = self.search_child(next_depth, reduction, move_i == 0, alpha, beta);
+            if extended {
+                self.extensions -= 1;
+            }
             // if eval.abs() == 968 {
             //     println!("Here at ply {}", self.position.ply());
             // }
             self.position.unmake_move();
+            if let Some(trace) = &mut self.trace {
+                trace.unplay();
+            }
+            if at_root {
+                let root_move = &mut self.root_moves[move_i];
+                root_move.nodes += self.nodes - nodes;
+                if eval > best_score {
+                    root_move.score = Some(eval);
+                } else {
+                    // The searches of the move with MTD(f) all give upper bounds.
+                    let bound = root_move.upper_bound.map_or(eval, |bound| bound.min(eval));
+                    root_move.upper_bound = Some(bound);
+                }
+            }
             if eval > best_score {
                 best_move = Some(bmove);
                 best_score = eval;
                 if best_score > alpha {
                     alpha = best_score;
                     if alpha >= beta {
+                        if matches!(bmove, BitboardMove::StoneMove(_)) {
+                            self.counter_moves.store(&self.position, bmove);
+                        }
                         break;
                     }
                 }
             }
+            move_i += 1;
         }
-        if let Some(best_move) = best_move {
-            let entry_type = match eval::decode_eval(best_score, self.position.ply() as isize) {
-                eval::ExplainableEval::Undetermined(_) => EntryType::Undetermined,
-                eval::ExplainableEval::Win(_) | eval::ExplainableEval::Loss(_) => match () {
-                    _ if best_score >= initial_beta => EntryType::LowerBound,
-                    _ if best_score <= initial_alpha => EntryType::UpperBound,
-                    _ => EntryType::Exact,
-                },
+        // With restricted root moves, the result is not stored in
+        // the table, since the other moves weren't searched.
+        if let Some(best_move) = best_move.filter(|_| !restricted_root) {
+            let bound = match () {
+                _ if best_score >= initial_beta => EntryType::LowerBound,
+                _ if best_score <= initial_alpha => EntryType::UpperBound,
+                _ => EntryType::Exact,
+            };
+            let entry_type = if eval::is_solved(best_score, self.position.ply() as isize, depth) {
+                bound
+            } else if eval::is_win(best_score) {
+                // There might be a faster win, so the score is only a lower bound.
+                match bound {
+                    EntryType::UpperBound => EntryType::Undetermined,
+                    _ => EntryType::LowerBound,
+                }
+            } else if eval::is_loss(best_score) {
+                // There might be a faster loss, so the score is only an upper bound.
+                match bound {
+                    EntryType::LowerBound => EntryType::Undetermined,
+                    _ => EntryType::UpperBound,
+                }
+            } else {
+                EntryType::Undetermined
             };
+            if let Some(trace) = &mut self.trace {
+                trace.tt_event(TtEvent::Store {
+                    entry_type: format!("{entry_type:?}"),
+                    score: best_score,
+                    best_move: best_move.to_player_move(&self.position).to_string(),
+                });
+            }
             self.t_table
                 .store(&self.position, best_score, best_move, entry_type);
         }
         best_score
     }
 
+    /// Search every move in the current position separately with iterative
+    /// deepening, until all of them are solved or one of the limits is reached.
+    /// Returns the moves with their scores from our perspective of the last
+    /// completed iteration, sorted from best to worst.
+    pub fn root_move_scores(&mut self, limits: SearchLimits) -> Vec<(PlayerMove, isize)> {
+        self.initialize_for_search();
+        let start = time::Instant::now();
+        self.limits = limits;
+        self.deadline = limits.max_time.map(|max_time| start + max_time);
+        let moves: Vec<BitboardMove> = movegen::MoveGen::new(&self.position, None).collect();
+        let mut scores = vec![];
+        for depth in 1..=limits.max_depth {
+            let mut new_scores = Vec::with_capacity(moves.len());
+            for &bmove in &moves {
+                let pmove = bmove.to_player_move(&self.position);
+                self.position.make_move(bmove);
+                let next_depth = match bmove {
+                    BitboardMove::SecondBest => depth,
+                    BitboardMove::StoneMove(_) => depth - 1,
+                };
+                let score = -self.negamax(next_depth, eval::LOSS, eval::WIN);
+                self.position.unmake_move();
+                if self.stopped {
+                    return scores;
+                }
+                new_scores.push((pmove, score));
+            }
+            // The sort is stable, so moves which are generated first are preferred.
+            new_scores.sort_by_key(|&(_, score)| -score);
+            scores = new_scores;
+            let ply = self.position.ply() as isize;
+            let all_solved = scores
+                .iter()
+                .all(|&(_, score)| eval::is_solved(score, ply, depth));
+            if all_solved {
+                break;
+            }
+            if matches!(limits.soft_time, Some(soft_time) if start.elapsed() >= soft_time) {
+                break;
+            }
+        }
+        scores
+    }
+
+    /// Search the current position, and choose a move to play according to
+    /// the engine and skill level of the solver. Returns `None` if the game is over.
+    pub fn choose_move(&mut self, limits: SearchLimits) -> Option<PlayerMove> {
+        if self.position.game_over() {
+            return None;
+        }
+        if self.engine == Engine::Mcts {
+            let seed = self.rng.next_u64();
+            let mut mcts = Mcts::new(self.abort.clone(), self.eval_params, seed);
+            let best_move = mcts.choose_move(&self.position, limits);
+            self.nodes = mcts.playouts();
+            return best_move;
+        }
+        if self.skill.is_full_strength() {
+            self.search_with_limits(limits);
+            return self.root_moves.first().map(|root_move| root_move.pmove);
+        }
+        let limits = SearchLimits {
+            max_depth: limits.max_depth.min(self.skill.max_depth()),
+            ..limits
+        };
+        let scores = self.root_move_scores(limits);
+        if scores.is_empty() {
+            // Not even the first iteration was completed.
+            return movegen::MoveGen::new(&self.position, None)
+                .next()
+                .map(|bmove| bmove.to_player_move(&self.position));
+        }
+        let ply = self.position.ply() as isize;
+        let noise = self.skill.noise();
+        scores
+            .into_iter()
+            .map(|(pmove, score)| match eval::decode_eval(score, ply) {
+                // Don't make wins and losses look better or worse.
+                eval::ExplainableEval::Undetermined(_) => {
+                    (pmove, score + self.rng.below(noise + 1) as isize)
+                }
+                _ => (pmove, score),
+            })
+            .rev()
+            .max_by_key(|&(_, score)| score)
+            .map(|(pmove, _)| pmove)
+    }
+
+    /// Search the position after one of the child moves, and return its score
+    /// from our perspective. The search is first done at a reduced depth
+    /// if `reduction` is non-zero, and with a null window when using pvs.
+    /// If these fail high, the move is searched again with the full depth
+    /// and window.
+    fn search_child(
+        &mut self,
+        depth: usize,
+        reduction: usize,
+        first_move: bool,
+        alpha: isize,
+        beta: isize,
+    ) -> isize {
+        if reduction > 0 {
+            let eval = -self.negamax(depth - reduction, -alpha - 1, -alpha);
+            // Only a proven loss can be trusted, because the reduced search
+            // might not be deep enough to find a win.
+            if eval <= alpha && eval < eval::IS_LOSS {
+                return eval;
+            }
+        }
+        if first_move || !self.options.pvs {
+            return -self.negamax(depth, -beta, -alpha);
+        }
+        // Try to prove that this move is not better than the best move so far.
+        let eval = -self.negamax(depth, -alpha - 1, -alpha);
+        if eval > alpha && eval < beta {
+            // The move might be better after all, so do a full search.
+            -self.negamax(depth, -beta, -alpha)
+        } else {
+            eval
+        }
+    }
+
+    /// Null move pruning, see [`SolverOptions::null_move`]. Returns the score
+    /// to cut off the node with, if both the search after passing and the
+    /// verification search fail high.
+    fn null_move_cutoff(&mut self, depth: usize, beta: isize) -> Option<isize> {
+        let pos = &self.position;
+        // Passing is only safe to try when nothing is about to happen,
+        // and it can't give a proven score.
+        if !pos.is_second_phase()
+            || pos.banned_move().is_some()
+            || pos.has_alignment(true)
+            || pos.has_alignment(false)
+            || eval::is_win(beta)
+            || eval::is_loss(beta)
+            || self.is_threatened()
+        {
+            return None;
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.play_null();
+        }
+        self.position.make_null_move();
+        self.skip_null_move = true;
+        let reduced_depth = depth - 1 - Self::NULL_MOVE_REDUCTION;
+        let eval = -self.negamax(reduced_depth, -beta, -beta + 1);
+        self.position.unmake_null_move();
+        if let Some(trace) = &mut self.trace {
+            trace.unplay();
+        }
+        if eval < beta || self.stopped {
+            return None;
+        }
+        // Passing can be better than any of our moves, so one of them
+        // has to be good enough as well.
+        self.skip_null_move = true;
+        let verified = self.negamax(depth - Self::NULL_MOVE_REDUCTION, beta - 1, beta);
+        (verified >= beta && !self.stopped).then_some(verified)
+    }
+
+    /// Returns whether one of the players is one move away from an alignment.
+    fn is_threatened(&self) -> bool {
+        let pos = &self.position;
+        pos.vertical_threats(true) != 0
+            || pos.vertical_threats(false) != 0
+            || pos.horizontal_threats(true) != 0
+            || pos.horizontal_threats(false) != 0
+    }
+
     /// Returns whether the search is being aborted.
     pub fn abort_search(&self) -> bool {
         self.abort.load(Ordering::Relaxed)
     }
 
+    /// The flag which aborts the search when it is set.
+    pub fn abort_flag(&self) -> Arc<AtomicBool> {
+        self.abort.clone()
+    }
+
+    /// The flag which is set while the search is pondering: searching the
+    /// position after the reply it expects, on the time of the opponent.
+    /// The time limits of a search only start once the flag is cleared,
+    /// when the opponent played the expected reply (a "ponderhit").
+    pub fn ponder_flag(&self) -> Arc<AtomicBool> {
+        self.ponder.clone()
+    }
+
+    /// Start the time limits of the search if it stopped pondering.
+    fn check_ponderhit(&mut self) {
+        if self.pondering && !self.ponder.load(Ordering::Relaxed) {
+            self.pondering = false;
+            self.start = time::Instant::now();
+            self.deadline = self.limits.max_time.map(|max_time| self.start + max_time);
+        }
+    }
+
     pub fn be_quiet(&mut self) {
         self.quiet = true
     }
@@ -167,53 +1144,988 @@ impl Solver {
         self.quiet = false
     }
 
+    /// Set a function which gets called with the results of every completed
+    /// iteration of the iterative deepening loop.
+    /// The handler is called even if the solver is quiet.
+    pub fn set_info_handler(&mut self, handler: impl Fn(SearchInfo) + Send + 'static) {
+        self.info_handler = Some(Box::new(handler));
+    }
+
+    pub fn clear_info_handler(&mut self) {
+        self.info_handler = None;
+    }
+
+    /// Set a function which gets called when a root move is started, once
+    /// the search takes longer than [`Solver::CURRMOVE_DELAY`]. This shows
+    /// how far along the iteration is. The handler is called even if the
+    /// solver is quiet.
+    pub fn set_currmove_handler(&mut self, handler: impl Fn(CurrMoveInfo) + Send + 'static) {
+        self.currmove_handler = Some(Box::new(handler));
+    }
+
+    /// Report the root move with the given index, which is about to be
+    /// searched, if the search is already running for a while.
+    fn report_current_move(&self, move_i: usize) {
+        if (self.quiet && self.currmove_handler.is_none())
+            || self.start.elapsed() < Self::CURRMOVE_DELAY
+        {
+            return;
+        }
+        let info = CurrMoveInfo {
+            depth: self.depth,
+            currmove: self.root_moves[move_i].pmove,
+            currmovenumber: move_i + 1,
+            total: self.root_moves.len(),
+            nodes: self.nodes,
+        };
+        if !self.quiet {
+            println!("{info}");
+        }
+        if let Some(handler) = &self.currmove_handler {
+            handler(info);
+        }
+    }
+
+    /// Set a function which gets called about every second during a
+    /// search, with the depth being searched and the nodes so far.
+    pub fn set_progress_handler(&mut self, handler: ProgressHandler) {
+        self.progress_handler = Some(handler);
+    }
+
+    /// The principal variation stored in the transposition table,
+    /// starting from the current position.
+    pub fn principal_variation(&mut self) -> Vec<PlayerMove> {
+        let mut pv = vec![];
+        let mut keys = vec![];
+        // The best root move is not always stored in the transposition
+        // table, e.g. after a search of only some of the moves.
+        let is_root = TranspositionTable::key(&self.position) == self.root_key;
+        let root_move = match is_root {
+            true => self.root_moves.first().map(|root_move| root_move.bmove),
+            false => None,
+        };
+        if let Some(bmove) = root_move {
+            pv.push(bmove.to_player_move(&self.position));
+            self.position.make_move(bmove);
+        }
+        while let Some(tt_entry) = self.t_table.get(&self.position) {
+            let key = TranspositionTable::key(&self.position);
+            if keys.contains(&key) {
+                // Prevent from being stuck in a loop.
+                break;
+            }
+            keys.push(key);
+            pv.push(tt_entry.best_move_for_printing());
+            self.position.make_move(tt_entry.best_move(&self.position));
+        }
+        // Set position back to original state.
+        for _ in 0..keys.len() + usize::from(root_move.is_some()) {
+            self.position.unmake_move();
+        }
+        pv
+    }
+
+    /// The reply to `pmove` which the last search expects, to ponder on.
+    /// Only known if `pmove` starts the principal variation.
+    pub fn expected_reply(&mut self, pmove: PlayerMove) -> Option<PlayerMove> {
+        match self.principal_variation()[..] {
+            [first, reply, ..] if first == pmove => Some(reply),
+            _ => None,
+        }
+    }
+
+    /// The root moves of the last search, with the best line after each
+    /// of them. The lines of the moves which are only known to be worse
+    /// than the best move can be short or wrong. Empty if the position
+    /// changed since the last search.
+    pub fn root_move_lines(&mut self) -> Vec<RootMoveLine> {
+        if TranspositionTable::key(&self.position) != self.root_key {
+            return vec![];
+        }
+        let root_moves = self.root_moves.clone();
+        root_moves
+            .into_iter()
+            .enumerate()
+            .map(|(i, root_move)| {
+                let pv = match i {
+                    0 => self.principal_variation(),
+                    _ => {
+                        self.position.make_move(root_move.bmove);
+                        let mut pv = self.principal_variation();
+                        self.position.unmake_move();
+                        pv.insert(0, root_move.pmove);
+                        pv
+                    }
+                };
+                RootMoveLine {
+                    pmove: root_move.pmove,
+                    score: root_move.score,
+                    pv,
+                }
+            })
+            .collect()
+    }
+
+    /// Check if the search should be stopped, either because it
+    /// was aborted, or because one of the hard limits was reached.
+    /// This is also when the progress of the search is reported.
+    fn check_limits(&mut self) -> bool {
+        self.check_ponderhit();
+        let out_of_nodes =
+            matches!(self.limits.max_nodes, Some(max_nodes) if self.nodes >= max_nodes);
+        let out_of_time =
+            matches!(self.deadline, Some(deadline) if time::Instant::now() >= deadline);
+        self.stopped = self.abort_search() || out_of_nodes || out_of_time;
+        if let Some(progress) = &mut self.progress {
+            progress.update(self.depth, self.nodes);
+        }
+        self.stopped
+    }
+
     fn initialize_for_search(&mut self) {
+        if !self.options.keep_tt {
+            self.clear_tt();
+        }
+        self.root_ply = self.position.ply();
+        self.root_key = TranspositionTable::key(&self.position);
+        let pv_move = self
+            .t_table
+            .get(&self.position)
+            .map(|tt_entry| tt_entry.best_move(&self.position));
+        self.root_moves = match self.position.game_over() {
+            true => vec![],
+            false => movegen::MoveGen::new(&self.position, pv_move)
+                .map(|bmove| RootMove::new(bmove, &self.position))
+                .collect(),
+        };
+        self.restricted_root = false;
+        self.counter_moves.clear();
         self.nodes = 0;
+        self.seldepth = 0;
+        self.extensions = 0;
+        self.stopped = false;
+        self.pondering = false;
+    }
+
+    /// Set up a search of the current position, see [`SearchBuilder`].
+    pub fn searcher(&mut self) -> SearchBuilder<'_> {
+        SearchBuilder {
+            solver: self,
+            limits: SearchLimits::default(),
+            multipv: 1,
+        }
     }
 
+    /// Search the current position with iterative deepening up to the given depth.
     pub fn search(&mut self, depth: usize) -> isize {
+        self.search_with_limits(SearchLimits::depth(depth))
+    }
+
+    /// Search the current position with iterative deepening until
+    /// the position is solved, or one of the limits is reached.
+    /// Returns the score of the last completed iteration.
+    pub fn search_with_limits(&mut self, limits: SearchLimits) -> isize {
         self.initialize_for_search();
-        let mut eval = 0;
+        self.iterative_deepening(limits)
+    }
+
+    /// The iterative deepening loop of [`Solver::search_with_limits`].
+    fn iterative_deepening(&mut self, limits: SearchLimits) -> isize {
+        self.remove_symmetric_root_moves();
         let start = time::Instant::now();
-        for depth in 1..=depth {
-            let new_eval = self.negamax(depth, eval::LOSS, eval::WIN);
-            if self.abort_search() {
+        self.start = start;
+        self.limits = limits;
+        self.deadline = limits.max_time.map(|max_time| start + max_time);
+        self.pondering = self.ponder.load(Ordering::Relaxed);
+        if self.pondering {
+            self.deadline = None;
+        }
+        self.progress = self
+            .progress_handler
+            .clone()
+            .map(|handler| ProgressReporter::new("search depth", None, handler));
+        let mut eval = 0;
+        let mut best_move = None;
+        let mut stable_iterations = 0;
+        for depth in 1..=limits.max_depth {
+            self.depth = depth;
+            let new_eval = self.search_root(depth, eval);
+            if self.stopped {
                 return eval;
             }
             eval = new_eval;
-            if !self.quiet {
+            let new_best_move = self.root_moves.first().map(|root_move| root_move.pmove);
+            match new_best_move == best_move {
+                true => stable_iterations += 1,
+                false => stable_iterations = 0,
+            }
+            best_move = new_best_move;
+            if !self.quiet || self.info_handler.is_some() {
                 let elapsed = start.elapsed();
-                let nodes = self.nodes;
-                let knps = self.nodes as u128 / (1 + elapsed.as_millis());
-                println!(
-                    "info depth {depth} score {eval} nodes {nodes} knps {knps} ({:?} total time)",
-                    elapsed
-                );
-                print!("pv");
-                let mut keys = vec![];
-                while let Some(tt_entry) = self.t_table.get(&self.position) {
-                    let key = TranspositionTable::key(&self.position);
-                    if keys.contains(&key) {
-                        // Prevent from being stuck in a loop.
-                        break;
-                    }
-                    keys.push(key);
-                    print!(" {}", tt_entry.best_move_for_printing());
-                    self.position.make_move(tt_entry.best_move(&self.position));
+                let info = SearchInfo {
+                    depth,
+                    seldepth: self.seldepth,
+                    score: eval,
+                    nodes: self.nodes,
+                    knps: self.nodes as u128 / (1 + elapsed.as_millis()),
+                    time: elapsed,
+                    ttfull: self.t_table.permille_full(),
+                    pv: self.principal_variation(),
+                    root_moves: self.root_move_lines(),
+                };
+                if !self.quiet {
+                    println!("{info}");
                 }
-                // Set position back to original state.
-                for _ in 0..keys.len() {
-                    self.position.unmake_move();
+                if let Some(handler) = &self.info_handler {
+                    handler(info);
                 }
-                println!();
             }
-            match eval::decode_eval(eval, self.position.ply() as isize) {
-                eval::ExplainableEval::Win(_) | eval::ExplainableEval::Loss(_) => {
+            // Search deeper until the fastest win or slowest loss is found.
+            if eval::is_solved(eval, self.position.ply() as isize, depth) {
+                break;
+            }
+            self.check_ponderhit();
+            if self.pondering {
+                continue;
+            }
+            // The time limits start after pondering.
+            let elapsed = self.start.elapsed();
+            let time_up =
+                |time: Option<time::Duration>| matches!(time, Some(time) if elapsed >= time);
+            // A change of the best move after the first iterations needs more time.
+            let soft_time = match stable_iterations == 0 && depth > Self::STABLE_ITERATIONS {
+                true => limits.unstable_time.or(limits.soft_time),
+                false => limits.soft_time,
+            };
+            if time_up(soft_time) {
+                // Not enough time left to complete another iteration.
+                break;
+            }
+            if stable_iterations >= Self::STABLE_ITERATIONS {
+                let easy_move = self
+                    .best_move_margin()
+                    .is_some_and(|margin| margin >= Self::EASY_MOVE_MARGIN);
+                // Searching deeper is unlikely to change the move.
+                if time_up(limits.stable_time) || (easy_move && time_up(limits.easy_time)) {
                     break;
                 }
-                _ => continue,
             }
         }
         eval
     }
+
+    /// Like [`Solver::search_with_limits`], but only search the given moves in
+    /// the current position, or all of them if `moves` is empty. The replies
+    /// are all searched. Returns the score of the best of the given moves, or
+    /// why one of them is illegal. Until the next search, the principal
+    /// variation starts with the best of the given moves.
+    pub fn search_moves_with_limits(
+        &mut self,
+        moves: &[PlayerMove],
+        limits: SearchLimits,
+    ) -> Result<isize, MoveFailed> {
+        for &pmove in moves {
+            self.position.is_legal(pmove)?;
+        }
+        self.initialize_for_search();
+        if !moves.is_empty() {
+            self.root_moves
+                .retain(|root_move| moves.contains(&root_move.pmove));
+            self.restricted_root = true;
+        }
+        Ok(self.iterative_deepening(limits))
+    }
+
+    /// The `n` best moves in the current position, with their scores from our
+    /// perspective, sorted from best to worst. Every move is found with a
+    /// search of the moves which were not found yet, so this does `n`
+    /// searches with the given limits. Returns fewer moves if there are
+    /// fewer legal moves, or if the search is aborted.
+    pub fn top_moves(&mut self, n: usize, limits: SearchLimits) -> Vec<(PlayerMove, isize)> {
+        self.top_lines(n, limits)
+            .lines
+            .into_iter()
+            // The first line is always searched, as it gives the score.
+            .take(n)
+            .filter_map(|line| Some((line.pmove, line.score?)))
+            .collect()
+    }
+
+    /// The `n` best lines in the current position, see [`Solver::top_moves`].
+    /// The first search is of all the moves, and gives the score of the position.
+    fn top_lines(&mut self, n: usize, limits: SearchLimits) -> SearchResult {
+        let start = time::Instant::now();
+        let mut remaining = match self.position.game_over() {
+            true => vec![],
+            false => self.position.legal_moves(),
+        };
+        let score = self.search_with_limits(limits);
+        let depth = self.depth - self.stopped as usize;
+        let mut nodes = self.nodes();
+        let mut line_score = score;
+        let mut lines = vec![];
+        loop {
+            let pv = self.principal_variation();
+            let Some(&pmove) = pv.first() else {
+                break;
+            };
+            lines.push(RootMoveLine {
+                pmove,
+                score: Some(line_score),
+                pv,
+            });
+            remaining.retain(|&other| other != pmove);
+            if lines.len() >= n || remaining.is_empty() || self.abort_search() {
+                break;
+            }
+            // The moves are legal, so the search can't fail.
+            line_score = self.search_moves_with_limits(&remaining, limits).unwrap();
+            nodes += self.nodes();
+            if self.abort_search() {
+                break;
+            }
+        }
+        // The searches can be inconsistent when they are cut off by the limits.
+        // The sort is stable, so moves which are found first are preferred.
+        lines.sort_by_key(|line| line.score.map(|score| -score));
+        SearchResult {
+            score,
+            depth,
+            nodes,
+            time: start.elapsed(),
+            lines,
+        }
+    }
+
+    /// Do a single iteration of the iterative deepening loop, stopping after
+    /// approximately `max_nodes` nodes. Returns `None` if the iteration was
+    /// not completed.
+    ///
+    /// This is for searching where the search can't run on its own thread,
+    /// like in the browser: call this for increasing depths, and do other
+    /// work in between. If an iteration is interrupted it has to be started
+    /// again, but the moves and results found are kept in the transposition
+    /// table, so the next attempt is faster.
+    pub fn search_iteration(&mut self, depth: usize, max_nodes: usize) -> Option<isize> {
+        self.initialize_for_search();
+        self.limits = SearchLimits {
+            max_depth: depth,
+            max_nodes: Some(max_nodes),
+            ..Default::default()
+        };
+        self.deadline = None;
+        self.progress = None;
+        self.depth = depth;
+        self.start = time::Instant::now();
+        self.remove_symmetric_root_moves();
+        let eval = self.search_root(depth, 0);
+        (!self.stopped).then_some(eval)
+    }
+
+    /// Search the root position to the given depth with [`Solver::search_depth`],
+    /// and sort the root moves by their new scores. The moves which are only
+    /// known to be worse than the best move keep their order. If the search
+    /// is stopped, the root moves keep the results of the previous iteration.
+    fn search_root(&mut self, depth: usize, guess: isize) -> isize {
+        let previous = self.root_moves.clone();
+        for root_move in &mut self.root_moves {
+            root_move.previous_score = root_move.score;
+            root_move.score = None;
+            root_move.upper_bound = None;
+            root_move.nodes = 0;
+        }
+        let eval = self.search_depth(depth, guess);
+        if self.stopped {
+            self.root_moves = previous;
+        } else {
+            // The sort is stable, and moves without a score come last.
+            self.root_moves
+                .sort_by_key(|root_move| std::cmp::Reverse(root_move.score));
+        }
+        eval
+    }
+
+    /// Search the current position with iterative deepening up to the given
+    /// depth, and record the tree searched in the last iteration, up to
+    /// `max_nodes` nodes. The earlier iterations fill the table and order
+    /// the moves as in a normal search, but aren't recorded.
+    pub fn trace_search(&mut self, depth: usize, max_nodes: usize) -> SearchTrace {
+        let depth = depth.max(1);
+        self.initialize_for_search();
+        let eval = self.iterative_deepening(SearchLimits::depth(depth - 1));
+        self.limits = SearchLimits::depth(depth);
+        self.deadline = None;
+        self.depth = depth;
+        self.trace = Some(SearchTrace::new(&self.position, depth, max_nodes));
+        self.search_root(depth, eval);
+        self.trace.take().unwrap()
+    }
+
+    /// Keep only one of the root moves which lead to positions that are
+    /// rotations or mirror images of each other, see [`SolverOptions::symmetry_plies`].
+    /// The moves given to [`Solver::search_moves_with_limits`] are all kept.
+    fn remove_symmetric_root_moves(&mut self) {
+        if self.restricted_root || self.position.ply() >= self.options.symmetry_plies {
+            return;
+        }
+        let mut keys = vec![];
+        for root_move in std::mem::take(&mut self.root_moves) {
+            if let BitboardMove::StoneMove(_) = root_move.bmove {
+                self.position.make_move(root_move.bmove);
+                let key = self.position.symmetric_key();
+                self.position.unmake_move();
+                if keys.contains(&key) {
+                    continue;
+                }
+                keys.push(key);
+            }
+            self.root_moves.push(root_move);
+        }
+    }
+
+    /// The moves in the root position of the last search, sorted from best to
+    /// worst by the results of the last completed iteration. The first move is
+    /// the best move, also if only some of the moves were searched. Moves
+    /// which are symmetric to one of the other moves are left out.
+    pub fn root_moves(&self) -> &[RootMove] {
+        &self.root_moves
+    }
+
+    /// How much better the best root move is than the other moves in the last
+    /// completed iteration, according to the upper bounds of the other moves.
+    /// `None` if the score of the best move or the bound of another move is
+    /// missing.
+    pub fn best_move_margin(&self) -> Option<isize> {
+        let (best_move, others) = self.root_moves.split_first()?;
+        let best_score = best_move.score?;
+        let mut margin = isize::MAX;
+        for root_move in others {
+            margin = margin.min(best_score - root_move.score.or(root_move.upper_bound)?);
+        }
+        Some(margin)
+    }
+
+    /// Search the current position to the given depth, using the search
+    /// driver of the options. The guess is the expected score, e.g. the
+    /// score of the previous iteration.
+    fn search_depth(&mut self, depth: usize, guess: isize) -> isize {
+        match self.options.driver {
+            SearchDriver::AlphaBeta => self.negamax(depth, eval::LOSS, eval::WIN),
+            SearchDriver::Mtdf => self.mtdf(depth, guess),
+        }
+    }
+
+    /// Find the score of the current position with MTD(f). Every null window
+    /// search gives either a new lower or a new upper bound on the score,
+    /// and the bounds of the positions searched are kept in the
+    /// transposition table, so the later searches are fast.
+    fn mtdf(&mut self, depth: usize, guess: isize) -> isize {
+        let mut lower = eval::LOSS;
+        let mut upper = eval::WIN;
+        let mut eval = guess.clamp(lower + 1, upper);
+        while lower < upper {
+            // The window is always between the bounds, so every
+            // search moves one of them.
+            let beta = if eval == lower { eval + 1 } else { eval };
+            eval = self.negamax(depth, beta - 1, beta);
+            if self.stopped {
+                return eval;
+            }
+            if eval < beta {
+                upper = eval;
+            } else {
+                lower = eval;
+            }
+        }
+        eval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_limits() {
+        let mut solver = Solver::default();
+        let limits = SearchLimits {
+            max_nodes: Some(5000),
+            ..Default::default()
+        };
+        solver.search_with_limits(limits);
+        // The limit is only checked every 1024 nodes.
+        assert!(solver.nodes() <= 5000 + 1024);
+
+        let limits = SearchLimits {
+            max_time: Some(time::Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let start = time::Instant::now();
+        solver.search_with_limits(limits);
+        assert!(start.elapsed() < time::Duration::from_secs(1));
+
+        let limits = SearchLimits {
+            soft_time: Some(time::Duration::ZERO),
+            ..Default::default()
+        };
+        solver.search_with_limits(limits);
+        // Only the first iteration is completed.
+        let nodes = solver.nodes();
+        solver.search(1);
+        assert_eq!(nodes, solver.nodes());
+
+        let infos = Arc::new(std::sync::Mutex::new(vec![]));
+        let handler_infos = infos.clone();
+        solver.set_info_handler(move |info| handler_infos.lock().unwrap().push(info));
+        solver.set_tt_size(1);
+        let limits = SearchLimits {
+            stable_time: Some(time::Duration::ZERO),
+            ..SearchLimits::depth(20)
+        };
+        solver.search_with_limits(limits);
+        // The search stops once the best move stays the same for long enough.
+        let last_depth = infos.lock().unwrap().last().unwrap().depth;
+        assert!(last_depth > Solver::STABLE_ITERATIONS && last_depth < 20);
+
+        solver.search(8);
+        let infos = infos.lock().unwrap();
+        for info in infos.iter() {
+            assert!(info.seldepth >= info.depth);
+        }
+        assert!(infos.last().unwrap().ttfull > 0);
+    }
+
+    #[test]
+    fn fastest_win() {
+        use eval::ExplainableEval::{Loss, Win};
+        // Positions with a forced win in the given number of moves, which are
+        // only found by searching deeper than the first win which is found.
+        for (moves, moves_to_win) in [
+            // The move "Second Best!" is called on is the only move left.
+            ("6 3 7 7 7 1 2 3 3 2 2 0 5 5 0 1", 3),
+            ("2 6 5 5 1 2 5 7 1 4 0 6 4 4 0", 5),
+            ("5 6 6 3 7 6 4 1 2 2 3 3 2 7 5", 5),
+            ("5 2 0 2 4 6 6 6 4 7 0 0 7 7 1 3 1-2 6-5 4-3 0-4", 7),
+        ] {
+            let mut solver = Solver::default();
+            solver
+                .position
+                .parse_and_play_moves(moves.split_whitespace().map(String::from).collect())
+                .unwrap();
+            let ply = solver.position.ply() as isize;
+            let score = solver.search(12);
+            assert_eq!(eval::decode_eval(score, ply), Win(moves_to_win), "{moves}");
+            assert!(eval::is_solved(score, ply, moves_to_win as usize));
+            assert_eq!(solver.principal_variation().len(), moves_to_win as usize);
+            // The opponent loses one move later, also with the
+            // results of the first search in the table.
+            let best_move = solver.principal_variation()[0];
+            solver.position.try_make_move(best_move).unwrap();
+            let score = solver.search(12);
+            assert_eq!(
+                eval::decode_eval(score, ply + 1),
+                Loss(moves_to_win - 1),
+                "{moves}"
+            );
+        }
+    }
+
+    #[test]
+    fn search_in_iterations() {
+        let position = Position::from_fen("O/X/XX/O/XO/././O X 3 -").unwrap();
+        let mut solver = Solver {
+            position: position.clone(),
+            ..Default::default()
+        };
+        let expected = solver.search(6);
+
+        let mut solver = Solver {
+            position,
+            ..Default::default()
+        };
+        assert_eq!(solver.search_iteration(6, 10), None);
+        let mut eval = 0;
+        for depth in 1..=6 {
+            eval = loop {
+                if let Some(eval) = solver.search_iteration(depth, 2000) {
+                    break eval;
+                }
+            };
+        }
+        assert_eq!(eval, expected);
+    }
+
+    #[test]
+    fn keep_tt() {
+        let mut solver = Solver {
+            position: Position::from_fen("O/X/XX/O/XO/././O X 3 -").unwrap(),
+            ..Default::default()
+        };
+        let eval = solver.search(6);
+        let nodes = solver.nodes();
+        // The second search finds the positions of the first in the table.
+        assert_eq!(solver.search(6), eval);
+        assert!(solver.nodes() < nodes);
+
+        solver.set_option("keep-tt", "false").unwrap();
+        assert_eq!(solver.search(6), eval);
+        assert_eq!(solver.nodes(), nodes);
+        solver.set_option("keep-tt", "true").unwrap();
+        solver.clear_tt();
+        assert_eq!(solver.search(6), eval);
+        assert_eq!(solver.nodes(), nodes);
+    }
+
+    #[test]
+    fn mtdf_same_score() {
+        // Positions from the benchmark, which are solved in 4 to 7 moves.
+        for moves in [
+            "2 2 0 0 0 1 4 2 6 5 4 7 5 5 1 6 0-7 0-7 0-1 5-4 1-0",
+            "1 1 3 6 3 7 5 1 2 3 5",
+            "0 0 6 2 6 4 7 2 2 4 4",
+        ] {
+            let mut position = Position::default();
+            let moves = moves.split_whitespace().map(|s| s.to_string()).collect();
+            position.parse_and_play_moves(moves).unwrap();
+            let mut solver = Solver {
+                position: position.clone(),
+                ..Default::default()
+            };
+            let expected = solver.search(20);
+
+            let mut solver = Solver {
+                position,
+                ..Default::default()
+            };
+            solver.options.driver = SearchDriver::Mtdf;
+            assert_eq!(solver.search(20), expected);
+        }
+    }
+
+    #[test]
+    fn search_moves() {
+        let mut position = Position::default();
+        let moves = "1 1 3 6 3 7 5 1 2 6".split_whitespace();
+        position
+            .parse_and_play_moves(moves.map(|s| s.to_string()).collect())
+            .unwrap();
+        let mut solver = Solver {
+            position,
+            ..Default::default()
+        };
+        let expected = solver.search(6);
+        let mut best = eval::LOSS;
+        let pvs = Arc::new(std::sync::Mutex::new(vec![]));
+        let handler_pvs = pvs.clone();
+        solver.set_info_handler(move |info| handler_pvs.lock().unwrap().push(info.pv));
+        for pmove in solver.position.legal_moves() {
+            let score = solver
+                .search_moves_with_limits(&[pmove], SearchLimits::depth(6))
+                .unwrap();
+            for pv in pvs.lock().unwrap().drain(..) {
+                assert_eq!(pv[0], pmove);
+            }
+            assert_eq!(solver.principal_variation()[0], pmove);
+            best = best.max(score);
+        }
+        assert_eq!(best, expected);
+        // The scores of the restricted searches are not stored.
+        assert_eq!(solver.search(6), expected);
+
+        let illegal = PlayerMove::StoneMove {
+            from: Some(0),
+            to: 1,
+        };
+        assert_eq!(
+            solver.search_moves_with_limits(&[illegal], SearchLimits::depth(6)),
+            Err(MoveFailed::InvalidFromSpot)
+        );
+    }
+
+    #[test]
+    fn top_moves() {
+        let mut position = Position::default();
+        let moves = "1 1 3 6 3 7 5 1 2 6".split_whitespace();
+        position
+            .parse_and_play_moves(moves.map(|s| s.to_string()).collect())
+            .unwrap();
+        let mut solver = Solver {
+            position,
+            ..Default::default()
+        };
+        let limits = SearchLimits::depth(6);
+        let expected = solver.search(6);
+        let best = solver.principal_variation()[0];
+        let top = solver.top_moves(3, limits);
+        assert_eq!(top.len(), 3);
+        assert_eq!(top[0], (best, expected));
+        assert!(top.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        // Every move is found only once.
+        assert!(!top[1..].iter().any(|&(pmove, _)| pmove == best));
+        assert_ne!(top[1].0, top[2].0);
+        let scores = solver.root_move_scores(limits);
+        assert_eq!(
+            scores[..3]
+                .iter()
+                .map(|&(_, score)| score)
+                .collect::<Vec<_>>(),
+            top.iter().map(|&(_, score)| score).collect::<Vec<_>>()
+        );
+
+        let num_moves = solver.position.legal_moves().len();
+        assert_eq!(solver.top_moves(100, limits).len(), num_moves);
+    }
+
+    #[test]
+    fn null_move() {
+        let mut solver = Solver::default();
+        solver.set_option("null-move", "true").unwrap();
+        assert!(solver.options.null_move);
+        // Null move pruning misses the win in the first line, but the
+        // results it finds are the same as without it.
+        let lines = [
+            "7 3 7 0 1 3 0 7 6 6 2 4 2 0 4 4 2-3 7-6",
+            "1 1 3 6 3 7 5 1 2 6 0 3 2 7 7 4 0-4",
+            "0 4 7 2 7 7 3 5 6 1 1 0 1 5 6 2",
+        ];
+        for (line_i, moves) in lines.into_iter().enumerate() {
+            let mut position = Position::default();
+            let moves = moves.split_whitespace().map(|s| s.to_string());
+            position.parse_and_play_moves(moves.collect()).unwrap();
+            let mut expected = Solver {
+                position: position.clone(),
+                ..Default::default()
+            };
+            let expected = expected.search(10);
+            solver.position = position.clone();
+            let score = solver.search(10);
+            assert_eq!(solver.position.to_fen(), position.to_fen());
+            if line_i == 0 {
+                assert!(eval::is_win(expected));
+                assert!(!eval::is_win(score));
+            } else if eval::is_win(score) || eval::is_loss(score) {
+                assert_eq!(score, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn shared_tt() {
+        let lines = ["1 1 3 6 3 7 5 1 2 6", "0 1 0 2 3 4", "2 2 5 4 1"];
+        let search = |moves: &str, tt: Option<Arc<SharedTranspositionTable>>| {
+            let mut solver = Solver::default();
+            if let Some(tt) = tt {
+                solver.share_tt(tt);
+            }
+            let moves = moves.split_whitespace().map(|s| s.to_string());
+            solver
+                .position
+                .parse_and_play_moves(moves.collect())
+                .unwrap();
+            (solver.search(5), solver.nodes())
+        };
+        let tt = Arc::new(SharedTranspositionTable::with_size_mb(8));
+        let threads: Vec<_> = lines
+            .into_iter()
+            .map(|moves| {
+                let tt = tt.clone();
+                std::thread::spawn(move || search(moves, Some(tt)))
+            })
+            .collect();
+        let shared: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        for (moves, (score, _)) in lines.into_iter().zip(&shared) {
+            assert_eq!(*score, search(moves, None).0);
+        }
+        assert!(tt.num_entries() > 0);
+        // A solver which starts later uses what the others found.
+        let (score, nodes) = search(lines[0], Some(tt.clone()));
+        assert_eq!(score, shared[0].0);
+        assert!(nodes < shared[0].1);
+
+        let mut solver = Solver::default();
+        solver.share_tt(tt.clone());
+        solver.clear_tt();
+        assert_eq!(tt.num_entries(), 0);
+        assert!(matches!(
+            solver.save_transposition_table(&std::env::temp_dir().join("shared_tt.bin")),
+            Err(error::Error::SharedTranspositionTable)
+        ));
+    }
+
+    #[test]
+    fn search_builder() {
+        let mut position = Position::default();
+        let moves = "1 1 3 6 3 7 5 1 2 6".split_whitespace();
+        position
+            .parse_and_play_moves(moves.map(|s| s.to_string()).collect())
+            .unwrap();
+        let mut solver = Solver {
+            position,
+            ..Default::default()
+        };
+        let expected = solver.search(6);
+        let top = solver.top_moves(2, SearchLimits::depth(6));
+        let result = solver.searcher().depth(6).multipv(2).run();
+        assert_eq!(result.score, expected);
+        // The win is found before depth 6.
+        assert!(eval::is_solved(result.score, 10, result.depth));
+        assert!(result.depth < 6);
+        assert_eq!(
+            result
+                .lines
+                .iter()
+                .map(|line| (line.pmove, line.score.unwrap()))
+                .collect::<Vec<_>>(),
+            top
+        );
+        assert_eq!(result.best_move(), Some(result.pv()[0]));
+
+        // The node limit stops the search before the depth is reached.
+        let result = solver.searcher().depth(20).nodes(2000).run();
+        assert!(result.depth < 20);
+        assert_eq!(result.lines.len(), 1);
+
+        // Black has an alignment which can't be banned, so the game is over.
+        let moves = "0 1 0 2 0".split_whitespace().map(|s| s.to_string());
+        solver.position = Position::default();
+        solver.position.set_rules(Rules {
+            second_best_on_alignment: false,
+            ..Default::default()
+        });
+        solver
+            .position
+            .parse_and_play_moves(moves.collect())
+            .unwrap();
+        let result = solver.searcher().time_ms(10_000).multipv(3).run();
+        assert!(result.lines.is_empty());
+        assert_eq!(result.best_move(), None);
+    }
+
+    #[test]
+    fn easy_move() {
+        let fens = [
+            "OOX/OX/XXO/OX/XO/./OO/XX O 5-1 -",
+            "OX/XO/OO/X/XXO/./OO/XX X - -",
+            "X/O/./././././. X - -",
+        ];
+        for fen in fens {
+            let mut solver = Solver {
+                position: Position::from_fen(fen).unwrap(),
+                ..Default::default()
+            };
+            solver.search(4);
+            // The bounds of the other moves are never below their scores.
+            let margin = solver.best_move_margin().unwrap();
+            let scores = solver.root_move_scores(SearchLimits::depth(4));
+            assert!(margin >= 0 && margin <= scores[0].1 - scores[1].1, "{fen}");
+        }
+
+        // The best move of the first position is much better than the others.
+        let mut solver = Solver {
+            position: Position::from_fen(fens[0]).unwrap(),
+            ..Default::default()
+        };
+        let depths = Arc::new(std::sync::Mutex::new(vec![]));
+        let handler_depths = depths.clone();
+        solver.set_info_handler(move |info| handler_depths.lock().unwrap().push(info.depth));
+        let limits = SearchLimits {
+            easy_time: Some(time::Duration::ZERO),
+            ..SearchLimits::depth(20)
+        };
+        solver.search_with_limits(limits);
+        assert!(solver.best_move_margin().unwrap() >= Solver::EASY_MOVE_MARGIN);
+        assert_eq!(
+            depths.lock().unwrap().last(),
+            Some(&(Solver::STABLE_ITERATIONS + 1))
+        );
+    }
+
+    #[test]
+    fn root_moves() {
+        let position = Position::from_fen("O/X/XX/O/XO/././O X 3 -").unwrap();
+        let mut solver = Solver {
+            position,
+            ..Default::default()
+        };
+        let eval = solver.search(6);
+        let root_moves = solver.root_moves().to_vec();
+        assert_eq!(root_moves.len(), solver.position.legal_moves().len());
+        // The best move is first, and is also the start of the principal variation.
+        assert_eq!(root_moves[0].score, Some(eval));
+        assert_eq!(solver.principal_variation()[0], root_moves[0].pmove);
+        assert!(root_moves[0].previous_score.is_some());
+        let scores: Vec<isize> = root_moves.iter().map_while(|m| m.score).collect();
+        assert!(scores.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(root_moves[scores.len()..].iter().all(|m| m.score.is_none()));
+        let root_nodes: usize = root_moves.iter().map(|m| m.nodes).sum();
+        assert!(root_nodes > 0 && root_nodes < solver.nodes());
+        // Every root move has a line starting with it.
+        let lines = solver.root_move_lines();
+        assert_eq!(lines.len(), root_moves.len());
+        assert_eq!(lines[0].pv, solver.principal_variation());
+        for (line, root_move) in lines.iter().zip(&root_moves) {
+            assert_eq!(line.pmove, root_move.pmove);
+            assert_eq!(line.score, root_move.score);
+            assert_eq!(line.pv[0], root_move.pmove);
+        }
+
+        // Only the given moves are root moves.
+        let moves = [root_moves[1].pmove, root_moves[2].pmove];
+        let eval = solver
+            .search_moves_with_limits(&moves, SearchLimits::depth(6))
+            .unwrap();
+        assert_eq!(solver.root_moves().len(), 2);
+        assert_eq!(solver.root_moves()[0].score, Some(eval));
+        assert!(moves.contains(&solver.principal_variation()[0]));
+    }
+
+    #[test]
+    fn symmetry() {
+        let search = |moves: &str, symmetry_plies: usize, depth: usize| {
+            let mut solver = Solver::default();
+            solver.options.symmetry_plies = symmetry_plies;
+            let moves = moves.split_whitespace().map(|s| s.to_string()).collect();
+            solver.position.parse_and_play_moves(moves).unwrap();
+            let eval = solver.search(depth);
+            (eval, solver.nodes(), solver.root_moves().len())
+        };
+        // All first moves are the same.
+        let (eval, nodes, root_moves) = search("", 4, 6);
+        let (eval_all, nodes_all, root_moves_all) = search("", 0, 6);
+        assert_eq!(eval, eval_all);
+        assert!(nodes < nodes_all);
+        assert_eq!(root_moves, 1);
+        assert_eq!(root_moves_all, Variant::STANDARD.stacks());
+        // A mirror image of the second move is the same.
+        let (eval, _, root_moves) = search("0", 4, 6);
+        assert_eq!(eval, search("0", 0, 6).0);
+        assert_eq!(root_moves, Variant::STANDARD.stacks() / 2 + 2);
+        // Solved positions keep their result.
+        for moves in ["1 1 3 6 3 7 5 1 2 6", "5 7 4 2 4 5 1 4 0"] {
+            assert_eq!(search(moves, 20, 8).0, search(moves, 0, 8).0);
+        }
+    }
+
+    #[test]
+    fn currmove() {
+        // The starting position is not solved quickly.
+        let mut solver = Solver::default();
+        let infos = Arc::new(std::sync::Mutex::new(vec![]));
+        let handler_infos = infos.clone();
+        solver.set_currmove_handler(move |info| handler_infos.lock().unwrap().push(info));
+        let limits = SearchLimits {
+            max_time: Some(Solver::CURRMOVE_DELAY + time::Duration::from_millis(500)),
+            ..Default::default()
+        };
+        solver.search_with_limits(limits);
+        let infos = infos.lock().unwrap();
+        assert!(!infos.is_empty());
+        let legal_moves = solver.position.legal_moves();
+        for info in infos.iter() {
+            assert!(legal_moves.contains(&info.currmove));
+            assert!(info.currmovenumber >= 1 && info.currmovenumber <= info.total);
+        }
+        assert!(infos.windows(2).all(|pair| pair[0].nodes <= pair[1].nodes));
+        assert!(infos[0].to_string().starts_with("info depth"));
+    }
 }
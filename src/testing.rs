@@ -0,0 +1,475 @@
+//! Tools for testing the solver against a deliberately simple one.
+//!
+//! The [`Oracle`] searches every move to a fixed depth by playing it with
+//! [`Position::try_make_move`], without a transposition table, pruning,
+//! extensions or the [`MoveGen`](crate::MoveGen) of the solver. It is far
+//! too slow to solve real positions, but on shallow searches it gives the
+//! exact result, which the solver should agree with (see [`agrees`]).
+//!
+//! [`fuzz_position`] plays random moves, checking that the position stays
+//! consistent. It runs briefly with the other tests, and as long as wanted
+//! with the `fuzz` feature:
+//!
+//! ```text
+//! SECOND_BEST_FUZZ_SECS=600 cargo test --release --features fuzz fuzz
+//! ```
+
+use crate::eval::{self, ExplainableEval};
+use crate::movegen::MoveGen;
+use crate::position::{BitboardMove, Color, MoveFailed, PlayerMove, Position, Rules, Variant};
+use crate::rng::Rng;
+use crate::solver::Solver;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// An exhaustive solver, finding the fastest win or slowest loss within a
+/// depth. See the [module documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct Oracle {
+    nodes: usize,
+    max_nodes: Option<usize>,
+    abort: Option<Arc<AtomicBool>>,
+}
+
+impl Oracle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give up on searches which need more than this many nodes.
+    pub fn with_max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = Some(max_nodes);
+        self
+    }
+
+    /// Stop searching when `abort` is set.
+    pub fn with_abort(mut self, abort: Arc<AtomicBool>) -> Self {
+        self.abort = Some(abort);
+        self
+    }
+
+    /// The number of positions searched in the last call to [`Oracle::solve`].
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    /// The fastest win or slowest loss within `depth` moves for the player
+    /// to move, or `Undetermined` if the result is further away. Returns
+    /// `None` if the search needs too many nodes or is aborted.
+    pub fn solve(&mut self, pos: &Position, depth: usize) -> Option<ExplainableEval> {
+        self.nodes = 0;
+        self.search(&mut pos.clone(), depth)
+    }
+
+    fn search(&mut self, pos: &mut Position, depth: usize) -> Option<ExplainableEval> {
+        self.nodes += 1;
+        if self
+            .max_nodes
+            .is_some_and(|max_nodes| self.nodes > max_nodes)
+            || self
+                .abort
+                .as_ref()
+                .is_some_and(|abort| abort.load(Ordering::Relaxed))
+        {
+            return None;
+        }
+        if pos.game_over() {
+            return Some(ExplainableEval::Loss(0));
+        }
+        if depth == 0 {
+            return Some(ExplainableEval::Undetermined(0));
+        }
+        let mut fastest_win = None;
+        let mut slowest_loss = Some(0);
+        for pmove in legal_moves(pos) {
+            pos.try_make_move(pmove).unwrap();
+            let result = self.search(pos, depth - 1);
+            pos.undo_last_player_move();
+            match result? {
+                ExplainableEval::Loss(moves) => {
+                    fastest_win =
+                        Some(fastest_win.map_or(moves + 1, |win: isize| win.min(moves + 1)))
+                }
+                ExplainableEval::Win(moves) => {
+                    slowest_loss = slowest_loss.map(|loss: isize| loss.max(moves + 1))
+                }
+                ExplainableEval::Undetermined(_) => slowest_loss = None,
+            }
+        }
+        Some(match (fastest_win, slowest_loss) {
+            (Some(moves), _) => ExplainableEval::Win(moves),
+            (None, Some(moves)) => ExplainableEval::Loss(moves),
+            (None, None) => ExplainableEval::Undetermined(0),
+        })
+    }
+}
+
+/// The legal moves in the position, found by trying every possible move.
+pub fn legal_moves(pos: &Position) -> Vec<PlayerMove> {
+    let from_spots: Vec<_> = match pos.is_second_phase() {
+        true => (0..pos.variant().stacks()).map(Some).collect(),
+        false => vec![None],
+    };
+    let mut moves = vec![PlayerMove::SecondBest];
+    for from in from_spots {
+        moves.extend((0..pos.variant().stacks()).map(|to| PlayerMove::StoneMove { from, to }));
+    }
+    moves.retain(|&pmove| pos.is_legal(pmove).is_ok());
+    moves
+}
+
+/// Whether a search to `depth` which found `score` agrees with the result
+/// of the oracle for the same depth: the solver has to find the exact result
+/// if it is within the depth, and must not claim a result otherwise.
+pub fn agrees(score: isize, ply: isize, depth: usize, oracle: ExplainableEval) -> bool {
+    match oracle {
+        ExplainableEval::Undetermined(_) => !eval::is_solved(score, ply, depth),
+        result => eval::decode_eval(score, ply) == result,
+    }
+}
+
+/// A random position, reached by playing up to `num_moves` random legal
+/// moves (including "Second Best!") from the start with the given rules.
+/// The game isn't over in the position: the moves stop before a move which
+/// ends the game. The same seed always gives the same position.
+pub fn random_position(seed: u64, num_moves: usize, rules: Rules) -> Position {
+    let mut rng = Rng::new(seed);
+    let mut pos = Position::default();
+    pos.set_rules(rules);
+    for _ in 0..num_moves {
+        let moves = legal_moves(&pos);
+        pos.try_make_move(moves[rng.below(moves.len())]).unwrap();
+        if pos.game_over() {
+            pos.undo_last_player_move();
+            break;
+        }
+    }
+    pos
+}
+
+/// A position for which the solver and the oracle disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The moves from the start of the game.
+    pub moves: String,
+    pub depth: usize,
+    /// The result found by the solver.
+    pub solver: ExplainableEval,
+    /// The result found by the oracle.
+    pub oracle: ExplainableEval,
+}
+
+/// Compare the solver with the oracle on `num_positions` random positions,
+/// each searched to a random depth up to `max_depth`. The solver is not
+/// cleared between searches, so results left in the transposition table by
+/// other positions and depths are used as well. Positions for which the
+/// oracle needs more than `max_nodes` nodes are skipped.
+pub fn differential_test(
+    solver: &mut Solver,
+    seed: u64,
+    num_positions: usize,
+    max_depth: usize,
+    max_nodes: usize,
+) -> Vec<Mismatch> {
+    let mut rng = Rng::new(seed);
+    let mut oracle = Oracle::new().with_max_nodes(max_nodes);
+    let rules = solver.position.rules();
+    let mut mismatches = vec![];
+    for _ in 0..num_positions {
+        // Most random games are over after this many moves.
+        let num_moves = rng.below(60);
+        let pos = random_position(rng.next_u64(), num_moves, rules);
+        let depth = 1 + rng.below(max_depth);
+        let Some(expected) = oracle.solve(&pos, depth) else {
+            continue;
+        };
+        let ply = pos.ply() as isize;
+        solver.position = pos;
+        let score = solver.search(depth);
+        if !agrees(score, ply, depth, expected) {
+            mismatches.push(Mismatch {
+                moves: PlayerMove::to_long_form(&solver.position.history()),
+                depth,
+                solver: eval::decode_eval(score, ply),
+                oracle: expected,
+            });
+        }
+    }
+    mismatches
+}
+
+/// What is compared before a move and after undoing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Snapshot {
+    fen: String,
+    ply: usize,
+    history: Vec<PlayerMove>,
+    legal_moves: Vec<PlayerMove>,
+}
+
+impl Snapshot {
+    fn new(pos: &Position) -> Self {
+        Self {
+            fen: pos.to_fen(),
+            ply: pos.ply(),
+            history: pos.history(),
+            legal_moves: sorted_moves(legal_moves(pos)),
+        }
+    }
+}
+
+fn sorted_moves(mut moves: Vec<PlayerMove>) -> Vec<PlayerMove> {
+    moves.sort_by_key(|pmove| pmove.to_string());
+    moves
+}
+
+/// Check that the position is consistent: with [`Position::validate`], and in
+/// every way that can be seen from the outside: the stacks, the stones of both
+/// players, the legal moves, and getting the same position back from its fen,
+/// board state and moves.
+pub fn check_position(pos: &Position) -> Result<(), String> {
+    pos.validate()
+        .map_err(|e| format!("invalid position: {e:?}"))?;
+    if pos.our_spots() & pos.free_spots() != 0 {
+        return Err("our stones are on free spots".to_string());
+    }
+    let variant = pos.variant();
+    let mut stones = [0; 2];
+    for stack_i in 0..variant.stacks() {
+        let height = pos.stack_height(stack_i);
+        if height > variant.height() {
+            return Err(format!("stack {stack_i} has {height} stones"));
+        }
+        // The spot above the highest stone is checked as well.
+        for h in 0..=variant.height() {
+            match pos.stone_at(stack_i, h) {
+                Some(color) if h < height => stones[color as usize] += 1,
+                None if h >= height => (),
+                _ => return Err(format!("stack {stack_i} has a gap or a stone above it")),
+            }
+        }
+    }
+    for color in [Color::Black, Color::White] {
+        if stones[color as usize] + pos.stones_in_hand(color) != variant.stones_per_player() {
+            return Err(format!("{color} has the wrong number of stones"));
+        }
+    }
+
+    let moves = sorted_moves(legal_moves(pos));
+    if sorted_moves(pos.legal_moves()) != moves {
+        return Err("the move generator gives other legal moves".to_string());
+    }
+    if pos.game_over() != moves.is_empty() {
+        return Err("the game is over if and only if there are no legal moves".to_string());
+    }
+    if let Some(banned) = pos.banned_move() {
+        let banned = BitboardMove::StoneMove(banned).to_player_move(pos);
+        if pos.is_legal(banned) != Err(MoveFailed::MoveBanned) {
+            return Err(format!("the banned move {banned} can be played"));
+        }
+    }
+
+    let fen = pos.to_fen();
+    let same_as =
+        |other: &Position| other.to_fen() == fen && sorted_moves(legal_moves(other)) == moves;
+    match Position::from_fen_with_variant(&fen, pos.variant()) {
+        Ok(from_fen) if from_fen.to_fen() == fen => (),
+        Ok(_) => return Err(format!("the fen {fen} gives another position")),
+        Err(e) => return Err(format!("the fen {fen} can't be read: {e:?}")),
+    }
+    match Position::from_board_state(&pos.board_state()) {
+        Ok(from_state) if from_state.to_fen() == fen => (),
+        Ok(_) => return Err("the board state gives another position".to_string()),
+        Err(e) => return Err(format!("the board state is invalid: {e:?}")),
+    }
+    let mut replayed = Position::with_variant(pos.variant());
+    replayed.set_rules(pos.rules());
+    let moves_text = pos.clone().serialize();
+    let history = moves_text.split_whitespace().map(String::from).collect();
+    if replayed.parse_and_play_moves(history).is_err()
+        || !same_as(&replayed)
+        || replayed.ply() != pos.ply()
+    {
+        return Err(format!("the moves {moves_text} give another position"));
+    }
+    Ok(())
+}
+
+/// Play random moves, legal and illegal ones, with all the ways the
+/// position can be changed: [`Position::try_make_move`],
+/// [`Position::make_move`] with moves of the [`MoveGen`](crate::MoveGen),
+/// [`Position::second_best`], [`Position::unmake_move`] and
+/// [`Position::undo_last_player_move`]. After every step the position is
+/// checked with [`check_position`], moves which fail must not change the
+/// position, and undoing a move must give back the position before it.
+/// The game is played with the given rules, on a board of the given size.
+///
+/// Returns what went wrong, with the moves leading to it.
+pub fn fuzz_position(
+    seed: u64,
+    steps: usize,
+    rules: Rules,
+    variant: Variant,
+) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    let mut pos = Position::with_variant(variant);
+    pos.set_rules(rules);
+    // The positions before the moves played, to compare with after undoing them.
+    let mut before = vec![];
+    for _ in 0..steps {
+        let snapshot = Snapshot::new(&pos);
+        let fail = |step: &str| {
+            let moves = PlayerMove::to_long_form(&snapshot.history);
+            format!("{step} after the moves \"{moves}\"")
+        };
+        // Keep the games from getting too long.
+        let undo = pos.game_over() || pos.ply() + 4 >= Position::MAX_MOVES;
+        match rng.below(10) {
+            _ if undo && before.is_empty() => return Err(fail("the game is over at the start")),
+            0..=3 if !undo => {
+                // Any move, also illegal ones and spots which don't exist.
+                let pmove = match rng.below(pos.variant().stacks() + 2) {
+                    0 => PlayerMove::SecondBest,
+                    _ => PlayerMove::StoneMove {
+                        from: match rng.below(3) {
+                            0 => None,
+                            _ => Some(rng.below(pos.variant().stacks() + 1)),
+                        },
+                        to: rng.below(pos.variant().stacks() + 1),
+                    },
+                };
+                let legal = snapshot.legal_moves.contains(&pmove);
+                match pos.try_make_move(pmove) {
+                    Ok(()) if legal => before.push(snapshot),
+                    Ok(()) => return Err(fail(&format!("the illegal move {pmove} was played"))),
+                    Err(e) if legal => {
+                        return Err(fail(&format!("the legal move {pmove} failed: {e:?}")))
+                    }
+                    Err(_) if Snapshot::new(&pos) != snapshot => {
+                        return Err(fail(&format!(
+                            "the failed move {pmove} changed the position"
+                        )))
+                    }
+                    Err(_) => continue,
+                }
+            }
+            4..=5 if !undo => {
+                let moves: Vec<_> = MoveGen::new(&pos, None).collect();
+                pos.make_move(moves[rng.below(moves.len())]);
+                before.push(snapshot);
+            }
+            6 if !undo && pos.can_second_best() => {
+                pos.second_best();
+                before.push(snapshot);
+            }
+            _ => {
+                let Some(previous) = before.pop() else {
+                    continue;
+                };
+                match rng.below(2) {
+                    0 => pos.unmake_move(),
+                    _ => {
+                        pos.undo_last_player_move();
+                    }
+                }
+                if Snapshot::new(&pos) != previous {
+                    return Err(fail("undoing a move gave another position"));
+                }
+            }
+        }
+        check_position(&pos).map_err(|e| {
+            let moves = PlayerMove::to_long_form(&pos.history());
+            format!("{e}, after the moves \"{moves}\"")
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::SolverOptions;
+
+    #[test]
+    fn oracle() {
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(
+            "6 3 7 7 7 1 2 3 3 2 2 0 5 5 0 1"
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+        )
+        .unwrap();
+        let mut moves = legal_moves(&pos);
+        let mut solver_moves = pos.legal_moves();
+        moves.sort_by_key(|pmove| pmove.to_string());
+        solver_moves.sort_by_key(|pmove| pmove.to_string());
+        assert_eq!(moves, solver_moves);
+
+        let mut oracle = Oracle::new();
+        assert_eq!(oracle.solve(&pos, 3), Some(ExplainableEval::Win(3)));
+        assert_eq!(
+            oracle.solve(&pos, 2),
+            Some(ExplainableEval::Undetermined(0))
+        );
+        let mut oracle = oracle.with_max_nodes(3);
+        assert_eq!(oracle.solve(&pos, 3), None);
+    }
+
+    #[test]
+    fn differential() {
+        let mut solver = Solver::default();
+        let mismatches = differential_test(&mut solver, 1, 2000, 4, 100_000);
+        assert!(mismatches.is_empty(), "{mismatches:#?}");
+
+        // The other options of the search shouldn't change the results.
+        solver.options = SolverOptions {
+            pvs: true,
+            threat_extensions: 1,
+            ..SolverOptions::default()
+        };
+        let mismatches = differential_test(&mut solver, 2, 1000, 4, 100_000);
+        assert!(mismatches.is_empty(), "{mismatches:#?}");
+    }
+
+    #[test]
+    fn fuzz() {
+        for seed in 0..20 {
+            fuzz_position(seed, 2000, Rules::default(), Variant::STANDARD).unwrap();
+        }
+        let rules = Rules {
+            second_best_on_alignment: true,
+            second_best_limit: Some(2),
+        };
+        fuzz_position(20, 2000, rules, Variant::STANDARD).unwrap();
+        for (seed, (stacks, height)) in [(4, 3), (6, 3), (6, 4), (4, 6)].into_iter().enumerate() {
+            let variant = Variant::new(stacks, height).unwrap();
+            fuzz_position(30 + seed as u64, 2000, rules, variant).unwrap();
+        }
+    }
+
+    /// Fuzz for `SECOND_BEST_FUZZ_SECS` seconds (60 by default), with random rules.
+    #[cfg(feature = "fuzz")]
+    #[test]
+    fn fuzz_long() {
+        let secs = std::env::var("SECOND_BEST_FUZZ_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(60);
+        let end = std::time::Instant::now() + std::time::Duration::from_secs(secs);
+        let mut rng = Rng::from_time();
+        while std::time::Instant::now() < end {
+            let seed = rng.next_u64();
+            let rules = Rules {
+                second_best_on_alignment: rng.below(2) == 0,
+                second_best_limit: [None, Some(0), Some(1), Some(3)][rng.below(4)],
+            };
+            let (stacks, height) = [(8, 3), (4, 3), (6, 3), (6, 4), (4, 6)][rng.below(5)];
+            let variant = Variant::new(stacks, height).unwrap();
+            if let Err(e) = fuzz_position(seed, 10_000, rules, variant) {
+                panic!("seed {seed} with {rules:?} on {variant}: {e}");
+            }
+        }
+    }
+}
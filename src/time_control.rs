@@ -0,0 +1,151 @@
+//! Deciding how much time to spend on a move when playing under a clock,
+//! e.g. in engine matches with `go btime <ms> wtime <ms> binc <ms> winc <ms>`.
+//!
+//! The time left is divided over the moves which are still expected. Moves
+//! of the first phase, where stones are only placed, get less time than
+//! moves of the second phase, which decide most games. The search stops
+//! early when the best move is stable, and even earlier when it is also much
+//! better than the other moves (see [`SearchLimits::easy_time`]). When the
+//! best move changes late in the search, it gets more time.
+
+use crate::position::{Color, Position};
+use crate::solver::SearchLimits;
+use std::time::Duration;
+
+/// The clocks of both players, as given to `go`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeControl {
+    /// The time left for black (X).
+    pub btime: Duration,
+    /// The time left for white (O).
+    pub wtime: Duration,
+    /// The time black gets after each of its moves.
+    pub binc: Duration,
+    /// The time white gets after each of its moves.
+    pub winc: Duration,
+}
+
+impl TimeControl {
+    /// The number of moves of the second phase a player is expected to play.
+    const SECOND_PHASE_MOVES: u32 = 12;
+    /// How many times as much time a move of the second phase gets as a
+    /// move of the first phase.
+    const SECOND_PHASE_WEIGHT: u32 = 3;
+    /// Time which is never used, for sending the move back and forth.
+    pub const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+
+    /// Parse the arguments of `go`: names followed by a number of
+    /// milliseconds, e.g. `btime 60000 wtime 60000 binc 500 winc 500`.
+    /// The times which aren't given are zero.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut time_control = Self::default();
+        let mut args = args.iter();
+        while let Some(name) = args.next() {
+            let field = match name.as_str() {
+                "btime" => &mut time_control.btime,
+                "wtime" => &mut time_control.wtime,
+                "binc" => &mut time_control.binc,
+                "winc" => &mut time_control.winc,
+                _ => return Err(format!("Unknown time control `{name}`")),
+            };
+            let Some(value) = args.next() else {
+                return Err(format!("Missing the milliseconds after `{name}`"));
+            };
+            let millis = value
+                .parse()
+                .map_err(|_| format!("Invalid number of milliseconds for `{name}`: {value}"))?;
+            *field = Duration::from_millis(millis);
+        }
+        Ok(time_control)
+    }
+
+    /// The time left and the increment of the given player.
+    pub fn clock(&self, color: Color) -> (Duration, Duration) {
+        match color {
+            Color::Black => (self.btime, self.binc),
+            Color::White => (self.wtime, self.winc),
+        }
+    }
+
+    /// The time to spend on a move: the part of the time left for this move,
+    /// and most of the increment.
+    pub fn budget(&self, pos: &Position) -> Duration {
+        let (time, increment) = self.clock(pos.current_player());
+        let available = time.saturating_sub(Self::MOVE_OVERHEAD);
+        let (weight, placements_left) = match pos.is_second_phase() {
+            true => (Self::SECOND_PHASE_WEIGHT, 0),
+            false => (1, pos.variant().stones_per_player() - pos.num_turns() / 2),
+        };
+        let total_weight =
+            placements_left as u32 + Self::SECOND_PHASE_MOVES * Self::SECOND_PHASE_WEIGHT;
+        let budget = available * weight / total_weight + increment * 3 / 4;
+        budget.min(available / 2)
+    }
+
+    /// The limits of the search for the move in the given position.
+    /// No new iteration is started after half of the budget, because it would
+    /// likely take longer than the rest, and the search is stopped when it
+    /// takes far more than the budget. If the best move just changed, the
+    /// whole budget can be used.
+    pub fn limits(&self, pos: &Position) -> SearchLimits {
+        let (time, _) = self.clock(pos.current_player());
+        let available = time.saturating_sub(Self::MOVE_OVERHEAD);
+        let budget = self.budget(pos);
+        SearchLimits {
+            max_time: Some((budget * 3).min(available / 2)),
+            soft_time: Some(budget / 2),
+            stable_time: Some(budget / 4),
+            easy_time: Some(budget / 8),
+            unstable_time: Some(budget),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budgets() {
+        let args = |args: &str| {
+            args.split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        let time_control = TimeControl::parse(&args("btime 60000 wtime 30000 binc 1000")).unwrap();
+        assert_eq!(time_control.btime, Duration::from_secs(60));
+        assert_eq!(time_control.wtime, Duration::from_secs(30));
+        assert_eq!(time_control.binc, Duration::from_secs(1));
+        assert_eq!(time_control.winc, Duration::ZERO);
+        assert!(TimeControl::parse(&args("btime")).is_err());
+        assert!(TimeControl::parse(&args("btime soon")).is_err());
+        assert!(TimeControl::parse(&args("movetime 100")).is_err());
+
+        // The clock of the player to move is used.
+        let mut pos = Position::default();
+        let black_budget = time_control.budget(&pos);
+        pos.parse_and_play_moves(args("0")).unwrap();
+        let white_budget = time_control.budget(&pos);
+        assert!(white_budget < black_budget);
+        // Most of the increment is used.
+        assert!(black_budget > Duration::from_millis(750));
+
+        // The moves of the second phase get more time.
+        let time_control = TimeControl::parse(&args("btime 60000 wtime 60000")).unwrap();
+        let first_phase = time_control.budget(&Position::default());
+        let mut pos = Position::default();
+        pos.parse_and_play_moves(args("0 1 2 3 4 5 6 7 0 1 2 3 4 5 6 7"))
+            .unwrap();
+        assert!(pos.is_second_phase());
+        assert!(time_control.budget(&pos) > 2 * first_phase);
+
+        // The time left is never used up.
+        let time_control = TimeControl::parse(&args("btime 100 wtime 100 binc 5000")).unwrap();
+        let limits = time_control.limits(&pos);
+        assert!(limits.max_time.unwrap() < Duration::from_millis(100));
+        assert!(limits.soft_time <= limits.max_time);
+        let time_control = TimeControl::parse(&args("btime 10 wtime 10")).unwrap();
+        assert_eq!(time_control.limits(&pos).max_time, Some(Duration::ZERO));
+    }
+}
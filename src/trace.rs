@@ -0,0 +1,310 @@
+//! Recording the tree visited by a search, for debugging the search.
+//!
+//! A trace is made with [`Solver::trace_search`](crate::Solver::trace_search),
+//! and contains every node of the last iteration with its window, its
+//! result, and what happened with the transposition table there. Pruning
+//! bugs show up as nodes with a wrong score for their window, or as table
+//! entries which are used where they shouldn't be.
+//!
+//! Traces are saved as JSON, and can be printed as an indented tree.
+
+use crate::position::{PlayerMove, Position};
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::path::Path;
+
+/// Something which happened with the transposition table in a node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum TtEvent {
+    /// An entry was found. Its score is only used if it is `usable`,
+    /// i.e. if it was stored at the same ply or later.
+    Hit {
+        entry_type: String,
+        score: isize,
+        best_move: String,
+        usable: bool,
+    },
+    /// The score of the entry was returned, without searching the moves.
+    Cutoff { score: isize },
+    /// The result of the node was stored.
+    Store {
+        entry_type: String,
+        score: isize,
+        best_move: String,
+    },
+}
+
+impl Display for TtEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hit {
+                entry_type,
+                score,
+                best_move,
+                usable,
+            } => {
+                write!(f, "hit {entry_type} {score} {best_move}")?;
+                if !usable {
+                    write!(f, " (not usable)")?;
+                }
+                Ok(())
+            }
+            Self::Cutoff { score } => write!(f, "cutoff {score}"),
+            Self::Store {
+                entry_type,
+                score,
+                best_move,
+            } => write!(f, "store {entry_type} {score} {best_move}"),
+        }
+    }
+}
+
+/// A node of the search tree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceNode {
+    /// The move leading to this node. `None` for the root, and for
+    /// a position which is searched again deeper, without a move,
+    /// because of a threat extension.
+    #[serde(rename = "move")]
+    pub pmove: Option<String>,
+    pub depth: usize,
+    pub alpha: isize,
+    pub beta: isize,
+    /// The result of the node, `None` if the search was stopped.
+    pub score: Option<isize>,
+    pub tt: Vec<TtEvent>,
+    pub children: Vec<TraceNode>,
+}
+
+impl TraceNode {
+    fn write_tree(&self, f: &mut std::fmt::Formatter<'_>, indent: usize) -> std::fmt::Result {
+        write!(f, "{:indent$}", "")?;
+        if let Some(pmove) = &self.pmove {
+            write!(f, "{pmove}: ")?;
+        }
+        write!(
+            f,
+            "depth {} [{}, {}] -> ",
+            self.depth, self.alpha, self.beta
+        )?;
+        match self.score {
+            Some(score) => write!(f, "{score}")?,
+            None => write!(f, "stopped")?,
+        }
+        for event in &self.tt {
+            write!(f, ", tt {event}")?;
+        }
+        writeln!(f)?;
+        for child in &self.children {
+            child.write_tree(f, indent + 2)?;
+        }
+        Ok(())
+    }
+
+    /// The number of nodes in the tree starting at this node.
+    pub fn size(&self) -> usize {
+        1 + self.children.iter().map(TraceNode::size).sum::<usize>()
+    }
+}
+
+/// The tree searched in one iteration, see the [module documentation](self).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchTrace {
+    /// The searched position, in compact notation.
+    pub fen: String,
+    pub depth: usize,
+    /// The maximal number of nodes recorded.
+    pub max_nodes: usize,
+    /// The number of nodes which were searched, but not recorded
+    /// because there were already `max_nodes` nodes.
+    pub skipped_nodes: usize,
+    pub root: Option<TraceNode>,
+    /// The nodes which are being searched, from the root down.
+    #[serde(skip)]
+    stack: Vec<TraceNode>,
+    /// The moves played from the root position.
+    #[serde(skip)]
+    moves: Vec<String>,
+    /// The number of moves played when the nodes on the stack were entered.
+    #[serde(skip)]
+    moves_played: Vec<usize>,
+    /// The number of nodes being searched which are not recorded.
+    #[serde(skip)]
+    skipping: usize,
+    #[serde(skip)]
+    entered_nodes: usize,
+}
+
+impl SearchTrace {
+    pub fn new(pos: &Position, depth: usize, max_nodes: usize) -> Self {
+        Self {
+            fen: pos.to_fen(),
+            depth,
+            max_nodes,
+            skipped_nodes: 0,
+            root: None,
+            stack: vec![],
+            moves: vec![],
+            moves_played: vec![],
+            skipping: 0,
+            entered_nodes: 0,
+        }
+    }
+
+    /// Start searching a node with the given window.
+    pub(crate) fn enter(&mut self, depth: usize, alpha: isize, beta: isize) {
+        if self.skipping > 0 || self.entered_nodes >= self.max_nodes {
+            self.skipping += 1;
+            self.skipped_nodes += 1;
+            return;
+        }
+        self.entered_nodes += 1;
+        // Without a new move, the same position is searched again.
+        let pmove = match self.moves_played.last() {
+            Some(&played) if played == self.moves.len() => None,
+            _ => self.moves.last().cloned(),
+        };
+        self.moves_played.push(self.moves.len());
+        self.stack.push(TraceNode {
+            pmove,
+            depth,
+            alpha,
+            beta,
+            score: None,
+            tt: vec![],
+            children: vec![],
+        });
+    }
+
+    /// Finish the node which was entered last.
+    pub(crate) fn exit(&mut self, score: Option<isize>) {
+        if self.skipping > 0 {
+            self.skipping -= 1;
+            return;
+        }
+        self.moves_played.pop();
+        let Some(mut node) = self.stack.pop() else {
+            return;
+        };
+        node.score = score;
+        match self.stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => self.root = Some(node),
+        }
+    }
+
+    /// Record something which happened with the table in the current node.
+    pub(crate) fn tt_event(&mut self, event: TtEvent) {
+        if self.skipping > 0 {
+            return;
+        }
+        if let Some(node) = self.stack.last_mut() {
+            node.tt.push(event);
+        }
+    }
+
+    /// The given move is played, and the position after it will be searched.
+    pub(crate) fn play(&mut self, pmove: PlayerMove) {
+        self.moves.push(pmove.to_string());
+    }
+
+    /// A null move is played, see [`crate::solver::SolverOptions::null_move`].
+    pub(crate) fn play_null(&mut self) {
+        self.moves.push("null".to_string());
+    }
+
+    /// The last move played is taken back.
+    pub(crate) fn unplay(&mut self) {
+        self.moves.pop();
+    }
+
+    /// The number of nodes in the trace.
+    pub fn recorded_nodes(&self) -> usize {
+        self.root.as_ref().map_or(0, TraceNode::size)
+    }
+
+    /// Save the trace as JSON.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::from)?;
+        std::fs::write(path, json)
+    }
+
+    /// Load a trace saved with [`SearchTrace::save`].
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        serde_json::from_str(&json).map_err(std::io::Error::from)
+    }
+}
+
+impl Display for SearchTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Search of depth {} from {}: {} nodes recorded, {} not recorded",
+            self.depth,
+            self.fen,
+            self.recorded_nodes(),
+            self.skipped_nodes
+        )?;
+        match &self.root {
+            Some(root) => root.write_tree(f, 0),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Solver;
+
+    #[test]
+    fn trace_search() {
+        let position = Position::from_fen("O/X/XX/O/XO/././O X 3 -").unwrap();
+        let mut solver = Solver::default();
+        solver.position = position.clone();
+        let eval = solver.search(5);
+
+        let mut solver = Solver::default();
+        solver.position = position;
+        let trace = solver.trace_search(5, 100_000);
+        let root = trace.root.as_ref().unwrap();
+        assert_eq!(root.score, Some(eval));
+        assert_eq!(root.depth, 5);
+        assert_eq!(root.pmove, None);
+        assert!(!root.children.is_empty());
+        // Every child is reached by a legal move.
+        let legal_moves: Vec<String> = solver
+            .position
+            .legal_moves()
+            .iter()
+            .map(|pmove| pmove.to_string())
+            .collect();
+        for child in &root.children {
+            assert!(legal_moves.contains(child.pmove.as_ref().unwrap()));
+            assert!(child.score.is_some());
+        }
+        assert_eq!(trace.skipped_nodes, 0);
+        assert!(trace.recorded_nodes() > root.children.len());
+        // The table was used in the last iteration.
+        assert!(root
+            .tt
+            .iter()
+            .any(|event| matches!(event, TtEvent::Hit { .. })));
+
+        // Only the first nodes are recorded.
+        let trace = solver.trace_search(5, 10);
+        assert_eq!(trace.recorded_nodes(), 10);
+        assert!(trace.skipped_nodes > 0);
+        assert!(trace.to_string().lines().count() > 10);
+
+        let path = std::env::temp_dir().join("second_best_trace_test.json");
+        trace.save(&path).unwrap();
+        let loaded = SearchTrace::load(&path).unwrap();
+        assert_eq!(loaded.root, trace.root);
+        assert_eq!(loaded.to_string(), trace.to_string());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
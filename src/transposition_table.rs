@@ -1,7 +1,10 @@
 use crate::{
+    error::{Error, Result},
     eval,
-    position::{BitboardMove, PlayerMove, Position},
+    position::{BitboardMove, PlayerMove, Position, Rules, Variant},
 };
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
 
 /// A compact storage of a move in 8 bits.
 /// The bits are decomposed as follows:
@@ -9,7 +12,7 @@ use crate::{
 /// 0111_0000 -- The column of the to spot.
 /// 0000_1111 -- The column of the "from" spot.
 /// If the "from" spot is None, then 8 (0b0000_1000) is
-/// stored as the from spot.
+/// stored as the from spot, which is [`Variant::MAX_STACKS`].
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TTMove(u8);
 
@@ -20,7 +23,7 @@ impl TTMove {
 
     fn from(&self) -> Option<usize> {
         let from_stack = (self.0 & Self::FROM_BITS) as usize;
-        if from_stack == 8 {
+        if from_stack == Variant::MAX_STACKS {
             return None;
         }
         Some(from_stack)
@@ -40,11 +43,12 @@ impl TTMove {
             BitboardMove::StoneMove(smove) => {
                 let from_spot = smove & pos.from_spots(true);
                 let to_spot = smove & pos.free_spots();
+                let column_bits = pos.variant().height() as u32 + 1;
                 // Add a sentinel bit to ensure that `from_stack` becomes
                 // 8 if `from_spot` was empty.
-                let from_spot = from_spot | 1 << 32;
-                let from_stack = (from_spot.trailing_zeros() / 4) as u8;
-                let to_stack = (to_spot.trailing_zeros() / 4) as u8;
+                let from_spot = from_spot | 1 << (column_bits * Variant::MAX_STACKS as u32);
+                let from_stack = (from_spot.trailing_zeros() / column_bits) as u8;
+                let to_stack = (to_spot.trailing_zeros() / column_bits) as u8;
                 Self(from_stack | (to_stack << 4))
             }
         }
@@ -55,9 +59,10 @@ impl TTMove {
             return BitboardMove::SecondBest;
         }
         BitboardMove::StoneMove({
-            let smove = pos.free_spots() & Position::column_mask(self.to());
+            let variant = pos.variant();
+            let smove = pos.free_spots() & variant.column_mask(self.to());
             match self.from() {
-                Some(from) => smove | pos.from_spots(true) & Position::column_mask(from),
+                Some(from) => smove | pos.from_spots(true) & variant.column_mask(from),
                 None => smove,
             }
         })
@@ -83,6 +88,27 @@ pub enum EntryType {
     UpperBound,
 }
 
+impl EntryType {
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Undetermined => 0,
+            Self::Exact => 1,
+            Self::LowerBound => 2,
+            Self::UpperBound => 3,
+        }
+    }
+
+    fn from_u8(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(Self::Undetermined),
+            1 => Some(Self::Exact),
+            2 => Some(Self::LowerBound),
+            3 => Some(Self::UpperBound),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Entry {
     score: i16,
@@ -107,11 +133,13 @@ impl Entry {
         }
     }
 
-    /// The score for mate evals depends on the ply.
+    /// The score for mate evals depends on the ply, see [`eval::loss_score`].
+    /// The win or loss is as far away from the position at `ply` as it was
+    /// from the position when it was stored.
     pub fn score(&self, ply: isize) -> isize {
-        if self.score as isize >= eval::IS_WIN {
+        if eval::is_win(self.score as isize) {
             self.score as isize - ply + self.ply as isize
-        } else if self.score as isize <= eval::IS_LOSS {
+        } else if eval::is_loss(self.score as isize) {
             self.score as isize + ply - self.ply as isize
         } else {
             self.score as isize
@@ -145,6 +173,8 @@ type PartialKey = u32;
 /// from a previous iteration of the iterative
 /// deepening loop to get a quicker result.
 pub struct TranspositionTable {
+    /// The number of entries, a prime number.
+    size: usize,
     entries: Box<[Entry]>,
     /// We store the keys in the table as well,
     /// to be able to detect collisions.
@@ -161,17 +191,11 @@ pub struct TranspositionTable {
 
 impl Default for TranspositionTable {
     fn default() -> Self {
-        Self {
-            entries: (0..Self::SIZE).map(|_| Entry::default()).collect(),
-            // Ensure that the initial keys stored are not valid.
-            keys: (0..Self::SIZE)
-                .map(|_| Self::SIZE as PartialKey + 1)
-                .collect(),
-        }
+        Self::with_size_mb(Self::DEFAULT_SIZE_MB)
     }
 }
 
-/// The following are functions to find the next prime factor at compile time
+// The following are functions to find the next prime factor at compile time
 
 const fn med(min: u64, max: u64) -> u64 {
     (min + max) / 2
@@ -184,7 +208,7 @@ const fn has_factor(n: u64, min: u64, max: u64) -> bool {
     }
     // do not search for factor above sqrt(n)
     else if min + 1 >= max {
-        n % min == 0
+        n.is_multiple_of(min)
     } else {
         has_factor(n, min, med(min, max)) || has_factor(n, med(min, max), max)
     }
@@ -201,12 +225,46 @@ const fn next_prime(n: u64) -> u64 {
 }
 
 impl TranspositionTable {
-    const SIZE: usize = next_prime(1 << 23) as usize;
+    /// The size of the table if it isn't set, which holds 2^23 positions.
+    pub const DEFAULT_SIZE_MB: usize = 80;
+    /// The largest size of the table, so that the number of
+    /// entries still fits in a [`PartialKey`].
+    pub const MAX_SIZE_MB: usize = 32 * 1024;
+    /// The memory used by a single entry, with its key.
+    const ENTRY_BYTES: usize = std::mem::size_of::<Entry>() + std::mem::size_of::<PartialKey>();
+
+    /// A table using about `size_mb` megabytes of memory, which must
+    /// be between 1 and [`Self::MAX_SIZE_MB`].
+    pub fn with_size_mb(size_mb: usize) -> Self {
+        assert!((1..=Self::MAX_SIZE_MB).contains(&size_mb));
+        let size = next_prime((size_mb << 20) as u64 / Self::ENTRY_BYTES as u64);
+        Self::with_size(size as usize)
+    }
+
+    /// An empty table with `size` entries.
+    fn with_size(size: usize) -> Self {
+        Self {
+            size,
+            entries: (0..size).map(|_| Entry::default()).collect(),
+            // Ensure that the initial keys stored are not valid.
+            keys: (0..size).map(|_| size as PartialKey + 1).collect(),
+        }
+    }
+
+    /// The size of the table in megabytes, rounded down.
+    pub fn size_mb(&self) -> usize {
+        (self.size * Self::ENTRY_BYTES) >> 20
+    }
+
+    /// The key stored for entries which are not used (see [`Self::with_size`]).
+    fn empty_key(&self) -> PartialKey {
+        self.size as PartialKey + 1
+    }
 
     #[inline(always)]
     fn index(&self, key: Key) -> usize {
         // Make the keys a bit more spread out.
-        key as usize % Self::SIZE
+        key as usize % self.size
     }
 
     /// Get a unique key for the given position.
@@ -218,24 +276,51 @@ impl TranspositionTable {
     /// - So we can find the free spots by looking at the
     ///   top set bits on each stack.
     /// - Once we know free_spots, we can get played_spots
-    ///   by subtracting [`Self::BOTTOM`].
+    ///   by subtracting the bottom row.
     /// - We can also get `our_spots` by removing `free_spots`
     ///   from the key.
     ///
     /// Because of "Second Best!" we also need to know the last
     /// move, and whether or not "Second Best!" is possible.
+    /// If the number of "Second Best!" calls is limited, the calls
+    /// left are stored in the unused bits on top of the columns.
+    ///
+    /// The key doesn't store the size of the board, so the table has to
+    /// be cleared when it changes.
     pub fn key(pos: &Position) -> Key {
-        let u32mask = 0xFFFF_FFFF;
+        let first_copy = pos.variant().first_copy();
         let last_move_info = match pos.last_stone_move() {
-            Some(smove) => smove & !u32mask,
+            Some(smove) => smove & !first_copy,
             None => 0,
         };
         let second_best_info = match pos.can_second_best() {
             true => last_move_info,
-            false => last_move_info | (1 << 35),
+            false => last_move_info | pos.variant().spare_bits().next().unwrap(),
         };
-        let pos_info = (pos.our_spots() | pos.free_spots()) & u32mask;
-        second_best_info | pos_info
+        let pos_info = (pos.our_spots() | pos.free_spots()) & first_copy;
+        second_best_info | pos_info | Self::calls_left_info(pos)
+    }
+
+    /// The "Second Best!" calls left for both players, using three of the
+    /// unused bits each, see [`Rules::MAX_SECOND_BEST_LIMIT`].
+    fn calls_left_info(pos: &Position) -> Key {
+        let us = pos.current_player();
+        let (Some(ours), Some(theirs)) = (
+            pos.second_best_calls_left(us),
+            pos.second_best_calls_left(us.other()),
+        ) else {
+            return 0;
+        };
+        debug_assert!(ours.max(theirs) <= Rules::MAX_SECOND_BEST_LIMIT);
+        let calls = ours | theirs << 3;
+        // The first spare bit is used by `key`.
+        pos.variant()
+            .spare_bits()
+            .skip(1)
+            .take(6)
+            .enumerate()
+            .filter(|&(i, _)| calls & (1 << i) != 0)
+            .fold(0, |info, (_, bit)| info | bit)
     }
 
     /// Store a score and move in the transposition table.
@@ -331,6 +416,263 @@ impl TranspositionTable {
         }
         None
     }
+
+    /// Remove all positions from the table, keeping its size.
+    pub fn clear(&mut self) {
+        let empty = self.empty_key();
+        self.entries.fill(Entry::default());
+        self.keys.fill(empty);
+    }
+
+    /// The number of positions stored in the table.
+    pub fn num_entries(&self) -> usize {
+        let empty = self.empty_key();
+        self.keys.iter().filter(|&&key| key != empty).count()
+    }
+
+    /// How full the table is, in per mille. Only a thousand entries spread
+    /// over the table are counted, so that this is fast enough to report
+    /// after every iteration of a search. The keys aren't hashed, so the
+    /// first entries aren't a good sample.
+    pub fn permille_full(&self) -> usize {
+        let empty = self.empty_key();
+        let step = (self.size / 1000).max(1);
+        let sample = self.keys.iter().step_by(step).take(1000);
+        let (used, total) = sample.fold((0, 0), |(used, total), &key| {
+            (used + usize::from(key != empty), total + 1)
+        });
+        used * 1000 / total
+    }
+}
+
+/// A transposition table which several solvers can use at the same time,
+/// e.g. solvers searching different positions of the same game, so that
+/// memory isn't used for a table per solver. The solvers should use the
+/// same house rules, as the scores depend on them.
+///
+/// The table is split into shards, each behind its own lock, so that
+/// solvers rarely have to wait for each other.
+pub struct SharedTranspositionTable {
+    shards: Box<[Mutex<TranspositionTable>]>,
+}
+
+impl Default for SharedTranspositionTable {
+    fn default() -> Self {
+        Self::with_size_mb(TranspositionTable::DEFAULT_SIZE_MB)
+    }
+}
+
+impl SharedTranspositionTable {
+    /// The number of shards. This is a prime number, as the keys aren't
+    /// hashed (see [`TranspositionTable::index`]).
+    const SHARDS: usize = 31;
+
+    /// A table using about `size_mb` megabytes of memory in total, which
+    /// must be between 1 and [`TranspositionTable::MAX_SIZE_MB`].
+    pub fn with_size_mb(size_mb: usize) -> Self {
+        assert!((1..=TranspositionTable::MAX_SIZE_MB).contains(&size_mb));
+        let entries = (size_mb << 20) / TranspositionTable::ENTRY_BYTES;
+        let shard_size = next_prime((entries / Self::SHARDS) as u64) as usize;
+        Self {
+            shards: (0..Self::SHARDS)
+                .map(|_| Mutex::new(TranspositionTable::with_size(shard_size)))
+                .collect(),
+        }
+    }
+
+    /// The shard in which the position is stored. Since the number of shards
+    /// and the size of the shards are different primes, the positions of a
+    /// shard are still spread over all of its entries.
+    fn shard(&self, pos: &Position) -> MutexGuard<'_, TranspositionTable> {
+        let key = TranspositionTable::key(pos);
+        self.shards[key as usize % Self::SHARDS].lock().unwrap()
+    }
+
+    /// See [`TranspositionTable::store`].
+    pub(crate) fn store(
+        &self,
+        pos: &Position,
+        score: isize,
+        best_move: BitboardMove,
+        entry_type: EntryType,
+    ) {
+        self.shard(pos).store(pos, score, best_move, entry_type);
+    }
+
+    /// See [`TranspositionTable::get`].
+    pub(crate) fn get(&self, pos: &Position) -> Option<Entry> {
+        self.shard(pos).get(pos)
+    }
+
+    /// Remove all positions from the table, for all the solvers using it.
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().clear();
+        }
+    }
+
+    /// The size of the table in megabytes, rounded down.
+    pub fn size_mb(&self) -> usize {
+        let shard_size = self.shards[0].lock().unwrap().size;
+        (self.shards.len() * shard_size * TranspositionTable::ENTRY_BYTES) >> 20
+    }
+
+    /// The number of positions stored in the table.
+    pub fn num_entries(&self) -> usize {
+        let shards = self.shards.iter();
+        shards
+            .map(|shard| shard.lock().unwrap().num_entries())
+            .sum()
+    }
+
+    /// How full the table is, in per mille, see
+    /// [`TranspositionTable::permille_full`].
+    pub fn permille_full(&self) -> usize {
+        let shards = self.shards.iter();
+        let total: usize = shards
+            .map(|shard| shard.lock().unwrap().permille_full())
+            .sum();
+        total / self.shards.len()
+    }
+}
+
+/// The transposition table a solver uses: its own, or one it shares with
+/// other solvers (see [`SharedTranspositionTable`]).
+pub(crate) enum SolverTable {
+    Own(TranspositionTable),
+    Shared(Arc<SharedTranspositionTable>),
+}
+
+impl Default for SolverTable {
+    fn default() -> Self {
+        Self::Own(TranspositionTable::default())
+    }
+}
+
+impl SolverTable {
+    pub fn store(
+        &mut self,
+        pos: &Position,
+        score: isize,
+        best_move: BitboardMove,
+        entry_type: EntryType,
+    ) {
+        match self {
+            Self::Own(table) => table.store(pos, score, best_move, entry_type),
+            Self::Shared(table) => table.store(pos, score, best_move, entry_type),
+        }
+    }
+
+    pub fn get(&self, pos: &Position) -> Option<Entry> {
+        match self {
+            Self::Own(table) => table.get(pos),
+            Self::Shared(table) => table.get(pos),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        match self {
+            Self::Own(table) => table.clear(),
+            Self::Shared(table) => table.clear(),
+        }
+    }
+
+    pub fn size_mb(&self) -> usize {
+        match self {
+            Self::Own(table) => table.size_mb(),
+            Self::Shared(table) => table.size_mb(),
+        }
+    }
+
+    pub fn num_entries(&self) -> usize {
+        match self {
+            Self::Own(table) => table.num_entries(),
+            Self::Shared(table) => table.num_entries(),
+        }
+    }
+
+    pub fn permille_full(&self) -> usize {
+        match self {
+            Self::Own(table) => table.permille_full(),
+            Self::Shared(table) => table.permille_full(),
+        }
+    }
+}
+
+/// Saving and loading the table, to continue long searches later.
+///
+/// The file starts with [`Self::FILE_MAGIC`], the version of the format,
+/// and the size of the table as 8 bytes. Then for every position stored:
+/// - the index in the table and the partial key (4 bytes each),
+/// - the score (2 bytes),
+/// - the best move, the entry type and the ply (1 byte each).
+///
+/// All numbers are little endian.
+impl TranspositionTable {
+    const FILE_MAGIC: &'static [u8; 4] = b"SBTT";
+    const FILE_VERSION: u8 = 1;
+    const HEADER_SIZE: usize = 4 + 1 + 8;
+    const ENTRY_SIZE: usize = 4 + 4 + 2 + 1 + 1 + 1;
+
+    /// Save the positions stored in the table to a file.
+    /// Returns the number of positions saved.
+    pub fn save(&self, path: &Path) -> Result<usize> {
+        let empty = self.empty_key();
+        let mut bytes = Vec::with_capacity(Self::HEADER_SIZE);
+        bytes.extend_from_slice(Self::FILE_MAGIC);
+        bytes.push(Self::FILE_VERSION);
+        bytes.extend_from_slice(&(self.size as u64).to_le_bytes());
+        let mut num_entries = 0;
+        for (index, (&key, entry)) in self.keys.iter().zip(self.entries.iter()).enumerate() {
+            if key == empty {
+                continue;
+            }
+            bytes.extend_from_slice(&(index as u32).to_le_bytes());
+            bytes.extend_from_slice(&key.to_le_bytes());
+            bytes.extend_from_slice(&entry.score.to_le_bytes());
+            bytes.push(entry.best_move.0);
+            bytes.push(entry.entry_type.to_u8());
+            bytes.push(entry.ply);
+            num_entries += 1;
+        }
+        std::fs::write(path, bytes)?;
+        Ok(num_entries)
+    }
+
+    /// Load a table saved with [`TranspositionTable::save`].
+    /// The table gets the size it was saved with.
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let (header, entries) = bytes
+            .split_at_checked(Self::HEADER_SIZE)
+            .ok_or(Error::InvalidTranspositionTable)?;
+        let size = u64::from_le_bytes(header[5..].try_into().unwrap());
+        if &header[..4] != Self::FILE_MAGIC
+            || header[4] != Self::FILE_VERSION
+            || size == 0
+            || size >= PartialKey::MAX as u64
+            || entries.len() % Self::ENTRY_SIZE != 0
+        {
+            return Err(Error::InvalidTranspositionTable);
+        }
+        let mut table = Self::with_size(size as usize);
+        for chunk in entries.chunks_exact(Self::ENTRY_SIZE) {
+            let index = u32::from_le_bytes(chunk[0..4].try_into().unwrap()) as usize;
+            let key = PartialKey::from_le_bytes(chunk[4..8].try_into().unwrap());
+            let entry_type = EntryType::from_u8(chunk[11]);
+            let (Some(entry_type), true) = (entry_type, index < table.size) else {
+                return Err(Error::InvalidTranspositionTable);
+            };
+            table.keys[index] = key;
+            table.entries[index] = Entry {
+                score: i16::from_le_bytes(chunk[8..10].try_into().unwrap()),
+                best_move: TTMove(chunk[10]),
+                entry_type,
+                ply: chunk[12],
+            };
+        }
+        Ok(table)
+    }
 }
 
 #[cfg(test)]
@@ -371,6 +713,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn standard_key() {
+        // The bits of the key on the standard board are the same as before
+        // the board size could change, so saved tables can still be used.
+        let mut pos = Position::default();
+        pos.make_phase_one_move(0);
+        let key = 0x1_1111_1112;
+        assert_eq!(TranspositionTable::key(&pos), key);
+        pos.set_rules(Rules {
+            second_best_on_alignment: true,
+            second_best_limit: Some(7),
+        });
+        let calls_left: Key = [39, 43, 47, 51, 55, 59].iter().map(|bit| 1 << bit).sum();
+        assert_eq!(TranspositionTable::key(&pos), key | calls_left);
+    }
+
     #[test]
     fn tt_entries() {
         let mut pos = Position::default();
@@ -409,6 +767,95 @@ mod tests {
         }
     }
 
+    #[test]
+    fn sizes() {
+        let tt = TranspositionTable::default();
+        assert_eq!(tt.size, next_prime(1 << 23) as usize);
+        assert_eq!(tt.size_mb(), TranspositionTable::DEFAULT_SIZE_MB);
+        let tt = TranspositionTable::with_size_mb(1);
+        assert_eq!(tt.size, next_prime((1 << 20) / 10) as usize);
+        assert_eq!(tt.num_entries(), 0);
+        assert_eq!(tt.permille_full(), 0);
+    }
+
+    #[test]
+    fn clear() {
+        let mut tt = TranspositionTable::with_size_mb(1);
+        let pos = Position::default();
+        let bmove = BitboardMove::StoneMove(pos.stone_move(None, 0));
+        tt.store(&pos, 0, bmove, EntryType::Exact);
+        assert_eq!(tt.num_entries(), 1);
+        tt.clear();
+        assert_eq!(tt.num_entries(), 0);
+        assert!(tt.get(&pos).is_none());
+        assert_eq!(tt.size_mb(), 1);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut pos = Position::default();
+        let mut tt = TranspositionTable::with_size_mb(2);
+        let mut stored = vec![];
+        for (i, to) in [0, 1, 0, 2, 3].into_iter().enumerate() {
+            let bmove = BitboardMove::StoneMove(pos.stone_move(None, to));
+            tt.store(&pos, i as isize - 2, bmove, EntryType::LowerBound);
+            stored.push((pos.clone(), tt.get(&pos).unwrap()));
+            pos.make_move(bmove);
+        }
+        let path = std::env::temp_dir().join("second_best_tt_test.bin");
+        assert_eq!(tt.save(&path).unwrap(), 5);
+        let loaded = TranspositionTable::load(&path).unwrap();
+        // The table keeps its size.
+        assert_eq!(loaded.size, tt.size);
+        assert_eq!(loaded.num_entries(), 5);
+        for (pos, entry) in stored {
+            assert_eq!(loaded.get(&pos), Some(entry));
+        }
+
+        std::fs::write(&path, b"SBTT").unwrap();
+        assert!(matches!(
+            TranspositionTable::load(&path),
+            Err(Error::InvalidTranspositionTable)
+        ));
+    }
+
+    #[test]
+    fn shared() {
+        let tt = Arc::new(SharedTranspositionTable::with_size_mb(2));
+        assert_eq!(tt.size_mb(), 2);
+        let threads: Vec<_> = (0..4)
+            .map(|thread_i| {
+                let tt = tt.clone();
+                std::thread::spawn(move || {
+                    let mut pos = Position::default();
+                    pos.make_phase_one_move(thread_i);
+                    for to in 0..8 {
+                        let bmove = BitboardMove::StoneMove(pos.stone_move(None, to));
+                        tt.store(&pos, to as isize, bmove, EntryType::Exact);
+                        pos.make_move(bmove);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(tt.num_entries(), 4 * 8);
+        // Every thread sees what the others stored.
+        for thread_i in 0..4 {
+            let mut pos = Position::default();
+            pos.make_phase_one_move(thread_i);
+            for to in 0..8 {
+                let entry = tt.get(&pos).unwrap();
+                assert_eq!(entry.score(pos.ply() as isize), to as isize);
+                pos.make_move(entry.best_move(&pos));
+            }
+        }
+        tt.clear();
+        assert_eq!(tt.num_entries(), 0);
+        assert_eq!(tt.permille_full(), 0);
+    }
+
     #[test]
     fn second_best() {
         let mut pos = Position::default();
@@ -424,4 +871,22 @@ mod tests {
         // have a different effect, and hence the score might be different.
         assert_eq!(tt.get(&pos), None);
     }
+
+    #[test]
+    fn calls_left() {
+        // The same board, with a different number of calls left.
+        let key = |limit: usize| {
+            let mut pos = Position::default();
+            pos.set_rules(Rules {
+                second_best_limit: Some(limit),
+                ..Default::default()
+            });
+            pos.make_phase_one_move(1);
+            TranspositionTable::key(&pos)
+        };
+        let keys: Vec<Key> = (0..=Rules::MAX_SECOND_BEST_LIMIT).map(key).collect();
+        for (i, a) in keys.iter().enumerate() {
+            assert!(keys[i + 1..].iter().all(|b| a != b));
+        }
+    }
 }
@@ -0,0 +1,173 @@
+//! Verifying a claimed result, e.g. that a position is a win in 5 moves, to
+//! gain confidence that the solver is still correct after optimizations.
+//!
+//! The solver searches the position to the depth of the result, and the line
+//! it finds is checked with a slow reference search: the [`Oracle`], which
+//! has no transposition table or pruning. The reference search grows
+//! exponentially with the depth, so it is only run on the positions of the
+//! line which are close enough to its end.
+
+use crate::analysis::ExpectedResult;
+use crate::eval::{self, ExplainableEval};
+use crate::position::PlayerMove;
+use crate::solver::Solver;
+use crate::testing::Oracle;
+
+/// A position on the line of the solver, checked with the reference search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReferenceCheck {
+    /// The number of moves of the line played to reach the position.
+    pub moves_played: usize,
+    /// The result the position should have.
+    pub expected: ExpectedResult,
+    /// The result of the reference search, `None` if it needed too many nodes.
+    pub found: Option<ExplainableEval>,
+    pub nodes: usize,
+}
+
+impl ReferenceCheck {
+    pub fn passed(&self) -> bool {
+        self.found.is_none_or(|found| self.expected.matches(found))
+    }
+}
+
+/// The result of [`verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    pub expected: ExpectedResult,
+    /// The result of the solver.
+    pub found: ExplainableEval,
+    /// The line of the solver.
+    pub pv: Vec<PlayerMove>,
+    /// The number of nodes searched by the solver.
+    pub nodes: usize,
+    /// The positions checked with the reference search, from the start of the line.
+    pub checks: Vec<ReferenceCheck>,
+}
+
+impl Verification {
+    /// Whether the solver and the reference search agree with the expected result.
+    pub fn passed(&self) -> bool {
+        self.expected.matches(self.found) && self.checks.iter().all(ReferenceCheck::passed)
+    }
+}
+
+/// Verify that the position of the solver has the expected result, which has
+/// to include the number of moves. The reference search is run on the
+/// positions of the line of the solver, starting at its end, until a position
+/// needs more than `reference_nodes` nodes. If the solver finds another
+/// result, only the position itself is checked with the reference search.
+///
+/// Returns `None` if the solver is aborted.
+pub fn verify(
+    solver: &mut Solver,
+    expected: ExpectedResult,
+    reference_nodes: usize,
+) -> Option<Verification> {
+    let (ExpectedResult::Win(Some(moves)) | ExpectedResult::Loss(Some(moves))) = expected else {
+        panic!("the expected result needs the number of moves");
+    };
+    let moves = moves.max(0) as usize;
+    let ply = solver.position.ply() as isize;
+    let score = solver.search(moves);
+    let nodes = solver.nodes();
+    if solver.abort_search() {
+        return None;
+    }
+    let found = eval::decode_eval(score, ply);
+    let pv = solver.principal_variation();
+
+    // The positions on the line, with the result they should have.
+    let mut line = vec![(solver.position.clone(), expected)];
+    if expected.matches(found) {
+        let mut pos = solver.position.clone();
+        for (moves_played, &pmove) in pv.iter().enumerate().take(moves) {
+            pos.try_make_move(pmove).ok()?;
+            let moves_left = Some((moves - moves_played - 1) as isize);
+            // The players take turns, so the result switches every move.
+            let expected = match (expected, moves_played % 2 == 0) {
+                (ExpectedResult::Win(_), true) | (ExpectedResult::Loss(_), false) => {
+                    ExpectedResult::Loss(moves_left)
+                }
+                _ => ExpectedResult::Win(moves_left),
+            };
+            line.push((pos.clone(), expected));
+        }
+    }
+    let mut oracle = Oracle::new()
+        .with_max_nodes(reference_nodes)
+        .with_abort(solver.abort_flag());
+    let mut checks = vec![];
+    let mut too_many_nodes = false;
+    for (moves_played, (pos, expected)) in line.into_iter().enumerate().rev() {
+        let (found, nodes) = match too_many_nodes {
+            // The positions closer to the start need even more nodes.
+            true => (None, 0),
+            false => (oracle.solve(&pos, moves - moves_played), oracle.nodes()),
+        };
+        if solver.abort_search() {
+            return None;
+        }
+        too_many_nodes = found.is_none();
+        checks.push(ReferenceCheck {
+            moves_played,
+            expected,
+            found,
+            nodes,
+        });
+    }
+    checks.reverse();
+    Some(Verification {
+        expected,
+        found,
+        pv,
+        nodes,
+        checks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_results() {
+        let mut solver = Solver::default();
+        let moves = "6 3 7 7 7 1 2 3 3 2 2 0 5 5 0 1";
+        solver
+            .position
+            .parse_and_play_moves(moves.split_whitespace().map(String::from).collect())
+            .unwrap();
+        let verification = verify(&mut solver, ExpectedResult::Win(Some(3)), 100_000).unwrap();
+        assert!(verification.passed(), "{verification:?}");
+        assert_eq!(verification.pv.len(), 3);
+        // Every position of the line is small enough for the reference search.
+        assert_eq!(verification.checks.len(), 4);
+        let expected: Vec<_> = verification.checks.iter().map(|c| c.expected).collect();
+        assert_eq!(
+            expected,
+            [
+                ExpectedResult::Win(Some(3)),
+                ExpectedResult::Loss(Some(2)),
+                ExpectedResult::Win(Some(1)),
+                ExpectedResult::Loss(Some(0)),
+            ]
+        );
+        assert!(verification.checks.iter().all(|c| c.found.is_some()));
+
+        // A wrong number of moves is noticed by both searches.
+        let verification = verify(&mut solver, ExpectedResult::Win(Some(5)), 100_000).unwrap();
+        assert!(!verification.passed());
+        assert_eq!(verification.found, ExplainableEval::Win(3));
+        assert_eq!(verification.checks.len(), 1);
+        assert_eq!(verification.checks[0].found, Some(ExplainableEval::Win(3)));
+        let verification = verify(&mut solver, ExpectedResult::Loss(Some(3)), 100_000).unwrap();
+        assert!(!verification.passed());
+
+        // Positions which need too many nodes are skipped.
+        let verification = verify(&mut solver, ExpectedResult::Win(Some(3)), 3).unwrap();
+        assert!(verification.passed());
+        assert_eq!(verification.checks[0].found, None);
+        assert_eq!(verification.checks[3].found, Some(ExplainableEval::Loss(0)));
+    }
+}
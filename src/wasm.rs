@@ -0,0 +1,251 @@
+//! Bindings for using the solver from JavaScript, for a web front-end.
+//!
+//! Build with `wasm-pack build --target web -- --features wasm`.
+//! There are no threads in the browser, so instead of blocking until
+//! the search is done, the [`WasmSolver`] searches in small steps:
+//!
+//! ```js
+//! const solver = new Solver();
+//! solver.setPosition(position);
+//! solver.start(20);
+//! function work() {
+//!     if (!solver.step(100000)) {
+//!         setTimeout(work, 0);
+//!     } else {
+//!         console.log(solver.bestMove(), solver.score);
+//!     }
+//! }
+//! work();
+//! ```
+
+use crate::eval;
+use crate::position::{PlayerMove, Position};
+use crate::solver::Solver;
+
+use wasm_bindgen::prelude::*;
+
+fn to_js_error(e: impl std::fmt::Debug) -> JsValue {
+    JsValue::from_str(&format!("{e:?}"))
+}
+
+/// A position, see [`Position`].
+#[wasm_bindgen(js_name = Position)]
+pub struct WasmPosition(Position);
+
+#[wasm_bindgen(js_class = Position)]
+impl WasmPosition {
+    /// The starting position.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self(Position::default())
+    }
+
+    /// Create a position from the compact notation, see [`Position::from_fen`].
+    #[wasm_bindgen(js_name = fromFen)]
+    pub fn from_fen(fen: &str) -> Result<WasmPosition, JsValue> {
+        Position::from_fen(fen).map(Self).map_err(to_js_error)
+    }
+
+    /// The position in the compact notation.
+    #[wasm_bindgen(js_name = toFen)]
+    pub fn to_fen(&self) -> String {
+        self.0.to_fen()
+    }
+
+    /// Play the given moves, e.g. "0 1 2" or "0-1 !".
+    /// If one of the moves is illegal, none of them are played.
+    pub fn play(&mut self, moves: &str) -> Result<(), JsValue> {
+        let moves = PlayerMove::parse_moves(moves).map_err(to_js_error)?;
+        let mut position = self.0.clone();
+        for pmove in moves {
+            position.try_make_move(pmove).map_err(to_js_error)?;
+        }
+        self.0 = position;
+        Ok(())
+    }
+
+    /// Undo the last move, and return it. Returns `undefined` if there is nothing to undo.
+    pub fn undo(&mut self) -> Option<String> {
+        self.0
+            .undo_last_player_move()
+            .map(|pmove| pmove.to_string())
+    }
+
+    /// The legal moves, separated by spaces.
+    #[wasm_bindgen(js_name = legalMoves)]
+    pub fn legal_moves(&self) -> String {
+        PlayerMove::to_long_form(&self.0.legal_moves())
+    }
+
+    /// "X" or "O", the player to move.
+    #[wasm_bindgen(js_name = currentPlayer)]
+    pub fn current_player(&self) -> String {
+        self.0.current_player().to_string()
+    }
+
+    /// The color of the stone at the given spot, if there is one.
+    #[wasm_bindgen(js_name = stoneAt)]
+    pub fn stone_at(&self, stack_i: usize, height: usize) -> Option<String> {
+        self.0
+            .stone_at(stack_i, height)
+            .map(|color| color.to_string())
+    }
+
+    #[wasm_bindgen(js_name = canSecondBest)]
+    pub fn can_second_best(&self) -> bool {
+        self.0.can_second_best()
+    }
+
+    #[wasm_bindgen(js_name = gameOver)]
+    pub fn game_over(&self) -> bool {
+        self.0.game_over()
+    }
+
+    /// A description of the state of the game.
+    #[wasm_bindgen(js_name = gameStatus)]
+    pub fn game_status(&self) -> String {
+        self.0.game_status().to_string()
+    }
+}
+
+impl Default for WasmPosition {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A solver which searches in steps, see [`Solver::search_iteration`].
+#[wasm_bindgen(js_name = Solver)]
+pub struct WasmSolver {
+    solver: Solver,
+    max_depth: usize,
+    /// The depth of the last completed iteration.
+    depth: usize,
+    /// How many times the current iteration has been interrupted.
+    retries: u32,
+    score: isize,
+    nodes: usize,
+    done: bool,
+}
+
+#[wasm_bindgen(js_class = Solver)]
+impl WasmSolver {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            solver: Solver::default(),
+            max_depth: 0,
+            depth: 0,
+            retries: 0,
+            score: 0,
+            nodes: 0,
+            done: true,
+        }
+    }
+
+    /// Set the position to search. This stops the current search.
+    #[wasm_bindgen(js_name = setPosition)]
+    pub fn set_position(&mut self, position: &WasmPosition) {
+        self.solver.set_position(position.0.clone());
+        self.start(0);
+    }
+
+    /// Set one of the solver options, see [`crate::solver::SolverOptions::set`].
+    #[wasm_bindgen(js_name = setOption)]
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<(), JsValue> {
+        self.solver.set_option(name, value).map_err(to_js_error)
+    }
+
+    /// Start a new search of the position, up to the given depth.
+    pub fn start(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+        self.depth = 0;
+        self.retries = 0;
+        self.score = 0;
+        self.nodes = 0;
+        self.done = max_depth == 0 || self.solver.position.game_over();
+    }
+
+    /// Search for approximately `max_nodes` nodes, or until the current
+    /// iteration is completed. Returns true once the search is done.
+    ///
+    /// An interrupted iteration is started again on the next step, with
+    /// twice as many nodes each time, so that every iteration completes.
+    pub fn step(&mut self, max_nodes: usize) -> bool {
+        if self.done {
+            return true;
+        }
+        let max_nodes = max_nodes.saturating_mul(1 << self.retries.min(16));
+        let depth = self.depth + 1;
+        let result = self.solver.search_iteration(depth, max_nodes);
+        self.nodes += self.solver.nodes();
+        let Some(score) = result else {
+            self.retries += 1;
+            return false;
+        };
+        self.depth = depth;
+        self.retries = 0;
+        self.score = score;
+        let solved = eval::is_solved(score, self.solver.position.ply() as isize, depth);
+        self.done = solved || depth >= self.max_depth;
+        self.done
+    }
+
+    /// Whether the search is done.
+    #[wasm_bindgen(getter)]
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    /// The depth of the last completed iteration.
+    #[wasm_bindgen(getter)]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The score of the last completed iteration.
+    #[wasm_bindgen(getter)]
+    pub fn score(&self) -> isize {
+        self.score
+    }
+
+    /// The total number of nodes searched.
+    #[wasm_bindgen(getter)]
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    /// The score explained in words, see [`eval::explain_eval`].
+    pub fn explanation(&self) -> String {
+        let position = &self.solver.position;
+        eval::explain_eval(
+            position.current_player(),
+            self.score,
+            position.ply() as isize,
+        )
+    }
+
+    /// The best move found so far, if an iteration was completed.
+    #[wasm_bindgen(js_name = bestMove)]
+    pub fn best_move(&mut self) -> Option<String> {
+        if self.depth == 0 {
+            return None;
+        }
+        let pv = self.solver.principal_variation();
+        pv.first().map(|pmove| pmove.to_string())
+    }
+
+    /// The principal variation found so far, separated by spaces.
+    pub fn pv(&mut self) -> String {
+        if self.depth == 0 {
+            return String::new();
+        }
+        PlayerMove::to_long_form(&self.solver.principal_variation())
+    }
+}
+
+impl Default for WasmSolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}